@@ -0,0 +1,293 @@
+//! Acoustic precipitation sensing: band-limited features of short microphone windows.
+//!
+//! Only the features leave this module, the audio samples are dropped once analyzed.
+
+/// Drop impacts sit between wind rumble and hiss, see `BandPass`.
+pub const BAND_LOW_HZ: f32 = 1000.0;
+pub const BAND_HIGH_HZ: f32 = 6000.0;
+// Impacts closer than this are the same one ringing
+const IMPACT_HOLDOFF_MS: u32 = 5;
+pub const ACOUSTIC_THRESHOLDS_LEN: usize = 1 + 6 * 4;
+const THRESHOLDS_VERSION: u8 = 1;
+
+/// Qualitative precipitation activity heard over a window or a report period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ActivityLevel {
+    None,
+    Light,
+    Moderate,
+    Heavy,
+}
+
+impl ActivityLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            ActivityLevel::None => "none",
+            ActivityLevel::Light => "light",
+            ActivityLevel::Moderate => "moderate",
+            ActivityLevel::Heavy => "heavy",
+        }
+    }
+}
+
+/// Levels in dBFS of the band-passed signal, to be calibrated on site.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AcousticThresholds {
+    pub light_db: f32,
+    pub moderate_db: f32,
+    pub heavy_db: f32,
+    /// A sample above this starts a drop impact
+    pub impact_db: f32,
+    /// An impact peaking above this is a hail stone
+    pub hail_db: f32,
+    /// Impacts per window that make light rain even below `light_db`, for the drizzle onset
+    pub light_impacts: f32,
+}
+
+impl AcousticThresholds {
+    pub const NAMES: [&'static str; 6] = [
+        "light_db",
+        "moderate_db",
+        "heavy_db",
+        "impact_db",
+        "hail_db",
+        "light_impacts",
+    ];
+
+    /// Calibration command: `<name> <value>`, e.g. `hail_db -8`. Returns false when the
+    /// name or the value is invalid, the thresholds are then left as they were.
+    pub fn apply_command(&mut self, payload: &str) -> bool {
+        let mut words = payload.split_whitespace();
+        let (Some(name), Some(value), None) = (words.next(), words.next(), words.next()) else {
+            return false;
+        };
+        let Ok(value) = value.parse::<f32>() else {
+            return false;
+        };
+        let valid = match name {
+            "light_impacts" => (0.0..=1000.0).contains(&value),
+            _ => (-120.0..=0.0).contains(&value),
+        };
+        match self.field_mut(name) {
+            Some(field) if valid => {
+                *field = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut f32> {
+        match name {
+            "light_db" => Some(&mut self.light_db),
+            "moderate_db" => Some(&mut self.moderate_db),
+            "heavy_db" => Some(&mut self.heavy_db),
+            "impact_db" => Some(&mut self.impact_db),
+            "hail_db" => Some(&mut self.hail_db),
+            "light_impacts" => Some(&mut self.light_impacts),
+            _ => None,
+        }
+    }
+
+    /// In the order of `NAMES`.
+    pub fn values(&self) -> [f32; 6] {
+        [
+            self.light_db,
+            self.moderate_db,
+            self.heavy_db,
+            self.impact_db,
+            self.hail_db,
+            self.light_impacts,
+        ]
+    }
+
+    /// Level of a window or a period from its RMS and impacts per window.
+    pub fn classify(&self, rms_db: f32, impacts_per_window: f32) -> ActivityLevel {
+        if rms_db >= self.heavy_db {
+            ActivityLevel::Heavy
+        } else if rms_db >= self.moderate_db {
+            ActivityLevel::Moderate
+        } else if rms_db >= self.light_db
+            || (self.light_impacts > 0.0 && impacts_per_window >= self.light_impacts)
+        {
+            ActivityLevel::Light
+        } else {
+            ActivityLevel::None
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; ACOUSTIC_THRESHOLDS_LEN] {
+        let mut bytes = [0u8; ACOUSTIC_THRESHOLDS_LEN];
+        bytes[0] = THRESHOLDS_VERSION;
+        for (chunk, value) in bytes[1..].chunks_exact_mut(4).zip(self.values()) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// None for another version or a truncated record.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != ACOUSTIC_THRESHOLDS_LEN || bytes[0] != THRESHOLDS_VERSION {
+            return None;
+        }
+        let mut values = bytes[1..]
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]));
+        let mut next = || values.next().unwrap_or_default();
+        Some(Self {
+            light_db: next(),
+            moderate_db: next(),
+            heavy_db: next(),
+            impact_db: next(),
+            hail_db: next(),
+            light_impacts: next(),
+        })
+    }
+}
+
+/// Second order high-pass, against the wind rumble, then a first order low-pass. Cheap
+/// enough to run on every sample.
+#[derive(Clone, Copy, Debug)]
+pub struct BandPass {
+    hp_alpha: f32,
+    lp_alpha: f32,
+    // Input and output of both high-pass stages
+    hp: [(f32, f32); 2],
+    lp_out: f32,
+}
+
+impl BandPass {
+    pub fn new(sample_rate_hz: u32, low_hz: f32, high_hz: f32) -> Self {
+        let dt = 1.0 / sample_rate_hz as f32;
+        let rc = |hz: f32| 1.0 / (2.0 * core::f32::consts::PI * hz);
+        Self {
+            hp_alpha: rc(low_hz) / (rc(low_hz) + dt),
+            lp_alpha: dt / (rc(high_hz) + dt),
+            hp: [(0.0, 0.0); 2],
+            lp_out: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let mut y = x;
+        for (input, output) in self.hp.iter_mut() {
+            *output = self.hp_alpha * (*output + y - *input);
+            *input = y;
+            y = *output;
+        }
+        self.lp_out += self.lp_alpha * (y - self.lp_out);
+        self.lp_out
+    }
+}
+
+/// Features of one window, amplitudes relative to full scale.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WindowFeatures {
+    pub rms: f32,
+    pub peak: f32,
+    pub impacts: u32,
+    pub hail_impacts: u32,
+}
+
+impl WindowFeatures {
+    /// One pass over the window, so the cost only depends on its length. `samples` are
+    /// normalized to [-1, 1], the filter state carries over between windows.
+    pub fn analyze(
+        samples: impl Iterator<Item = f32>,
+        filter: &mut BandPass,
+        thresholds: &AcousticThresholds,
+        sample_rate_hz: u32,
+    ) -> Self {
+        let impact_level = db_to_amplitude(thresholds.impact_db);
+        let hail_level = db_to_amplitude(thresholds.hail_db);
+        let holdoff = sample_rate_hz * IMPACT_HOLDOFF_MS / 1000;
+        let mut features = Self::default();
+        let mut sum_squares = 0.0;
+        let mut count = 0u32;
+        // Samples left in the current impact and its peak
+        let mut impact_left = 0;
+        let mut impact_peak = 0.0f32;
+
+        for sample in samples {
+            let y = filter.process(sample);
+            let level = y.abs();
+            sum_squares += y * y;
+            count += 1;
+            features.peak = features.peak.max(level);
+            if impact_left > 0 {
+                impact_peak = impact_peak.max(level);
+                impact_left -= 1;
+                if impact_left == 0 && impact_peak >= hail_level {
+                    features.hail_impacts += 1;
+                }
+            } else if level >= impact_level {
+                features.impacts += 1;
+                impact_left = holdoff.max(1);
+                impact_peak = level;
+            }
+        }
+        if impact_left > 0 && impact_peak >= hail_level {
+            features.hail_impacts += 1;
+        }
+        if count > 0 {
+            features.rms = libm::sqrtf(sum_squares / count as f32);
+        }
+        features
+    }
+}
+
+pub fn amplitude_to_db(amplitude: f32) -> f32 {
+    20.0 * libm::log10f(amplitude.max(1e-6))
+}
+
+pub fn db_to_amplitude(db: f32) -> f32 {
+    libm::powf(10.0, db / 20.0)
+}
+
+/// Windows analyzed since the last publish.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AcousticReport {
+    pub windows: u32,
+    rms_sum: f32,
+    pub peak: f32,
+    pub impacts: u32,
+    pub hail_impacts: u32,
+    /// Windows whose analysis took longer than the CPU budget
+    pub overruns: u32,
+}
+
+impl AcousticReport {
+    pub fn add(&mut self, features: &WindowFeatures) {
+        self.windows += 1;
+        self.rms_sum += features.rms;
+        self.peak = self.peak.max(features.peak);
+        self.impacts += features.impacts;
+        self.hail_impacts += features.hail_impacts;
+    }
+
+    pub fn mean_rms_db(&self) -> f32 {
+        amplitude_to_db(self.rms_sum / self.windows.max(1) as f32)
+    }
+
+    pub fn level(&self, thresholds: &AcousticThresholds) -> ActivityLevel {
+        if self.windows == 0 {
+            return ActivityLevel::None;
+        }
+        thresholds.classify(
+            self.mean_rms_db(),
+            self.impacts as f32 / self.windows as f32,
+        )
+    }
+
+    /// Cross-check with the tips counted over the same period: steady rain heard without a
+    /// tip hints at a clogged gauge, tips with nothing heard at debris or a bumped gauge.
+    /// Light rain without tips is the expected drizzle onset.
+    pub fn consistent_with_tips(&self, thresholds: &AcousticThresholds, tips: u32) -> bool {
+        match self.level(thresholds) {
+            _ if self.windows == 0 => true,
+            ActivityLevel::None => tips < 2,
+            ActivityLevel::Light => true,
+            ActivityLevel::Moderate | ActivityLevel::Heavy => tips > 0,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    hal::{
+        delay::TickType,
+        gpio::AnyIOPin,
+        i2s::{
+            config::{
+                Config as I2sConfig, DataBitWidth, SlotMode, StdClkConfig, StdConfig,
+                StdGpioConfig, StdSlotConfig,
+            },
+            I2sDriver, I2sRx, I2S0,
+        },
+    },
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use weather_station::acoustic::*;
+
+use crate::provisioning::CONFIG;
+
+const NVS_NAMESPACE: &str = "acoustic";
+const NVS_KEY: &str = "thresholds";
+// 32 KB of samples at most, whatever the configured window
+const MAX_WINDOW_SAMPLES: usize = 8192;
+// A window shrunk after overruns never goes below this
+const MIN_WINDOW_SAMPLES: usize = 1024;
+// 24 bit samples, left aligned in the 32 bit slots
+const FULL_SCALE: f32 = 8_388_608.0;
+
+// Hail impacts since the cold boot
+#[link_section = ".rtc.data"]
+static mut HAIL_TOTAL: u32 = 0;
+
+/// Rain and hail heard by an I2S MEMS microphone (INMP441 or similar, L/R to ground).
+///
+/// A thread captures a short window every `acoustic_interval_s` and keeps its features, the
+/// samples themselves never leave it. Windows whose analysis exceeds `acoustic_budget_ms`
+/// are counted and shorten the next ones.
+pub struct AcousticMonitor {
+    nvs: EspNvs<NvsDefault>,
+    thresholds: Arc<Mutex<AcousticThresholds>>,
+    report: Arc<Mutex<AcousticReport>>,
+}
+
+impl AcousticMonitor {
+    pub fn start(i2s: I2S0, nvs: EspDefaultNvsPartition, cold_boot: bool) -> Result<Self> {
+        let nvs = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+        let mut buf = [0u8; ACOUSTIC_THRESHOLDS_LEN];
+        let thresholds = nvs
+            .get_blob(NVS_KEY, &mut buf)?
+            .and_then(AcousticThresholds::from_bytes)
+            .unwrap_or_else(default_thresholds);
+        if cold_boot {
+            unsafe { HAIL_TOTAL = 0 };
+        }
+
+        // Pins come from the config, they are not claimed by any other driver
+        let (sck, ws, sd) = unsafe {
+            (
+                AnyIOPin::new(CONFIG.mic_sck_gpio),
+                AnyIOPin::new(CONFIG.mic_ws_gpio),
+                AnyIOPin::new(CONFIG.mic_sd_gpio),
+            )
+        };
+        let rate = CONFIG.acoustic_sample_rate_hz;
+        let config = StdConfig::new(
+            I2sConfig::default(),
+            StdClkConfig::from_sample_rate_hz(rate),
+            StdSlotConfig::philips_slot_default(DataBitWidth::Bits32, SlotMode::Mono),
+            StdGpioConfig::default(),
+        );
+        let mut driver =
+            I2sDriver::new_std_rx(i2s, &config, sck, sd, Option::<AnyIOPin>::None, ws)?;
+        driver.rx_enable()?;
+
+        let monitor = Self {
+            nvs,
+            thresholds: Arc::new(Mutex::new(thresholds)),
+            report: Arc::new(Mutex::new(AcousticReport::default())),
+        };
+        let shared_thresholds = monitor.thresholds.clone();
+        let shared_report = monitor.report.clone();
+        let window_len = (rate as usize * CONFIG.acoustic_window_ms as usize / 1000)
+            .clamp(MIN_WINDOW_SAMPLES, MAX_WINDOW_SAMPLES);
+        let budget = Duration::from_millis(CONFIG.acoustic_budget_ms as u64);
+        let interval = Duration::from_secs(CONFIG.acoustic_interval_s.max(1) as u64);
+
+        std::thread::Builder::new()
+            .stack_size(4096)
+            .spawn(move || {
+                let mut filter = BandPass::new(rate, BAND_LOW_HZ, BAND_HIGH_HZ);
+                let mut samples = vec![0i32; window_len];
+                let mut len = window_len;
+                loop {
+                    std::thread::sleep(interval);
+                    // The DMA buffers filled up since the last window, only fresh audio counts
+                    drain(&mut driver);
+                    if let Err(e) = read_window(&mut driver, &mut samples[..len], rate) {
+                        log::error!("Microphone read failed: {e}");
+                        continue;
+                    }
+
+                    let thresholds = *shared_thresholds.lock().unwrap();
+                    let started = Instant::now();
+                    let features = WindowFeatures::analyze(
+                        samples[..len].iter().map(|&s| (s >> 8) as f32 / FULL_SCALE),
+                        &mut filter,
+                        &thresholds,
+                        rate,
+                    );
+                    let spent = started.elapsed();
+
+                    let mut report = shared_report.lock().unwrap();
+                    report.add(&features);
+                    if spent > budget {
+                        report.overruns += 1;
+                        len = (len / 2).max(MIN_WINDOW_SAMPLES);
+                        log::warn!(
+                            "Acoustic analysis took {}ms, window shortened to {len} samples",
+                            spent.as_millis()
+                        );
+                    } else if spent * 4 < budget {
+                        len = (len * 2).min(window_len);
+                    }
+                }
+            })?;
+        Ok(monitor)
+    }
+
+    /// Hands over the windows analyzed since the last call.
+    pub fn take_report(&self) -> AcousticReport {
+        let report = std::mem::take(&mut *self.report.lock().unwrap());
+        unsafe { HAIL_TOTAL = HAIL_TOTAL.saturating_add(report.hail_impacts) };
+        report
+    }
+
+    /// Drops the windows analyzed since the last call, while the gauge is being serviced.
+    pub fn discard_report(&self) {
+        *self.report.lock().unwrap() = AcousticReport::default();
+    }
+
+    pub fn thresholds(&self) -> AcousticThresholds {
+        *self.thresholds.lock().unwrap()
+    }
+
+    /// `<name> <value>` sets a threshold, `reset` brings back the config ones. Returns false
+    /// when the command is invalid.
+    pub fn calibrate(&mut self, command: &str) -> bool {
+        let mut thresholds = self.thresholds();
+        if command.trim() == "reset" {
+            thresholds = default_thresholds();
+        } else if !thresholds.apply_command(command) {
+            return false;
+        }
+        *self.thresholds.lock().unwrap() = thresholds;
+        self.nvs
+            .set_blob(NVS_KEY, &thresholds.to_bytes())
+            .map_err(|e| log::error!("fail storing acoustic thresholds: {e}"))
+            .ok();
+        true
+    }
+
+    pub fn hail_total(&self) -> u32 {
+        unsafe { HAIL_TOTAL }
+    }
+}
+
+fn default_thresholds() -> AcousticThresholds {
+    AcousticThresholds {
+        light_db: CONFIG.acoustic_light_db,
+        moderate_db: CONFIG.acoustic_moderate_db,
+        heavy_db: CONFIG.acoustic_heavy_db,
+        impact_db: CONFIG.acoustic_impact_db,
+        hail_db: CONFIG.acoustic_hail_db,
+        light_impacts: CONFIG.acoustic_light_impacts,
+    }
+}
+
+fn drain(driver: &mut I2sDriver<'static, I2sRx>) {
+    let mut scratch = [0u8; 1024];
+    while matches!(driver.read(&mut scratch, 0), Ok(len) if len > 0) {}
+}
+
+fn read_window(
+    driver: &mut I2sDriver<'static, I2sRx>,
+    samples: &mut [i32],
+    rate: u32,
+) -> Result<()> {
+    // Twice the window duration before giving up on a stalled bus
+    let timeout = TickType::new_millis(2000 * samples.len() as u64 / rate as u64 + 10).ticks();
+    let mut bytes = [0u8; 1024];
+    let mut filled = 0;
+    while filled < samples.len() {
+        let wanted = ((samples.len() - filled) * 4).min(bytes.len());
+        let len = driver.read(&mut bytes[..wanted], timeout)?;
+        if len == 0 {
+            anyhow::bail!("no data from the microphone");
+        }
+        for (sample, chunk) in samples[filled..]
+            .iter_mut()
+            .zip(bytes[..len].chunks_exact(4))
+        {
+            *sample = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        filled += len / 4;
+    }
+    Ok(())
+}
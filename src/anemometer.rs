@@ -0,0 +1,134 @@
+use anyhow::{anyhow, bail, Result};
+use esp_idf_svc::hal::{
+    delay::TickType,
+    gpio::{AnyIOPin, AnyOutputPin, Output, PinDriver},
+    uart::{config::Config as UartConfig, UartDriver, UART2},
+    units::Hertz,
+};
+use std::time::{Duration, Instant};
+use weather_station::{
+    modbus::*,
+    runtime::RUNTIME,
+    sensors::{Measurement, Sensor},
+};
+
+use crate::provisioning::CONFIG;
+
+// Ultrasonic sensors answer within a few tens of ms, glitches on the bus are common
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(200);
+const RETRIES: usize = 3;
+
+/// RS485 ultrasonic anemometer polled as a Modbus RTU slave on UART2.
+pub struct UltrasonicAnemometer<'d> {
+    uart: UartDriver<'d>,
+    // Driver enable and receiver enable tied together, high to transmit
+    de_re: PinDriver<'d, AnyOutputPin, Output>,
+    pub errors: u32,
+}
+
+impl UltrasonicAnemometer<'_> {
+    pub fn new(uart: UART2) -> Result<Self> {
+        // Pins come from the config, they are not claimed by any other driver
+        let (tx, rx, de_re) = unsafe {
+            (
+                AnyIOPin::new(CONFIG.rs485_tx_gpio),
+                AnyIOPin::new(CONFIG.rs485_rx_gpio),
+                AnyOutputPin::new(CONFIG.rs485_de_gpio),
+            )
+        };
+        let uart = UartDriver::new(
+            uart,
+            tx,
+            rx,
+            Option::<AnyIOPin>::None,
+            Option::<AnyIOPin>::None,
+            &UartConfig::new().baudrate(Hertz(CONFIG.rs485_baud)),
+        )?;
+        let mut de_re = PinDriver::output(de_re)?;
+        de_re.set_low()?;
+
+        Ok(Self {
+            uart,
+            de_re,
+            errors: 0,
+        })
+    }
+
+    /// Wind speed in km/h and direction in degrees from north, scaled as configured.
+    pub fn wind(&mut self) -> Result<(f32, f32)> {
+        let speed = self.read_register(CONFIG.wind_modbus_speed_register)?;
+        let direction = self.read_register(CONFIG.wind_modbus_direction_register)?;
+        let speed_kmh = speed as f32 * CONFIG.wind_modbus_speed_scale;
+        let direction_deg = (direction as f32 * CONFIG.wind_modbus_direction_scale
+            + RUNTIME.vane_offset_deg() as f32)
+            .rem_euclid(360.0);
+
+        Ok((speed_kmh, direction_deg))
+    }
+
+    fn read_register(&mut self, register: u16) -> Result<u16> {
+        let mut last_error = anyhow!("no attempt");
+        for attempt in 1..=RETRIES {
+            match self.transaction(register) {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    self.errors += 1;
+                    log::warn!("Anemometer register {register} attempt {attempt} failed: {e}");
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    fn transaction(&mut self, register: u16) -> Result<u16> {
+        let unit_id = CONFIG.wind_modbus_unit_id;
+        let function = CONFIG.wind_modbus_function;
+        let request = rtu_read_request(unit_id, function, register, 1);
+        self.uart.clear_rx()?;
+
+        self.de_re.set_high()?;
+        let sent = self.uart.write(&request).and_then(|_| {
+            self.uart
+                .wait_tx_done(TickType::from(RESPONSE_TIMEOUT).ticks())
+        });
+        // Release the bus even if sending failed
+        self.de_re.set_low()?;
+        sent?;
+
+        let expected = rtu_read_response_len(1);
+        let mut frame = [0u8; 16];
+        let mut len = 0;
+        let start = Instant::now();
+        while len < expected {
+            let remaining = RESPONSE_TIMEOUT.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                bail!("timeout after {len} bytes");
+            }
+            len += self
+                .uart
+                .read(&mut frame[len..expected], TickType::from(remaining).ticks())?;
+            // Exception responses are shorter than the expected one
+            if len == 5 && frame[1] & 0x80 != 0 {
+                break;
+            }
+        }
+
+        let values = parse_rtu_read_response(unit_id, function, 1, &frame[..len])?;
+        Ok(values[0])
+    }
+}
+
+impl Sensor for UltrasonicAnemometer<'_> {
+    fn id(&self) -> &str {
+        "rs485_anemometer"
+    }
+
+    fn read(&mut self) -> Result<Measurement> {
+        let (speed_kmh, direction_deg) = self.wind()?;
+        Ok(Measurement::Wind {
+            speed_kmh,
+            direction_deg,
+        })
+    }
+}
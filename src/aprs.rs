@@ -0,0 +1,113 @@
+//! APRS weather reports, as submitted to the Citizen Weather Observer Program over APRS-IS.
+//!
+//! A report is the station position followed by fixed width fields: wind direction and
+//! speed, gust, temperature in °F, rain of the last hour, the last 24 hours and since
+//! midnight in hundredths of an inch, humidity and sea level pressure in tenths of hPa.
+//! Fields without a value are filled with dots.
+use crate::reading::WeatherReading;
+use crate::runtime::{Field, FieldSelection};
+use crate::wunderground::{celsius_to_fahrenheit, kmh_to_mph, mm_to_in};
+
+const SOFTWARE: &str = "weather_station";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// First line sent to an APRS-IS server. CWOP stations without an amateur radio license
+/// use the passcode -1.
+pub fn login_line(callsign: &str, passcode: &str) -> String {
+    format!("user {callsign} pass {passcode} vers {SOFTWARE} {VERSION}\r\n")
+}
+
+/// `DDMM.mmN/DDDMM.mmW`, the position with the primary symbol table.
+pub fn position(latitude: f32, longitude: f32) -> String {
+    let coordinate = |value: f32, degree_digits: usize, positive: char, negative: char| {
+        let hemisphere = if value < 0.0 { negative } else { positive };
+        // Rounded to the hundredth of a minute first so 59.999 does not print as 60.00
+        let minutes = (value.abs() * 6000.0).round() as u32;
+        format!(
+            "{:0degree_digits$}{:02}.{:02}{hemisphere}",
+            minutes / 6000,
+            minutes % 6000 / 100,
+            minutes % 100
+        )
+    };
+    format!(
+        "{}/{}",
+        coordinate(latitude, 2, 'N', 'S'),
+        coordinate(longitude, 3, 'E', 'W')
+    )
+}
+
+// Right aligned, zero padded value, or dots when missing
+fn field(value: Option<f32>, width: usize, min: i32, max: i32) -> String {
+    match value.filter(|value| value.is_finite()) {
+        Some(value) => format!("{:0width$}", (value.round() as i32).clamp(min, max)),
+        None => ".".repeat(width),
+    }
+}
+
+/// Report of a reading, one APRS-IS line without the line end. `time_utc` is the day of the
+/// month, hour and minute it was taken as `DDHHMM`, None sends it without a time. The
+/// measurements left out by `fields` are sent as missing, and so are the wind and the rain
+/// of a reading taken during maintenance.
+pub fn weather_packet(
+    callsign: &str,
+    latitude: f32,
+    longitude: f32,
+    reading: &WeatherReading,
+    fields: FieldSelection,
+    time_utc: Option<&str>,
+) -> String {
+    let measured = |field: Field| fields.contains(field);
+    let outdoor = |field: Field| measured(field) && !reading.maintenance;
+    let rain = |mm: f32| outdoor(Field::Rain).then(|| mm_to_in(mm) * 100.0);
+    // 00 stands for 100%
+    let humidity = measured(Field::Humidity)
+        .then_some(reading.humidity)
+        .filter(|humidity| *humidity > 0.0)
+        .map(|humidity| humidity.round() % 100.0);
+    let header = match time_utc {
+        Some(time) => format!("@{time}z"),
+        None => "!".to_string(),
+    };
+    format!(
+        "{callsign}>APRS,TCPIP*:{header}{}_{}/{}g{}t{}r{}p{}P{}h{}b{}",
+        position(latitude, longitude),
+        field(
+            outdoor(Field::WindDirection).then_some(reading.wind_direction_deg),
+            3,
+            0,
+            360
+        ),
+        field(
+            outdoor(Field::WindSpeed).then(|| kmh_to_mph(reading.wind_speed_kmh)),
+            3,
+            0,
+            999
+        ),
+        field(
+            outdoor(Field::WindGust).then(|| kmh_to_mph(reading.wind_gust_kmh)),
+            3,
+            0,
+            999
+        ),
+        field(
+            measured(Field::Temperature).then(|| celsius_to_fahrenheit(reading.temperature)),
+            3,
+            -99,
+            999
+        ),
+        field(rain(reading.rain_totals.last_1h_mm), 3, 0, 999),
+        field(rain(reading.rain_totals.last_24h_mm), 3, 0, 999),
+        field(rain(reading.rain_totals.today_mm), 3, 0, 999),
+        field(humidity, 2, 0, 99),
+        field(
+            reading
+                .sea_level_pressure
+                .filter(|_| measured(Field::Pressure))
+                .map(|hpa| hpa * 10.0),
+            5,
+            0,
+            99999
+        ),
+    )
+}
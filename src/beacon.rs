@@ -0,0 +1,54 @@
+// Largest datagram sent without risking IP fragmentation on common LANs
+pub const SAFE_MTU: usize = 1400;
+
+/// Build the datagrams carrying `payload` (a JSON object).
+///
+/// Small payloads are sent as a single object with the station id and sequence number added.
+/// Larger ones are split in parts carrying an escaped slice of the payload to be concatenated
+/// by the receiver in `part` order.
+pub fn beacon_datagrams(station: &str, seq: u32, payload: &str, mtu: usize) -> Vec<String> {
+    let single = format!("{{\"station\": \"{station}\", \"seq\": {seq}, \"reading\": {payload}}}");
+    if single.len() <= mtu {
+        return vec![single];
+    }
+
+    // Room left for the chunk once the part header is written, escaping can double its size
+    let header_len = format!("{{\"station\": \"{station}\", \"seq\": {seq}, \"part\": 000, \"parts\": 000, \"chunk\": \"\"}}").len();
+    let chunk_len = (mtu.saturating_sub(header_len) / 2).max(1);
+    let chunks = split_at_char_boundaries(payload, chunk_len);
+    let parts = chunks.len();
+
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(part, chunk)| {
+            format!(
+                "{{\"station\": \"{station}\", \"seq\": {seq}, \"part\": {part}, \"parts\": {parts}, \"chunk\": \"{}\"}}",
+                escape_json(chunk)
+            )
+        })
+        .collect()
+}
+
+fn split_at_char_boundaries(s: &str, max_len: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let mut end = max_len.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            // max_len is smaller than the first char, send it whole
+            end = rest.char_indices().nth(1).map_or(rest.len(), |(i, _)| i);
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+pub fn escape_json(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
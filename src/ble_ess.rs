@@ -0,0 +1,215 @@
+use anyhow::{anyhow, Result};
+use esp_idf_svc::{
+    bt::{Ble, BtDriver},
+    hal::modem::BluetoothModem,
+    nvs::EspDefaultNvsPartition,
+    sys::{
+        esp, esp_attr_control_t, esp_attr_desc_t, esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+        esp_ble_adv_channel_t_ADV_CHNL_ALL, esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY,
+        esp_ble_adv_params_t, esp_ble_adv_type_t_ADV_TYPE_IND, esp_ble_gap_cb_param_t,
+        esp_ble_gap_config_adv_data_raw, esp_ble_gap_register_callback,
+        esp_ble_gap_set_device_name, esp_ble_gap_start_advertising, esp_ble_gatts_app_register,
+        esp_ble_gatts_cb_param_t, esp_ble_gatts_create_attr_tab, esp_ble_gatts_register_callback,
+        esp_ble_gatts_set_attr_value, esp_ble_gatts_start_service, esp_gap_ble_cb_event_t,
+        esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_RAW_SET_COMPLETE_EVT, esp_gatt_if_t,
+        esp_gatts_attr_db_t, esp_gatts_cb_event_t,
+        esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT,
+        esp_gatts_cb_event_t_ESP_GATTS_DISCONNECT_EVT, esp_gatts_cb_event_t_ESP_GATTS_REG_EVT,
+        ESP_GATT_AUTO_RSP, ESP_GATT_CHAR_PROP_BIT_READ, ESP_GATT_PERM_READ,
+        ESP_GATT_UUID_CHAR_DECLARE, ESP_GATT_UUID_PRI_SERVICE, ESP_UUID_LEN_16,
+    },
+};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Mutex;
+use weather_station::{ess::*, reading::WeatherReading};
+
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+// Service declaration, then a declaration and a value per characteristic
+const ATTRIBUTES: usize = 1 + 2 * CHARACTERISTICS.len();
+// Connectable advertising, 100 ms to phones scanning next to the station
+const ADV_INTERVAL: u16 = 160;
+
+static PRIMARY_SERVICE: u16 = ESP_GATT_UUID_PRI_SERVICE as u16;
+static CHARACTERISTIC_DECLARATION: u16 = ESP_GATT_UUID_CHAR_DECLARE as u16;
+static SERVICE: u16 = SERVICE_UUID;
+static READ_PROPERTY: u8 = ESP_GATT_CHAR_PROP_BIT_READ as u8;
+static UUIDS: [u16; CHARACTERISTICS.len()] = CHARACTERISTICS;
+// Initial value of every characteristic, the longest one is 4 bytes
+static ZERO: [u8; 4] = [0; 4];
+
+// Value handles, set once the attribute table is created. 0 until then
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLE: AtomicU16 = AtomicU16::new(0);
+static VALUE_HANDLES: [AtomicU16; CHARACTERISTICS.len()] = [NO_HANDLE; CHARACTERISTICS.len()];
+// Values taken before the table was created, applied once it is
+static PENDING: Mutex<Option<EssValues>> = Mutex::new(None);
+
+/// GATT server of the Environmental Sensing Service, so a phone next to the station reads
+/// the current values without WiFi. The values live in the Bluetooth stack, which answers
+/// the reads on its own.
+pub struct EssServer {
+    // The controller stays up as long as the server exists
+    _driver: BtDriver<'static, Ble>,
+}
+
+impl EssServer {
+    pub fn start(modem: BluetoothModem, nvs: EspDefaultNvsPartition) -> Result<Self> {
+        let driver = BtDriver::<Ble>::new(modem, Some(nvs))?;
+        esp!(unsafe { esp_ble_gap_register_callback(Some(gap_event)) })?;
+        esp!(unsafe { esp_ble_gatts_register_callback(Some(gatts_event)) })?;
+        esp!(unsafe { esp_ble_gatts_app_register(0) })?;
+        log::info!(
+            "BLE environmental sensing service advertised as {}",
+            device_name()
+        );
+
+        Ok(Self { _driver: driver })
+    }
+
+    pub fn update(&self, reading: &WeatherReading) {
+        let values = EssValues::from_reading(reading);
+        if !set_values(&values) {
+            *PENDING.lock().unwrap() = Some(values);
+        }
+    }
+}
+
+fn device_name() -> &'static str {
+    if CONFIG.ble_ess_name.is_empty() {
+        station_id()
+    } else {
+        CONFIG.ble_ess_name
+    }
+}
+
+// False while the attribute table is not created yet
+fn set_values(values: &EssValues) -> bool {
+    for (handle, value) in VALUE_HANDLES.iter().zip(values.values()) {
+        let handle = handle.load(Ordering::Relaxed);
+        if handle == 0 {
+            return false;
+        }
+        esp!(unsafe { esp_ble_gatts_set_attr_value(handle, value.len() as u16, value.as_ptr()) })
+            .map_err(|e| log::warn!("Fail setting BLE characteristic: {e}"))
+            .ok();
+    }
+    true
+}
+
+fn attribute(uuid: &'static u16, perm: u32, value: &'static [u8]) -> esp_gatts_attr_db_t {
+    esp_gatts_attr_db_t {
+        attr_control: esp_attr_control_t {
+            auto_rsp: ESP_GATT_AUTO_RSP as u8,
+        },
+        att_desc: esp_attr_desc_t {
+            uuid_length: ESP_UUID_LEN_16 as u16,
+            uuid_p: uuid as *const u16 as *mut u8,
+            perm: perm as u16,
+            max_length: value.len() as u16,
+            length: value.len() as u16,
+            value: value.as_ptr() as *mut u8,
+        },
+    }
+}
+
+fn attribute_table() -> Vec<esp_gatts_attr_db_t> {
+    let mut table = Vec::with_capacity(ATTRIBUTES);
+    // The service value is its UUID, little endian like every BLE integer
+    let service: &'static [u8] =
+        unsafe { std::slice::from_raw_parts(&SERVICE as *const u16 as *const u8, 2) };
+    table.push(attribute(&PRIMARY_SERVICE, ESP_GATT_PERM_READ, service));
+    for uuid in UUIDS.iter() {
+        table.push(attribute(
+            &CHARACTERISTIC_DECLARATION,
+            ESP_GATT_PERM_READ,
+            std::slice::from_ref(&READ_PROPERTY),
+        ));
+        let len = if *uuid == PRESSURE_UUID { 4 } else { 2 };
+        table.push(attribute(uuid, ESP_GATT_PERM_READ, &ZERO[..len]));
+    }
+    table
+}
+
+// Flags, the complete list of 16 bit services and the complete local name
+fn advertisement() -> Vec<u8> {
+    let mut data = vec![2, 0x01, 0x06, 3, 0x03];
+    data.extend_from_slice(&SERVICE_UUID.to_le_bytes());
+    // Legacy advertisements are 31 bytes
+    let name = &device_name().as_bytes()[..device_name().len().min(31 - data.len() - 2)];
+    data.push(name.len() as u8 + 1);
+    data.push(0x09);
+    data.extend_from_slice(name);
+    data
+}
+
+fn start_advertising() {
+    let mut params = esp_ble_adv_params_t {
+        adv_int_min: ADV_INTERVAL,
+        adv_int_max: ADV_INTERVAL,
+        adv_type: esp_ble_adv_type_t_ADV_TYPE_IND,
+        own_addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+        channel_map: esp_ble_adv_channel_t_ADV_CHNL_ALL,
+        adv_filter_policy: esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY,
+        ..Default::default()
+    };
+    esp!(unsafe { esp_ble_gap_start_advertising(&mut params) })
+        .map_err(|e| log::error!("Fail starting BLE advertising: {e}"))
+        .ok();
+}
+
+fn on_registered(gatts_if: esp_gatt_if_t) -> Result<()> {
+    let name = std::ffi::CString::new(device_name())?;
+    esp!(unsafe { esp_ble_gap_set_device_name(name.as_ptr()) })?;
+    let mut data = advertisement();
+    esp!(unsafe { esp_ble_gap_config_adv_data_raw(data.as_mut_ptr(), data.len() as u32) })?;
+    // Copied by the stack before the call returns
+    let table = attribute_table();
+    esp!(unsafe { esp_ble_gatts_create_attr_tab(table.as_ptr(), gatts_if, ATTRIBUTES as u16, 0) })?;
+    Ok(())
+}
+
+fn on_table_created(handles: &[u16]) -> Result<()> {
+    if handles.len() != ATTRIBUTES {
+        return Err(anyhow!(
+            "{} attribute handles for {ATTRIBUTES}",
+            handles.len()
+        ));
+    }
+    for (i, handle) in VALUE_HANDLES.iter().enumerate() {
+        handle.store(handles[2 + 2 * i], Ordering::Relaxed);
+    }
+    esp!(unsafe { esp_ble_gatts_start_service(handles[0]) })?;
+    if let Some(values) = PENDING.lock().unwrap().take() {
+        set_values(&values);
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn gap_event(event: esp_gap_ble_cb_event_t, _param: *mut esp_ble_gap_cb_param_t) {
+    if event == esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_RAW_SET_COMPLETE_EVT {
+        start_advertising();
+    }
+}
+
+unsafe extern "C" fn gatts_event(
+    event: esp_gatts_cb_event_t,
+    gatts_if: esp_gatt_if_t,
+    param: *mut esp_ble_gatts_cb_param_t,
+) {
+    #[allow(non_upper_case_globals)]
+    match event {
+        esp_gatts_cb_event_t_ESP_GATTS_REG_EVT => on_registered(gatts_if)
+            .unwrap_or_else(|e| log::error!("Fail creating the BLE service: {e}")),
+        esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT => {
+            let created = &(*param).add_attr_tab;
+            let handles = std::slice::from_raw_parts(created.handles, created.num_handle as usize);
+            on_table_created(handles)
+                .unwrap_or_else(|e| log::error!("Fail starting the BLE service: {e}"));
+        }
+        // Advertising stops while a phone is connected
+        esp_gatts_cb_event_t_ESP_GATTS_DISCONNECT_EVT => start_advertising(),
+        _ => {}
+    }
+}
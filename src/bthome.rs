@@ -0,0 +1,133 @@
+//! BTHome v2 BLE advertisements, decoded natively by Home Assistant's BTHome integration.
+//!
+//! Objects are little endian integers scaled as listed on bthome.io, sorted by object id.
+//! The encryption itself is left to the caller, this module only lays out the bytes.
+
+use crate::reading::WeatherReading;
+
+pub const SERVICE_UUID: u16 = 0xFCD2;
+// BTHome v2, regular (not trigger based) device
+pub const DEVICE_INFO: u8 = 0x40;
+pub const DEVICE_INFO_ENCRYPTED: u8 = DEVICE_INFO | 0x01;
+
+// Legacy advertising PDU
+const MAX_ADV_LEN: usize = 31;
+// Flags AD, then the service data AD header, UUID and device info byte
+const HEADER_LEN: usize = 3 + 2 + 2 + 1;
+/// Room for measurement objects in an unencrypted advertisement, after the packet id.
+pub const PLAIN_CAPACITY: usize = MAX_ADV_LEN - HEADER_LEN - 2;
+/// Room for measurement objects in an encrypted advertisement, after the counter and MIC.
+pub const ENCRYPTED_CAPACITY: usize = MAX_ADV_LEN - HEADER_LEN - 4 - 4;
+
+const ID_PACKET: u8 = 0x00;
+const ID_TEMPERATURE: u8 = 0x02;
+const ID_HUMIDITY: u8 = 0x03;
+const ID_PRESSURE: u8 = 0x04;
+const ID_SPEED: u8 = 0x44;
+const ID_DIRECTION: u8 = 0x5E;
+const ID_PRECIPITATION: u8 = 0x5F;
+
+/// Measurement objects of a reading, each one is its id followed by its value.
+pub fn objects(reading: &WeatherReading) -> Vec<Vec<u8>> {
+    let scaled = |value: f32, factor: f32| (value / factor).round() as i64;
+    let object = |id: u8, value: i64, len: usize| {
+        let mut object = vec![id];
+        object.extend_from_slice(&value.to_le_bytes()[..len]);
+        object
+    };
+    vec![
+        object(
+            ID_TEMPERATURE,
+            scaled(reading.temperature, 0.01).clamp(i16::MIN as i64, i16::MAX as i64),
+            2,
+        ),
+        object(
+            ID_HUMIDITY,
+            scaled(reading.humidity, 0.01).clamp(0, u16::MAX as i64),
+            2,
+        ),
+        object(
+            ID_PRESSURE,
+            scaled(reading.pressure, 0.01).clamp(0, 0xFF_FFFF),
+            3,
+        ),
+        object(
+            ID_SPEED,
+            scaled(reading.wind_speed_kmh / 3.6, 0.01).clamp(0, u16::MAX as i64),
+            2,
+        ),
+        object(
+            ID_DIRECTION,
+            scaled(reading.wind_direction_deg, 0.01).clamp(0, u16::MAX as i64),
+            2,
+        ),
+        object(
+            ID_PRECIPITATION,
+            scaled(reading.rain_mm, 0.1).clamp(0, u16::MAX as i64),
+            2,
+        ),
+    ]
+}
+
+/// Splits the objects in as few payloads of `capacity` bytes as possible, keeping their order.
+pub fn pack(objects: &[Vec<u8>], capacity: usize) -> Vec<Vec<u8>> {
+    let mut payloads: Vec<Vec<u8>> = Vec::new();
+    for object in objects {
+        match payloads.last_mut() {
+            Some(payload) if payload.len() + object.len() <= capacity => {
+                payload.extend_from_slice(object)
+            }
+            _ => payloads.push(object.clone()),
+        }
+    }
+    payloads
+}
+
+/// Unencrypted payload: the packet id lets the receiver drop repeated advertisements.
+pub fn plain_payload(packet_id: u8, objects: &[u8]) -> Vec<u8> {
+    let mut payload = vec![ID_PACKET, packet_id];
+    payload.extend_from_slice(objects);
+    payload
+}
+
+/// Encrypted payload: the ciphertext, then the counter and the 4 byte MIC.
+pub fn encrypted_payload(ciphertext: &[u8], counter: u32, mic: &[u8; 4]) -> Vec<u8> {
+    let mut payload = ciphertext.to_vec();
+    payload.extend_from_slice(&counter.to_le_bytes());
+    payload.extend_from_slice(mic);
+    payload
+}
+
+/// AES-CCM nonce: the BLE address as printed, the UUID, the device info and the counter.
+pub fn nonce(mac: &[u8; 6], device_info: u8, counter: u32) -> [u8; 13] {
+    let mut nonce = [0u8; 13];
+    nonce[..6].copy_from_slice(mac);
+    nonce[6..8].copy_from_slice(&SERVICE_UUID.to_le_bytes());
+    nonce[8] = device_info;
+    nonce[9..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+/// Raw advertising data: the flags and the BTHome service data.
+pub fn advertisement(device_info: u8, payload: &[u8]) -> Vec<u8> {
+    // LE general discoverable, BR/EDR not supported
+    let mut adv = vec![0x02, 0x01, 0x06];
+    adv.push((1 + 2 + 1 + payload.len()) as u8);
+    adv.push(0x16);
+    adv.extend_from_slice(&SERVICE_UUID.to_le_bytes());
+    adv.push(device_info);
+    adv.extend_from_slice(payload);
+    adv
+}
+
+/// 32 hex digits, as entered in Home Assistant when adding the device.
+pub fn parse_key(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0u8; 16];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(key)
+}
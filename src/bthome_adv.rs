@@ -0,0 +1,187 @@
+use aes::Aes128;
+use anyhow::{anyhow, Result};
+use ccm::{
+    aead::{generic_array::GenericArray, AeadInPlace, KeyInit},
+    consts::{U13, U4},
+    Ccm,
+};
+use esp_idf_svc::{
+    bt::{Ble, BtDriver},
+    hal::modem::BluetoothModem,
+    nvs::EspDefaultNvsPartition,
+    sys::{
+        esp, esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC, esp_ble_adv_channel_t_ADV_CHNL_ALL,
+        esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY, esp_ble_adv_params_t,
+        esp_ble_adv_type_t_ADV_TYPE_NONCONN_IND, esp_ble_gap_config_adv_data_raw,
+        esp_ble_gap_start_advertising, esp_ble_power_type_t_ESP_BLE_PWR_TYPE_ADV,
+        esp_ble_tx_power_set, esp_mac_type_t_ESP_MAC_BT, esp_power_level_t_ESP_PWR_LVL_N12,
+        esp_read_mac,
+    },
+};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::time::Duration;
+use weather_station::{bthome, reading::WeatherReading, time::unix_time_ms};
+
+use crate::provisioning::CONFIG;
+
+type BthomeCcm = Ccm<Aes128, U4, U13>;
+
+// Advertisements sent with the same payload before rotating to the next one
+const ADVERTS_PER_PAYLOAD: u32 = 5;
+// Non connectable advertising may not be faster than 100 ms
+const MIN_INTERVAL_MS: u32 = 100;
+
+// Encryption counter, the receiver expects it to keep increasing across deep sleeps
+#[link_section = ".rtc.data"]
+static mut COUNTER: u32 = 0;
+
+/// Broadcasts the readings as BTHome v2 advertisements from a dedicated thread.
+///
+/// When the objects of a reading do not fit in one advertisement, the payloads are rotated
+/// every few advertising intervals until the next reading.
+pub struct BthomeBroadcaster {
+    tx: SyncSender<WeatherReading>,
+}
+
+impl BthomeBroadcaster {
+    pub fn push(&self, reading: &WeatherReading) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(*reading) {
+            log::debug!("BTHome update still pending, dropping this one");
+        }
+    }
+}
+
+/// Starts the BLE controller next to WiFi, relying on the IDF software coexistence.
+pub fn bthome_start(
+    modem: BluetoothModem,
+    nvs: EspDefaultNvsPartition,
+) -> Result<BthomeBroadcaster> {
+    let key = match CONFIG.bthome_key {
+        "" => None,
+        hex => Some(
+            bthome::parse_key(hex).ok_or_else(|| anyhow!("bthome_key must be 32 hex digits"))?,
+        ),
+    };
+    let driver = BtDriver::<Ble>::new(modem, Some(nvs))?;
+    if CONFIG.bthome_low_power {
+        esp!(unsafe {
+            esp_ble_tx_power_set(
+                esp_ble_power_type_t_ESP_BLE_PWR_TYPE_ADV,
+                esp_power_level_t_ESP_PWR_LVL_N12,
+            )
+        })?;
+    }
+    let mut mac = [0u8; 6];
+    esp!(unsafe { esp_read_mac(mac.as_mut_ptr(), esp_mac_type_t_ESP_MAC_BT) })?;
+
+    let interval_ms = CONFIG.bthome_interval_ms.max(MIN_INTERVAL_MS);
+    // 0.625 ms units
+    let interval = (interval_ms * 8 / 5).min(u16::MAX as u32) as u16;
+    let rotate = Duration::from_millis((interval_ms * ADVERTS_PER_PAYLOAD) as u64);
+
+    let (tx, rx) = mpsc::sync_channel::<WeatherReading>(1);
+    std::thread::Builder::new()
+        .stack_size(4096)
+        .spawn(move || {
+            // The controller stays up as long as the thread runs
+            let _driver = driver;
+            let mut adverts: Vec<Vec<u8>> = Vec::new();
+            let mut next = 0;
+            let mut advertising = false;
+            loop {
+                match rx.recv_timeout(rotate) {
+                    Ok(reading) => {
+                        adverts = build_adverts(&reading, key.as_ref(), &mac);
+                        next = 0;
+                    }
+                    // A single payload stays in place until the next reading
+                    Err(RecvTimeoutError::Timeout) if adverts.len() <= 1 => continue,
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                let Some(advert) = adverts.get(next % adverts.len().max(1)) else {
+                    continue;
+                };
+                next += 1;
+                let mut data = advert.clone();
+                let configured = esp!(unsafe {
+                    esp_ble_gap_config_adv_data_raw(data.as_mut_ptr(), data.len() as u32)
+                });
+                if let Err(e) = configured {
+                    log::warn!("Fail setting BTHome advertisement: {e}");
+                    continue;
+                }
+                if !advertising {
+                    let mut params = esp_ble_adv_params_t {
+                        adv_int_min: interval,
+                        adv_int_max: interval,
+                        adv_type: esp_ble_adv_type_t_ADV_TYPE_NONCONN_IND,
+                        own_addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+                        channel_map: esp_ble_adv_channel_t_ADV_CHNL_ALL,
+                        adv_filter_policy: esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY,
+                        ..Default::default()
+                    };
+                    advertising = esp!(unsafe { esp_ble_gap_start_advertising(&mut params) })
+                        .map_err(|e| log::error!("Fail starting BTHome advertising: {e}"))
+                        .is_ok();
+                }
+            }
+        })?;
+
+    log::info!(
+        "BTHome advertising every {interval_ms} ms{}",
+        if key.is_some() {
+            " with encryption"
+        } else {
+            ""
+        }
+    );
+    Ok(BthomeBroadcaster { tx })
+}
+
+fn build_adverts(reading: &WeatherReading, key: Option<&[u8; 16]>, mac: &[u8; 6]) -> Vec<Vec<u8>> {
+    let objects = bthome::objects(reading);
+    let Some(key) = key else {
+        let packet_id = unsafe { COUNTER } as u8;
+        let payloads = bthome::pack(&objects, bthome::PLAIN_CAPACITY);
+        unsafe { COUNTER = COUNTER.wrapping_add(payloads.len() as u32) };
+        return payloads
+            .iter()
+            .enumerate()
+            .map(|(i, objects)| {
+                let payload = bthome::plain_payload(packet_id.wrapping_add(i as u8), objects);
+                bthome::advertisement(bthome::DEVICE_INFO, &payload)
+            })
+            .collect();
+    };
+
+    bthome::pack(&objects, bthome::ENCRYPTED_CAPACITY)
+        .iter()
+        .filter_map(|objects| {
+            let counter = next_counter();
+            encrypt(key, mac, counter, objects)
+                .map_err(|e| log::error!("Fail encrypting BTHome payload: {e}"))
+                .ok()
+                .map(|payload| bthome::advertisement(bthome::DEVICE_INFO_ENCRYPTED, &payload))
+        })
+        .collect()
+}
+
+// Never reuses a counter, so never a nonce, even after a power loss once the clock is synced
+fn next_counter() -> u32 {
+    let unix_s = (unix_time_ms() / 1000) as u32;
+    unsafe {
+        COUNTER = COUNTER.wrapping_add(1).max(unix_s);
+        COUNTER
+    }
+}
+
+fn encrypt(key: &[u8; 16], mac: &[u8; 6], counter: u32, objects: &[u8]) -> Result<Vec<u8>> {
+    let nonce = bthome::nonce(mac, bthome::DEVICE_INFO_ENCRYPTED, counter);
+    let mut ciphertext = objects.to_vec();
+    let mic = BthomeCcm::new(GenericArray::from_slice(key))
+        .encrypt_in_place_detached(GenericArray::from_slice(&nonce), &[], &mut ciphertext)
+        .map_err(|_| anyhow!("AES-CCM failed"))?;
+    let mic: [u8; 4] = mic.as_slice().try_into()?;
+    Ok(bthome::encrypted_payload(&ciphertext, counter, &mic))
+}
@@ -0,0 +1,81 @@
+use esp_idf_svc::hal::{delay::FreeRtos, gpio::*};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+use weather_station::{
+    runtime::{ButtonPress, BUTTON_DOUBLE_PRESS_GAP, BUTTON_LONG_PRESS},
+    *,
+};
+
+use crate::mqtt::MqttEvent;
+
+const POLL_MS: u32 = 20;
+
+// Runs forever, presses are forwarded to the main loop like the MQTT commands
+pub fn watch_button(
+    mut pin_button: PinDriver<AnyIOPin, Input>,
+    mut led: Option<PinDriver<AnyOutputPin, Output>>,
+    tx: Sender<MqttEvent>,
+) {
+    let mut pressed_at: Option<Instant> = None;
+    // A short press is held back until it is clear no second one follows
+    let mut pending_short: Option<Instant> = None;
+
+    loop {
+        // Interrupts are disabled after firing, same as the rain and anemometer pins
+        if BUTTON_FLAG.swap(false, Ordering::Relaxed) {
+            pin_button
+                .enable_interrupt()
+                .map_err(|e| log::error!("fail enabling button interrupt: {e}"))
+                .ok();
+        } else if pressed_at.is_none() {
+            if pending_short.is_some_and(|at| at.elapsed() >= BUTTON_DOUBLE_PRESS_GAP) {
+                pending_short = None;
+                if tx.send(MqttEvent::Button(ButtonPress::Short)).is_err() {
+                    return;
+                }
+            }
+            FreeRtos::delay_ms(POLL_MS);
+            continue;
+        }
+
+        match (pin_button.is_low(), pressed_at) {
+            (true, None) => pressed_at = Some(Instant::now()),
+            // Lit once held long enough to enter provisioning
+            (true, Some(start)) if start.elapsed() >= BUTTON_LONG_PRESS => {
+                if let Some(led) = led.as_mut() {
+                    led.set_high().ok();
+                }
+            }
+            (false, Some(start)) => {
+                pressed_at = None;
+                if let Some(led) = led.as_mut() {
+                    led.set_low().ok();
+                }
+                let press = match ButtonPress::from_duration(start.elapsed()) {
+                    Some(ButtonPress::Short) if pending_short.take().is_some() => {
+                        Some(ButtonPress::Double)
+                    }
+                    Some(ButtonPress::Short) => {
+                        pending_short = Some(Instant::now());
+                        None
+                    }
+                    // A long press cancels a short one just before it
+                    Some(press) => {
+                        pending_short = None;
+                        Some(press)
+                    }
+                    // Contact bounce
+                    None => None,
+                };
+                if let Some(press) = press {
+                    if tx.send(MqttEvent::Button(press)).is_err() {
+                        return;
+                    }
+                }
+            }
+            _ => {}
+        }
+        FreeRtos::delay_ms(POLL_MS);
+    }
+}
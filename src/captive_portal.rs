@@ -0,0 +1,152 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    hal::delay::FreeRtos,
+    http::{
+        server::{Configuration as HttpConfiguration, EspHttpServer},
+        Method,
+    },
+    io::{Read, Write},
+    nvs::EspDefaultNvsPartition,
+    sys::esp_restart,
+    wifi::{AccessPointConfiguration, AuthMethod, BlockingWifi, Configuration, EspWifi},
+};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use weather_station::portal::*;
+
+use crate::provisioning::{WeatherStationProvisioner, CONFIG};
+use crate::station_id;
+
+// Far above what the form sends
+const MAX_FORM_LEN: usize = 1024;
+
+/// Setup portal: a SoftAP named `<station_id>-setup` serving a form for the WiFi and broker
+/// settings. Every DNS name resolves to the station, so phones show the form on their own.
+///
+/// The settings are stored in NVS and the station reboots with them. Without a submission the
+/// station reboots after `portal_timeout_s` to try the stored network again.
+pub fn run_portal(wifi: &mut BlockingWifi<EspWifi<'static>>, nvs: EspDefaultNvsPartition) -> ! {
+    if let Err(e) = serve(wifi, nvs) {
+        log::error!("Setup portal failed: {e}");
+    }
+    log::warn!("Leaving the setup portal, rebooting");
+    FreeRtos::delay_ms(1000);
+    unsafe { esp_restart() }
+}
+
+fn serve(wifi: &mut BlockingWifi<EspWifi<'static>>, nvs: EspDefaultNvsPartition) -> Result<()> {
+    let ssid = format!("{}-setup", station_id());
+    wifi.stop().ok();
+    wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+        ssid: heapless::String::try_from(ssid.as_str()).unwrap_or_default(),
+        password: heapless::String::try_from(CONFIG.portal_password).unwrap_or_default(),
+        auth_method: if CONFIG.portal_password.is_empty() {
+            AuthMethod::None
+        } else {
+            AuthMethod::WPA2Personal
+        },
+        ..Default::default()
+    }))?;
+    wifi.start()?;
+    let ip = wifi.wifi().ap_netif().get_ip_info()?.ip;
+    log::warn!("Setup portal on access point {ssid}, http://{ip}/");
+
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 53))?;
+    std::thread::Builder::new()
+        .stack_size(4096)
+        .spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+                if let Some(answer) = dns_answer(&buf[..len], ip.octets()) {
+                    socket.send_to(&answer, peer).ok();
+                }
+            }
+        })?;
+
+    let saved = Arc::new(AtomicBool::new(false));
+    let mut server = EspHttpServer::new(&HttpConfiguration {
+        stack_size: 8192,
+        uri_match_wildcard: true,
+        ..Default::default()
+    })?;
+    let done = saved.clone();
+    server.fn_handler("/save", Method::Post, move |mut req| {
+        let mut body = vec![];
+        let mut buf = [0u8; 256];
+        loop {
+            let len = req.read(&mut buf)?;
+            if len == 0 || body.len() + len > MAX_FORM_LEN {
+                break;
+            }
+            body.extend_from_slice(&buf[..len]);
+        }
+        let outcome = parse_form(&String::from_utf8_lossy(&body))
+            .ok_or_else(|| anyhow::anyhow!("malformed form"))
+            .and_then(|fields| {
+                WeatherStationProvisioner::new(nvs.clone())?.store_portal(&fields)?;
+                Ok(fields.len())
+            });
+        let (status, message) = match outcome {
+            Ok(count) => {
+                log::warn!("Setup portal: {count} settings stored");
+                done.store(true, Ordering::Relaxed);
+                (200, "Settings saved, the station reboots.".to_string())
+            }
+            Err(e) => {
+                log::warn!("Setup portal: invalid settings: {e}");
+                (
+                    400,
+                    format!("Invalid settings: {}", html_escape(&e.to_string())),
+                )
+            }
+        };
+        let mut resp = req.into_response(status, None, &[("Content-Type", "text/html")])?;
+        resp.write_all(page(&format!("<p>{message}</p>")).as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+    // Any other path, the captive portal checks of phones included
+    server.fn_handler("/*", Method::Get, |req| {
+        let mut resp = req.into_response(200, None, &[("Content-Type", "text/html")])?;
+        resp.write_all(page(&form()).as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    let timeout = Duration::from_secs(CONFIG.portal_timeout_s.max(60) as u64);
+    let start = Instant::now();
+    while !saved.load(Ordering::Relaxed) && start.elapsed() < timeout {
+        FreeRtos::delay_ms(500);
+    }
+    Ok(())
+}
+
+fn page(body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+         <title>{id} setup</title></head><body><h1>{id}</h1>{body}</body></html>",
+        id = station_id()
+    )
+}
+
+// Passwords are never sent back, an empty field keeps the current value
+fn form() -> String {
+    let text = |label: &str, name: &str, value: &str| {
+        format!(
+            "<p><label>{label}<br><input name=\"{name}\" value=\"{}\"></label></p>",
+            html_escape(value)
+        )
+    };
+    let password = |label: &str, name: &str| {
+        format!("<p><label>{label}<br><input name=\"{name}\" type=\"password\"></label></p>")
+    };
+    format!(
+        "<form method=\"post\" action=\"/save\">{}{}{}{}{}<p><button>Save</button></p></form>\
+         <p>Empty fields keep their current value.</p>",
+        text("WiFi network", "wifi_ssid", CONFIG.wifi_ssid),
+        password("WiFi password", "wifi_pass"),
+        text("Broker URL", "broker_url", CONFIG.broker_url),
+        text("MQTT user", "mqtt_user", CONFIG.mqtt_user),
+        password("MQTT password", "mqtt_pass"),
+    )
+}
@@ -0,0 +1,336 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::{
+    hal::{
+        delay::{FreeRtos, TickType},
+        gpio::{AnyIOPin, AnyOutputPin, Output, PinDriver},
+        uart::{config::Config as UartConfig, UartDriver, UART1},
+        units::Hertz,
+    },
+    netif::{EspNetif, EspNetifDriver, NetifConfiguration},
+};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::provisioning::CONFIG;
+
+const AT_TIMEOUT: Duration = Duration::from_secs(2);
+const DIAL_TIMEOUT: Duration = Duration::from_secs(30);
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(60);
+const BOOT_TIMEOUT: Duration = Duration::from_secs(10);
+// PPP negotiation after CONNECT, the call is dropped and redialed past this
+const PPP_UP_TIMEOUT: Duration = Duration::from_secs(30);
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+// Silence required on both sides of "+++" for the modem to leave data mode
+const ESCAPE_GUARD_MS: u32 = 1100;
+const PWRKEY_PULSE_MS: u32 = 1200;
+const RESET_PULSE_MS: u32 = 300;
+const POLL_READ: Duration = Duration::from_millis(20);
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CellularStatus {
+    /// PPP link up with an address
+    pub connected: bool,
+    pub ip: Option<Ipv4Addr>,
+    /// Last AT+CSQ value, 0 to 31, None when the modem reports it unknown
+    pub csq: Option<u8>,
+}
+
+impl CellularStatus {
+    pub fn rssi_dbm(&self) -> Option<i32> {
+        self.csq.map(|csq| -113 + 2 * csq as i32)
+    }
+
+    pub fn to_json(&self) -> String {
+        let csq = self.csq.map_or("null".to_string(), |csq| csq.to_string());
+        let rssi = self
+            .rssi_dbm()
+            .map_or("null".to_string(), |rssi| rssi.to_string());
+        format!(
+            "{{\"connected\": {}, \"csq\": {csq}, \"rssi_dbm\": {rssi}}}",
+            self.connected
+        )
+    }
+}
+
+/// A SIM7000/SIM800 modem dialing a PPP link over UART1.
+///
+/// The modem runs in its own thread: enabling it powers the modem up if needed and dials,
+/// disabling it hangs up. The signal quality is read every `cellular_csq_interval_s` by
+/// briefly leaving data mode.
+pub struct Cellular {
+    enabled: Arc<AtomicBool>,
+    status: Arc<Mutex<CellularStatus>>,
+}
+
+impl Cellular {
+    pub fn start(uart: UART1) -> Result<Self> {
+        let modem = SimModem::new(uart)?;
+        let enabled = Arc::new(AtomicBool::new(false));
+        let status = Arc::new(Mutex::new(CellularStatus::default()));
+
+        let (thread_enabled, thread_status) = (enabled.clone(), status.clone());
+        std::thread::Builder::new()
+            .stack_size(6144)
+            .spawn(move || modem.run(thread_enabled, thread_status))?;
+
+        Ok(Self { enabled, status })
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> CellularStatus {
+        *self.status.lock().unwrap()
+    }
+}
+
+struct SimModem {
+    uart: Arc<Mutex<UartDriver<'static>>>,
+    // Set while AT commands are exchanged, PPP frames are dropped meanwhile
+    command_mode: Arc<AtomicBool>,
+    pwrkey: Option<PinDriver<'static, AnyOutputPin, Output>>,
+    reset: Option<PinDriver<'static, AnyOutputPin, Output>>,
+}
+
+impl SimModem {
+    fn new(uart: UART1) -> Result<Self> {
+        // Pins come from the config, they are not claimed by any other driver
+        let (tx, rx) = unsafe {
+            (
+                AnyIOPin::new(CONFIG.cellular_tx_gpio),
+                AnyIOPin::new(CONFIG.cellular_rx_gpio),
+            )
+        };
+        let driver = UartDriver::new(
+            uart,
+            tx,
+            rx,
+            Option::<AnyIOPin>::None,
+            Option::<AnyIOPin>::None,
+            &UartConfig::new().baudrate(Hertz(CONFIG.cellular_baud)),
+        )?;
+        let output = |gpio: i32| -> Result<_> {
+            Ok(match gpio {
+                gpio if gpio >= 0 => {
+                    let mut pin = PinDriver::output(unsafe { AnyOutputPin::new(gpio) })?;
+                    pin.set_high()?;
+                    Some(pin)
+                }
+                _ => None,
+            })
+        };
+
+        Ok(Self {
+            uart: Arc::new(Mutex::new(driver)),
+            command_mode: Arc::new(AtomicBool::new(true)),
+            pwrkey: output(CONFIG.cellular_pwrkey_gpio)?,
+            reset: output(CONFIG.cellular_reset_gpio)?,
+        })
+    }
+
+    fn run(mut self, enabled: Arc<AtomicBool>, status: Arc<Mutex<CellularStatus>>) {
+        let mut link: Option<(EspNetifDriver<'static, EspNetif>, Instant)> = None;
+        let mut last_csq = Instant::now();
+        let mut buf = [0u8; 256];
+        loop {
+            let wanted = enabled.load(Ordering::Relaxed);
+            if !wanted {
+                if link.take().is_some() {
+                    log::info!("Hanging up cellular link");
+                    self.hang_up();
+                    *status.lock().unwrap() = CellularStatus::default();
+                }
+                FreeRtos::delay_ms(500);
+                continue;
+            }
+
+            let Some((netif, dialed)) = link.as_ref() else {
+                match self.dial().and_then(|csq| Ok((csq, self.ppp_netif()?))) {
+                    Ok((csq, netif)) => {
+                        log::info!("Cellular call connected, CSQ {csq:?}");
+                        status.lock().unwrap().csq = csq;
+                        link = Some((netif, Instant::now()));
+                        last_csq = Instant::now();
+                    }
+                    Err(e) => {
+                        log::warn!("Cellular dial failed: {e}");
+                        self.hang_up();
+                        FreeRtos::delay_ms(RETRY_DELAY.as_millis() as u32);
+                    }
+                }
+                continue;
+            };
+
+            let len = self
+                .uart
+                .lock()
+                .unwrap()
+                .read(&mut buf, TickType::from(POLL_READ).ticks())
+                .unwrap_or(0);
+            if len > 0 {
+                netif
+                    .rx(&buf[..len])
+                    .map_err(|e| log::warn!("PPP input dropped: {e}"))
+                    .ok();
+            }
+            let connected = netif.netif().is_up().unwrap_or(false);
+            {
+                let mut status = status.lock().unwrap();
+                status.connected = connected;
+                status.ip = netif.netif().get_ip_info().ok().map(|info| info.ip);
+            }
+
+            if !connected && dialed.elapsed() > PPP_UP_TIMEOUT {
+                log::warn!("PPP link not up, redialing");
+                link = None;
+                self.hang_up();
+            } else if last_csq.elapsed()
+                >= Duration::from_secs(CONFIG.cellular_csq_interval_s as u64)
+            {
+                last_csq = Instant::now();
+                match self.poll_signal() {
+                    Ok(csq) => status.lock().unwrap().csq = csq,
+                    Err(e) => {
+                        log::warn!("Fail reading cellular signal, redialing: {e}");
+                        link = None;
+                        self.hang_up();
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns the signal quality read before dialing
+    fn dial(&mut self) -> Result<Option<u8>> {
+        self.command_mode.store(true, Ordering::Relaxed);
+        self.wake()?;
+        self.command("ATE0", "OK", AT_TIMEOUT)?;
+        if !self
+            .command("AT+CPIN?", "OK", AT_TIMEOUT)?
+            .contains("READY")
+        {
+            bail!("SIM not ready");
+        }
+        self.command(
+            &format!("AT+CGDCONT=1,\"IP\",\"{}\"", CONFIG.cellular_apn),
+            "OK",
+            AT_TIMEOUT,
+        )?;
+        let start = Instant::now();
+        while !self
+            .command("AT+CGATT?", "OK", AT_TIMEOUT)?
+            .contains("+CGATT: 1")
+        {
+            if start.elapsed() > ATTACH_TIMEOUT {
+                bail!("not attached to the network");
+            }
+            FreeRtos::delay_ms(1000);
+        }
+        let csq = parse_csq(&self.command("AT+CSQ", "OK", AT_TIMEOUT)?);
+        self.command("ATD*99#", "CONNECT", DIAL_TIMEOUT)?;
+        self.command_mode.store(false, Ordering::Relaxed);
+        Ok(csq)
+    }
+
+    // Gets the modem answering AT commands, escaping a call left over by a previous boot and
+    // powering it on as a last resort
+    fn wake(&mut self) -> Result<()> {
+        if self.command("AT", "OK", AT_TIMEOUT).is_ok() {
+            return Ok(());
+        }
+        self.escape();
+        if self.command("AT", "OK", AT_TIMEOUT).is_ok() {
+            return Ok(());
+        }
+        if let Some(pwrkey) = self.pwrkey.as_mut() {
+            log::info!("Powering the modem on");
+            pwrkey.set_low()?;
+            FreeRtos::delay_ms(PWRKEY_PULSE_MS);
+            pwrkey.set_high()?;
+        } else if let Some(reset) = self.reset.as_mut() {
+            log::info!("Resetting the modem");
+            reset.set_low()?;
+            FreeRtos::delay_ms(RESET_PULSE_MS);
+            reset.set_high()?;
+        }
+        let start = Instant::now();
+        while start.elapsed() < BOOT_TIMEOUT {
+            if self.command("AT", "OK", AT_TIMEOUT).is_ok() {
+                return Ok(());
+            }
+        }
+        bail!("modem not answering")
+    }
+
+    fn poll_signal(&mut self) -> Result<Option<u8>> {
+        self.escape();
+        let csq = parse_csq(&self.command("AT+CSQ", "OK", AT_TIMEOUT)?);
+        self.command("ATO", "CONNECT", AT_TIMEOUT)?;
+        self.command_mode.store(false, Ordering::Relaxed);
+        Ok(csq)
+    }
+
+    fn hang_up(&mut self) {
+        self.escape();
+        self.command("ATH", "OK", AT_TIMEOUT)
+            .map_err(|e| log::warn!("Fail hanging up: {e}"))
+            .ok();
+    }
+
+    // Switches the modem from data to command mode, a no-op when it is already there
+    fn escape(&mut self) {
+        self.command_mode.store(true, Ordering::Relaxed);
+        FreeRtos::delay_ms(ESCAPE_GUARD_MS);
+        self.uart.lock().unwrap().write(b"+++").ok();
+        FreeRtos::delay_ms(ESCAPE_GUARD_MS);
+    }
+
+    fn command(&self, command: &str, expect: &str, timeout: Duration) -> Result<String> {
+        let uart = self.uart.lock().unwrap();
+        uart.clear_rx()?;
+        uart.write(command.as_bytes())?;
+        uart.write(b"\r")?;
+
+        let start = Instant::now();
+        let mut response = String::new();
+        let mut buf = [0u8; 64];
+        while start.elapsed() < timeout {
+            let len = uart.read(&mut buf, TickType::from(POLL_READ).ticks())?;
+            response.push_str(&String::from_utf8_lossy(&buf[..len]));
+            if response.contains(expect) {
+                return Ok(response);
+            }
+            if response.contains("ERROR") || response.contains("NO CARRIER") {
+                bail!("{command}: {}", response.trim());
+            }
+        }
+        bail!("{command}: no answer")
+    }
+
+    fn ppp_netif(&self) -> Result<EspNetifDriver<'static, EspNetif>> {
+        let netif = EspNetif::new_with_conf(&NetifConfiguration::ppp_default_client())?;
+        let uart = self.uart.clone();
+        let command_mode = self.command_mode.clone();
+
+        Ok(EspNetifDriver::new(
+            netif,
+            |_| Ok(()),
+            move |frame: &[u8]| {
+                if command_mode.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                uart.lock().unwrap().write(frame).map(|_| ())
+            },
+        )?)
+    }
+}
+
+// "+CSQ: 17,99", 99 meaning unknown
+fn parse_csq(response: &str) -> Option<u8> {
+    let (_, value) = response.split_once("+CSQ:")?;
+    let (rssi, _) = value.split_once(',')?;
+    rssi.trim().parse().ok().filter(|&csq: &u8| csq <= 31)
+}
@@ -0,0 +1,131 @@
+// Pure computations, usable without std or alloc
+
+// Dry air density at 15°C and sea level, in kg/m3
+pub const STANDARD_AIR_DENSITY: f32 = 1.225;
+// Upper bound (m/s, exclusive) of Beaufort forces 0 to 11
+const BEAUFORT_LIMITS_MS: [f32; 12] = [
+    0.5, 1.6, 3.4, 5.5, 8.0, 10.8, 13.9, 17.2, 20.8, 24.5, 28.5, 32.7,
+];
+
+// Mean speed from the rotations counted over `elapsed_s`, `kmh_per_hz` is the speed of one
+// rotation per second
+pub fn wind_speed_kmh(rotations: u32, elapsed_s: f32, kmh_per_hz: f32) -> f32 {
+    if elapsed_s <= 0.0 {
+        return 0.0;
+    }
+    rotations as f32 / elapsed_s * kmh_per_hz
+}
+
+// Pulses counted between two reads of a hardware counter that wraps to 0 at `limit`. The
+// counter must be read before it counts `limit` more pulses
+pub fn counter_delta(last: i16, now: i16, limit: i16) -> u32 {
+    let delta = now as i32 - last as i32;
+    if delta < 0 {
+        (delta + limit as i32) as u32
+    } else {
+        delta as u32
+    }
+}
+
+// Contact bounce filter of pulses only known by their count: at most one pulse is kept per
+// `min_interval_ms`, 0 keeps them all
+#[derive(Clone, Copy, Debug)]
+pub struct PulseDebounce {
+    min_interval_ms: u32,
+    credit_ms: u32,
+}
+
+impl PulseDebounce {
+    pub const fn new(min_interval_ms: u32) -> Self {
+        Self {
+            min_interval_ms,
+            credit_ms: min_interval_ms,
+        }
+    }
+
+    // Pulses kept out of the ones counted over the last `elapsed_ms`
+    pub fn filter(&mut self, pulses: u32, elapsed_ms: u32) -> u32 {
+        if self.min_interval_ms == 0 {
+            return pulses;
+        }
+        let credit = self.credit_ms.saturating_add(elapsed_ms);
+        let kept = pulses.min(credit / self.min_interval_ms);
+        // Idle time only carries over one interval, the next pulse may come right after
+        self.credit_ms = (credit - kept * self.min_interval_ms).min(self.min_interval_ms);
+        kept
+    }
+}
+
+// Convert a raw reading of a `bits` resolution angle sensor into degrees
+pub fn degrees_from_counts(raw: u16, bits: u32) -> f32 {
+    (raw as f32) * (360.0 / (1u32 << bits) as f32)
+}
+
+// Rotate a measured angle by the vane offset, giving degrees from north
+pub fn apply_vane_offset(angle_deg: f32, offset_deg: i32) -> f32 {
+    let angle = (angle_deg + offset_deg as f32) % 360.0;
+    if angle < 0.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
+}
+
+pub fn wind_direction_from_angle(angle: f32) -> &'static str {
+    match angle {
+        angle if angle >= 0.0 && angle < 45.0 => "N",
+        angle if angle >= 45.0 && angle < 90.0 => "NE",
+        angle if angle >= 90.0 && angle < 135.0 => "E",
+        angle if angle >= 135.0 && angle < 180.0 => "SE",
+        angle if angle >= 180.0 && angle < 225.0 => "S",
+        angle if angle >= 225.0 && angle < 270.0 => "SW",
+        angle if angle >= 270.0 && angle < 315.0 => "W",
+        angle if angle >= 315.0 && angle < 360.0 => "NW",
+        _ => "Invalid Angle",
+    }
+}
+
+// Magnus formula, valid for -45°C to 60°C
+pub fn calculate_dew_point(temperature: f32, humidity: f32) -> f32 {
+    const B: f32 = 17.62;
+    const C: f32 = 243.12;
+
+    let gamma = libm::logf(humidity.max(0.1) / 100.0) + B * temperature / (C + temperature);
+    C * gamma / (B - gamma)
+}
+
+// Pressure reduced to sea level (QFF) with the hypsometric formula, from the station pressure
+// and temperature at `altitude_m`
+pub fn sea_level_pressure(pressure_hpa: f32, temperature: f32, altitude_m: f32) -> f32 {
+    const LAPSE_RATE: f32 = 0.0065;
+
+    let drop = LAPSE_RATE * altitude_m;
+    pressure_hpa * libm::powf(1.0 - drop / (temperature + drop + 273.15), -5.257)
+}
+
+pub fn beaufort_from_ms(wind_speed_ms: f32) -> u8 {
+    BEAUFORT_LIMITS_MS
+        .iter()
+        .position(|&limit| wind_speed_ms < limit)
+        .unwrap_or(BEAUFORT_LIMITS_MS.len()) as u8
+}
+
+// Kinetic power carried by the wind through 1 m2: 1/2 * rho * v^3
+pub fn wind_power_density_wm2(wind_speed_ms: f32, air_density: f32) -> f32 {
+    0.5 * air_density * wind_speed_ms * wind_speed_ms * wind_speed_ms
+}
+
+/// CRC-16/MODBUS, also used by the ESP-NOW node frames. Transmitted low byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |mut crc, byte| {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
@@ -0,0 +1,86 @@
+use anyhow::{bail, Context, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use weather_station::{
+    aprs::{login_line, weather_packet},
+    reading::TimedReading,
+    runtime::RUNTIME,
+    time::synced_aprs_time,
+};
+
+use crate::http::ReadingHistory;
+use crate::provisioning::CONFIG;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Submits the latest reading to CWOP over APRS-IS every `cwop_interval_s`. Demo and test
+/// readings are never sent.
+pub fn cwop_start(history: ReadingHistory) -> Result<()> {
+    if CONFIG.cwop_callsign.is_empty() {
+        bail!("cwop_callsign is required");
+    }
+    if CONFIG.cwop_latitude == 0.0 && CONFIG.cwop_longitude == 0.0 {
+        bail!("cwop_latitude and cwop_longitude are required");
+    }
+
+    std::thread::Builder::new()
+        .stack_size(8192)
+        .spawn(move || {
+            let mut last_sent = 0;
+            loop {
+                std::thread::sleep(Duration::from_secs(CONFIG.cwop_interval_s as u64));
+                let Some(latest) = history.lock().unwrap().last().copied() else {
+                    continue;
+                };
+                if latest.timestamp_ms == last_sent
+                    || latest.reading.demo
+                    || latest.reading.synthetic
+                {
+                    continue;
+                }
+                // Not retried, a late report would only duplicate the next one
+                submit(&latest).unwrap_or_else(|e| log::warn!("Fail submitting CWOP report: {e}"));
+                last_sent = latest.timestamp_ms;
+            }
+        })?;
+    log::info!("Submitting CWOP reports as {}", CONFIG.cwop_callsign);
+
+    Ok(())
+}
+
+fn submit(timed: &TimedReading) -> Result<()> {
+    let address = CONFIG
+        .cwop_server
+        .to_socket_addrs()?
+        .next()
+        .context("CWOP server not resolved")?;
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+
+    // Server banner, then the answer to the login
+    reader.read_line(&mut line)?;
+    stream.write_all(login_line(CONFIG.cwop_callsign, CONFIG.cwop_passcode).as_bytes())?;
+    line.clear();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("# logresp") {
+        bail!("unexpected login answer {}", line.trim());
+    }
+
+    let packet = weather_packet(
+        CONFIG.cwop_callsign,
+        CONFIG.cwop_latitude,
+        CONFIG.cwop_longitude,
+        &timed.reading,
+        RUNTIME.fields(),
+        synced_aprs_time(timed.timestamp_ms).as_deref(),
+    );
+    stream.write_all(format!("{packet}\r\n").as_bytes())?;
+    stream.flush()?;
+    log::debug!("CWOP report sent: {packet}");
+
+    Ok(())
+}
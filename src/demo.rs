@@ -0,0 +1,91 @@
+use crate::rain::mm_to_tips;
+use core::f32::consts::PI;
+
+/// Plausible synthetic weather, used in place of the sensors in demo mode.
+///
+/// Temperature and humidity follow a diurnal cycle, pressure is a bounded random walk, wind
+/// is a random walk with gusts and rain comes in occasional showers.
+pub struct DemoWeather {
+    rng: u32,
+    pressure: f32,
+    wind_ms: f32,
+    gust_ms: f32,
+    direction: f32,
+    shower_s: f32,
+    rotations: f32,
+    tips: f32,
+}
+
+impl DemoWeather {
+    pub const fn new(seed: u32) -> Self {
+        Self {
+            rng: if seed == 0 { 0x2545_F491 } else { seed },
+            pressure: 1013.0,
+            wind_ms: 3.0,
+            gust_ms: 0.0,
+            direction: 225.0,
+            shower_s: 0.0,
+            rotations: 0.0,
+            tips: 0.0,
+        }
+    }
+
+    // xorshift32, uniform in [-1, 1]
+    fn noise(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    /// Advance the simulation by `dt_s`, returns the anemometer rotations and rain tips that
+    /// happened meanwhile. Rotations are paced in real time for an anemometer turning once
+    /// per second at `kmh_per_hz`, tips for a bucket holding `mm_per_tip`.
+    pub fn step(&mut self, dt_s: f32, kmh_per_hz: f32, mm_per_tip: f32) -> (u32, u32) {
+        self.pressure = (self.pressure + self.noise() * 0.02 * dt_s).clamp(990.0, 1035.0);
+        self.wind_ms = (self.wind_ms + self.noise() * 0.2 * dt_s).clamp(0.0, 15.0);
+        self.gust_ms = if self.noise() > 0.98 {
+            self.wind_ms * 0.6
+        } else {
+            self.gust_ms * 0.9
+        };
+        self.direction = (self.direction + self.noise() * 5.0 * dt_s + 360.0) % 360.0;
+
+        if self.shower_s <= 0.0 && self.noise() > 0.9995 {
+            self.shower_s = 600.0;
+        }
+        if self.shower_s > 0.0 {
+            self.shower_s -= dt_s;
+            // ~6 mm/h
+            self.tips += mm_to_tips(6.0 / 3600.0 * dt_s, mm_per_tip).unwrap_or_default();
+        }
+        if kmh_per_hz > 0.0 {
+            self.rotations += (self.wind_ms + self.gust_ms) * 3.6 / kmh_per_hz * dt_s;
+        }
+
+        let rotations = self.rotations as u32;
+        let tips = self.tips as u32;
+        self.rotations -= rotations as f32;
+        self.tips -= tips as f32;
+        (rotations, tips)
+    }
+
+    pub fn wind_direction(&mut self) -> f32 {
+        (self.direction + self.noise() * 15.0 + 360.0) % 360.0
+    }
+
+    /// Temperature (°C), humidity (%) and pressure (hPa) at `hour_of_day` (local, 0-24).
+    pub fn environment(&mut self, hour_of_day: f32) -> (f32, f32, f32) {
+        // Warmest around 15h, coldest around 3h
+        let cycle = libm::sinf((hour_of_day - 9.0) * PI / 12.0);
+        let temperature = 14.0 + 7.0 * cycle + self.noise() * 0.2;
+        let humidity = (70.0 - 20.0 * cycle + self.noise()).clamp(5.0, 100.0);
+        (temperature, humidity, self.pressure)
+    }
+}
+
+impl Default for DemoWeather {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,74 @@
+//! Values derived from the measurements: dew point, heat index and wind chill.
+use crate::compute::calculate_dew_point;
+use crate::reading::WeatherReading;
+use serde::Serialize;
+
+// Wind chill is only defined at or below this temperature and above this wind
+const WIND_CHILL_MAX_C: f32 = 10.0;
+const WIND_CHILL_MIN_KMH: f32 = 4.8;
+// The full heat index regression applies from this heat index, in °F
+const HEAT_INDEX_REGRESSION_F: f32 = 80.0;
+
+/// Derived values of a reading, all in °C.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Derived {
+    pub dew_point: f32,
+    pub heat_index: f32,
+    pub wind_chill: f32,
+}
+
+impl Derived {
+    pub fn compute(temperature: f32, humidity: f32, wind_speed_kmh: f32) -> Self {
+        Self {
+            dew_point: calculate_dew_point(temperature, humidity),
+            heat_index: heat_index_c(temperature, humidity),
+            wind_chill: wind_chill_c(temperature, wind_speed_kmh),
+        }
+    }
+
+    /// None before the humidity was ever measured.
+    pub fn from_reading(reading: &WeatherReading) -> Option<Self> {
+        (reading.humidity > 0.0).then(|| {
+            Self::compute(
+                reading.temperature,
+                reading.humidity,
+                reading.wind_speed_kmh,
+            )
+        })
+    }
+}
+
+/// Apparent temperature of hot and humid air, the NWS Rothfusz regression with its
+/// adjustments. Outside its domain, when Steadman's simple formula stays below 80°F, it is the
+/// air temperature.
+pub fn heat_index_c(temperature: f32, humidity: f32) -> f32 {
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = humidity.clamp(0.0, 100.0);
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < HEAT_INDEX_REGRESSION_F {
+        return temperature;
+    }
+    let mut hi = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+        - 0.224_755_4 * t * rh
+        - 0.006_837_83 * t * t
+        - 0.054_817_17 * rh * rh
+        + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh
+        - 0.000_001_99 * t * t * rh * rh;
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        hi -= (13.0 - rh) / 4.0 * libm::sqrtf((17.0 - (t - 95.0).abs()) / 17.0);
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        hi += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+    }
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+/// Wind chill index of Environment Canada and the NWS. Outside its domain, above 10°C or
+/// below 4.8 km/h, it is the air temperature.
+pub fn wind_chill_c(temperature: f32, wind_speed_kmh: f32) -> f32 {
+    if temperature > WIND_CHILL_MAX_C || wind_speed_kmh <= WIND_CHILL_MIN_KMH {
+        return temperature;
+    }
+    let v = libm::powf(wind_speed_kmh, 0.16);
+    13.12 + 0.6215 * temperature - 11.37 * v + 0.3965 * temperature * v
+}
@@ -0,0 +1,51 @@
+use core::time::Duration;
+
+/// The sensors must not be read more often than this, whatever the configured cycle.
+pub const DHT_MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DhtKind {
+    Dht11,
+    Dht22,
+}
+
+impl DhtKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dht11" => Some(DhtKind::Dht11),
+            "dht22" | "am2302" => Some(DhtKind::Dht22),
+            _ => None,
+        }
+    }
+
+    /// How long the host holds the line low to start a conversion.
+    pub fn start_signal(self) -> Duration {
+        match self {
+            DhtKind::Dht11 => Duration::from_millis(18),
+            DhtKind::Dht22 => Duration::from_millis(1),
+        }
+    }
+
+    /// Temperature (°C) and humidity (%) from the 5 bytes sent by the sensor.
+    pub fn decode(self, frame: [u8; 5]) -> Option<(f32, f32)> {
+        let sum = frame[..4].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if sum != frame[4] {
+            return None;
+        }
+        match self {
+            DhtKind::Dht11 => {
+                let humidity = frame[0] as f32 + frame[1] as f32 / 10.0;
+                let temperature = frame[2] as f32 + (frame[3] & 0x7F) as f32 / 10.0;
+                let sign = if frame[3] & 0x80 != 0 { -1.0 } else { 1.0 };
+                Some((sign * temperature, humidity))
+            }
+            DhtKind::Dht22 => {
+                let humidity = u16::from_be_bytes([frame[0], frame[1]]) as f32 / 10.0;
+                // Sign and magnitude, not two's complement
+                let temperature = u16::from_be_bytes([frame[2] & 0x7F, frame[3]]) as f32 / 10.0;
+                let sign = if frame[2] & 0x80 != 0 { -1.0 } else { 1.0 };
+                Some((sign * temperature, humidity))
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+
+/// Read-only hardware probes that can be requested over MQTT.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagCommand {
+    I2cScan,
+    I2cRead { addr: u8, reg: u8, len: u8 },
+    Gpio { pin: u8 },
+    Config,
+    Adc { channel: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagRequest {
+    pub id: String,
+    pub command: DiagCommand,
+}
+
+const MAX_GPIO: u8 = 39;
+const MAX_ADC1_CHANNEL: u8 = 7;
+const MAX_I2C_READ_LEN: u8 = 32;
+
+// Payload format: `<correlation id> <subcommand> [args...]`, numbers in decimal or 0x hex
+//   i2c_scan | i2c_read <addr> <reg> [len] | gpio <pin> | config | adc <channel>
+pub fn parse_diag(payload: &str) -> Result<DiagRequest, (String, String)> {
+    let mut words = payload.split_whitespace();
+    let id = words.next().unwrap_or_default().to_string();
+    let fail = |msg: &str| (id.clone(), msg.to_string());
+
+    let command = match words.next() {
+        Some("i2c_scan") => DiagCommand::I2cScan,
+        Some("i2c_read") => {
+            let addr = parse_num(words.next()).ok_or_else(|| fail("missing i2c address"))?;
+            let reg = parse_num(words.next()).ok_or_else(|| fail("missing register"))?;
+            let len = words.next().map_or(Some(1), |w| parse_num(Some(w)));
+            match len {
+                Some(len) if (1..=MAX_I2C_READ_LEN).contains(&len) && addr < 0x80 => {
+                    DiagCommand::I2cRead { addr, reg, len }
+                }
+                _ => return Err(fail("invalid i2c read arguments")),
+            }
+        }
+        Some("i2c_write") => return Err(fail("register writes are not supported")),
+        Some("gpio") => match parse_num(words.next()) {
+            Some(pin) if pin <= MAX_GPIO => DiagCommand::Gpio { pin },
+            _ => return Err(fail("invalid gpio number")),
+        },
+        Some("config") => DiagCommand::Config,
+        Some("adc") => match parse_num(words.next()) {
+            Some(channel) if channel <= MAX_ADC1_CHANNEL => DiagCommand::Adc { channel },
+            _ => return Err(fail("invalid adc channel")),
+        },
+        Some(_) => return Err(fail("unknown subcommand")),
+        None => return Err(fail("missing subcommand")),
+    };
+
+    Ok(DiagRequest { id, command })
+}
+
+// `result` is already JSON, the id and the error message come from the request and are
+// escaped
+pub fn diag_response(id: &str, result: Result<String, String>) -> String {
+    let id = json_string(id);
+    match result {
+        Ok(value) => format!("{{\"id\": {id}, \"ok\": true, \"result\": {value}}}"),
+        Err(msg) => format!(
+            "{{\"id\": {id}, \"ok\": false, \"error\": {}}}",
+            json_string(&msg)
+        ),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    serde_json::to_string(text).unwrap_or_else(|_| "\"\"".to_string())
+}
+
+fn parse_num(word: Option<&str>) -> Option<u8> {
+    let word = word?;
+    match word.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+/// Allows at most one action per `min_interval`.
+pub struct RateLimiter {
+    min_interval: Duration,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last: None,
+        }
+    }
+
+    pub fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        match self.last {
+            Some(last) if now.duration_since(last) < self.min_interval => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+}
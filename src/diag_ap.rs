@@ -0,0 +1,96 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    http::{server::EspHttpServer, Method},
+    io::Write,
+    sys::esp_timer_get_time,
+};
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use weather_station::reading::WeatherReading;
+
+use crate::http;
+use crate::logger;
+use crate::network::Network;
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+/// Access point brought up when WiFi stays down for `diag_ap_after_min`, so a phone next to
+/// the station can read `/diag`: the last reading and the recent log lines. It goes away once
+/// WiFi is back.
+pub struct DiagAp {
+    latest_reading: Arc<Mutex<WeatherReading>>,
+    // Only created when no other server is running, the endpoint then lives on it
+    server: Option<EspHttpServer<'static>>,
+    registered: bool,
+    active: bool,
+}
+
+impl DiagAp {
+    pub fn new(latest_reading: Arc<Mutex<WeatherReading>>) -> Self {
+        Self {
+            latest_reading,
+            server: None,
+            registered: false,
+            active: false,
+        }
+    }
+
+    /// Follows the WiFi state, `http_server` is the station server when it runs.
+    pub fn update(
+        &mut self,
+        network: &mut Network,
+        http_server: Option<&mut EspHttpServer<'static>>,
+    ) {
+        let after = Duration::from_secs(CONFIG.diag_ap_after_min as u64 * 60);
+        let outage = network.wifi_down_for();
+        if !self.active && outage.is_some_and(|outage| outage >= after) {
+            if let Err(e) = network.start_diag_ap() {
+                log::error!("Fail starting the diagnostic access point: {e}");
+                return;
+            }
+            self.active = true;
+            if !self.registered {
+                self.registered = self
+                    .register(http_server)
+                    .map_err(|e| log::error!("Fail serving the diagnostic page: {e}"))
+                    .is_ok();
+            }
+        } else if self.active && outage.is_none() {
+            self.active = false;
+            network
+                .stop_diag_ap()
+                .unwrap_or_else(|e| log::error!("Fail stopping the diagnostic access point: {e}"));
+        }
+    }
+
+    // Kept registered once done, the page is only reachable through the access point or the
+    // station network
+    fn register(&mut self, http_server: Option<&mut EspHttpServer<'static>>) -> Result<()> {
+        let server = match http_server {
+            Some(server) => server,
+            None => self.server.insert(http::http_server_create()?),
+        };
+        let latest_reading = self.latest_reading.clone();
+        server.fn_handler("/diag", Method::Get, move |req| {
+            let page = diag_page(&latest_reading.lock().unwrap());
+            let mut resp = req.into_response(200, None, &[("Content-Type", "text/plain")])?;
+            resp.write_all(page.as_bytes())?;
+            Ok::<(), anyhow::Error>(())
+        })?;
+        Ok(())
+    }
+}
+
+fn diag_page(reading: &WeatherReading) -> String {
+    let uptime_s = unsafe { esp_timer_get_time() } / 1_000_000;
+    let mut page = format!(
+        "{} diagnostics, WiFi unreachable\nuptime: {uptime_s}s\n\nlast reading:\n{}\n\nrecent log:\n",
+        station_id(),
+        reading.to_json()
+    );
+    for line in logger::recent_lines() {
+        writeln!(page, "{line}").ok();
+    }
+    page
+}
@@ -0,0 +1,161 @@
+use esp_idf_svc::{
+    hal::{delay::TickType, i2c::I2cDriver},
+    mqtt::client::EspMqttClient,
+    sys::{adc1_get_raw, gpio_get_level},
+};
+use std::cell::RefCell;
+use std::time::Duration;
+use weather_station::{
+    diag::*,
+    power::adc1_channel,
+    runtime::{PublishGroup, RUNTIME},
+};
+
+use crate::mqtt;
+use crate::provisioning::CONFIG;
+
+/// Internal temperature sensor of the chip, available on the ESP32-S2/S3/C-series.
+pub struct ChipTempSensor {
+    #[cfg(not(esp32))]
+    handle: esp_idf_svc::sys::temperature_sensor_handle_t,
+}
+
+impl ChipTempSensor {
+    // None when the chip has no sensor or the driver fails to start
+    pub fn new() -> Option<Self> {
+        #[cfg(esp32)]
+        {
+            log::info!("No internal temperature sensor on this chip");
+            None
+        }
+        #[cfg(not(esp32))]
+        {
+            use esp_idf_svc::sys::*;
+
+            let config = temperature_sensor_config_t {
+                range_min: -10,
+                range_max: 80,
+                ..Default::default()
+            };
+            let mut handle = std::ptr::null_mut();
+            let started = unsafe {
+                esp!(temperature_sensor_install(&config, &mut handle))
+                    .and_then(|_| esp!(temperature_sensor_enable(handle)))
+            };
+            match started {
+                Ok(()) => Some(Self { handle }),
+                Err(e) => {
+                    log::warn!("Internal temperature sensor unavailable: {e}");
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn read(&mut self) -> Option<f32> {
+        #[cfg(esp32)]
+        {
+            None
+        }
+        #[cfg(not(esp32))]
+        {
+            use esp_idf_svc::sys::*;
+
+            let mut celsius = 0.0;
+            unsafe { esp!(temperature_sensor_get_celsius(self.handle, &mut celsius)) }
+                .map_err(|e| log::error!("Failed to read chip temperature: {e}"))
+                .ok()
+                .map(|_| celsius)
+        }
+    }
+}
+
+/// Compares the chip temperature with the configured limit, warning once per crossing.
+/// Returns true when the alert state changed.
+pub fn check_chip_temp(mqtt_cli: &mut EspMqttClient, celsius: f32, overheating: &mut bool) -> bool {
+    let limit = CONFIG.chip_temp_limit_c;
+    if celsius > limit && !*overheating {
+        log::warn!("Chip temperature {celsius:.1}C above the {limit:.1}C limit");
+        mqtt::publish_alert(
+            mqtt_cli,
+            "chip_temp_high",
+            &format!("{{\"chip_temp\": {celsius}, \"limit\": {limit}}}"),
+        );
+    }
+    let changed = *overheating != (celsius > limit);
+    *overheating = celsius > limit;
+    changed
+}
+
+// Minimum delay between two diagnostics commands
+pub const DIAG_MIN_INTERVAL: Duration = Duration::from_secs(2);
+const I2C_TIMEOUT_MS: u64 = 20;
+
+pub fn handle_diag(
+    mqtt_cli: &mut EspMqttClient,
+    payload: &[u8],
+    limiter: &mut RateLimiter,
+    i2c_bus: &RefCell<I2cDriver>,
+) {
+    let response = match parse_diag(&String::from_utf8_lossy(payload)) {
+        Ok(request) if limiter.allow() => {
+            log::info!("Running diag command {:?}", request.command);
+            diag_response(&request.id, run_diag(request.command, i2c_bus))
+        }
+        Ok(request) => diag_response(&request.id, Err("rate limited".to_string())),
+        Err((id, msg)) => diag_response(&id, Err(msg)),
+    };
+
+    mqtt::publish_diag_response(mqtt_cli, &response);
+}
+
+fn run_diag(command: DiagCommand, i2c_bus: &RefCell<I2cDriver>) -> Result<String, String> {
+    let timeout = TickType::new_millis(I2C_TIMEOUT_MS).ticks();
+
+    match command {
+        DiagCommand::I2cScan => {
+            let mut i2c = i2c_bus.try_borrow_mut().map_err(|_| "i2c bus busy")?;
+            let found: Vec<String> = (0x08..0x78)
+                .filter(|&addr| i2c.read(addr, &mut [0u8; 1], timeout).is_ok())
+                .map(|addr| format!("\"0x{addr:02X}\""))
+                .collect();
+            Ok(format!("[{}]", found.join(", ")))
+        }
+        DiagCommand::I2cRead { addr, reg, len } => {
+            let mut i2c = i2c_bus.try_borrow_mut().map_err(|_| "i2c bus busy")?;
+            let mut buf = [0u8; 32];
+            let buf = &mut buf[..len as usize];
+            i2c.write_read(addr, &[reg], buf, timeout)
+                .map_err(|e| format!("i2c read failed: {e}"))?;
+            let bytes: Vec<String> = buf.iter().map(|b| format!("\"0x{b:02X}\"")).collect();
+            Ok(format!("[{}]", bytes.join(", ")))
+        }
+        DiagCommand::Gpio { pin } => {
+            let level = unsafe { gpio_get_level(pin as i32) };
+            Ok(level.to_string())
+        }
+        DiagCommand::Config => {
+            let intervals: Vec<String> = PublishGroup::ALL
+                .iter()
+                .map(|&group| format!("\"{}\": {}", group.name(), RUNTIME.interval_s(group)))
+                .collect();
+            Ok(format!(
+                "{{\"intervals_s\": {{{}}}, \"vane_offset_deg\": {}}}",
+                intervals.join(", "),
+                RUNTIME.vane_offset_deg()
+            ))
+        }
+        DiagCommand::Adc { channel } => {
+            // Only the battery channel is set up by the station, configuring another one
+            // could disturb a pin in use
+            if adc1_channel(CONFIG.battery_adc_gpio) != Some(channel) {
+                return Err("adc channel not configured".to_string());
+            }
+            let raw = unsafe { adc1_get_raw(channel as u32) };
+            if raw < 0 {
+                return Err("adc read failed".to_string());
+            }
+            Ok(raw.to_string())
+        }
+    }
+}
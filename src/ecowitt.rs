@@ -0,0 +1,106 @@
+//! Report of the Ecowitt gateway "customized upload" protocol.
+//!
+//! Ecowitt gateways POST their observations as a form in imperial units to a local server.
+//! Consumers of it, such as the WeeWX interceptor or the Home Assistant Ecowitt integration,
+//! key the station on `PASSKEY`.
+use crate::derive::Derived;
+use crate::reading::WeatherReading;
+use crate::runtime::{Field, FieldSelection};
+use crate::wunderground::{
+    celsius_to_fahrenheit, hpa_to_inhg, kmh_to_mph, mm_to_in, percent_encode,
+};
+use core::fmt::Write;
+
+const STATION_TYPE: &str = "weather_station";
+const MODEL: &str = "ESP32";
+
+/// Form body of a report, `application/x-www-form-urlencoded`. `date_utc` is the time the
+/// reading was taken as `YYYY-MM-DD HH:MM:SS`, None when the clock is not set. The
+/// measurements left out by `fields` are not sent, neither are the wind and the rain of a
+/// reading taken during maintenance.
+pub fn report_form(
+    passkey: &str,
+    reading: &WeatherReading,
+    fields: FieldSelection,
+    date_utc: Option<&str>,
+) -> String {
+    let mut form = format!(
+        "PASSKEY={}&stationtype={STATION_TYPE}&model={MODEL}",
+        percent_encode(passkey)
+    );
+    if let Some(date_utc) = date_utc {
+        write!(form, "&dateutc={}", percent_encode(date_utc)).ok();
+    }
+    let measured = |field: Field| fields.contains(field);
+    let outdoor = |field: Field| measured(field) && !reading.maintenance;
+    let values = [
+        (
+            "tempf",
+            measured(Field::Temperature).then(|| celsius_to_fahrenheit(reading.temperature)),
+            1,
+        ),
+        (
+            "humidity",
+            measured(Field::Humidity).then_some(reading.humidity),
+            0,
+        ),
+        (
+            "dewptf",
+            Derived::from_reading(reading)
+                .filter(|_| measured(Field::Derived))
+                .map(|derived| celsius_to_fahrenheit(derived.dew_point)),
+            1,
+        ),
+        // Relative is the sea level pressure, absolute the one at the station
+        (
+            "baromrelin",
+            reading
+                .sea_level_pressure
+                .filter(|_| measured(Field::Pressure))
+                .map(hpa_to_inhg),
+            3,
+        ),
+        (
+            "baromabsin",
+            (measured(Field::Pressure) && reading.pressure > 0.0)
+                .then(|| hpa_to_inhg(reading.pressure)),
+            3,
+        ),
+        (
+            "windspeedmph",
+            outdoor(Field::WindSpeed).then(|| kmh_to_mph(reading.wind_speed_kmh)),
+            1,
+        ),
+        (
+            "windgustmph",
+            outdoor(Field::WindGust).then(|| kmh_to_mph(reading.wind_gust_kmh)),
+            1,
+        ),
+        (
+            "winddir",
+            outdoor(Field::WindDirection).then_some(reading.wind_direction_deg),
+            0,
+        ),
+        (
+            "hourlyrainin",
+            outdoor(Field::Rain).then(|| mm_to_in(reading.rain_totals.last_1h_mm)),
+            2,
+        ),
+        (
+            "24hrainin",
+            outdoor(Field::Rain).then(|| mm_to_in(reading.rain_totals.last_24h_mm)),
+            2,
+        ),
+        (
+            "dailyrainin",
+            outdoor(Field::Rain).then(|| mm_to_in(reading.rain_totals.today_mm)),
+            2,
+        ),
+    ];
+    for (name, value, decimals) in values {
+        if let Some(value) = value.filter(|value| value.is_finite()) {
+            write!(form, "&{name}={value:.decimals$}").ok();
+        }
+    }
+    form
+}
@@ -0,0 +1,73 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::{
+    http::{
+        client::{Configuration, EspHttpConnection},
+        Method,
+    },
+    io::Write,
+};
+use std::time::Duration;
+use weather_station::{ecowitt::report_form, runtime::RUNTIME, time::synced_utc_datetime};
+
+use crate::http::ReadingHistory;
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Posts the latest reading to `ecowitt_url` every `ecowitt_interval_s` the way an Ecowitt
+/// gateway does, for consumers on the local network. A failed report is not retried, the
+/// next one follows shortly.
+pub fn ecowitt_start(history: ReadingHistory) -> Result<()> {
+    if CONFIG.ecowitt_url.is_empty() {
+        bail!("ecowitt_url is required");
+    }
+
+    std::thread::Builder::new()
+        .stack_size(8192)
+        .spawn(move || {
+            let mut last_sent = 0;
+            loop {
+                std::thread::sleep(Duration::from_secs(CONFIG.ecowitt_interval_s as u64));
+                let Some(latest) = history.lock().unwrap().last().copied() else {
+                    continue;
+                };
+                if latest.timestamp_ms == last_sent || latest.reading.synthetic {
+                    continue;
+                }
+                let form = report_form(
+                    station_id(),
+                    &latest.reading,
+                    RUNTIME.fields(),
+                    synced_utc_datetime(latest.timestamp_ms).as_deref(),
+                );
+                match send(&form) {
+                    Ok(200..=299) => {}
+                    Ok(status) => log::warn!("Ecowitt report refused with status {status}"),
+                    Err(e) => log::warn!("Fail sending Ecowitt report: {e}"),
+                }
+                last_sent = latest.timestamp_ms;
+            }
+        })?;
+    log::info!("Sending Ecowitt reports to {}", CONFIG.ecowitt_url);
+
+    Ok(())
+}
+
+fn send(form: &str) -> Result<u16> {
+    let mut conn = EspHttpConnection::new(&Configuration {
+        timeout: Some(REQUEST_TIMEOUT),
+        ..Default::default()
+    })?;
+
+    let content_length = form.len().to_string();
+    let headers = [
+        ("Content-Type", "application/x-www-form-urlencoded"),
+        ("Content-Length", content_length.as_str()),
+    ];
+    conn.initiate_request(Method::Post, CONFIG.ecowitt_url, &headers)?;
+    conn.write_all(form.as_bytes())?;
+    conn.initiate_response()?;
+
+    Ok(conn.status())
+}
@@ -0,0 +1,78 @@
+use esp_idf_svc::{
+    hal::delay::FreeRtos,
+    mqtt::client::EspMqttClient,
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    sys::{
+        esp_deep_sleep_start, esp_sleep_disable_wakeup_source,
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL,
+    },
+};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use weather_station::*;
+
+use crate::mqtt;
+use crate::network::Network;
+
+const NVS_NAMESPACE: &str = "emergency";
+// Operating range of the BME680, anything outside means a faulty sensor or a runaway heater
+const TEMPERATURE_RANGE_C: std::ops::RangeInclusive<f32> = -40.0..=85.0;
+// Out of range readings in a row from one sensor before the station stops, a single glitch
+// must not need a physical reset
+const OUT_OF_RANGE_READINGS: u32 = 3;
+// Leaves the MQTT client time to send the event before the connection is dropped
+const FLUSH_DELAY_MS: u32 = 500;
+
+/// Out of range temperatures in a row, per environment sensor.
+#[derive(Default)]
+pub struct TemperatureWatch {
+    in_a_row: HashMap<String, u32>,
+}
+
+impl TemperatureWatch {
+    /// True once `sensor` read `OUT_OF_RANGE_READINGS` temperatures out of range in a row.
+    pub fn out_of_range(&mut self, sensor: &str, temperature: f32) -> bool {
+        let count = self.in_a_row.entry(sensor.to_string()).or_default();
+        *count = if TEMPERATURE_RANGE_C.contains(&temperature) {
+            0
+        } else {
+            *count + 1
+        };
+        *count >= OUT_OF_RANGE_READINGS
+    }
+}
+
+/// Stops the station until a physical reset: the counters are saved to NVS, the event is
+/// published and the chip enters deep sleep with every wakeup source disabled.
+pub fn emergency_stop(
+    reason: &str,
+    mut mqtt_cli: EspMqttClient,
+    network: &mut Network,
+    nvs: EspDefaultNvsPartition,
+) -> ! {
+    log::error!("EMERGENCY STOP: {reason}");
+
+    match EspNvs::new(nvs, NVS_NAMESPACE, true) {
+        Ok(mut storage) => {
+            storage
+                .set_u32("rain_count", RAIN_COUNT.load(Ordering::Relaxed))
+                .and_then(|_| {
+                    storage.set_u32("rotation_count", ROTATION_COUNT.load(Ordering::Relaxed))
+                })
+                .map_err(|e| log::error!("fail storing counters: {e}"))
+                .ok();
+        }
+        Err(e) => log::error!("fail opening nvs: {e}"),
+    }
+
+    mqtt::publish_system_event(&mut mqtt_cli, &format!("emergency_stop: {reason}"));
+    FreeRtos::delay_ms(FLUSH_DELAY_MS);
+    drop(mqtt_cli);
+
+    network.shutdown();
+
+    unsafe {
+        esp_sleep_disable_wakeup_source(esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL);
+        esp_deep_sleep_start()
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Result};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10},
+        MonoTextStyle,
+    },
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle},
+    text::Text,
+};
+use epd_waveshare::{
+    color::Color,
+    epd2in9_v2::{Display2in9, Epd2in9},
+    prelude::*,
+};
+use esp_idf_svc::hal::{
+    delay::FreeRtos,
+    gpio::{AnyIOPin, AnyInputPin, AnyOutputPin, PinDriver},
+    spi::{config::Config as SpiConfig, SpiDeviceDriver, SpiDriver, SpiDriverConfig, SPI2},
+    units::Hertz,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::provisioning::CONFIG;
+
+const SPI_BAUD: Hertz = Hertz(4_000_000);
+const COMPASS_RADIUS: i32 = 40;
+const POLL_PERIOD: Duration = Duration::from_secs(1);
+
+// Kept in RTC memory so the full refresh cadence survives deep sleep
+#[link_section = ".rtc.data"]
+static mut REFRESH_CYCLE: u32 = 0;
+
+/// Values shown on the panel, updated by the main loop.
+#[derive(Clone, Debug, Default)]
+pub struct DisplayStatus {
+    pub temperature: f32,
+    // Min and max over the last 24 hours
+    pub temperature_24h: Option<(f32, f32)>,
+    pub humidity: f32,
+    // Sea level pressure when known, None without a pressure sensor
+    pub pressure: Option<f32>,
+    pub wind_speed_kmh: f32,
+    pub gust_max_24h: Option<f32>,
+    pub wind_direction_deg: f32,
+    pub rain_today_mm: f32,
+    pub battery_v: Option<f32>,
+    pub time: String,
+}
+
+pub type SharedDisplayStatus = Arc<Mutex<Option<DisplayStatus>>>;
+
+/// Drives a 2.9" SSD1680 panel on SPI2 from its own thread, a refresh takes seconds.
+///
+/// Refreshes are partial except every `epaper_full_refresh_every` cycles, where a full
+/// refresh clears the ghosting left by the partial ones.
+pub fn epaper_start(spi: SPI2) -> Result<SharedDisplayStatus> {
+    // Pins come from the config, they are not claimed by any other driver
+    let (sclk, mosi, cs, busy, dc, rst) = unsafe {
+        (
+            AnyIOPin::new(CONFIG.epaper_sclk_gpio),
+            AnyIOPin::new(CONFIG.epaper_mosi_gpio),
+            AnyIOPin::new(CONFIG.epaper_cs_gpio),
+            AnyInputPin::new(CONFIG.epaper_busy_gpio),
+            AnyOutputPin::new(CONFIG.epaper_dc_gpio),
+            AnyOutputPin::new(CONFIG.epaper_rst_gpio),
+        )
+    };
+    let driver = SpiDriver::new(spi, sclk, mosi, None::<AnyIOPin>, &SpiDriverConfig::new())?;
+    let mut spi = SpiDeviceDriver::new(driver, Some(cs), &SpiConfig::new().baudrate(SPI_BAUD))?;
+    let mut delay = FreeRtos;
+    let mut epd = Epd2in9::new(
+        &mut spi,
+        PinDriver::input(busy)?,
+        PinDriver::output(dc)?,
+        PinDriver::output(rst)?,
+        &mut delay,
+        None,
+    )
+    .map_err(|e| anyhow!("e-paper init failed: {e:?}"))?;
+
+    let status: SharedDisplayStatus = Arc::new(Mutex::new(None));
+    let shared = status.clone();
+    std::thread::Builder::new()
+        .stack_size(8192)
+        .spawn(move || {
+            let mut display = Display2in9::default();
+            display.set_rotation(DisplayRotation::Rotate90);
+            let refresh_period = Duration::from_secs(CONFIG.epaper_refresh_s as u64);
+            let mut last_refresh: Option<Instant> = None;
+            loop {
+                std::thread::sleep(POLL_PERIOD);
+                if last_refresh.is_some_and(|last| last.elapsed() < refresh_period) {
+                    continue;
+                }
+                let Some(status) = shared.lock().unwrap().take() else {
+                    continue;
+                };
+                draw(&mut display, &status);
+                // Only the display thread touches the counter
+                let cycle = unsafe { &mut *std::ptr::addr_of_mut!(REFRESH_CYCLE) };
+                let lut = if *cycle % CONFIG.epaper_full_refresh_every.max(1) == 0 {
+                    RefreshLut::Full
+                } else {
+                    RefreshLut::Quick
+                };
+                let refreshed = epd
+                    .set_lut(&mut spi, &mut delay, Some(lut))
+                    .and_then(|_| {
+                        epd.update_and_display_frame(&mut spi, display.buffer(), &mut delay)
+                    })
+                    .and_then(|_| epd.sleep(&mut spi, &mut delay));
+                match refreshed {
+                    Ok(()) => *cycle = cycle.wrapping_add(1),
+                    Err(e) => log::error!("e-paper refresh failed: {e:?}"),
+                }
+                last_refresh = Some(Instant::now());
+            }
+        })?;
+
+    Ok(status)
+}
+
+// 296x128 landscape: readings on the left, wind compass on the right
+fn draw(display: &mut Display2in9, status: &DisplayStatus) {
+    let big = MonoTextStyle::new(&FONT_10X20, Color::Black);
+    let small = MonoTextStyle::new(&FONT_6X10, Color::Black);
+    let stroke = |width| PrimitiveStyle::with_stroke(Color::Black, width);
+
+    display.clear(Color::White).ok();
+    let lines = [
+        (format!("{:.1} C", status.temperature), big, 24),
+        (
+            status
+                .temperature_24h
+                .map_or("24h --".to_string(), |(min, max)| {
+                    format!("24h {min:.1} / {max:.1} C")
+                }),
+            small,
+            40,
+        ),
+        (
+            match status.pressure {
+                Some(pressure) => format!("{:.0} %  {pressure:.0} hPa", status.humidity),
+                None => format!("{:.0} %", status.humidity),
+            },
+            small,
+            56,
+        ),
+        (
+            match status.gust_max_24h {
+                Some(gust) => format!("Wind {:.1} km/h max {gust:.0}", status.wind_speed_kmh),
+                None => format!("Wind {:.1} km/h", status.wind_speed_kmh),
+            },
+            small,
+            70,
+        ),
+        (
+            format!("Rain today {:.1} mm", status.rain_today_mm),
+            small,
+            84,
+        ),
+        (
+            status
+                .battery_v
+                .map_or("Battery --".to_string(), |v| format!("Battery {v:.2} V")),
+            small,
+            98,
+        ),
+        (status.time.clone(), small, 120),
+    ];
+    for (text, style, y) in lines {
+        Text::new(&text, Point::new(8, y), style).draw(display).ok();
+    }
+
+    // The arrow points where the wind comes from
+    let center = Point::new(240, 64);
+    Circle::with_center(center, (COMPASS_RADIUS * 2) as u32)
+        .into_styled(stroke(2))
+        .draw(display)
+        .ok();
+    Text::new("N", center - Point::new(3, COMPASS_RADIUS + 4), small)
+        .draw(display)
+        .ok();
+    let (sin, cos) = status.wind_direction_deg.to_radians().sin_cos();
+    let tip = center
+        + Point::new(
+            (sin * (COMPASS_RADIUS - 6) as f32) as i32,
+            -(cos * (COMPASS_RADIUS - 6) as f32) as i32,
+        );
+    Line::new(center, tip)
+        .into_styled(stroke(3))
+        .draw(display)
+        .ok();
+    Circle::with_center(center, 6)
+        .into_styled(PrimitiveStyle::with_fill(Color::Black))
+        .draw(display)
+        .ok();
+}
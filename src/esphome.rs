@@ -0,0 +1,530 @@
+//! ESPHome native API: protobuf messages, frames and the sensor entities of the station.
+//!
+//! Only the messages needed by a read-only sensor node are implemented, see `api.proto` in the
+//! ESPHome sources for the field numbers.
+
+use std::io::{self, Read};
+
+use crate::reading::WeatherReading;
+
+pub const API_VERSION_MAJOR: u32 = 1;
+pub const API_VERSION_MINOR: u32 = 10;
+
+// Message types
+pub const HELLO_REQUEST: u16 = 1;
+pub const HELLO_RESPONSE: u16 = 2;
+pub const CONNECT_REQUEST: u16 = 3;
+pub const CONNECT_RESPONSE: u16 = 4;
+pub const DISCONNECT_REQUEST: u16 = 5;
+pub const DISCONNECT_RESPONSE: u16 = 6;
+pub const PING_REQUEST: u16 = 7;
+pub const PING_RESPONSE: u16 = 8;
+pub const DEVICE_INFO_REQUEST: u16 = 9;
+pub const DEVICE_INFO_RESPONSE: u16 = 10;
+pub const LIST_ENTITIES_REQUEST: u16 = 11;
+pub const LIST_ENTITIES_SENSOR_RESPONSE: u16 = 16;
+pub const LIST_ENTITIES_DONE_RESPONSE: u16 = 19;
+pub const SUBSCRIBE_STATES_REQUEST: u16 = 20;
+pub const SENSOR_STATE_RESPONSE: u16 = 25;
+
+// First byte of a frame
+pub const PLAINTEXT_INDICATOR: u8 = 0x00;
+pub const NOISE_INDICATOR: u8 = 0x01;
+pub const NOISE_PROTOCOL: &str = "Noise_NNpsk0_25519_ChaChaPoly_SHA256";
+pub const NOISE_PROLOGUE: &[u8] = b"NoiseAPIInit\x00\x00";
+// Frames above this are refused, the station only expects short requests
+pub const MAX_FRAME_LEN: usize = 1024;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_LEN: u32 = 2;
+const WIRE_FIXED32: u32 = 5;
+
+/// Protobuf message under construction, fields are appended in order.
+#[derive(Default)]
+pub struct ProtoWriter {
+    buf: Vec<u8>,
+}
+
+impl ProtoWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Default values are omitted, as proto3 does
+    pub fn uint32(mut self, field: u32, value: u32) -> Self {
+        if value != 0 {
+            put_varint(&mut self.buf, (field << 3 | WIRE_VARINT) as u64);
+            put_varint(&mut self.buf, value as u64);
+        }
+        self
+    }
+
+    pub fn int32(mut self, field: u32, value: i32) -> Self {
+        if value != 0 {
+            put_varint(&mut self.buf, (field << 3 | WIRE_VARINT) as u64);
+            // Negative values are sign extended to 10 bytes
+            put_varint(&mut self.buf, value as i64 as u64);
+        }
+        self
+    }
+
+    pub fn bool(self, field: u32, value: bool) -> Self {
+        self.uint32(field, value as u32)
+    }
+
+    pub fn string(mut self, field: u32, value: &str) -> Self {
+        if !value.is_empty() {
+            put_varint(&mut self.buf, (field << 3 | WIRE_LEN) as u64);
+            put_varint(&mut self.buf, value.len() as u64);
+            self.buf.extend_from_slice(value.as_bytes());
+        }
+        self
+    }
+
+    pub fn fixed32(mut self, field: u32, value: u32) -> Self {
+        if value != 0 {
+            put_varint(&mut self.buf, (field << 3 | WIRE_FIXED32) as u64);
+            self.buf.extend_from_slice(&value.to_le_bytes());
+        }
+        self
+    }
+
+    pub fn float(self, field: u32, value: f32) -> Self {
+        self.fixed32(field, value.to_bits())
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Returns the first string (or bytes) field with this number, None when it is absent or the
+/// message is malformed.
+pub fn string_field(message: &[u8], field: u32) -> Option<String> {
+    let mut rest = message;
+    while !rest.is_empty() {
+        let key = take_varint(&mut rest)?;
+        let len = match (key & 7) as u32 {
+            WIRE_VARINT => {
+                take_varint(&mut rest)?;
+                0
+            }
+            1 => 8,
+            WIRE_LEN => take_varint(&mut rest)? as usize,
+            WIRE_FIXED32 => 4,
+            _ => return None,
+        };
+        if len > rest.len() {
+            return None;
+        }
+        let (value, tail) = rest.split_at(len);
+        if (key >> 3) as u32 == field && (key & 7) as u32 == WIRE_LEN {
+            return Some(String::from_utf8_lossy(value).into_owned());
+        }
+        rest = tail;
+    }
+    None
+}
+
+/// `0x00`, payload length and message type as varints, then the payload.
+pub fn plaintext_frame(msg_type: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 6);
+    frame.push(PLAINTEXT_INDICATOR);
+    put_varint(&mut frame, payload.len() as u64);
+    put_varint(&mut frame, msg_type as u64);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Reads the rest of a plaintext frame once its indicator byte was consumed.
+pub fn read_plaintext_frame(reader: &mut impl Read) -> io::Result<(u16, Vec<u8>)> {
+    let len = read_varint(reader)? as usize;
+    let msg_type = read_varint(reader)?;
+    if len > MAX_FRAME_LEN || msg_type > u16::MAX as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad plaintext frame",
+        ));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok((msg_type as u16, payload))
+}
+
+/// `0x01`, big endian length, then the handshake message or the ciphertext.
+pub fn noise_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 3);
+    frame.push(NOISE_INDICATOR);
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Reads the rest of a noise frame once its indicator byte was consumed.
+pub fn read_noise_frame(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    reader.read_exact(&mut len)?;
+    let len = u16::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "noise frame too long",
+        ));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Plaintext of an encrypted frame: big endian message type and payload length, then the
+/// payload.
+pub fn noise_message(msg_type: u16, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(payload.len() + 4);
+    message.extend_from_slice(&msg_type.to_be_bytes());
+    message.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+pub fn parse_noise_message(message: &[u8]) -> Option<(u16, &[u8])> {
+    if message.len() < 4 {
+        return None;
+    }
+    let msg_type = u16::from_be_bytes([message[0], message[1]]);
+    let len = u16::from_be_bytes([message[2], message[3]]) as usize;
+    message.get(4..4 + len).map(|payload| (msg_type, payload))
+}
+
+/// Sent by the server after the client hello of an encrypted connection: the chosen protocol,
+/// then the node name and MAC address, both NUL terminated.
+pub fn noise_server_hello(name: &str, mac: &str) -> Vec<u8> {
+    let mut hello = vec![NOISE_INDICATOR];
+    hello.extend_from_slice(name.as_bytes());
+    hello.push(0);
+    hello.extend_from_slice(mac.as_bytes());
+    hello.push(0);
+    hello
+}
+
+pub fn hello_response(name: &str, server_info: &str) -> Vec<u8> {
+    ProtoWriter::new()
+        .uint32(1, API_VERSION_MAJOR)
+        .uint32(2, API_VERSION_MINOR)
+        .string(3, server_info)
+        .string(4, name)
+        .finish()
+}
+
+/// Static description of the node shown in Home Assistant.
+pub struct DeviceInfo<'a> {
+    pub name: &'a str,
+    pub friendly_name: &'a str,
+    pub mac_address: &'a str,
+    pub version: &'a str,
+    pub model: &'a str,
+    pub manufacturer: &'a str,
+    pub has_deep_sleep: bool,
+}
+
+impl DeviceInfo<'_> {
+    pub fn to_message(&self) -> Vec<u8> {
+        ProtoWriter::new()
+            .string(2, self.name)
+            .string(3, self.mac_address)
+            .string(4, self.version)
+            .string(6, self.model)
+            .bool(7, self.has_deep_sleep)
+            .string(12, self.manufacturer)
+            .string(13, self.friendly_name)
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateClass {
+    Measurement = 1,
+    TotalIncreasing = 2,
+}
+
+/// A sensor entity, its key is derived from the object id like ESPHome does.
+pub struct SensorEntity {
+    pub object_id: &'static str,
+    pub name: &'static str,
+    pub unit: &'static str,
+    /// Home Assistant device class, empty for none
+    pub device_class: &'static str,
+    pub state_class: StateClass,
+    pub icon: &'static str,
+    pub accuracy_decimals: i32,
+    pub value: fn(&WeatherReading) -> f32,
+}
+
+impl SensorEntity {
+    pub fn key(&self) -> u32 {
+        fnv1_hash(self.object_id)
+    }
+
+    /// `unique_id` is prefixed with the node name so two stations never clash.
+    pub fn list_message(&self, node_name: &str) -> Vec<u8> {
+        ProtoWriter::new()
+            .string(1, self.object_id)
+            .fixed32(2, self.key())
+            .string(3, self.name)
+            .string(4, &format!("{node_name}sensor{}", self.object_id))
+            .string(5, self.icon)
+            .string(6, self.unit)
+            .int32(7, self.accuracy_decimals)
+            .string(9, self.device_class)
+            .uint32(10, self.state_class as u32)
+            .finish()
+    }
+
+    pub fn state_message(&self, reading: &WeatherReading) -> Vec<u8> {
+        let value = (self.value)(reading);
+        ProtoWriter::new()
+            .fixed32(1, self.key())
+            .float(2, value)
+            .bool(3, !value.is_finite())
+            .finish()
+    }
+}
+
+pub const SENSORS: [SensorEntity; 6] = [
+    SensorEntity {
+        object_id: "temperature",
+        name: "Temperature",
+        unit: "°C",
+        device_class: "temperature",
+        state_class: StateClass::Measurement,
+        icon: "",
+        accuracy_decimals: 1,
+        value: |r| r.temperature,
+    },
+    SensorEntity {
+        object_id: "humidity",
+        name: "Humidity",
+        unit: "%",
+        device_class: "humidity",
+        state_class: StateClass::Measurement,
+        icon: "",
+        accuracy_decimals: 1,
+        value: |r| r.humidity,
+    },
+    SensorEntity {
+        object_id: "pressure",
+        name: "Pressure",
+        unit: "hPa",
+        device_class: "atmospheric_pressure",
+        state_class: StateClass::Measurement,
+        icon: "",
+        accuracy_decimals: 1,
+        value: |r| r.pressure,
+    },
+    SensorEntity {
+        object_id: "wind_speed",
+        name: "Wind speed",
+        unit: "km/h",
+        device_class: "wind_speed",
+        state_class: StateClass::Measurement,
+        icon: "",
+        accuracy_decimals: 1,
+        value: |r| r.wind_speed_kmh,
+    },
+    SensorEntity {
+        object_id: "wind_direction",
+        name: "Wind direction",
+        unit: "°",
+        device_class: "",
+        state_class: StateClass::Measurement,
+        icon: "mdi:compass-rose",
+        accuracy_decimals: 0,
+        value: |r| r.wind_direction_deg,
+    },
+    // Rain since the previous cycle
+    SensorEntity {
+        object_id: "rain",
+        name: "Rain",
+        unit: "mm",
+        device_class: "precipitation",
+        state_class: StateClass::Measurement,
+        icon: "",
+        accuracy_decimals: 1,
+        value: |r| r.rain_mm,
+    },
+];
+
+/// 32 bit FNV-1 hash, ESPHome's entity key.
+pub fn fnv1_hash(s: &str) -> u32 {
+    s.bytes().fold(2166136261, |hash, byte| {
+        hash.wrapping_mul(16777619) ^ byte as u32
+    })
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn take_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7F) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint too long",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // HelloRequest of aioesphomeapi, client_info "aioesphomeapi" and API 1.10, as sent by Home
+    // Assistant
+    const HELLO_REQUEST_FRAME: [u8; 22] = [
+        0x00, 0x13, 0x01, 0x0A, 0x0D, b'a', b'i', b'o', b'e', b's', b'p', b'h', b'o', b'm', b'e',
+        b'a', b'p', b'i', 0x10, 0x01, 0x18, 0x0A,
+    ];
+
+    #[test]
+    fn reads_varints() {
+        assert_eq!(read_varint(&mut &[0x00][..]).unwrap(), 0);
+        assert_eq!(read_varint(&mut &[0x96, 0x01][..]).unwrap(), 150);
+        assert_eq!(
+            read_varint(&mut &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F][..]).unwrap(),
+            u32::MAX as u64
+        );
+        assert_eq!(
+            read_varint(&mut &[0x80][..]).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            read_varint(&mut &[0xFF; 11][..]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn reads_plaintext_frames() {
+        let mut reader = &HELLO_REQUEST_FRAME[1..];
+        let (msg_type, payload) = read_plaintext_frame(&mut reader).unwrap();
+        assert_eq!(msg_type, HELLO_REQUEST);
+        assert_eq!(payload, HELLO_REQUEST_FRAME[3..]);
+        assert!(reader.is_empty());
+        assert_eq!(string_field(&payload, 1).as_deref(), Some("aioesphomeapi"));
+        assert_eq!(string_field(&payload, 4), None);
+
+        // PingRequest, no payload
+        let (msg_type, payload) = read_plaintext_frame(&mut &[0x00, 0x07][..]).unwrap();
+        assert_eq!((msg_type, payload.len()), (PING_REQUEST, 0));
+
+        // 1025 bytes announced
+        let err = read_plaintext_frame(&mut &[0x81, 0x08, 0x07][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = read_plaintext_frame(&mut &[0x05, 0x07, 0x01][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn writes_plaintext_frames() {
+        assert_eq!(plaintext_frame(PING_RESPONSE, &[]), [0x00, 0x00, 0x08]);
+        assert_eq!(
+            plaintext_frame(HELLO_REQUEST, &HELLO_REQUEST_FRAME[3..]),
+            HELLO_REQUEST_FRAME
+        );
+    }
+
+    #[test]
+    fn frames_noise_messages() {
+        assert_eq!(noise_message(PING_REQUEST, &[]), [0x00, 0x07, 0x00, 0x00]);
+        assert_eq!(
+            noise_message(DEVICE_INFO_RESPONSE, &[0x12, 0x01, b'x']),
+            [0x00, 0x0A, 0x00, 0x03, 0x12, 0x01, b'x']
+        );
+        assert_eq!(
+            parse_noise_message(&[0x00, 0x0A, 0x00, 0x03, 0x12, 0x01, b'x']),
+            Some((DEVICE_INFO_RESPONSE, &[0x12, 0x01, b'x'][..]))
+        );
+        assert_eq!(parse_noise_message(&[0x00, 0x07, 0x00]), None);
+        assert_eq!(parse_noise_message(&[0x00, 0x0A, 0x00, 0x03, 0x12]), None);
+
+        // The client hello of an encrypted connection is an empty frame
+        assert_eq!(noise_frame(&[]), [0x01, 0x00, 0x00]);
+        assert_eq!(noise_frame(&[0xAB, 0xCD]), [0x01, 0x00, 0x02, 0xAB, 0xCD]);
+        assert_eq!(
+            read_noise_frame(&mut &[0x00, 0x02, 0xAB, 0xCD][..]).unwrap(),
+            [0xAB, 0xCD]
+        );
+        let err = read_noise_frame(&mut &[0x04, 0x01][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn writes_proto_fields() {
+        assert_eq!(
+            hello_response("node", "ws"),
+            [0x08, 0x01, 0x10, 0x0A, 0x1A, 0x02, b'w', b's', 0x22, 0x04, b'n', b'o', b'd', b'e']
+        );
+        // Defaults are left out
+        let empty = ProtoWriter::new()
+            .uint32(1, 0)
+            .int32(2, 0)
+            .bool(3, false)
+            .string(4, "")
+            .float(5, 0.0)
+            .finish();
+        assert!(empty.is_empty());
+        assert_eq!(
+            ProtoWriter::new().int32(7, -1).finish(),
+            [0x38, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]
+        );
+        assert_eq!(
+            ProtoWriter::new().uint32(1, 300).finish(),
+            [0x08, 0xAC, 0x02]
+        );
+    }
+
+    #[test]
+    fn encodes_sensor_states() {
+        let temperature = &SENSORS[0];
+        assert_eq!(temperature.key(), 0x35A1_23F9);
+        let reading = WeatherReading {
+            temperature: 21.5,
+            ..Default::default()
+        };
+        assert_eq!(
+            temperature.state_message(&reading),
+            [0x0D, 0xF9, 0x23, 0xA1, 0x35, 0x15, 0x00, 0x00, 0xAC, 0x41]
+        );
+        let reading = WeatherReading {
+            temperature: f32::NAN,
+            ..Default::default()
+        };
+        assert_eq!(
+            temperature.state_message(&reading)[5..],
+            [0x15, 0x00, 0x00, 0xC0, 0x7F, 0x18, 0x01]
+        );
+    }
+}
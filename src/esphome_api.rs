@@ -0,0 +1,321 @@
+use anyhow::{bail, Result};
+use base64::Engine;
+use esp_idf_svc::sys::esp_efuse_mac_get_default;
+use snow::{HandshakeState, TransportState};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use weather_station::{esphome, reading::WeatherReading};
+
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+const API_PORT: u16 = 6053;
+const MAX_CLIENTS: usize = 2;
+// How often a client thread looks for a new reading while its socket is idle
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Once the first byte of a frame is in, the rest must follow within this delay
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+// A silent client is pinged, then dropped if it still says nothing
+const KEEPALIVE: Duration = Duration::from_secs(20);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+// Noise payloads carry a 16 byte authentication tag
+const NOISE_BUF_LEN: usize = esphome::MAX_FRAME_LEN + 16;
+
+static ACTIVE_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Latest reading handed to the API clients, `cycle` counts the pushes.
+#[derive(Default)]
+struct StateFeed {
+    reading: Mutex<WeatherReading>,
+    cycle: AtomicU32,
+}
+
+/// Native API server on port 6053, one thread per client connection.
+///
+/// Clients subscribed to the states get every reading pushed by the measurement loop. The
+/// client threads answer the requests and keep the connection alive on their own, so a slow
+/// Home Assistant never delays a measurement.
+pub struct EsphomeApi {
+    feed: Arc<StateFeed>,
+}
+
+impl EsphomeApi {
+    pub fn push(&self, reading: &WeatherReading) {
+        *self.feed.reading.lock().unwrap() = *reading;
+        self.feed.cycle.fetch_add(1, Ordering::Release);
+    }
+}
+
+pub fn esphome_serve() -> Result<EsphomeApi> {
+    let psk = match CONFIG.esphome_key {
+        "" => None,
+        key => Some(decode_key(key)?),
+    };
+    let listener = TcpListener::bind(("0.0.0.0", API_PORT))?;
+    let feed = Arc::new(StateFeed::default());
+
+    let server_feed = feed.clone();
+    std::thread::Builder::new()
+        .stack_size(6000)
+        .spawn(move || {
+            log::info!(
+                "ESPHome API listening on port {API_PORT}{}",
+                if psk.is_some() {
+                    " with encryption"
+                } else {
+                    ""
+                }
+            );
+            for stream in listener.incoming().flatten() {
+                if ACTIVE_CLIENTS.fetch_add(1, Ordering::Relaxed) >= MAX_CLIENTS {
+                    ACTIVE_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                    log::warn!("ESPHome API client limit reached, dropping connection");
+                    continue;
+                }
+                let feed = server_feed.clone();
+                // The noise handshake needs a larger stack than a plain request loop
+                let spawned = std::thread::Builder::new().stack_size(8192).spawn(move || {
+                    handle_client(stream, psk, &feed)
+                        .unwrap_or_else(|e| log::warn!("ESPHome API client error: {e}"));
+                    ACTIVE_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                });
+                if spawned.is_err() {
+                    log::error!("Fail spawning ESPHome API client thread");
+                    ACTIVE_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        })?;
+
+    Ok(EsphomeApi { feed })
+}
+
+// `esphome_key` is the base64 pre-shared key of the ESPHome `api: encryption: key:` option
+fn decode_key(key: &str) -> Result<[u8; 32]> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(key)?;
+    match <[u8; 32]>::try_from(bytes) {
+        Ok(psk) => Ok(psk),
+        Err(_) => bail!("esphome_key must be 32 bytes encoded in base64"),
+    }
+}
+
+fn handle_client(stream: TcpStream, psk: Option<[u8; 32]>, feed: &StateFeed) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut conn = Connection::accept(stream, psk)?;
+    log::info!("ESPHome API client {peer} connected");
+
+    let name = node_name();
+    let mut subscribed = false;
+    let mut seen_cycle = 0;
+    let mut last_rx = Instant::now();
+    let mut ping_sent = false;
+    loop {
+        if let Some((msg_type, payload)) = conn.recv()? {
+            last_rx = Instant::now();
+            ping_sent = false;
+            match msg_type {
+                esphome::HELLO_REQUEST => {
+                    let client = esphome::string_field(&payload, 1).unwrap_or_default();
+                    log::info!("ESPHome API client {peer} is {client}");
+                    let server_info = format!("weather-station {}", env!("CARGO_PKG_VERSION"));
+                    conn.send(
+                        esphome::HELLO_RESPONSE,
+                        &esphome::hello_response(&name, &server_info),
+                    )?;
+                }
+                // No password, any connect succeeds
+                esphome::CONNECT_REQUEST => conn.send(esphome::CONNECT_RESPONSE, &[])?,
+                esphome::DISCONNECT_REQUEST => {
+                    conn.send(esphome::DISCONNECT_RESPONSE, &[])?;
+                    log::info!("ESPHome API client {peer} disconnected");
+                    return Ok(());
+                }
+                esphome::PING_REQUEST => conn.send(esphome::PING_RESPONSE, &[])?,
+                esphome::DEVICE_INFO_REQUEST => {
+                    let info = esphome::DeviceInfo {
+                        name: &name,
+                        friendly_name: station_id(),
+                        mac_address: &mac_address(),
+                        version: env!("CARGO_PKG_VERSION"),
+                        model: "ESP32",
+                        manufacturer: "Espressif",
+                        has_deep_sleep: true,
+                    };
+                    conn.send(esphome::DEVICE_INFO_RESPONSE, &info.to_message())?;
+                }
+                esphome::LIST_ENTITIES_REQUEST => {
+                    for sensor in &esphome::SENSORS {
+                        conn.send(
+                            esphome::LIST_ENTITIES_SENSOR_RESPONSE,
+                            &sensor.list_message(&name),
+                        )?;
+                    }
+                    conn.send(esphome::LIST_ENTITIES_DONE_RESPONSE, &[])?;
+                }
+                esphome::SUBSCRIBE_STATES_REQUEST => {
+                    // The latest reading goes out right away, if there is one yet
+                    subscribed = true;
+                    seen_cycle = 0;
+                }
+                // Answers to our keepalive and subscriptions the station has nothing for
+                _ => {}
+            }
+        }
+
+        let cycle = feed.cycle.load(Ordering::Acquire);
+        if subscribed && cycle != seen_cycle {
+            seen_cycle = cycle;
+            let reading = *feed.reading.lock().unwrap();
+            for sensor in &esphome::SENSORS {
+                conn.send(
+                    esphome::SENSOR_STATE_RESPONSE,
+                    &sensor.state_message(&reading),
+                )?;
+            }
+        }
+
+        if last_rx.elapsed() >= CLIENT_TIMEOUT {
+            bail!("{peer} stopped answering");
+        }
+        if last_rx.elapsed() >= KEEPALIVE && !ping_sent {
+            conn.send(esphome::PING_REQUEST, &[])?;
+            ping_sent = true;
+        }
+    }
+}
+
+enum Codec {
+    Plaintext,
+    Noise(Box<TransportState>),
+}
+
+struct Connection {
+    stream: TcpStream,
+    codec: Codec,
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    /// Runs the noise handshake when a key is configured. Plaintext clients are refused then,
+    /// and encrypted ones are refused without a key.
+    fn accept(stream: TcpStream, psk: Option<[u8; 32]>) -> Result<Self> {
+        stream.set_read_timeout(Some(FRAME_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let mut conn = Self {
+            stream,
+            codec: Codec::Plaintext,
+            buf: vec![0; NOISE_BUF_LEN],
+        };
+        let Some(psk) = psk else {
+            return Ok(conn);
+        };
+
+        let params = esphome::NOISE_PROTOCOL.parse()?;
+        let mut handshake = snow::Builder::new(params)
+            .prologue(esphome::NOISE_PROLOGUE)
+            .psk(0, &psk)
+            .build_responder()?;
+        // The client hello carries nothing, the server hello names the node
+        conn.read_noise_frame()?;
+        let hello = esphome::noise_server_hello(&node_name(), &mac_address());
+        conn.stream.write_all(&esphome::noise_frame(&hello))?;
+
+        match conn.noise_handshake(&mut handshake) {
+            Ok(()) => {
+                conn.codec = Codec::Noise(Box::new(handshake.into_transport_mode()?));
+                Ok(conn)
+            }
+            Err(e) => {
+                // Tells the client its key is wrong instead of just closing
+                let mut failure = vec![1];
+                failure.extend_from_slice(b"Handshake MAC failure");
+                conn.stream.write_all(&esphome::noise_frame(&failure)).ok();
+                Err(e)
+            }
+        }
+    }
+
+    fn noise_handshake(&mut self, handshake: &mut HandshakeState) -> Result<()> {
+        let frame = self.read_noise_frame()?;
+        let Some((&0, message)) = frame.split_first() else {
+            bail!("bad handshake frame");
+        };
+        handshake.read_message(message, &mut self.buf)?;
+        let len = handshake.write_message(&[], &mut self.buf)?;
+        let mut reply = vec![0];
+        reply.extend_from_slice(&self.buf[..len]);
+        self.stream.write_all(&esphome::noise_frame(&reply))?;
+        Ok(())
+    }
+
+    fn read_noise_frame(&mut self) -> Result<Vec<u8>> {
+        let mut indicator = [0u8];
+        self.stream.read_exact(&mut indicator)?;
+        if indicator[0] != esphome::NOISE_INDICATOR {
+            bail!("plaintext client while encryption is enabled");
+        }
+        Ok(esphome::read_noise_frame(&mut self.stream)?)
+    }
+
+    /// Next message, None when nothing arrived within the poll interval.
+    fn recv(&mut self) -> Result<Option<(u16, Vec<u8>)>> {
+        self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut indicator = [0u8];
+        match self.stream.read(&mut indicator) {
+            Ok(0) => bail!("connection closed"),
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        self.stream.set_read_timeout(Some(FRAME_TIMEOUT))?;
+
+        match &mut self.codec {
+            Codec::Plaintext if indicator[0] == esphome::PLAINTEXT_INDICATOR => {
+                Ok(Some(esphome::read_plaintext_frame(&mut self.stream)?))
+            }
+            Codec::Noise(transport) if indicator[0] == esphome::NOISE_INDICATOR => {
+                let frame = esphome::read_noise_frame(&mut self.stream)?;
+                let len = transport.read_message(&frame, &mut self.buf)?;
+                match esphome::parse_noise_message(&self.buf[..len]) {
+                    Some((msg_type, payload)) => Ok(Some((msg_type, payload.to_vec()))),
+                    None => bail!("bad encrypted message"),
+                }
+            }
+            Codec::Plaintext => bail!("encrypted client while no esphome_key is set"),
+            Codec::Noise(_) => bail!("plaintext client while encryption is enabled"),
+        }
+    }
+
+    fn send(&mut self, msg_type: u16, payload: &[u8]) -> Result<()> {
+        let frame = match &mut self.codec {
+            Codec::Plaintext => esphome::plaintext_frame(msg_type, payload),
+            Codec::Noise(transport) => {
+                let message = esphome::noise_message(msg_type, payload);
+                let len = transport.write_message(&message, &mut self.buf)?;
+                esphome::noise_frame(&self.buf[..len])
+            }
+        };
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+}
+
+// ESPHome node names are lowercase
+fn node_name() -> String {
+    station_id().to_lowercase()
+}
+
+fn mac_address() -> String {
+    let mut mac = [0u8; 6];
+    unsafe {
+        esp_efuse_mac_get_default(mac.as_mut_ptr());
+    }
+    mac.iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
@@ -0,0 +1,74 @@
+//! Values of the Bluetooth Environmental Sensing Service, read by phones over GATT.
+//!
+//! Each characteristic is a little endian integer with the unit and resolution of the GATT
+//! specification supplement, e.g. hundredths of °C for the temperature.
+use crate::reading::WeatherReading;
+
+pub const SERVICE_UUID: u16 = 0x181A;
+pub const TEMPERATURE_UUID: u16 = 0x2A6E;
+pub const HUMIDITY_UUID: u16 = 0x2A6F;
+pub const PRESSURE_UUID: u16 = 0x2A6D;
+pub const WIND_SPEED_UUID: u16 = 0x2A70;
+pub const WIND_DIRECTION_UUID: u16 = 0x2A71;
+pub const RAINFALL_UUID: u16 = 0x2A78;
+
+/// Encoded value of every characteristic of the service, in the order of `CHARACTERISTICS`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EssValues {
+    /// sint16, 0.01 °C
+    pub temperature: [u8; 2],
+    /// uint16, 0.01 %
+    pub humidity: [u8; 2],
+    /// uint32, 0.1 Pa
+    pub pressure: [u8; 4],
+    /// True wind speed, uint16, 0.01 m/s
+    pub wind_speed: [u8; 2],
+    /// True wind direction, uint16, 0.01°
+    pub wind_direction: [u8; 2],
+    /// Rain since local midnight, uint16, mm
+    pub rainfall: [u8; 2],
+}
+
+/// Characteristic UUIDs of the service.
+pub const CHARACTERISTICS: [u16; 6] = [
+    TEMPERATURE_UUID,
+    HUMIDITY_UUID,
+    PRESSURE_UUID,
+    WIND_SPEED_UUID,
+    WIND_DIRECTION_UUID,
+    RAINFALL_UUID,
+];
+
+impl EssValues {
+    pub fn from_reading(reading: &WeatherReading) -> Self {
+        // f32::round needs std
+        let scaled =
+            |value: f32, factor: f32, max: f32| libm::roundf(value * factor).clamp(0.0, max);
+        let temperature = libm::roundf(reading.temperature * 100.0)
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        Self {
+            temperature: temperature.to_le_bytes(),
+            humidity: (scaled(reading.humidity, 100.0, 10_000.0) as u16).to_le_bytes(),
+            // hPa to 0.1 Pa
+            pressure: (scaled(reading.pressure, 1000.0, u32::MAX as f32) as u32).to_le_bytes(),
+            wind_speed: (scaled(reading.wind_speed_kmh / 3.6, 100.0, u16::MAX as f32) as u16)
+                .to_le_bytes(),
+            wind_direction: (scaled(reading.wind_direction_deg % 360.0, 100.0, 35_999.0) as u16)
+                .to_le_bytes(),
+            rainfall: (scaled(reading.rain_totals.today_mm, 1.0, u16::MAX as f32) as u16)
+                .to_le_bytes(),
+        }
+    }
+
+    /// Values in the order of `CHARACTERISTICS`.
+    pub fn values(&self) -> [&[u8]; 6] {
+        [
+            &self.temperature,
+            &self.humidity,
+            &self.pressure,
+            &self.wind_speed,
+            &self.wind_direction,
+            &self.rainfall,
+        ]
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    eth::{BlockingEth, EspEth, EthDriver, SpiEth, SpiEthChipset},
+    eventloop::EspSystemEventLoop,
+    hal::{
+        gpio::{AnyIOPin, AnyInputPin, AnyOutputPin},
+        spi::{SpiDriver, SpiDriverConfig, SPI2},
+        units::Hertz,
+    },
+    netif::{EspNetif, NetifConfiguration},
+    sys::{esp_mac_type_t_ESP_MAC_ETH, esp_read_mac},
+};
+
+use crate::network::fixed_ip;
+use crate::provisioning::CONFIG;
+
+pub type EthLink = BlockingEth<EspEth<'static, SpiEth<SpiDriver<'static>>>>;
+
+const W5500_CLOCK: Hertz = Hertz(20_000_000);
+
+/// Creates the W5500 driver and its netif, DHCP unless `eth_static_ip` is set.
+pub fn eth_init(spi: SPI2, sys_loop: EspSystemEventLoop) -> Result<EthLink> {
+    // Pins come from the config, they are not claimed by any other driver
+    let (sclk, mosi, miso, cs, int) = unsafe {
+        (
+            AnyIOPin::new(CONFIG.eth_sclk_gpio),
+            AnyIOPin::new(CONFIG.eth_mosi_gpio),
+            AnyIOPin::new(CONFIG.eth_miso_gpio),
+            AnyOutputPin::new(CONFIG.eth_cs_gpio),
+            AnyInputPin::new(CONFIG.eth_int_gpio),
+        )
+    };
+    let rst = (CONFIG.eth_rst_gpio >= 0).then(|| unsafe { AnyOutputPin::new(CONFIG.eth_rst_gpio) });
+    let spi = SpiDriver::new(spi, sclk, mosi, Some(miso), &SpiDriverConfig::new())?;
+
+    // The W5500 has no MAC of its own, it gets the one reserved for Ethernet in the efuses
+    let mut mac = [0u8; 6];
+    unsafe { esp_read_mac(mac.as_mut_ptr(), esp_mac_type_t_ESP_MAC_ETH) };
+
+    let driver = EthDriver::new_spi(
+        spi,
+        int,
+        Some(cs),
+        rst,
+        SpiEthChipset::W5500,
+        W5500_CLOCK,
+        Some(&mac),
+        None,
+        sys_loop.clone(),
+    )?;
+    let eth = if CONFIG.eth_static_ip.is_empty() {
+        EspEth::wrap(driver)?
+    } else {
+        EspEth::wrap_all(driver, static_netif()?)?
+    };
+
+    Ok(BlockingEth::wrap(eth, sys_loop)?)
+}
+
+fn static_netif() -> Result<EspNetif> {
+    Ok(EspNetif::new_with_conf(&NetifConfiguration {
+        ip_configuration: Some(fixed_ip(
+            CONFIG.eth_static_ip,
+            CONFIG.eth_gateway,
+            CONFIG.eth_netmask_bits,
+            CONFIG.eth_dns,
+        )?),
+        ..NetifConfiguration::eth_default_client()
+    })?)
+}
@@ -0,0 +1,131 @@
+//! Pressure tendency and the Zambretti short term forecast.
+//!
+//! The Zambretti forecaster picks one of 26 forecasts from the sea level pressure, its
+//! tendency over the last 3 hours, the wind direction and the season. It is meant for the
+//! next 12 hours and works best in temperate latitudes.
+use serde::Serialize;
+
+// Below this change over 3 hours the pressure is steady
+const STEADY_HPA_PER_H: f32 = 1.6 / 3.0;
+// Pressure range of the forecast table, split in 22 steps
+const BARO_TOP_HPA: f32 = 1050.0;
+const BARO_BOTTOM_HPA: f32 = 950.0;
+const BARO_STEPS: usize = 22;
+// Seasonal correction, in percent of the range
+const SEASON_PERCENT: f32 = 7.0;
+
+const FORECASTS: [&str; 26] = [
+    "Settled fine",
+    "Fine weather",
+    "Becoming fine",
+    "Fine, becoming less settled",
+    "Fine, possible showers",
+    "Fairly fine, improving",
+    "Fairly fine, possible showers early",
+    "Fairly fine, showery later",
+    "Showery early, improving",
+    "Changeable, mending",
+    "Fairly fine, showers likely",
+    "Rather unsettled, clearing later",
+    "Unsettled, probably improving",
+    "Showery, bright intervals",
+    "Showery, becoming less settled",
+    "Changeable, some rain",
+    "Unsettled, short fine intervals",
+    "Unsettled, rain later",
+    "Unsettled, some rain",
+    "Mostly very unsettled",
+    "Occasional rain, worsening",
+    "Rain at times, very unsettled",
+    "Rain at frequent intervals",
+    "Rain, very unsettled",
+    "Stormy, may improve",
+    "Stormy, much rain",
+];
+// Forecast of each pressure step, from the lowest pressure up
+const RISING: [u8; BARO_STEPS] = [
+    25, 25, 25, 24, 24, 19, 16, 12, 11, 9, 8, 6, 5, 2, 1, 1, 0, 0, 0, 0, 0, 0,
+];
+const STEADY: [u8; BARO_STEPS] = [
+    25, 25, 25, 25, 25, 25, 23, 23, 22, 18, 15, 13, 10, 4, 1, 1, 0, 0, 0, 0, 0, 0,
+];
+const FALLING: [u8; BARO_STEPS] = [
+    25, 25, 25, 25, 25, 25, 25, 25, 23, 23, 21, 20, 17, 14, 7, 3, 1, 1, 1, 0, 0, 0,
+];
+// Pressure correction of the 16 compass points from north, in percent of the range, for the
+// northern hemisphere. Northerly winds bring better weather than southerly ones.
+const WIND_PERCENT: [f32; 16] = [
+    6.0, 5.0, 5.0, 2.0, -0.5, -2.0, -5.0, -8.5, -12.0, -10.0, -6.0, -4.5, -3.0, -0.5, 1.5, 3.0,
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PressureTendency {
+    Rising,
+    #[default]
+    Steady,
+    Falling,
+}
+
+impl PressureTendency {
+    pub fn from_rate(hpa_per_h: f32) -> Self {
+        if hpa_per_h >= STEADY_HPA_PER_H {
+            PressureTendency::Rising
+        } else if hpa_per_h <= -STEADY_HPA_PER_H {
+            PressureTendency::Falling
+        } else {
+            PressureTendency::Steady
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PressureTendency::Rising => "rising",
+            PressureTendency::Steady => "steady",
+            PressureTendency::Falling => "falling",
+        }
+    }
+}
+
+/// April to September in the northern hemisphere, October to March in the southern one.
+pub fn is_summer(month: u32, southern_hemisphere: bool) -> bool {
+    (4..=9).contains(&month) != southern_hemisphere
+}
+
+/// Zambretti forecast. `wind_direction_deg` is None in a calm, the direction then tells
+/// nothing.
+pub fn zambretti(
+    sea_level_hpa: f32,
+    tendency: PressureTendency,
+    wind_direction_deg: Option<f32>,
+    summer: bool,
+    southern_hemisphere: bool,
+) -> &'static str {
+    let range = BARO_TOP_HPA - BARO_BOTTOM_HPA;
+    let mut pressure = sea_level_hpa;
+    if let Some(direction) = wind_direction_deg {
+        // The table is for the northern hemisphere, south is north down under
+        let flip = if southern_hemisphere { 180.0 } else { 0.0 };
+        let direction = (direction + flip) % 360.0;
+        let direction = if direction < 0.0 {
+            direction + 360.0
+        } else {
+            direction
+        };
+        let point = ((direction / 22.5 + 0.5) as usize) % 16;
+        pressure += WIND_PERCENT[point] / 100.0 * range;
+    }
+    match tendency {
+        PressureTendency::Rising if summer => pressure += SEASON_PERCENT / 100.0 * range,
+        PressureTendency::Falling if !summer => pressure -= SEASON_PERCENT / 100.0 * range,
+        _ => {}
+    }
+    let step = ((pressure - BARO_BOTTOM_HPA) / (range / BARO_STEPS as f32))
+        .clamp(0.0, (BARO_STEPS - 1) as f32) as usize;
+    let table = match tendency {
+        PressureTendency::Rising => &RISING,
+        PressureTendency::Steady => &STEADY,
+        PressureTendency::Falling => &FALLING,
+    };
+    FORECASTS[table[step] as usize]
+}
@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Result};
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use weather_station::{node_frame::*, reading::*, runtime::RUNTIME, time::unix_time_ms};
+
+use crate::mqtt;
+use crate::provisioning::CONFIG;
+use crate::transport::format_mac;
+
+pub type SharedNodeTable = Arc<Mutex<NodeTable>>;
+
+struct Node {
+    mac: [u8; 6],
+    id: String,
+    reading: Option<WeatherReading>,
+    // Latest sensor frame, of nodes that do not measure the weather
+    sensors: Option<SensorFrame>,
+    rssi: i8,
+    last_seen: Option<Instant>,
+    last_seen_ms: u64,
+    // New frame not yet republished
+    pending: bool,
+    online: bool,
+}
+
+/// Battery nodes allowed to report through this gateway, with their latest frame.
+pub struct NodeTable {
+    nodes: Vec<Node>,
+}
+
+impl NodeTable {
+    /// `peers` is a comma separated list of MAC addresses, e.g. `24:6F:28:AA:BB:CC`.
+    pub fn new(peers: &str) -> Result<Self> {
+        let nodes = peers
+            .split(',')
+            .filter(|peer| !peer.trim().is_empty())
+            .map(|peer| {
+                let mac =
+                    parse_mac(peer.trim()).ok_or_else(|| anyhow!("invalid gateway peer {peer}"))?;
+                Ok(Node {
+                    mac,
+                    id: node_id(&mac),
+                    reading: None,
+                    sensors: None,
+                    rssi: 0,
+                    last_seen: None,
+                    last_seen_ms: 0,
+                    pending: false,
+                    online: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { nodes })
+    }
+
+    pub fn is_allowed(&self, mac: &[u8; 6]) -> bool {
+        self.nodes.iter().any(|node| node.mac == *mac)
+    }
+
+    /// Runs in the ESP-NOW receive callback, publishing is left to the main loop.
+    pub fn handle_frame(&mut self, mac: &[u8; 6], rssi: i8, frame: &[u8]) {
+        let Some(node) = self.nodes.iter_mut().find(|node| node.mac == *mac) else {
+            return;
+        };
+        let decoded = match frame.first() {
+            Some(&SENSOR_FRAME_VERSION) => {
+                SensorFrame::from_bytes(frame).map(|sensors| node.sensors = Some(sensors))
+            }
+            _ => WeatherReading::from_node_frame(frame).map(|reading| node.reading = Some(reading)),
+        };
+        match decoded {
+            Ok(()) => {
+                node.rssi = rssi;
+                node.last_seen = Some(Instant::now());
+                node.last_seen_ms = unix_time_ms();
+                node.pending = true;
+            }
+            Err(e) => log::warn!("Dropping frame from node {}: {e:?}", node.id),
+        }
+    }
+}
+
+/// Publishes the availability changes, and the node readings when `readings` is set.
+///
+/// Without `gateway_merge` the new frames are republished as they arrive. With it they are
+/// published with the Environment group of the station, the latest frame of every online
+/// node each time.
+pub fn publish_nodes(mqtt_cli: &mut EspMqttClient, table: &SharedNodeTable, readings: bool) {
+    let offline_after = Duration::from_secs(CONFIG.gateway_offline_s as u64);
+    let mut table = table.lock().unwrap();
+    for node in table.nodes.iter_mut() {
+        let online = node
+            .last_seen
+            .is_some_and(|seen| seen.elapsed() < offline_after);
+        if online != node.online {
+            node.online = online;
+            log::info!(
+                "Node {} is {}",
+                node.id,
+                if online { "online" } else { "offline" }
+            );
+            publish_node(
+                mqtt_cli,
+                &node.id,
+                "availability",
+                if online { "online" } else { "offline" },
+                true,
+            );
+        }
+        let due = if CONFIG.gateway_merge {
+            node.online
+        } else {
+            node.pending
+        };
+        if !readings || !due {
+            continue;
+        }
+        node.pending = false;
+        let mut payload = String::from("{");
+        if let Some(reading) = node.reading {
+            payload += &format!(
+                "\"reading\": {}, ",
+                reading.to_json_fields(RUNTIME.fields())
+            );
+        }
+        if let Some(sensors) = &node.sensors {
+            payload += &format!("\"sensors\": {}, ", sensors.to_json());
+        }
+        payload += &format!(
+            "\"rssi\": {}, \"last_seen\": {}, \"mac\": \"{}\"}}",
+            node.rssi,
+            node.last_seen_ms,
+            format_mac(&node.mac)
+        );
+        publish_node(mqtt_cli, &node.id, "state", &payload, false);
+    }
+}
+
+fn publish_node(mqtt_cli: &mut EspMqttClient, id: &str, name: &str, payload: &str, retain: bool) {
+    let topic = format!("{}/{id}/{name}", CONFIG.gateway_prefix);
+    mqtt::publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        retain,
+        payload.as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing node {id} {name}: {e}"))
+    .ok();
+}
+
+// Same scheme as the station id, so a node is known by the id it would use itself
+fn node_id(mac: &[u8; 6]) -> String {
+    format!("ws-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5])
+}
+
+fn parse_mac(text: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut parts = text.split(':');
+    for byte in mac.iter_mut() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
+}
@@ -0,0 +1,385 @@
+//! Home Assistant MQTT discovery: one retained config message per sensor on
+//! `<prefix>/sensor/<station_id>/<object_id>/config`, all attached to a single device.
+//!
+//! The state topics depend on the publish mode. The consolidated `<topic>/state` is read
+//! through value templates, the separate group topics mostly carry the bare value.
+use crate::runtime::Field;
+use serde::Serialize;
+
+/// Topic under the station topic carrying a sensor when the groups are published separately.
+pub struct SplitSource {
+    pub topic: &'static str,
+    /// Key of the value when the topic carries a JSON object
+    pub key: Option<&'static str>,
+    /// The value is a compass point rather than a number
+    pub cardinal: bool,
+}
+
+pub struct HaSensor {
+    pub object_id: &'static str,
+    pub name: &'static str,
+    /// Empty for none
+    pub unit: &'static str,
+    /// Home Assistant device class, empty for none
+    pub device_class: &'static str,
+    pub icon: &'static str,
+    /// Left out with this field, None when it is always published
+    pub field: Option<Field>,
+    /// Path of the value in the consolidated payload
+    pub state_path: &'static str,
+    /// None when only the consolidated payload carries it
+    pub split: Option<SplitSource>,
+    /// Only measured by a BME680
+    pub bme680: bool,
+    pub diagnostic: bool,
+}
+
+pub const SENSORS: [HaSensor; 15] = [
+    HaSensor {
+        object_id: "temperature",
+        name: "Temperature",
+        unit: "°C",
+        device_class: "temperature",
+        icon: "",
+        field: Some(Field::Temperature),
+        state_path: "temperature",
+        split: Some(SplitSource {
+            topic: "bme680",
+            key: Some("temperature"),
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "humidity",
+        name: "Humidity",
+        unit: "%",
+        device_class: "humidity",
+        icon: "",
+        field: Some(Field::Humidity),
+        state_path: "humidity",
+        split: Some(SplitSource {
+            topic: "bme680",
+            key: Some("humidity"),
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "pressure",
+        name: "Pressure",
+        unit: "hPa",
+        device_class: "atmospheric_pressure",
+        icon: "",
+        field: Some(Field::Pressure),
+        state_path: "pressure",
+        split: Some(SplitSource {
+            topic: "bme680",
+            key: Some("pressure"),
+            cardinal: false,
+        }),
+        bme680: true,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "sea_level_pressure",
+        name: "Sea level pressure",
+        unit: "hPa",
+        device_class: "atmospheric_pressure",
+        icon: "",
+        field: Some(Field::Pressure),
+        state_path: "sea_level_pressure",
+        split: Some(SplitSource {
+            topic: "bme680",
+            key: Some("sea_level_pressure"),
+            cardinal: false,
+        }),
+        bme680: true,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "gas_resistance",
+        name: "Gas resistance",
+        unit: "Ω",
+        device_class: "",
+        icon: "mdi:air-filter",
+        field: None,
+        state_path: "gas_resistance",
+        split: None,
+        bme680: true,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "iaq",
+        name: "Air quality index",
+        unit: "",
+        device_class: "aqi",
+        icon: "",
+        field: None,
+        state_path: "iaq.index",
+        split: Some(SplitSource {
+            topic: "iaq",
+            key: Some("index"),
+            cardinal: false,
+        }),
+        bme680: true,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "wind_speed",
+        name: "Wind speed",
+        unit: "km/h",
+        device_class: "wind_speed",
+        icon: "",
+        field: Some(Field::WindSpeed),
+        state_path: "wind_speed",
+        split: Some(SplitSource {
+            topic: "anemo/wind_speed",
+            key: None,
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "wind_gust",
+        name: "Wind gust",
+        unit: "km/h",
+        device_class: "wind_speed",
+        icon: "",
+        field: Some(Field::WindGust),
+        state_path: "wind_gust",
+        split: Some(SplitSource {
+            topic: "anemo/wind_gust",
+            key: None,
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "dew_point",
+        name: "Dew point",
+        unit: "°C",
+        device_class: "temperature",
+        icon: "",
+        field: Some(Field::Derived),
+        state_path: "dew_point",
+        split: Some(SplitSource {
+            topic: "bme680",
+            key: Some("dew_point"),
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "heat_index",
+        name: "Heat index",
+        unit: "°C",
+        device_class: "temperature",
+        icon: "",
+        field: Some(Field::Derived),
+        state_path: "heat_index",
+        split: Some(SplitSource {
+            topic: "bme680",
+            key: Some("heat_index"),
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "wind_chill",
+        name: "Wind chill",
+        unit: "°C",
+        device_class: "temperature",
+        icon: "",
+        field: Some(Field::Derived),
+        state_path: "wind_chill",
+        split: Some(SplitSource {
+            topic: "bme680",
+            key: Some("wind_chill"),
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "wind_direction",
+        name: "Wind direction",
+        unit: "°",
+        device_class: "",
+        icon: "mdi:compass-rose",
+        field: Some(Field::WindDirection),
+        state_path: "wind_direction",
+        split: Some(SplitSource {
+            topic: "anemo/wind_direction",
+            key: None,
+            cardinal: true,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    // Rain since the previous cycle
+    HaSensor {
+        object_id: "rain",
+        name: "Rain",
+        unit: "mm",
+        device_class: "precipitation",
+        icon: "",
+        field: Some(Field::Rain),
+        state_path: "rain",
+        split: Some(SplitSource {
+            topic: "rain",
+            key: None,
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "rain_today",
+        name: "Rain today",
+        unit: "mm",
+        device_class: "precipitation",
+        icon: "",
+        field: Some(Field::RainTotals),
+        state_path: "rain_totals.today",
+        split: Some(SplitSource {
+            topic: "rain/today",
+            key: None,
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: false,
+    },
+    HaSensor {
+        object_id: "wifi_rssi",
+        name: "WiFi signal",
+        unit: "dBm",
+        device_class: "signal_strength",
+        icon: "",
+        field: None,
+        state_path: "wifi.rssi",
+        split: Some(SplitSource {
+            topic: "wifi",
+            key: None,
+            cardinal: false,
+        }),
+        bme680: false,
+        diagnostic: true,
+    },
+];
+
+/// Station wide settings of the config messages.
+pub struct DiscoveryContext<'a> {
+    pub station_id: &'a str,
+    /// Station topic the states are published under
+    pub topic: &'a str,
+    pub split: bool,
+    /// `<topic>/availability` carries `online` or `maintenance`, next to `<topic>/status`
+    pub availability: bool,
+    pub sw_version: &'a str,
+}
+
+#[derive(Serialize)]
+struct HaDevice<'a> {
+    identifiers: [&'a str; 1],
+    name: &'a str,
+    model: &'a str,
+    sw_version: &'a str,
+}
+
+#[derive(Serialize)]
+struct HaAvailability<'a> {
+    topic: String,
+    payload_available: &'a str,
+    payload_not_available: &'a str,
+}
+
+#[derive(Serialize)]
+struct HaConfig<'a> {
+    name: &'a str,
+    unique_id: String,
+    state_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_template: Option<String>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    unit_of_measurement: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    device_class: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_class: Option<&'a str>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    icon: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity_category: Option<&'a str>,
+    availability: Vec<HaAvailability<'a>>,
+    // Available only when every topic says so
+    availability_mode: &'a str,
+    device: HaDevice<'a>,
+}
+
+impl HaSensor {
+    pub fn config_topic(&self, prefix: &str, station_id: &str) -> String {
+        format!("{prefix}/sensor/{station_id}/{}/config", self.object_id)
+    }
+
+    /// Config message, None when the sensor has no state topic in this publish mode.
+    pub fn config_payload(&self, ctx: &DiscoveryContext) -> Option<String> {
+        let (state_topic, value_template, cardinal) = if ctx.split {
+            let split = self.split.as_ref()?;
+            (
+                format!("{}/{}", ctx.topic, split.topic),
+                split.key.map(|key| format!("{{{{ value_json.{key} }}}}")),
+                split.cardinal,
+            )
+        } else {
+            (
+                format!("{}/state", ctx.topic),
+                Some(format!("{{{{ value_json.{} }}}}", self.state_path)),
+                false,
+            )
+        };
+        let config = HaConfig {
+            name: self.name,
+            unique_id: format!("{}_{}", ctx.station_id, self.object_id),
+            state_topic,
+            value_template,
+            // A compass point has no unit and cannot be graphed
+            unit_of_measurement: if cardinal { "" } else { self.unit },
+            device_class: self.device_class,
+            state_class: (!cardinal).then_some("measurement"),
+            icon: self.icon,
+            entity_category: self.diagnostic.then_some("diagnostic"),
+            availability: self.availability(ctx),
+            availability_mode: "all",
+            device: HaDevice {
+                identifiers: [ctx.station_id],
+                name: ctx.station_id,
+                model: "Weather station",
+                sw_version: ctx.sw_version,
+            },
+        };
+        serde_json::to_string(&config).ok()
+    }
+
+    fn availability<'a>(&self, ctx: &DiscoveryContext) -> Vec<HaAvailability<'a>> {
+        let mut availability = vec![HaAvailability {
+            topic: format!("{}/status", ctx.topic),
+            payload_available: "online",
+            payload_not_available: "offline",
+        }];
+        if ctx.availability {
+            availability.push(HaAvailability {
+                topic: format!("{}/availability", ctx.topic),
+                payload_available: "online",
+                payload_not_available: "maintenance",
+            });
+        }
+        availability
+    }
+}
@@ -0,0 +1,409 @@
+use anyhow::Result;
+use base64::Engine;
+use esp_idf_svc::{
+    hal::delay::FreeRtos,
+    http::{
+        server::{Configuration, EspHttpServer},
+        Method,
+    },
+    io::{Read, Write},
+    nvs::EspDefaultNvsPartition,
+    sys::{
+        esp_get_free_heap_size, esp_get_minimum_free_heap_size, esp_restart, esp_timer_get_time,
+    },
+};
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+use std::sync::{mpsc::Sender, Arc, Mutex};
+use weather_station::{
+    portal::{html_escape, parse_settings, FieldKind, SETTINGS_FIELDS},
+    reading::{interpolate_gap, ReadingPayload, TimedReading},
+    runtime::RUNTIME,
+    time::{iso8601_utc, synced_iso8601_utc},
+    CircularBuffer,
+};
+
+use crate::mqtt::MqttEvent;
+use crate::provisioning::{WeatherStationProvisioner, CONFIG};
+use crate::station_id;
+use crate::transport::WeatherNetwork;
+
+// Readings kept in RAM for the interpolation endpoint
+pub const HISTORY_LEN: usize = 64;
+const MAX_INTERPOLATION_STEPS: u32 = 100;
+// Far above what the settings form sends
+const MAX_FORM_LEN: usize = 2048;
+
+pub type ReadingHistory = Arc<Mutex<CircularBuffer<TimedReading, HISTORY_LEN>>>;
+
+pub fn http_server_create() -> Result<EspHttpServer<'static>> {
+    let server = EspHttpServer::new(&Configuration {
+        stack_size: 8192,
+        ..Default::default()
+    })?;
+    Ok(server)
+}
+
+pub fn register_network_endpoint(
+    server: &mut EspHttpServer<'static>,
+    network: Arc<Mutex<WeatherNetwork>>,
+) -> Result<()> {
+    server.fn_handler("/api/v1/network", Method::Get, move |req| {
+        let payload = network
+            .lock()
+            .map(|network| network.to_json())
+            .unwrap_or_else(|_| "[]".to_string());
+
+        let mut resp = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+        resp.write_all(payload.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+    Ok(())
+}
+
+// GET /api/v1/interpolate?t1=<ms>&t2=<ms>&steps=<n>
+pub fn register_interpolate_endpoint(
+    server: &mut EspHttpServer<'static>,
+    history: ReadingHistory,
+) -> Result<()> {
+    server.fn_handler("/api/v1/interpolate", Method::Get, move |req| {
+        let query = req.uri().split_once('?').map_or("", |(_, query)| query);
+        let t1 = query_param(query, "t1");
+        let t2 = query_param(query, "t2");
+        let steps = query_param(query, "steps").unwrap_or(10);
+
+        let (status, payload) = match (t1, t2) {
+            (Some(t1), Some(t2))
+                if t1 <= t2 && (1..=MAX_INTERPOLATION_STEPS as u64).contains(&steps) =>
+            {
+                let history = history.lock().unwrap();
+                match interpolate_gap(history.as_slice(), t1, t2, steps as u32) {
+                    Some(points) => (200, points_to_json(&points)),
+                    None => (
+                        404,
+                        "{\"error\": \"no readings around the requested gap\"}".to_string(),
+                    ),
+                }
+            }
+            _ => (
+                400,
+                "{\"error\": \"expected t1 <= t2 and 1 <= steps <= 100\"}".to_string(),
+            ),
+        };
+
+        let mut resp = req.into_response(status, None, &[("Content-Type", "application/json")])?;
+        resp.write_all(payload.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct CurrentPayload {
+    // Null when the clock was not synced at the time
+    time: Option<String>,
+    #[serde(flatten)]
+    reading: ReadingPayload,
+}
+
+#[derive(Serialize)]
+struct StatusPayload {
+    station_id: &'static str,
+    version: &'static str,
+    uptime_s: u64,
+    free_heap: u32,
+    min_free_heap: u32,
+    readings_kept: usize,
+}
+
+/// REST API for consumers that do not speak MQTT: `GET /api/v1/current` returns the latest
+/// reading with the published fields, `GET /api/v1/status` the state of the station, and
+/// `POST /api/v1/measure` measures and publishes every group right away.
+///
+/// With a `settings_password`, measuring needs the same basic auth as the settings page.
+pub fn register_api_endpoints(
+    server: &mut EspHttpServer<'static>,
+    history: ReadingHistory,
+    events: Sender<MqttEvent>,
+) -> Result<()> {
+    let latest = history.clone();
+    server.fn_handler("/api/v1/current", Method::Get, move |req| {
+        let (status, payload) = match latest.lock().unwrap().last() {
+            Some(timed) => (
+                200,
+                serde_json::to_string(&CurrentPayload {
+                    time: synced_iso8601_utc(timed.timestamp_ms),
+                    reading: timed.reading.payload(RUNTIME.fields()),
+                })?,
+            ),
+            None => (503, "{\"error\": \"no reading yet\"}".to_string()),
+        };
+
+        let mut resp = req.into_response(status, None, &[("Content-Type", "application/json")])?;
+        resp.write_all(payload.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+    server.fn_handler("/api/v1/status", Method::Get, move |req| {
+        let payload = serde_json::to_string(&StatusPayload {
+            station_id: station_id(),
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_s: unsafe { esp_timer_get_time() } as u64 / 1_000_000,
+            free_heap: unsafe { esp_get_free_heap_size() },
+            min_free_heap: unsafe { esp_get_minimum_free_heap_size() },
+            readings_kept: history.lock().unwrap().len(),
+        })?;
+
+        let mut resp = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+        resp.write_all(payload.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+    server.fn_handler("/api/v1/measure", Method::Post, move |req| {
+        if !CONFIG.settings_password.is_empty() && !authorized(req.header("Authorization")) {
+            req.into_response(
+                401,
+                None,
+                &[("WWW-Authenticate", "Basic realm=\"station\"")],
+            )?;
+            return Ok(());
+        }
+        let queued = events.send(MqttEvent::Measure).is_ok();
+        let (status, payload) = if queued {
+            (202, "{\"status\": \"measuring\"}")
+        } else {
+            (503, "{\"error\": \"the main loop is not running\"}")
+        };
+
+        let mut resp = req.into_response(status, None, &[("Content-Type", "application/json")])?;
+        resp.write_all(payload.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+    Ok(())
+}
+
+/// Settings page on `/`: the latest reading and a form for the network, broker, intervals and
+/// calibration. Saved settings are merged into the NVS config like a `cmd/config` update and
+/// applied by rebooting, so the station can be fixed on site while the broker is unreachable.
+///
+/// Protected by HTTP basic auth with `settings_password`, any user name is accepted.
+pub fn register_settings_page(
+    server: &mut EspHttpServer<'static>,
+    history: ReadingHistory,
+    nvs: EspDefaultNvsPartition,
+) -> Result<()> {
+    server.fn_handler("/", Method::Get, move |req| {
+        if !authorized(req.header("Authorization")) {
+            req.into_response(
+                401,
+                None,
+                &[("WWW-Authenticate", "Basic realm=\"station\"")],
+            )?;
+            return Ok(());
+        }
+        let latest = history.lock().unwrap().last().copied();
+        let mut resp = req.into_response(200, None, &[("Content-Type", "text/html")])?;
+        resp.write_all(settings_page(&format!("{}{}", readings(latest), form())).as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+    server.fn_handler("/settings", Method::Post, move |mut req| {
+        if !authorized(req.header("Authorization")) {
+            req.into_response(
+                401,
+                None,
+                &[("WWW-Authenticate", "Basic realm=\"station\"")],
+            )?;
+            return Ok(());
+        }
+        let mut body = vec![];
+        let mut buf = [0u8; 256];
+        loop {
+            let len = req.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            // A cut form would store its last value truncated
+            if body.len() + len > MAX_FORM_LEN {
+                log::warn!("Settings page: form over {MAX_FORM_LEN} bytes refused");
+                let mut resp = req.into_response(413, None, &[("Content-Type", "text/html")])?;
+                resp.write_all(
+                    settings_page("<p>Settings too long.</p><p><a href=\"/\">Back</a></p>")
+                        .as_bytes(),
+                )?;
+                return Ok(());
+            }
+            body.extend_from_slice(&buf[..len]);
+        }
+        let outcome = parse_settings(&String::from_utf8_lossy(&body))
+            .map_err(anyhow::Error::msg)
+            .and_then(|fields| {
+                // Values shown in the form come back as they were
+                let changed: Map<String, Value> = fields
+                    .into_iter()
+                    .filter(|(field, text)| current_value(field.name).as_ref() != Some(text))
+                    .map(|(field, text)| (field.name.to_string(), json_value(field.kind, text)))
+                    .collect();
+                if changed.is_empty() {
+                    return Ok(false);
+                }
+                WeatherStationProvisioner::new(nvs.clone())?
+                    .store_update(&Value::Object(changed).to_string())
+            });
+        let (status, message) = match outcome {
+            Ok(true) => {
+                log::warn!("Settings page: config stored, rebooting to apply it");
+                // After the response is sent
+                std::thread::Builder::new()
+                    .stack_size(4096)
+                    .spawn(|| {
+                        FreeRtos::delay_ms(1000);
+                        unsafe { esp_restart() }
+                    })
+                    .map_err(|e| log::error!("Fail scheduling the reboot: {e}"))
+                    .ok();
+                (200, "Settings saved, the station reboots.".to_string())
+            }
+            Ok(false) => (200, "Nothing changed.".to_string()),
+            Err(e) => {
+                log::warn!("Settings page: invalid settings: {e}");
+                (
+                    400,
+                    format!("Invalid settings: {}", html_escape(&e.to_string())),
+                )
+            }
+        };
+        let mut resp = req.into_response(status, None, &[("Content-Type", "text/html")])?;
+        resp.write_all(
+            settings_page(&format!("<p>{message}</p><p><a href=\"/\">Back</a></p>")).as_bytes(),
+        )?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+    Ok(())
+}
+
+fn authorized(header: Option<&str>) -> bool {
+    let Some(credentials) = header
+        .and_then(|header| header.strip_prefix("Basic "))
+        .and_then(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .ok()
+        })
+    else {
+        return false;
+    };
+    String::from_utf8_lossy(&credentials)
+        .split_once(':')
+        .is_some_and(|(_, password)| password == CONFIG.settings_password)
+}
+
+// Stored value of a settings page field, None for the secrets
+fn current_value(name: &str) -> Option<String> {
+    let value = match name {
+        "wifi_ssid" => CONFIG.wifi_ssid.to_string(),
+        "broker_url" => CONFIG.broker_url.to_string(),
+        "mqtt_user" => CONFIG.mqtt_user.to_string(),
+        "topic" => CONFIG.topic.to_string(),
+        "wind_interval_s" => CONFIG.wind_interval_s.to_string(),
+        "env_interval_s" => CONFIG.env_interval_s.to_string(),
+        "rain_interval_s" => CONFIG.rain_interval_s.to_string(),
+        "diag_interval_s" => CONFIG.diag_interval_s.to_string(),
+        "mm_per_tip" => CONFIG.mm_per_tip.to_string(),
+        "anemo_kmh_per_hz" => CONFIG.anemo_kmh_per_hz.to_string(),
+        "bme680_temp_offset" => CONFIG.bme680_temp_offset.to_string(),
+        "bme680_humidity_offset" => CONFIG.bme680_humidity_offset.to_string(),
+        "bme680_pressure_offset" => CONFIG.bme680_pressure_offset.to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// The text was checked against the kind by `parse_settings`
+fn json_value(kind: FieldKind, text: String) -> Value {
+    match kind {
+        FieldKind::Text | FieldKind::Secret => Value::String(text),
+        FieldKind::Unsigned => text.parse::<u32>().map(Value::from).unwrap_or(Value::Null),
+        FieldKind::Float => text
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map_or(Value::Null, Value::Number),
+    }
+}
+
+fn settings_page(body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+         <title>{id}</title></head><body><h1>{id}</h1>{body}</body></html>",
+        id = station_id()
+    )
+}
+
+fn readings(latest: Option<TimedReading>) -> String {
+    let Some(TimedReading {
+        timestamp_ms,
+        reading,
+    }) = latest
+    else {
+        return "<p>No reading yet.</p>".to_string();
+    };
+    let rows = [
+        ("Temperature", format!("{:.1} °C", reading.temperature)),
+        ("Humidity", format!("{:.0} %", reading.humidity)),
+        ("Pressure", format!("{:.1} hPa", reading.pressure)),
+        ("Wind", format!("{:.1} km/h", reading.wind_speed_kmh)),
+        ("Gust", format!("{:.1} km/h", reading.wind_gust_kmh)),
+        ("Direction", format!("{:.0}°", reading.wind_direction_deg)),
+        ("Rain", format!("{:.1} mm", reading.rain_mm)),
+    ];
+    let rows: String = rows
+        .iter()
+        .map(|(label, value)| format!("<tr><td>{label}</td><td>{value}</td></tr>"))
+        .collect();
+    format!(
+        "<h2>Latest reading</h2><p>{}</p><table>{rows}</table>",
+        iso8601_utc(timestamp_ms)
+    )
+}
+
+// Secrets are never sent back, an empty field keeps the current value
+fn form() -> String {
+    let inputs: String = SETTINGS_FIELDS
+        .iter()
+        .map(|field| {
+            let input = match field.kind {
+                FieldKind::Secret => format!("<input name=\"{}\" type=\"password\">", field.name),
+                _ => format!(
+                    "<input name=\"{}\" value=\"{}\">",
+                    field.name,
+                    html_escape(&current_value(field.name).unwrap_or_default())
+                ),
+            };
+            format!("<p><label>{}<br>{input}</label></p>", field.label)
+        })
+        .collect();
+    format!(
+        "<h2>Settings</h2><form method=\"post\" action=\"/settings\">{inputs}\
+         <p><button>Save</button></p></form><p>Empty fields keep their current value.</p>"
+    )
+}
+
+fn query_param(query: &str, name: &str) -> Option<u64> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+fn points_to_json(points: &[TimedReading]) -> String {
+    let entries: Vec<String> = points
+        .iter()
+        .map(|p| {
+            format!(
+                "{{\"timestamp\": {}, \"reading\": {}}}",
+                p.timestamp_ms,
+                p.reading.to_json()
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(", "))
+}
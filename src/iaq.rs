@@ -0,0 +1,109 @@
+//! Indoor air quality estimate from the BME680 gas resistance.
+//!
+//! The gas resistance drops as volatile compounds rise, but its absolute value differs from
+//! one sensor to the next. It is compared with a baseline, the resistance of the cleanest air
+//! seen lately, and combined with the distance of the humidity from its ideal. The score maps
+//! onto the 0 to 500 IAQ scale of Bosch, where lower is better.
+use serde::Serialize;
+
+// Ideal relative humidity, and the share of the score it weighs
+const HUMIDITY_BASELINE: f32 = 40.0;
+const HUMIDITY_WEIGHT: f32 = 25.0;
+// The baseline drifts down to a lower resistance over this time, following the sensor ageing
+const BASELINE_DECAY_S: f32 = 3.0 * 24.0 * 3600.0;
+// Observation time before the baseline is trusted
+pub const CALIBRATION_S: u32 = 24 * 3600;
+// Upper bounds of the levels, the last one is open
+const LEVELS: [(u16, &str); 6] = [
+    (50, "excellent"),
+    (100, "good"),
+    (150, "lightly_polluted"),
+    (200, "moderately_polluted"),
+    (250, "heavily_polluted"),
+    (350, "severely_polluted"),
+];
+
+pub const IAQ_BASELINE_VERSION: u8 = 1;
+pub const IAQ_BASELINE_LEN: usize = 1 + 4 + 4;
+
+/// Air quality of one measurement.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct AirQuality {
+    /// 0 (clean) to 500
+    pub index: u16,
+    pub level: &'static str,
+    /// The baseline was observed for less than `CALIBRATION_S`
+    pub calibrating: bool,
+}
+
+/// Rolling gas resistance baseline, kept across reboots.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IaqBaseline {
+    /// 0 before the first measurement
+    pub gas_ohm: f32,
+    pub observed_s: u32,
+}
+
+impl IaqBaseline {
+    /// Folds a measurement into the baseline and rates it.
+    pub fn update(&mut self, gas_ohm: f32, humidity: f32, elapsed_s: f32) -> AirQuality {
+        if gas_ohm > self.gas_ohm {
+            // Cleaner air than ever seen, the baseline follows at once
+            self.gas_ohm = gas_ohm;
+        } else {
+            let decay = (elapsed_s / BASELINE_DECAY_S).clamp(0.0, 1.0);
+            self.gas_ohm += (gas_ohm - self.gas_ohm) * decay;
+        }
+        self.observed_s = self.observed_s.saturating_add(elapsed_s as u32);
+        let index = iaq_index(gas_ohm, self.gas_ohm, humidity);
+        AirQuality {
+            index,
+            level: iaq_level(index),
+            calibrating: self.observed_s < CALIBRATION_S,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; IAQ_BASELINE_LEN] {
+        let mut bytes = [0u8; IAQ_BASELINE_LEN];
+        bytes[0] = IAQ_BASELINE_VERSION;
+        bytes[1..5].copy_from_slice(&self.gas_ohm.to_le_bytes());
+        bytes[5..9].copy_from_slice(&self.observed_s.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != IAQ_BASELINE_LEN || bytes[0] != IAQ_BASELINE_VERSION {
+            return None;
+        }
+        Some(Self {
+            gas_ohm: f32::from_le_bytes(bytes[1..5].try_into().ok()?),
+            observed_s: u32::from_le_bytes(bytes[5..9].try_into().ok()?),
+        })
+    }
+}
+
+/// IAQ index of a gas resistance against the baseline, at a relative humidity.
+pub fn iaq_index(gas_ohm: f32, baseline_ohm: f32, humidity: f32) -> u16 {
+    let humidity_offset = humidity - HUMIDITY_BASELINE;
+    let humidity_score = if humidity_offset > 0.0 {
+        (100.0 - HUMIDITY_BASELINE - humidity_offset) / (100.0 - HUMIDITY_BASELINE)
+    } else {
+        (HUMIDITY_BASELINE + humidity_offset) / HUMIDITY_BASELINE
+    } * HUMIDITY_WEIGHT;
+    let gas_weight = 100.0 - HUMIDITY_WEIGHT;
+    let gas_score = if baseline_ohm > 0.0 && gas_ohm < baseline_ohm {
+        gas_ohm / baseline_ohm * gas_weight
+    } else {
+        gas_weight
+    };
+    // Score 100 is the cleanest air, index 0
+    let score = (humidity_score.clamp(0.0, HUMIDITY_WEIGHT) + gas_score.max(0.0)).min(100.0);
+    ((100.0 - score) * 5.0) as u16
+}
+
+pub fn iaq_level(index: u16) -> &'static str {
+    LEVELS
+        .iter()
+        .find(|(limit, _)| index <= *limit)
+        .map_or("extremely_polluted", |(_, level)| level)
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use std::time::{Duration, Instant};
+use weather_station::iaq::*;
+
+const NVS_NAMESPACE: &str = "iaq";
+const NVS_KEY: &str = "baseline";
+// Saved this often while awake, and before deep sleep
+const SAVE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Gas resistance baseline of the IAQ estimate, persisted in NVS so the calibration survives
+/// resets and deep sleep.
+pub struct IaqStore {
+    nvs: EspNvs<NvsDefault>,
+    baseline: IaqBaseline,
+    last_update: Option<Instant>,
+    last_save: Instant,
+}
+
+impl IaqStore {
+    pub fn load(nvs: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+        // Sized from the stored record, other schema versions may have another length
+        let mut buf = vec![0u8; nvs.blob_len(NVS_KEY)?.unwrap_or_default()];
+        let baseline = match nvs.get_blob(NVS_KEY, &mut buf)? {
+            Some(bytes) => IaqBaseline::from_bytes(bytes).unwrap_or_else(|| {
+                log::warn!(
+                    "Unknown IAQ baseline schema {:?}, starting over",
+                    bytes.first()
+                );
+                IaqBaseline::default()
+            }),
+            None => IaqBaseline::default(),
+        };
+
+        Ok(Self {
+            nvs,
+            baseline,
+            last_update: None,
+            last_save: Instant::now(),
+        })
+    }
+
+    /// Rates a measurement. Only the time between measurements of this boot counts towards
+    /// the calibration.
+    pub fn update(&mut self, gas_ohm: f32, humidity: f32) -> AirQuality {
+        let elapsed = self
+            .last_update
+            .map_or(Duration::ZERO, |last| last.elapsed());
+        self.last_update = Some(Instant::now());
+        let quality = self
+            .baseline
+            .update(gas_ohm, humidity, elapsed.as_secs_f32());
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.save();
+        }
+        quality
+    }
+
+    pub fn save(&mut self) {
+        self.last_save = Instant::now();
+        self.nvs
+            .set_blob(NVS_KEY, &self.baseline.to_bytes())
+            .map_err(|e| log::error!("fail storing the IAQ baseline: {e}"))
+            .ok();
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::{
+    http::{
+        client::{Configuration, EspHttpConnection},
+        Method,
+    },
+    io::Write,
+    sys::esp_crt_bundle_attach,
+};
+use std::time::Duration;
+use weather_station::{line_protocol::reading_line, runtime::RUNTIME, time::synced_iso8601_utc};
+
+use crate::http::ReadingHistory;
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+const MAX_ATTEMPTS: u32 = 4;
+const FIRST_BACKOFF: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Writes the readings taken since the last write to InfluxDB every `influx_interval_s`, as
+/// line protocol on `<influx_url>/api/v2/write`. Runs alongside MQTT.
+pub fn influx_start(history: ReadingHistory) -> Result<()> {
+    if CONFIG.influx_url.is_empty() || CONFIG.influx_bucket.is_empty() {
+        bail!("influx_url and influx_bucket are required");
+    }
+    let url = format!(
+        "{}/api/v2/write?org={}&bucket={}&precision=ms",
+        CONFIG.influx_url.trim_end_matches('/'),
+        CONFIG.influx_org,
+        CONFIG.influx_bucket
+    );
+    let authorization = format!("Token {}", CONFIG.influx_token);
+
+    std::thread::Builder::new()
+        .stack_size(8192)
+        .spawn(move || {
+            // Readings of the boot are written, not the ones of an earlier run
+            let mut last_written = 0;
+            loop {
+                std::thread::sleep(Duration::from_secs(CONFIG.influx_interval_s as u64));
+                let (body, newest) = {
+                    let history = history.lock().unwrap();
+                    let pending: Vec<_> = history
+                        .as_slice()
+                        .iter()
+                        .filter(|timed| timed.timestamp_ms > last_written)
+                        .collect();
+                    let Some(newest) = pending.last().map(|timed| timed.timestamp_ms) else {
+                        continue;
+                    };
+                    let lines: Vec<String> = pending
+                        .into_iter()
+                        .filter_map(|timed| {
+                            // Left to the server when the clock was not set yet
+                            let timestamp =
+                                synced_iso8601_utc(timed.timestamp_ms).map(|_| timed.timestamp_ms);
+                            reading_line(
+                                CONFIG.influx_measurement,
+                                station_id(),
+                                timed,
+                                RUNTIME.fields(),
+                                timestamp,
+                            )
+                        })
+                        .collect();
+                    (lines.join("\n"), newest)
+                };
+                // Dropped after the last attempt, like the uploads
+                if !body.is_empty() {
+                    write(&url, &authorization, &body);
+                }
+                last_written = newest;
+            }
+        })?;
+    log::info!(
+        "Writing readings to InfluxDB bucket {}",
+        CONFIG.influx_bucket
+    );
+
+    Ok(())
+}
+
+fn write(url: &str, authorization: &str, body: &str) {
+    let mut backoff = FIRST_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        match send(url, authorization, body) {
+            Ok(200..=299) => return,
+            Ok(status @ 400..=499) => {
+                log::error!("InfluxDB write rejected with status {status}");
+                return;
+            }
+            Ok(status) => {
+                log::warn!("InfluxDB write {attempt}/{MAX_ATTEMPTS} failed: status {status}")
+            }
+            Err(e) => log::warn!("InfluxDB write {attempt}/{MAX_ATTEMPTS} failed: {e}"),
+        }
+        if attempt < MAX_ATTEMPTS {
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+fn send(url: &str, authorization: &str, body: &str) -> Result<u16> {
+    let mut conn = EspHttpConnection::new(&Configuration {
+        timeout: Some(REQUEST_TIMEOUT),
+        crt_bundle_attach: Some(esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+
+    let content_length = body.len().to_string();
+    let headers = [
+        ("Authorization", authorization),
+        ("Content-Type", "text/plain; charset=utf-8"),
+        ("Content-Length", content_length.as_str()),
+    ];
+    conn.initiate_request(Method::Post, url, &headers)?;
+    conn.write_all(body.as_bytes())?;
+    conn.initiate_response()?;
+
+    Ok(conn.status())
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use esp_idf_svc::sys::{esp_deep_sleep_start, esp_sleep_enable_timer_wakeup};
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+use weather_station::{runtime::Backoff, sensors::Sensor};
+
+// Attempts of a subsystem at boot, degraded sensors are retried by the main loop afterwards
+pub const BOOT_ATTEMPTS: u32 = 3;
+const BOOT_RETRY_FIRST: Duration = Duration::from_millis(500);
+const BOOT_RETRY_MAX: Duration = Duration::from_secs(4);
+const SENSOR_RETRY_FIRST: Duration = Duration::from_secs(30);
+const SENSOR_RETRY_MAX: Duration = Duration::from_secs(30 * 60);
+// Deep sleep before another boot when the station cannot run at all
+const RESTART_FIRST_S: u32 = 30;
+const RESTART_MAX_S: u32 = 30 * 60;
+
+// Delay of the next restart after a failed boot, doubled by each one. Kept through deep sleep
+#[link_section = ".rtc.data"]
+static mut RESTART_DELAY_S: u32 = 0;
+
+/// Runs `setup` up to `attempts` times, with a growing delay between them.
+pub fn retry<T>(name: &str, attempts: u32, mut setup: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut backoff = Backoff::new(BOOT_RETRY_FIRST, BOOT_RETRY_MAX);
+    let mut attempt = 1;
+    loop {
+        match setup() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => {
+                let delay = backoff.next_delay();
+                log::warn!(
+                    "Fail setting up {name} ({attempt}/{attempts}), retrying in {}ms: {e}",
+                    delay.as_millis()
+                );
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Gives up on this boot when a subsystem the station cannot run without failed. The station
+/// sleeps and boots again, the delay grows with each failed boot.
+pub fn restart_later(name: &str, e: impl Display) -> ! {
+    // Only main touches the RTC static, there is no concurrent access
+    let delay_s = unsafe { &mut *std::ptr::addr_of_mut!(RESTART_DELAY_S) };
+    *delay_s = (*delay_s * 2).clamp(RESTART_FIRST_S, RESTART_MAX_S);
+    log::error!(
+        "Fail setting up {name}, booting again in {}s: {e}",
+        *delay_s
+    );
+    unsafe {
+        esp_sleep_enable_timer_wakeup(*delay_s as u64 * 1_000_000);
+        esp_deep_sleep_start();
+    }
+}
+
+/// The boot went through, the next failed one starts from the first delay again.
+pub fn boot_completed() {
+    unsafe { *std::ptr::addr_of_mut!(RESTART_DELAY_S) = 0 };
+}
+
+/// Sensor whose setup failed at boot. It is published as degraded and set up again by the
+/// main loop, with a growing delay between the attempts.
+pub struct PendingSensor<'a> {
+    name: &'static str,
+    setup: Box<dyn FnMut() -> Result<Box<dyn Sensor + 'a>> + 'a>,
+    backoff: Backoff,
+    retry_at: Instant,
+}
+
+impl<'a> PendingSensor<'a> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The sensor once its setup succeeds, None until then or before the retry is due.
+    pub fn retry(&mut self) -> Option<Box<dyn Sensor + 'a>> {
+        if Instant::now() < self.retry_at {
+            return None;
+        }
+        match (self.setup)() {
+            Ok(sensor) => {
+                log::info!("{} sensor recovered", self.name);
+                Some(sensor)
+            }
+            Err(e) => {
+                let delay = self.backoff.next_delay();
+                log::warn!(
+                    "Fail setting up {} sensor, retrying in {}s: {e}",
+                    self.name,
+                    delay.as_secs()
+                );
+                self.retry_at = Instant::now() + delay;
+                None
+            }
+        }
+    }
+}
+
+/// Sets up a sensor with `BOOT_ATTEMPTS` attempts, or hands it to the main loop for later.
+pub fn start_sensor<'a>(
+    name: &'static str,
+    mut setup: impl FnMut() -> Result<Box<dyn Sensor + 'a>> + 'a,
+) -> Result<Box<dyn Sensor + 'a>, PendingSensor<'a>> {
+    match retry(name, BOOT_ATTEMPTS, &mut setup) {
+        Ok(sensor) => Ok(sensor),
+        Err(e) => {
+            log::error!("Fail setting up {name} sensor, marked as degraded: {e}");
+            let mut backoff = Backoff::new(SENSOR_RETRY_FIRST, SENSOR_RETRY_MAX);
+            Err(PendingSensor {
+                name,
+                setup: Box::new(setup),
+                retry_at: Instant::now() + backoff.next_delay(),
+                backoff,
+            })
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Irrigation interlock: inhibits an irrigation controller after rain.
+
+/// Longest rain window the rule can look at, in hours.
+pub const MAX_WINDOW_H: usize = 24;
+/// Version, newest hour, last rain, last wet time, mode, then the hourly totals.
+pub const INTERLOCK_STATE_LEN: usize = 1 + 4 + 4 + 4 + 1 + MAX_WINDOW_H * 4;
+const STATE_VERSION: u8 = 1;
+
+/// Manual override set from the command topic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterlockMode {
+    /// The rain rule decides
+    Auto,
+    /// Irrigation inhibited whatever the rain
+    Inhibit,
+    /// Irrigation allowed whatever the rain
+    Release,
+}
+
+impl InterlockMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(InterlockMode::Auto),
+            "inhibit" => Some(InterlockMode::Inhibit),
+            "release" => Some(InterlockMode::Release),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InterlockMode::Auto => "auto",
+            InterlockMode::Inhibit => "inhibit",
+            InterlockMode::Release => "release",
+        }
+    }
+}
+
+/// Irrigation is inhibited once `threshold_mm` fell in the last `window_h` hours, or while it
+/// rains (a tip less than `event_gap_s` ago, 0 to ignore), and until `dry_out_s` after that.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InterlockRule {
+    pub window_h: u32,
+    pub threshold_mm: f32,
+    pub event_gap_s: u32,
+    pub dry_out_s: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InterlockStatus {
+    pub inhibited: bool,
+    pub mode: InterlockMode,
+    /// The rain rule alone, before the dry-out and the override
+    pub wet: bool,
+    pub rain_window_mm: f32,
+    pub rain_event: bool,
+    /// Time left before the rule releases the interlock, 0 while it is still wet
+    pub dry_out_remaining_s: u32,
+}
+
+/// Rain history and override behind the interlock, small enough to be persisted as a whole.
+///
+/// Times are unix seconds, the history is only fed and evaluated on a synced clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interlock {
+    // Newest last, `hour` is the unix hour of the newest bucket
+    hourly_mm: [f32; MAX_WINDOW_H],
+    hour: u32,
+    last_rain_s: u32,
+    last_wet_s: u32,
+    pub mode: InterlockMode,
+}
+
+impl Interlock {
+    /// No rain history, which leaves irrigation allowed.
+    pub const fn new() -> Self {
+        Self {
+            hourly_mm: [0.0; MAX_WINDOW_H],
+            hour: 0,
+            last_rain_s: 0,
+            last_wet_s: 0,
+            mode: InterlockMode::Auto,
+        }
+    }
+
+    pub fn add_rain(&mut self, now_s: u32, rain_mm: f32) {
+        self.advance(now_s);
+        if rain_mm > 0.0 {
+            self.hourly_mm[MAX_WINDOW_H - 1] += rain_mm;
+            self.last_rain_s = now_s;
+        }
+    }
+
+    /// Rain of the last `window_h` hours, the current one included.
+    pub fn rain_mm(&self, now_s: u32, window_h: u32) -> f32 {
+        let age = (now_s / 3600).saturating_sub(self.hour) as usize;
+        let window = (window_h as usize).clamp(1, MAX_WINDOW_H);
+        // Buckets older than the window once shifted by the hours since the newest one
+        let skip = (MAX_WINDOW_H + age).saturating_sub(window);
+        self.hourly_mm
+            .iter()
+            .skip(skip)
+            .fold(0.0, |total, mm| total + mm)
+    }
+
+    /// Applies the rule and the override. Remembers when it was last wet, for the dry-out.
+    pub fn evaluate(&mut self, now_s: u32, rule: &InterlockRule) -> InterlockStatus {
+        let rain_window_mm = self.rain_mm(now_s, rule.window_h);
+        let rain_event = rule.event_gap_s > 0
+            && self.last_rain_s != 0
+            && now_s.saturating_sub(self.last_rain_s) < rule.event_gap_s;
+        let wet = rain_event || (rule.threshold_mm > 0.0 && rain_window_mm >= rule.threshold_mm);
+        if wet {
+            self.last_wet_s = now_s;
+        }
+        let dry_out_remaining_s = match (wet, self.last_wet_s) {
+            (false, last_wet) if last_wet != 0 => rule
+                .dry_out_s
+                .saturating_sub(now_s.saturating_sub(last_wet)),
+            _ => 0,
+        };
+        let inhibited = match self.mode {
+            InterlockMode::Auto => wet || dry_out_remaining_s > 0,
+            InterlockMode::Inhibit => true,
+            InterlockMode::Release => false,
+        };
+
+        InterlockStatus {
+            inhibited,
+            mode: self.mode,
+            wet,
+            rain_window_mm,
+            rain_event,
+            dry_out_remaining_s,
+        }
+    }
+
+    fn advance(&mut self, now_s: u32) {
+        let hour = now_s / 3600;
+        let shift = hour.saturating_sub(self.hour) as usize;
+        if shift >= MAX_WINDOW_H {
+            self.hourly_mm = [0.0; MAX_WINDOW_H];
+        } else if shift > 0 {
+            self.hourly_mm.copy_within(shift.., 0);
+            self.hourly_mm[MAX_WINDOW_H - shift..].fill(0.0);
+        }
+        self.hour = self.hour.max(hour);
+    }
+
+    pub fn to_bytes(&self) -> [u8; INTERLOCK_STATE_LEN] {
+        let mut bytes = [0u8; INTERLOCK_STATE_LEN];
+        bytes[0] = STATE_VERSION;
+        bytes[1..5].copy_from_slice(&self.hour.to_le_bytes());
+        bytes[5..9].copy_from_slice(&self.last_rain_s.to_le_bytes());
+        bytes[9..13].copy_from_slice(&self.last_wet_s.to_le_bytes());
+        bytes[13] = self.mode as u8;
+        for (chunk, mm) in bytes[14..].chunks_exact_mut(4).zip(self.hourly_mm) {
+            chunk.copy_from_slice(&mm.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// None for another version or a truncated record.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != INTERLOCK_STATE_LEN || bytes[0] != STATE_VERSION {
+            return None;
+        }
+        let word = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let mode = match bytes[13] {
+            1 => InterlockMode::Inhibit,
+            2 => InterlockMode::Release,
+            _ => InterlockMode::Auto,
+        };
+        let mut hourly_mm = [0.0; MAX_WINDOW_H];
+        for (mm, chunk) in hourly_mm.iter_mut().zip(bytes[14..].chunks_exact(4)) {
+            *mm = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        Some(Self {
+            hourly_mm,
+            hour: word(1),
+            last_rain_s: word(5),
+            last_wet_s: word(9),
+            mode,
+        })
+    }
+}
+
+impl Default for Interlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    hal::gpio::{AnyOutputPin, Output, PinDriver},
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{esp, gpio_deep_sleep_hold_en, gpio_hold_dis, gpio_hold_en},
+};
+use weather_station::interlock::{
+    Interlock, InterlockMode, InterlockRule, InterlockStatus, INTERLOCK_STATE_LEN,
+};
+
+use crate::provisioning::CONFIG;
+
+const NVS_NAMESPACE: &str = "interlock";
+const NVS_KEY: &str = "state";
+
+/// Drives the irrigation interlock relay from the rain history.
+///
+/// The history and the override are kept in NVS, so a reboot restores the interlock as soon
+/// as the clock is synced. The relay level is held through deep sleep.
+pub struct IrrigationInterlock {
+    relay: PinDriver<'static, AnyOutputPin, Output>,
+    nvs: EspNvs<NvsDefault>,
+    interlock: Interlock,
+    rule: InterlockRule,
+    status: Option<InterlockStatus>,
+}
+
+impl IrrigationInterlock {
+    /// A cold boot releases the relay until the rule is evaluated, a deep sleep wakeup keeps
+    /// the level held before the sleep.
+    pub fn new(nvs: EspDefaultNvsPartition, cold_boot: bool) -> Result<Self> {
+        let nvs = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+        let mut buf = [0u8; INTERLOCK_STATE_LEN];
+        let interlock = nvs
+            .get_blob(NVS_KEY, &mut buf)?
+            .and_then(Interlock::from_bytes)
+            .unwrap_or_default();
+        // Pins come from the config, they are not claimed by any other driver
+        let relay = PinDriver::output(unsafe { AnyOutputPin::new(CONFIG.interlock_gpio) })?;
+        esp!(unsafe { gpio_deep_sleep_hold_en() })?;
+
+        let mut irrigation = Self {
+            relay,
+            nvs,
+            interlock,
+            rule: InterlockRule {
+                window_h: CONFIG.interlock_window_h,
+                threshold_mm: CONFIG.interlock_threshold_mm,
+                event_gap_s: CONFIG.interlock_event_gap_s,
+                dry_out_s: CONFIG.interlock_dry_out_s,
+            },
+            status: None,
+        };
+        if cold_boot {
+            irrigation.drive(false)?;
+        }
+        Ok(irrigation)
+    }
+
+    /// Rain of the last publish interval, on a synced clock.
+    pub fn add_rain(&mut self, now_s: u32, rain_mm: f32) {
+        self.interlock.add_rain(now_s, rain_mm);
+        if rain_mm > 0.0 {
+            self.save();
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: InterlockMode) {
+        self.interlock.mode = mode;
+        self.save();
+    }
+
+    /// Applies the rule on a synced clock, returns true when the state changed.
+    pub fn update(&mut self, now_s: u32) -> bool {
+        let status = self.interlock.evaluate(now_s, &self.rule);
+        let previous = self.status.replace(status);
+        // The end of the wet spell starts the dry-out, it must survive a reboot
+        if previous.is_some_and(|p| p.wet != status.wet) {
+            self.save();
+        }
+        if previous.map(|p| p.inhibited) != Some(status.inhibited) {
+            log::info!(
+                "Irrigation {}",
+                if status.inhibited {
+                    "inhibited"
+                } else {
+                    "allowed"
+                }
+            );
+            self.drive(status.inhibited)
+                .unwrap_or_else(|e| log::error!("Fail driving the interlock relay: {e}"));
+        }
+        previous.map_or(true, |p| {
+            (p.inhibited, p.mode, p.wet) != (status.inhibited, status.mode, status.wet)
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        match self.status {
+            Some(s) => format!(
+                "{{\"inhibited\": {}, \"mode\": \"{}\", \"rain_event\": {}, \"rain_window_mm\": {:.1}, \"window_h\": {}, \"dry_out_remaining_s\": {}}}",
+                s.inhibited,
+                s.mode.name(),
+                s.rain_event,
+                s.rain_window_mm,
+                self.rule.window_h,
+                s.dry_out_remaining_s
+            ),
+            // Not evaluated before the clock is synced
+            None => format!(
+                "{{\"inhibited\": null, \"mode\": \"{}\"}}",
+                self.interlock.mode.name()
+            ),
+        }
+    }
+
+    fn drive(&mut self, inhibited: bool) -> Result<()> {
+        let pin = self.relay.pin();
+        esp!(unsafe { gpio_hold_dis(pin) })?;
+        if inhibited == CONFIG.interlock_active_high {
+            self.relay.set_high()?;
+        } else {
+            self.relay.set_low()?;
+        }
+        esp!(unsafe { gpio_hold_en(pin) })?;
+        Ok(())
+    }
+
+    fn save(&mut self) {
+        self.nvs
+            .set_blob(NVS_KEY, &self.interlock.to_bytes())
+            .map_err(|e| log::error!("fail storing interlock state: {e}"))
+            .ok();
+    }
+}
@@ -1,15 +1,67 @@
-use anyhow::Result;
-use as5600::As5600;
-use bosch_bme680::*;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod acoustic;
+#[cfg(feature = "std")]
+pub mod aprs;
+#[cfg(feature = "std")]
+pub mod beacon;
+#[cfg(feature = "std")]
+pub mod bthome;
+pub mod compute;
+pub mod demo;
+pub mod derive;
+pub mod dht;
+#[cfg(feature = "std")]
+pub mod diag;
+#[cfg(feature = "std")]
+pub mod ecowitt;
+#[cfg(feature = "esphome")]
+pub mod esphome;
+pub mod ess;
+pub mod forecast;
+#[cfg(feature = "std")]
+pub mod ha_discovery;
+pub mod iaq;
+pub mod interlock;
+#[cfg(feature = "std")]
+pub mod line_protocol;
+#[cfg(feature = "std")]
+pub mod modbus;
+#[cfg(feature = "std")]
+pub mod nmea;
+pub mod node_frame;
+#[cfg(feature = "std")]
+pub mod platform;
+#[cfg(feature = "std")]
+pub mod portal;
+pub mod power;
+pub mod publish_options;
+pub mod rain;
+pub mod reading;
+pub mod runtime;
+#[cfg(feature = "std")]
+pub mod sdi12;
+#[cfg(feature = "std")]
+pub mod sensors;
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod store_forward;
+#[cfg(feature = "std")]
+pub mod template;
+#[cfg(feature = "std")]
+pub mod time;
+pub mod vedirect;
+#[cfg(feature = "std")]
+pub mod wifi_quality;
+#[cfg(feature = "std")]
+pub mod wunderground;
+
 use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use embedded_hal_bus::i2c::*;
-use esp_idf_svc::{
-    hal::{delay::Ets, gpio::*, i2c::I2cDriver},
-    sys::{esp_sleep_enable_gpio_wakeup, esp_sleep_enable_timer_wakeup},
-};
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
-use std::time::{Duration, Instant};
+use core::time::Duration;
+
+pub use compute::*;
+#[cfg(feature = "std")]
+pub use platform::*;
 
 //CONFIG
 #[toml_cfg::toml_config]
@@ -18,135 +70,763 @@ pub struct Config {
     mqtt_user: &'static str,
     #[default("")]
     mqtt_pass: &'static str,
+    // mqtt:// or mqtts://
     #[default("")]
     broker_url: &'static str,
+    // PEM of the CA signing an mqtts:// broker, empty to check it against the IDF bundle
+    #[default("")]
+    mqtt_ca_cert: &'static str,
+    // PEM client certificate and key for brokers requiring them, both or none
+    #[default("")]
+    mqtt_client_cert: &'static str,
+    #[default("")]
+    mqtt_client_key: &'static str,
+    // `pattern:qos:retain` entries overriding the flags of the matching topics, e.g.
+    // `state:1:true,rain/tip:0:false`, with the `+` and `#` wildcards
+    #[default("")]
+    mqtt_topic_options: &'static str,
     #[default("")]
     wifi_ssid: &'static str,
     #[default("")]
     wifi_pass: &'static str,
+    // Fallback networks tried after wifi_ssid, `ssid:password` pairs separated by commas
+    #[default("")]
+    wifi_networks: &'static str,
+    // Empty for DHCP
+    #[default("")]
+    wifi_static_ip: &'static str,
+    #[default("")]
+    wifi_gateway: &'static str,
+    #[default(24)]
+    wifi_netmask_bits: u8,
+    #[default("")]
+    wifi_dns: &'static str,
+    // Disconnect reasons, association times and signal histogram on `<topic>/wifi/quality`
+    #[default(true)]
+    wifi_quality_enabled: bool,
+    #[default(3600)]
+    wifi_quality_interval_s: u32,
+    // Failed WiFi connections at boot before the setup portal opens, 0 to never open it
+    #[default(3)]
+    portal_after_attempts: u32,
+    // Password of the setup access point, empty for an open one, 8 characters at least
+    #[default("")]
+    portal_password: &'static str,
+    // The portal closes and the station reboots to try the network again after this
+    #[default(600)]
+    portal_timeout_s: u32,
+    // Minutes of WiFi outage before the diagnostic access point comes up, 0 to never start it
+    #[default(10)]
+    diag_ap_after_min: u32,
     #[default("")]
     topic: &'static str,
     #[default("")]
     client_id: &'static str,
+    // Empty to derive the id from the MAC address
+    #[default("")]
+    device_id: &'static str,
+    // "wifi", "ethernet", "ethernet_wifi" (Ethernet preferred, WiFi while its link is down),
+    // "cellular" or "wifi_cellular" (cellular during WiFi outages)
+    #[default("wifi")]
+    network_mode: &'static str,
+    // W5500 on SPI2
+    #[default(18)]
+    eth_sclk_gpio: i32,
+    #[default(23)]
+    eth_mosi_gpio: i32,
+    #[default(19)]
+    eth_miso_gpio: i32,
+    #[default(5)]
+    eth_cs_gpio: i32,
+    #[default(35)]
+    eth_int_gpio: i32,
+    // -1 when the reset line is not wired
+    #[default(-1)]
+    eth_rst_gpio: i32,
+    // Empty for DHCP
+    #[default("")]
+    eth_static_ip: &'static str,
+    #[default("")]
+    eth_gateway: &'static str,
+    #[default(24)]
+    eth_netmask_bits: u8,
+    #[default("")]
+    eth_dns: &'static str,
+    // SIM7000/SIM800 modem on UART1, in place of the MPPT charger
+    #[default("")]
+    cellular_apn: &'static str,
+    #[default(115200)]
+    cellular_baud: u32,
+    #[default(16)]
+    cellular_rx_gpio: i32,
+    #[default(17)]
+    cellular_tx_gpio: i32,
+    // -1 when the line is not wired
+    #[default(-1)]
+    cellular_pwrkey_gpio: i32,
+    #[default(-1)]
+    cellular_reset_gpio: i32,
+    // WiFi outage before switching to cellular in "wifi_cellular" mode
+    #[default(120)]
+    cellular_after_s: u32,
+    // Publish intervals are multiplied by this while on cellular
+    #[default(4)]
+    cellular_interval_factor: u32,
+    #[default(300)]
+    cellular_csq_interval_s: u32,
     #[default(60_000_000)]
     deep_sleep_interval_us: u64,
     #[default(61)]
     active_duration_s: u64,
+    // Local time ranges with their own publish interval, e.g. "22:00-06:00/600/sleep"
+    #[default("")]
+    quiet_hours: &'static str,
+    // Deep sleep between measurement rounds, woken by the timer, the rain gauge and the wind
+    #[default(false)]
+    low_power_enabled: bool,
+    #[default(false)]
+    network_hub_enabled: bool,
+    // Republish the ESP-NOW frames of battery nodes over MQTT
+    #[default(false)]
+    gateway_enabled: bool,
+    // Comma separated MAC addresses of the nodes accepted by the gateway
+    #[default("")]
+    gateway_peers: &'static str,
+    #[default("homeweather/nodes")]
+    gateway_prefix: &'static str,
+    #[default(600)]
+    gateway_offline_s: u32,
+    // Publish the node readings with the Environment group instead of as they arrive
+    #[default(false)]
+    gateway_merge: bool,
+    #[default(false)]
+    modbus_enabled: bool,
+    #[default(1)]
+    modbus_unit_id: u8,
+    // ESPHome native API on port 6053, alongside MQTT
+    #[default(false)]
+    esphome_enabled: bool,
+    // Base64 encryption key as in ESPHome's `api: encryption: key:`, empty for plaintext
+    #[default("")]
+    esphome_key: &'static str,
+    // Modbus RTU slave on the RS485 transceiver, not available with the RS485 wind sensor
+    #[default(false)]
+    modbus_rtu_enabled: bool,
+    #[default(1)]
+    modbus_rtu_address: u8,
+    #[default(9600)]
+    modbus_rtu_baud: u32,
+    // NMEA 0183 sentences at 4800 baud on the RS485 transceiver, when the bus is free
+    #[default(false)]
+    nmea_enabled: bool,
+    // Two letter talker id, "WI" for weather instruments
+    #[default("WI")]
+    nmea_talker: &'static str,
+    // "pulse" for the cup anemometer and AS5600 vane, "modbus" for an RS485 ultrasonic sensor
+    #[default("pulse")]
+    wind_source: &'static str,
+    #[default(32)]
+    rs485_rx_gpio: i32,
+    #[default(33)]
+    rs485_tx_gpio: i32,
+    // DE and RE of the transceiver tied together
+    #[default(14)]
+    rs485_de_gpio: i32,
+    #[default(9600)]
+    rs485_baud: u32,
+    #[default(1)]
+    wind_modbus_unit_id: u8,
+    // 3 for holding registers, 4 for input registers
+    #[default(3)]
+    wind_modbus_function: u8,
+    #[default(0)]
+    wind_modbus_speed_register: u16,
+    #[default(1)]
+    wind_modbus_direction_register: u16,
+    // Register value times the scale gives km/h, 0.36 for sensors reporting 0.1 m/s
+    #[default(0.36)]
+    wind_modbus_speed_scale: f32,
+    #[default(1.0)]
+    wind_modbus_direction_scale: f32,
+    #[default(false)]
+    diag_enabled: bool,
+    #[default(false)]
+    tca9548a_enabled: bool,
+    // Wind vane sensor: "as5600" or "mt6701" on I2C, "as5048a" on SPI
+    #[default("as5600")]
+    vane_sensor: &'static str,
+    #[default(18)]
+    as5048a_sclk_gpio: i32,
+    #[default(23)]
+    as5048a_mosi_gpio: i32,
+    #[default(19)]
+    as5048a_miso_gpio: i32,
+    #[default(15)]
+    as5048a_cs_gpio: i32,
+    // Also used by the MT6701
+    #[default(0)]
+    as5600_mux_channel: u8,
+    // Second AS5600 vane behind the mux, e.g. at another height. Published on its own, with
+    // its own fixed offset
+    #[default(false)]
+    vane_2_enabled: bool,
+    #[default("vane_2")]
+    vane_2_name: &'static str,
+    #[default(2)]
+    as5600_2_mux_channel: u8,
+    #[default(0.0)]
+    vane_2_offset_deg: f32,
+    // Off when only a DHT is fitted
+    #[default(true)]
+    bme680_enabled: bool,
+    #[default(1)]
+    bme680_mux_channel: u8,
+    #[default("outdoor")]
+    bme680_name: &'static str,
+    #[default(0x77)]
+    bme680_address: u8,
+    #[default(0.0)]
+    bme680_temp_offset: f32,
+    #[default(0.0)]
+    bme680_humidity_offset: f32,
+    #[default(0.0)]
+    bme680_pressure_offset: f32,
+    // Second BME680, e.g. inside the enclosure to watch for condensation
+    #[default(false)]
+    bme680_2_enabled: bool,
+    #[default("enclosure")]
+    bme680_2_name: &'static str,
+    #[default(0x76)]
+    bme680_2_address: u8,
+    #[default(1)]
+    bme680_2_mux_channel: u8,
+    #[default(0.0)]
+    bme680_2_temp_offset: f32,
+    #[default(0.0)]
+    bme680_2_humidity_offset: f32,
+    #[default(0.0)]
+    bme680_2_pressure_offset: f32,
+    // DHT11/DHT22 (AM2302) on a single GPIO, no pressure or gas
+    #[default(false)]
+    dht_enabled: bool,
+    #[default(26)]
+    dht_gpio: i32,
+    // "dht11" or "dht22"
+    #[default("dht22")]
+    dht_type: &'static str,
+    #[default("outdoor")]
+    dht_name: &'static str,
+    #[default(0.0)]
+    dht_temp_offset: f32,
+    #[default(0.0)]
+    dht_humidity_offset: f32,
+    // Name of the environment sensor the published weather and derived metrics come from
+    #[default("outdoor")]
+    env_outdoor: &'static str,
+    // Height of the pressure sensor above sea level in m, for the sea level pressure
+    #[default(0.0)]
+    altitude_m: f32,
+    // Mirrors the wind directions and the seasons of the Zambretti forecast
+    #[default(false)]
+    southern_hemisphere: bool,
+    #[default(false)]
+    udp_beacon_enabled: bool,
+    #[default("255.255.255.255")]
+    udp_beacon_addr: &'static str,
+    #[default(5005)]
+    udp_beacon_port: u16,
+    // BTHome BLE advertisements, only used when built with the `bthome` feature
+    #[default(false)]
+    bthome_enabled: bool,
+    #[default(1000)]
+    bthome_interval_ms: u32,
+    // -12 dBm instead of the default +3 dBm
+    #[default(false)]
+    bthome_low_power: bool,
+    // 32 hex digits, empty for unencrypted advertisements
+    #[default("")]
+    bthome_key: &'static str,
+    // BLE environmental sensing service, only used when built with the `ble_ess` feature
+    #[default(false)]
+    ble_ess_enabled: bool,
+    // Advertised name, the station id when empty
+    #[default("")]
+    ble_ess_name: &'static str,
+    // SNTP server, empty to leave the clock alone
+    #[default("pool.ntp.org")]
+    ntp_server: &'static str,
+    // Advertised as `<mdns_hostname>.local`, empty to turn mDNS off
+    #[default("weather-station")]
+    mdns_hostname: &'static str,
+    #[default(0)]
+    utc_offset_minutes: i32,
+    #[default(false)]
+    dst_active: bool,
+    #[default(false)]
+    syslog_enabled: bool,
+    #[default("")]
+    syslog_host: &'static str,
+    #[default(514)]
+    syslog_port: u16,
+    #[default("warn")]
+    syslog_level: &'static str,
+    #[default(61)]
+    wind_interval_s: u32,
+    #[default(61)]
+    env_interval_s: u32,
+    #[default(61)]
+    rain_interval_s: u32,
+    #[default(61)]
+    diag_interval_s: u32,
+    #[default(true)]
+    split_group_publish: bool,
+    // Comma separated fields to publish, or `-name` to leave one out, empty for all of them
+    #[default("")]
+    published_fields: &'static str,
+    #[default(false)]
+    rain_tip_events: bool,
+    // Rain gauge calibration: depth of rain per bucket tip, in mm
+    #[default(0.233)]
+    mm_per_tip: f32,
+    // Rain gauge edges closer than this to the last tip are contact bounce
+    #[default(100)]
+    rain_debounce_ms: u32,
+    #[default(1.0)]
+    wind_calm_kmh: f32,
+    // Anemometer calibration: wind speed for one rotation per second, 0.0174 m per rotation
+    #[default(0.0625799)]
+    anemo_kmh_per_hz: f32,
+    // At most one anemometer pulse is counted per interval this long, 0 counts them all
+    #[default(0)]
+    anemo_debounce_ms: u32,
+    // Gusts are the highest speed over samples this long within each wind window
+    #[default(3)]
+    gust_sample_s: u32,
+    #[default(10)]
+    polling_base_interval_s: u32,
+    // Window of the temperature and pressure rate of change
+    #[default(10800)]
+    trend_window_s: u32,
+    // Last known values older than this are not republished after a reboot, 0 to never restore
+    #[default(21600)]
+    restore_max_age_s: u32,
+    // Readings taken while the broker is unreachable are replayed on `<topic>/replay`
+    #[default(true)]
+    offline_buffer_enabled: bool,
+    // Readings per NVS batch, also the RAM queue length
+    #[default(30)]
+    offline_batch_len: u32,
+    #[default(8)]
+    offline_max_batches: u32,
+    #[default(false)]
+    http_enabled: bool,
+    // Basic auth password of the settings page on the HTTP server, empty to not serve it
+    #[default("")]
+    settings_password: &'static str,
+    // Above this the internal chip temperature raises an alert
+    #[default(75.0)]
+    chip_temp_limit_c: f32,
+    // Enclosure button and its feedback LED, -1 when not fitted
+    #[default(-1)]
+    button_gpio: i32,
+    #[default(-1)]
+    status_led_gpio: i32,
+    // Maintenance mode expiry when `cmd/maintenance` or the button does not give a duration
+    #[default(3600)]
+    maintenance_duration_s: u32,
+    // Publishes `online` or `maintenance` on `<topic>/availability`, for Home Assistant
+    #[default(false)]
+    maintenance_availability: bool,
+    // Home Assistant MQTT discovery messages, published on every connection
+    #[default(true)]
+    ha_discovery_enabled: bool,
+    #[default("homeassistant")]
+    ha_discovery_prefix: &'static str,
+    #[default(false)]
+    ina_enabled: bool,
+    // "ina219", "ina226" or "ina3221"
+    #[default("ina219")]
+    ina_model: &'static str,
+    #[default(0x40)]
+    ina_battery_addr: u8,
+    // 0 when no solar panel monitor is fitted
+    #[default(0)]
+    ina_solar_addr: u8,
+    #[default(0.1)]
+    ina_shunt_ohm: f32,
+    #[default(3.2)]
+    ina_max_current_a: f32,
+    // Set when the battery shunt is wired so that charging reads negative
+    #[default(false)]
+    ina_battery_inverted: bool,
+    // With ina_model "ina3221", the single device at ina_battery_addr: channels (1-3) of the
+    // battery and the panel, 0 for none
+    #[default(2)]
+    ina3221_battery_channel: u8,
+    #[default(1)]
+    ina3221_solar_channel: u8,
+    // Below this battery voltage the polling slows down, unless a fuel gauge is fitted
+    #[default(3.4)]
+    low_battery_v: f32,
+    // Battery divider on an ADC1 pin (32 to 39), -1 when not fitted
+    #[default(-1)]
+    battery_adc_gpio: i32,
+    // Battery voltage over the pin voltage, (R1 + R2) / R2
+    #[default(2.0)]
+    battery_divider_ratio: f32,
+    // Voltages read as 0 % and 100 %
+    #[default(3.3)]
+    battery_empty_v: f32,
+    #[default(4.2)]
+    battery_full_v: f32,
+    // MAX17048 (1 cell) or MAX17049 (2 cells) fuel gauge at 0x36
+    #[default(false)]
+    fuel_gauge_enabled: bool,
+    #[default("max17048")]
+    fuel_gauge_model: &'static str,
+    // State of charge below which the battery is low, also the gauge alert threshold (1-32 %)
+    #[default(20)]
+    fuel_gauge_low_soc: u8,
+    // ALRT output, -1 to only poll the alert flag
+    #[default(-1)]
+    fuel_gauge_alert_gpio: i32,
+    // Irrigation interlock relay, inhibits watering after rain
+    #[default(false)]
+    interlock_enabled: bool,
+    #[default(-1)]
+    interlock_gpio: i32,
+    // Relay energized (high) while irrigation is inhibited, false for a low level trigger
+    #[default(true)]
+    interlock_active_high: bool,
+    // Rain over the last window_h hours (1-24) at or above which irrigation is inhibited
+    #[default(24)]
+    interlock_window_h: u32,
+    #[default(5.0)]
+    interlock_threshold_mm: f32,
+    // A tip less than this ago is an ongoing rain event, 0 to only use the threshold
+    #[default(1800)]
+    interlock_event_gap_s: u32,
+    // Irrigation stays inhibited this long after the rain rule stops matching
+    #[default(43200)]
+    interlock_dry_out_s: u32,
+    // I2S MEMS microphone listening to the rain and hail, -1 pins when not fitted
+    #[default(false)]
+    acoustic_enabled: bool,
+    #[default(-1)]
+    mic_sck_gpio: i32,
+    #[default(-1)]
+    mic_ws_gpio: i32,
+    #[default(-1)]
+    mic_sd_gpio: i32,
+    #[default(16000)]
+    acoustic_sample_rate_hz: u32,
+    // One window of this length is analyzed every acoustic_interval_s
+    #[default(250)]
+    acoustic_window_ms: u32,
+    #[default(10)]
+    acoustic_interval_s: u32,
+    // CPU time allowed to analyze a window, longer windows are shortened
+    #[default(30)]
+    acoustic_budget_ms: u32,
+    // Default thresholds in dBFS, calibrated on site with `cmd/acoustic`
+    #[default(-55.0)]
+    acoustic_light_db: f32,
+    #[default(-42.0)]
+    acoustic_moderate_db: f32,
+    #[default(-32.0)]
+    acoustic_heavy_db: f32,
+    #[default(-30.0)]
+    acoustic_impact_db: f32,
+    #[default(-12.0)]
+    acoustic_hail_db: f32,
+    // Drop impacts per window that make light rain below acoustic_light_db, 0 to ignore
+    #[default(2.0)]
+    acoustic_light_impacts: f32,
+    #[default(false)]
+    mppt_enabled: bool,
+    #[default(16)]
+    mppt_rx_gpio: i32,
+    #[default(17)]
+    mppt_tx_gpio: i32,
+    #[default(19200)]
+    mppt_baud: u32,
+    // SDI-12 probes on UART1, shared with the cellular modem and the MPPT charger
+    #[default(false)]
+    sdi12_enabled: bool,
+    #[default(-1)]
+    sdi12_tx_gpio: i32,
+    #[default(-1)]
+    sdi12_rx_gpio: i32,
+    // Tri-state buffer enable, high while transmitting, -1 when not fitted
+    #[default(-1)]
+    sdi12_dir_gpio: i32,
+    // `address:index:name[:scale]` entries separated by commas, index counts the values from 1
+    #[default("")]
+    sdi12_fields: &'static str,
+    // Measure with aMC! and check the CRC of the values
+    #[default(false)]
+    sdi12_crc: bool,
+    #[default(3)]
+    sdi12_retries: u32,
+    #[default(60)]
+    sdi12_interval_s: u32,
+    #[default(false)]
+    uploader_enabled: bool,
+    // http:// or https://, certificates are checked against the IDF bundle
+    #[default("")]
+    uploader_url: &'static str,
+    #[default("POST")]
+    uploader_method: &'static str,
+    // `Name: value` pairs separated by `|`
+    #[default("")]
+    uploader_headers: &'static str,
+    #[default(300)]
+    uploader_interval_s: u32,
+    #[default("{\"temperature\": {{temperature}}, \"wind_speed\": {{wind_speed}}, \"timestamp\": {{timestamp}}}")]
+    uploader_template: &'static str,
+    // InfluxDB v2 write endpoint, written alongside MQTT
+    #[default(false)]
+    influx_enabled: bool,
+    // Base URL of the server, e.g. http://influx.local:8086
+    #[default("")]
+    influx_url: &'static str,
+    #[default("")]
+    influx_org: &'static str,
+    #[default("")]
+    influx_bucket: &'static str,
+    // API token with write access to the bucket
+    #[default("")]
+    influx_token: &'static str,
+    #[default("weather")]
+    influx_measurement: &'static str,
+    #[default(60)]
+    influx_interval_s: u32,
+    // Weather Underground personal weather station
+    #[default(false)]
+    wunderground_enabled: bool,
+    #[default("")]
+    wunderground_station_id: &'static str,
+    // Station key of the station page, not the account password
+    #[default("")]
+    wunderground_key: &'static str,
+    #[default(300)]
+    wunderground_interval_s: u32,
+    // Ecowitt gateway reports to a local server, e.g. http://192.168.1.10:4199/data/report/
+    #[default(false)]
+    ecowitt_enabled: bool,
+    #[default("")]
+    ecowitt_url: &'static str,
+    #[default(60)]
+    ecowitt_interval_s: u32,
+    // Citizen Weather Observer Program over APRS-IS
+    #[default(false)]
+    cwop_enabled: bool,
+    // CWOP id (e.g. CW1234) or amateur radio callsign
+    #[default("")]
+    cwop_callsign: &'static str,
+    // -1 for CWOP ids, the APRS-IS passcode of a licensed callsign
+    #[default("-1")]
+    cwop_passcode: &'static str,
+    #[default("cwop.aprs.net:14580")]
+    cwop_server: &'static str,
+    // Position of the station in decimal degrees, north and east positive
+    #[default(0.0)]
+    cwop_latitude: f32,
+    #[default(0.0)]
+    cwop_longitude: f32,
+    // CWOP asks for no more than one report every 5 minutes
+    #[default(600)]
+    cwop_interval_s: u32,
+    // E-paper panel, only used when built with the `epaper` feature
+    #[default(false)]
+    epaper_enabled: bool,
+    #[default(18)]
+    epaper_sclk_gpio: i32,
+    #[default(23)]
+    epaper_mosi_gpio: i32,
+    #[default(5)]
+    epaper_cs_gpio: i32,
+    #[default(19)]
+    epaper_dc_gpio: i32,
+    #[default(4)]
+    epaper_rst_gpio: i32,
+    #[default(13)]
+    epaper_busy_gpio: i32,
+    // Minimum time between refreshes, 0 refreshes on every publish cycle
+    #[default(180)]
+    epaper_refresh_s: u32,
+    #[default(10)]
+    epaper_full_refresh_every: u32,
+    // SSD1306 OLED on the sensor I2C bus, only used when built with the `oled` feature
+    #[default(false)]
+    oled_enabled: bool,
+    #[default(0x3C)]
+    oled_address: u8,
+    // Time each page stays on screen
+    #[default(5)]
+    oled_page_s: u32,
+    // SX127x LoRa radio on SPI2, only used when built with the `lora` feature
+    #[default(false)]
+    lora_enabled: bool,
+    #[default(18)]
+    lora_sclk_gpio: i32,
+    #[default(23)]
+    lora_mosi_gpio: i32,
+    #[default(19)]
+    lora_miso_gpio: i32,
+    #[default(5)]
+    lora_cs_gpio: i32,
+    #[default(14)]
+    lora_rst_gpio: i32,
+    // 868.1 MHz in Europe, 915 MHz in the Americas
+    #[default(868100000)]
+    lora_frequency_hz: u32,
+    // 7 to 12, higher reaches further at a longer airtime
+    #[default(9)]
+    lora_spreading_factor: u32,
+    #[default(14)]
+    lora_tx_power_dbm: i32,
+    // 0x12 for private networks, must match the gateway
+    #[default(18)]
+    lora_sync_word: u32,
+    // Send every reading, not only while the broker is unreachable
+    #[default(false)]
+    lora_always: bool,
+    // Fetched on cold boots, `{{station_id}}` is substituted. Empty to disable
+    #[default("")]
+    config_url: &'static str,
+    // Uptime after which the station is considered healthy and the crash count is cleared
+    #[default(5)]
+    healthy_uptime_min: u32,
+    // The main loop resets the station when it hangs this long, 0 disables the watchdog
+    #[default(60)]
+    watchdog_timeout_s: u32,
+    #[default(false)]
+    demo_mode: bool,
+    // Release builds also need this one to run in demo mode
+    #[default(false)]
+    demo_mode_release: bool,
 }
 
-// GLOBAL ATOMIC VAR
-pub static RAIN_FLAG: AtomicBool = AtomicBool::new(false);
-pub static ROTATION_FLAG: AtomicBool = AtomicBool::new(false);
-pub static ROTATION_COUNT: AtomicU32 = AtomicU32::new(0);
-pub static RAIN_COUNT: AtomicU32 = AtomicU32::new(0);
-
-fn rain_pin_callback() {
-    RAIN_FLAG.store(true, Ordering::Relaxed);
+/// Demo mode replaces the sensors with synthetic data. It is honored in release builds only
+/// when explicitly confirmed with `demo_mode_release`.
+pub fn demo_mode_active(config: &Config) -> bool {
+    config.demo_mode && (cfg!(debug_assertions) || config.demo_mode_release)
 }
 
-fn anemo_pin_callback() {
-    ROTATION_FLAG.store(true, Ordering::Relaxed);
+// Above either threshold the weather is considered active
+const ACTIVE_RAIN_MM_H: f32 = 4.0;
+const ACTIVE_WIND_MS: f32 = 10.0;
+
+/// Sensor polling period, shortened while the weather is active and lengthened on low battery.
+pub struct SensorPollingScheduler {
+    base_interval_secs: u32,
+    active: AtomicU32,
+    low_battery: AtomicBool,
 }
 
-pub fn set_intterupt(
-    pin_rain: &mut PinDriver<Gpio25, Input>,
-    pin_anemo: &mut PinDriver<Gpio27, Input>,
-) -> Result<()> {
-    pin_anemo.set_pull(Pull::Up)?;
-    pin_rain.set_pull(Pull::Up)?;
-    pin_anemo.set_interrupt_type(InterruptType::PosEdge)?;
-    pin_rain.set_interrupt_type(InterruptType::PosEdge)?;
-
-    unsafe {
-        pin_rain.subscribe(rain_pin_callback)?;
-        pin_anemo.subscribe(anemo_pin_callback)?;
-        esp_sleep_enable_gpio_wakeup();
-        esp_sleep_enable_timer_wakeup(CONFIG.deep_sleep_interval_us); //wake up every 60 seconds
+impl SensorPollingScheduler {
+    pub const fn new(base_interval_secs: u32) -> Self {
+        Self {
+            base_interval_secs,
+            active: AtomicU32::new(base_interval_secs),
+            low_battery: AtomicBool::new(false),
+        }
+    }
+
+    pub fn adjust(&self, rain_rate: f32, wind_speed_ms: f32) {
+        let interval = if self.low_battery.load(Ordering::Relaxed) {
+            self.base_interval_secs * 2
+        } else if rain_rate >= ACTIVE_RAIN_MM_H || wind_speed_ms >= ACTIVE_WIND_MS {
+            (self.base_interval_secs / 5).max(1)
+        } else {
+            self.base_interval_secs
+        };
+        self.active.store(interval, Ordering::Relaxed);
+    }
+
+    // Takes effect on the next adjustment
+    pub fn set_low_battery(&self, low: bool) {
+        self.low_battery.store(low, Ordering::Relaxed);
+    }
+
+    pub fn interval(&self) -> Duration {
+        let secs = self.active.load(Ordering::Relaxed);
+        Duration::from_secs(secs as u64)
     }
+}
 
-    pin_rain.enable_interrupt()?;
-    pin_anemo.enable_interrupt()?;
+/// Fixed capacity buffer keeping the last `N` items, oldest first.
+///
+/// Items are kept contiguous so the content can be borrowed as a slice. Pushing on a full
+/// buffer shifts everything by one, which is fine for the small sizes used on the station.
+#[derive(Clone)]
+pub struct CircularBuffer<T, const N: usize> {
+    buf: [T; N],
+    len: usize,
+}
 
-    Ok(())
+impl<T: Copy, const N: usize> CircularBuffer<T, N> {
+    // Const so buffers can live in RTC memory statics
+    pub const fn new(fill: T) -> Self {
+        Self {
+            buf: [fill; N],
+            len: 0,
+        }
+    }
 }
 
-pub fn check_time_passed() -> bool {
-    static LAST_TIME: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+impl<T, const N: usize> CircularBuffer<T, N> {
+    pub fn push(&mut self, item: T) {
+        if N == 0 {
+            return;
+        }
+        if self.len == N {
+            self.buf.rotate_left(1);
+            self.buf[N - 1] = item;
+        } else {
+            self.buf[self.len] = item;
+            self.len += 1;
+        }
+    }
 
-    let now = Instant::now();
-    let mut last_time = LAST_TIME.lock().unwrap();
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.as_slice().iter()
+    }
 
-    if now.duration_since(*last_time) >= Duration::from_secs(CONFIG.active_duration_s) {
-        *last_time = now; // Reset the last time
-        return true;
+    pub fn as_slice(&self) -> &[T] {
+        &self.buf[..self.len]
     }
 
-    false
-}
+    pub fn last(&self) -> Option<&T> {
+        self.as_slice().last()
+    }
 
-//Check if the flag was set to true, add to the global count and reset it. The function is needed
-//to be able to reactivate interrupt which are automatically disabled upon fireing once.
-pub fn check_rain_flag(pin_rain: &mut PinDriver<Gpio25, Input>) {
-    if RAIN_FLAG.load(Ordering::Relaxed) {
-        RAIN_COUNT.store(RAIN_COUNT.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
-        RAIN_FLAG.store(false, Ordering::Relaxed);
-        pin_rain
-            .enable_interrupt()
-            .map_err(|e| log::error!("fail enabling rain interrupt: {e}"))
-            .ok();
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
     }
-}
 
-pub fn check_rotation_flag(pin_anemo: &mut PinDriver<Gpio27, Input>) {
-    if ROTATION_FLAG.load(Ordering::Relaxed) {
-        ROTATION_COUNT.store(
-            ROTATION_COUNT.load(Ordering::Relaxed) + 1,
-            Ordering::Relaxed,
-        );
-        ROTATION_FLAG.store(false, Ordering::Relaxed);
-        pin_anemo
-            .enable_interrupt()
-            .map_err(|e| log::error!("fail enabling rain interrupt: {e}"))
-            .ok();
+    pub fn clear(&mut self) {
+        self.len = 0;
     }
 }
 
-pub fn get_bme_readings(bme: &mut Bme680<RefCellDevice<I2cDriver>, &mut Ets>) -> MeasurmentData {
-    match bme.measure() {
-        Ok(readings) => readings,
-        Err(e) => {
-            log::error!("Failed to get BME readings: {:?}", e);
-            MeasurmentData {
-                temperature: 0.0,
-                pressure: 0.0,
-                humidity: 0.0,
-                gas_resistance: None,
-            }
-        }
+impl<T: Copy + Default, const N: usize> Default for CircularBuffer<T, N> {
+    fn default() -> Self {
+        Self::new(T::default())
     }
 }
 
-pub fn get_wind_direction(as5600: &mut As5600<RefCellDevice<I2cDriver>>) -> String {
-    let reading = match as5600.angle() {
-        Ok(value) => value,
-        Err(_) => {
-            log::error!("Couldn't read wind direction");
-            return "NA".to_string();
-        }
-    };
-
-    let angle = (reading as f32) * (360.0 / 4096.0);
-    let direction = match angle {
-        angle if angle >= 0.0 && angle < 45.0 => "N",
-        angle if angle >= 45.0 && angle < 90.0 => "NE",
-        angle if angle >= 90.0 && angle < 135.0 => "E",
-        angle if angle >= 135.0 && angle < 180.0 => "SE",
-        angle if angle >= 180.0 && angle < 225.0 => "S",
-        angle if angle >= 225.0 && angle < 270.0 => "SW",
-        angle if angle >= 270.0 && angle < 315.0 => "W",
-        angle if angle >= 315.0 && angle < 360.0 => "NW",
-        _ => "Invalid Angle",
-    };
-
-    direction.to_string()
+impl<T: serde::Serialize, const N: usize> serde::Serialize for CircularBuffer<T, N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
 }
@@ -0,0 +1,73 @@
+//! InfluxDB line protocol: `measurement,tag=value field=value,... timestamp`, one point per
+//! line.
+use crate::reading::TimedReading;
+use crate::runtime::FieldSelection;
+use core::fmt::Write;
+
+/// Escapes the commas, spaces and equal signs of a measurement name or a tag value.
+pub fn escape_key(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, ',' | ' ' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The point of a reading, tagged with the station id. The measurements left out by `fields`
+/// or never measured are skipped, None when none is left. Without `timestamp_ms` the server
+/// dates the point, the write must use `precision=ms`.
+pub fn reading_line(
+    measurement: &str,
+    station_id: &str,
+    timed: &TimedReading,
+    fields: FieldSelection,
+    timestamp_ms: Option<u64>,
+) -> Option<String> {
+    let payload = timed.reading.payload(fields);
+    let values = [
+        ("temperature", payload.temperature),
+        ("humidity", payload.humidity),
+        ("pressure", payload.pressure),
+        ("sea_level_pressure", payload.sea_level_pressure),
+        ("wind_speed", payload.wind_speed),
+        ("wind_gust", payload.wind_gust),
+        ("wind_direction", payload.wind_direction),
+        ("rain", payload.rain),
+    ];
+    let mut line = format!(
+        "{},station={} ",
+        escape_key(measurement),
+        escape_key(station_id)
+    );
+    let mut first = true;
+    for (name, value) in values {
+        let Some(value) = value.filter(|value| value.is_finite()) else {
+            continue;
+        };
+        if !first {
+            line.push(',');
+        }
+        first = false;
+        write!(line, "{name}={value}").ok();
+    }
+    if first {
+        return None;
+    }
+    // Flags only qualify the measurements
+    for (name, flag) in [
+        ("demo", payload.demo),
+        ("maintenance", payload.maintenance),
+        ("interpolated", payload.interpolated),
+    ] {
+        if flag {
+            write!(line, ",{name}=true").ok();
+        }
+    }
+    if let Some(timestamp_ms) = timestamp_ms {
+        write!(line, " {timestamp_ms}").ok();
+    }
+    Some(line)
+}
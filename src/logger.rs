@@ -0,0 +1,128 @@
+use chrono::{Datelike, Timelike};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use weather_station::time::TimezonedClock;
+
+use crate::syslog::SyslogSink;
+
+// Lines kept in RAM for the diagnostic access point
+const RECENT_LINES: usize = 40;
+const UTC: TimezonedClock = TimezonedClock::new(0);
+// Records of the firmware and the library, the other crates keep the default level
+const STATION_TARGET: &str = "weather_station";
+
+/// Serial logger prefixing each record with the local time, records are also forwarded to
+/// the remote sinks once they are attached.
+struct LocalTimeLogger {
+    // UTC until the provisioned offset is known
+    clock: OnceLock<TimezonedClock>,
+    // LevelFilter as usize, for the station records and the others
+    station_level: AtomicUsize,
+    default_level: AtomicUsize,
+    syslog: OnceLock<SyslogSink>,
+    recent: Mutex<VecDeque<String>>,
+}
+
+static LOGGER: LocalTimeLogger = LocalTimeLogger {
+    clock: OnceLock::new(),
+    station_level: AtomicUsize::new(LevelFilter::Error as usize),
+    default_level: AtomicUsize::new(LevelFilter::Info as usize),
+    syslog: OnceLock::new(),
+    recent: Mutex::new(VecDeque::new()),
+};
+
+/// `station_level` applies to the records of the station, `level` to the other crates.
+pub fn init(level: LevelFilter, station_level: LevelFilter) {
+    LOGGER
+        .default_level
+        .store(level as usize, Ordering::Relaxed);
+    LOGGER
+        .station_level
+        .store(station_level as usize, Ordering::Relaxed);
+    log::set_logger(&LOGGER)
+        .map(|()| log::set_max_level(level.max(station_level)))
+        .unwrap_or_else(|e| println!("Fail setting logger: {e}"));
+}
+
+/// Local time of the records, from the provisioned offset. Only the first call counts.
+pub fn set_clock(clock: TimezonedClock) {
+    LOGGER.clock.set(clock).ok();
+}
+
+/// Changes the level of the station records only.
+pub fn set_station_level(level: LevelFilter) {
+    LOGGER
+        .station_level
+        .store(level as usize, Ordering::Relaxed);
+    log::set_max_level(level.max(level_of(&LOGGER.default_level)));
+}
+
+fn level_of(level: &AtomicUsize) -> LevelFilter {
+    LevelFilter::iter()
+        .nth(level.load(Ordering::Relaxed))
+        .unwrap_or(LevelFilter::Off)
+}
+
+// Needs the network up, records logged before are only printed on serial
+pub fn attach_syslog(sink: SyslogSink) {
+    LOGGER.syslog.set(sink).ok();
+}
+
+/// The last `RECENT_LINES` lines logged, oldest first.
+pub fn recent_lines() -> Vec<String> {
+    LOGGER
+        .recent
+        .lock()
+        .map(|recent| recent.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+impl Log for LocalTimeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+        let station = target
+            .strip_prefix(STATION_TARGET)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"));
+        let level = if station {
+            &self.station_level
+        } else {
+            &self.default_level
+        };
+        metadata.level() <= level_of(level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = self.clock.get().unwrap_or(&UTC).local_now();
+        let line = format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {:<5} {}: {}",
+            now.year(),
+            now.month(),
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        println!("{line}");
+        // Skipped rather than waited for, a record logged while the lines are read is lost
+        if let Ok(mut recent) = self.recent.try_lock() {
+            if recent.len() == RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line);
+        }
+
+        if let Some(syslog) = LOGGER.syslog.get() {
+            syslog.send(record);
+        }
+    }
+
+    fn flush(&self) {}
+}
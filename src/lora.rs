@@ -0,0 +1,171 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::hal::{
+    delay::FreeRtos,
+    gpio::{AnyIOPin, AnyOutputPin, Output, PinDriver},
+    spi::{config::Config as SpiConfig, SpiDeviceDriver, SpiDriver, SpiDriverConfig, SPI2},
+    units::Hertz,
+};
+use std::time::{Duration, Instant};
+use weather_station::reading::{WeatherReading, NODE_FRAME_LEN};
+
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+const SPI_BAUD: Hertz = Hertz(1_000_000);
+const CRYSTAL_HZ: u64 = 32_000_000;
+// Longest airtime of a frame at SF12 is about 1.5 s
+const TX_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_ID_LEN: usize = 16;
+
+// SX1276/77/78/79 registers in LoRa mode
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_FIFO_ADDR_PTR: u8 = 0x0D;
+const REG_FIFO_TX_BASE_ADDR: u8 = 0x0E;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_MODEM_CONFIG_1: u8 = 0x1D;
+const REG_MODEM_CONFIG_2: u8 = 0x1E;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_MODEM_CONFIG_3: u8 = 0x26;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_VERSION: u8 = 0x42;
+
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const IRQ_TX_DONE: u8 = 0x08;
+const CHIP_VERSION: u8 = 0x12;
+// 125 kHz bandwidth, coding rate 4/5, explicit header
+const MODEM_CONFIG_1: u8 = 0x72;
+// Payload CRC on
+const MODEM_CRC_ON: u8 = 0x04;
+// Automatic gain control, and the low data rate optimization required from SF11 at 125 kHz
+const MODEM_AGC_AUTO: u8 = 0x04;
+const MODEM_LOW_DATA_RATE: u8 = 0x08;
+// PA_BOOST output, the one wired on most modules
+const PA_BOOST: u8 = 0x80;
+
+/// SX127x LoRa radio on SPI2, sending the readings to a LoRa gateway when the station cannot
+/// reach the broker.
+///
+/// A frame is the length of the station id, the id, then the node frame of the reading
+/// (version, reading, CRC-16), so a gateway decodes it like an ESP-NOW node frame.
+pub struct LoraRadio {
+    spi: SpiDeviceDriver<'static, SpiDriver<'static>>,
+    // Held high, releasing it would leave the radio line floating
+    _reset: PinDriver<'static, AnyOutputPin, Output>,
+}
+
+impl LoraRadio {
+    pub fn new(spi: SPI2) -> Result<Self> {
+        // Pins come from the config, they are not claimed by any other driver
+        let (sclk, mosi, miso, cs, reset) = unsafe {
+            (
+                AnyIOPin::new(CONFIG.lora_sclk_gpio),
+                AnyIOPin::new(CONFIG.lora_mosi_gpio),
+                AnyIOPin::new(CONFIG.lora_miso_gpio),
+                AnyIOPin::new(CONFIG.lora_cs_gpio),
+                AnyOutputPin::new(CONFIG.lora_rst_gpio),
+            )
+        };
+        let driver = SpiDriver::new(spi, sclk, mosi, Some(miso), &SpiDriverConfig::new())?;
+        let spi = SpiDeviceDriver::new(driver, Some(cs), &SpiConfig::new().baudrate(SPI_BAUD))?;
+        let mut reset = PinDriver::output(reset)?;
+        reset.set_low()?;
+        FreeRtos::delay_ms(1);
+        reset.set_high()?;
+        FreeRtos::delay_ms(10);
+
+        let mut radio = Self { spi, _reset: reset };
+        let version = radio.read_register(REG_VERSION)?;
+        if version != CHIP_VERSION {
+            bail!("no SX127x found, version register reads {version:#04x}");
+        }
+        radio.configure()?;
+        log::info!(
+            "LoRa radio ready on {} Hz, SF{}",
+            CONFIG.lora_frequency_hz,
+            CONFIG.lora_spreading_factor
+        );
+
+        Ok(radio)
+    }
+
+    /// Sends a reading and waits for the end of the transmission.
+    pub fn send_reading(&mut self, reading: &WeatherReading) {
+        let id = &station_id().as_bytes()[..station_id().len().min(MAX_ID_LEN)];
+        let mut frame = Vec::with_capacity(1 + id.len() + NODE_FRAME_LEN);
+        frame.push(id.len() as u8);
+        frame.extend_from_slice(id);
+        frame.extend_from_slice(&reading.to_node_frame());
+        self.transmit(&frame)
+            .unwrap_or_else(|e| log::error!("Fail sending LoRa frame: {e}"));
+    }
+
+    fn configure(&mut self) -> Result<()> {
+        // The modem can only be switched to LoRa while asleep
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)?;
+        let frf = (CONFIG.lora_frequency_hz as u64) << 19;
+        let frf = (frf / CRYSTAL_HZ) as u32;
+        self.write_register(REG_FRF_MSB, (frf >> 16) as u8)?;
+        self.write_register(REG_FRF_MSB + 1, (frf >> 8) as u8)?;
+        self.write_register(REG_FRF_MSB + 2, frf as u8)?;
+        let power = CONFIG.lora_tx_power_dbm.clamp(2, 17) as u8;
+        self.write_register(REG_PA_CONFIG, PA_BOOST | 0x70 | (power - 2))?;
+        let spreading_factor = CONFIG.lora_spreading_factor.clamp(7, 12) as u8;
+        self.write_register(REG_MODEM_CONFIG_1, MODEM_CONFIG_1)?;
+        self.write_register(REG_MODEM_CONFIG_2, (spreading_factor << 4) | MODEM_CRC_ON)?;
+        let low_data_rate = if spreading_factor >= 11 {
+            MODEM_LOW_DATA_RATE
+        } else {
+            0
+        };
+        self.write_register(REG_MODEM_CONFIG_3, MODEM_AGC_AUTO | low_data_rate)?;
+        self.write_register(REG_SYNC_WORD, CONFIG.lora_sync_word as u8)?;
+        self.write_register(REG_FIFO_TX_BASE_ADDR, 0)?;
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_STANDBY)?;
+        self.write_register(REG_FIFO_ADDR_PTR, 0)?;
+        let mut burst = Vec::with_capacity(1 + frame.len());
+        burst.push(REG_FIFO | 0x80);
+        burst.extend_from_slice(frame);
+        self.spi.write(&burst)?;
+        self.write_register(REG_PAYLOAD_LENGTH, frame.len() as u8)?;
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_TX)?;
+
+        let start = Instant::now();
+        let done = loop {
+            if self.read_register(REG_IRQ_FLAGS)? & IRQ_TX_DONE != 0 {
+                break true;
+            }
+            if start.elapsed() > TX_TIMEOUT {
+                break false;
+            }
+            FreeRtos::delay_ms(10);
+        };
+        self.write_register(REG_IRQ_FLAGS, 0xFF)?;
+        // Asleep between readings, the radio draws about 1 µA
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)?;
+        if !done {
+            bail!("transmission not done after {}s", TX_TIMEOUT.as_secs());
+        }
+        Ok(())
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8> {
+        let mut read = [0u8; 2];
+        self.spi.transfer(&mut read, &[register & 0x7F, 0])?;
+        Ok(read[1])
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<()> {
+        self.spi.write(&[register | 0x80, value])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use esp_idf_svc::sys::{
+    esp_deep_sleep_start, esp_sleep_enable_ext0_wakeup, esp_sleep_enable_ext1_wakeup,
+    esp_sleep_enable_timer_wakeup, esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ALL_LOW,
+    esp_sleep_get_wakeup_cause, esp_sleep_pd_config,
+    esp_sleep_pd_domain_t_ESP_PD_DOMAIN_RTC_PERIPH, esp_sleep_pd_option_t_ESP_PD_OPTION_ON,
+    esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0, esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1, gpio_num_t,
+    rtc_gpio_deinit, rtc_gpio_get_level, rtc_gpio_init, rtc_gpio_mode_t_RTC_GPIO_MODE_INPUT_ONLY,
+    rtc_gpio_pulldown_dis, rtc_gpio_pullup_en, rtc_gpio_set_direction,
+};
+use std::time::{Duration, Instant};
+use weather_station::runtime::{PublishGroup, RUNTIME};
+
+use crate::{maintenance, provisioning::CONFIG, quiet_hours};
+
+const RAIN_GPIO: gpio_num_t = 25;
+const ANEMO_GPIO: gpio_num_t = 27;
+// Below this the anemometer wakes the station when it starts turning again
+pub const CALM_KMH: f32 = 1.0;
+// Longest the gauge contact is waited for to open after a tip
+const TIP_RELEASE: Duration = Duration::from_millis(500);
+const MIN_SLEEP: Duration = Duration::from_secs(1);
+
+// Tips that woke the station and those not published before the last sleep
+#[link_section = ".rtc.data"]
+static mut WAKEUP_TIPS: u32 = 0;
+#[link_section = ".rtc.data"]
+static mut UNPUBLISHED_TIPS: u32 = 0;
+// Part of the unpublished tips already added to the rain accumulation
+#[link_section = ".rtc.data"]
+static mut SAMPLED_TIPS: u32 = 0;
+// The anemometer wakeup was armed for the last sleep
+#[link_section = ".rtc.data"]
+static mut WIND_ARMED: bool = false;
+
+/// Handles a deep sleep wakeup by the rain gauge in low power mode.
+///
+/// The tip is counted in RTC memory and the station goes back to sleep for what is left of
+/// the timer, before any driver or the network is started. Returns on every other wakeup,
+/// with the pins given back to the GPIO matrix.
+pub fn handle_tip_wakeup() {
+    if !CONFIG.low_power_enabled {
+        return;
+    }
+    let cause = unsafe { esp_sleep_get_wakeup_cause() };
+    if cause == esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0 {
+        if !maintenance::discard_sleep_tip() {
+            unsafe { WAKEUP_TIPS += 1 };
+        }
+        // The wakeup is on the low level, it would fire again while the contact is closed
+        let released = Instant::now();
+        while unsafe { rtc_gpio_get_level(RAIN_GPIO) } == 0 && released.elapsed() < TIP_RELEASE {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let remaining = quiet_hours::remaining_sleep().max(MIN_SLEEP);
+        arm_wakeups(unsafe { WIND_ARMED });
+        unsafe {
+            esp_sleep_enable_timer_wakeup(remaining.as_micros() as u64);
+            esp_deep_sleep_start();
+        }
+    }
+    if cause == esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1 {
+        log::info!("Woken up by the anemometer");
+    }
+    unsafe {
+        rtc_gpio_deinit(RAIN_GPIO);
+        rtc_gpio_deinit(ANEMO_GPIO);
+    }
+}
+
+/// Tips counted in RTC memory since the last boot, the ones that woke the station apart, and
+/// how many of the unpublished ones the rain accumulation already holds.
+pub fn take_tips() -> (u32, u32, u32) {
+    unsafe {
+        let tips = (WAKEUP_TIPS, UNPUBLISHED_TIPS, SAMPLED_TIPS);
+        WAKEUP_TIPS = 0;
+        UNPUBLISHED_TIPS = 0;
+        SAMPLED_TIPS = 0;
+        tips
+    }
+}
+
+/// Keeps the tips not published yet through the coming deep sleep, `sampled` of them being
+/// already in the rain accumulation.
+pub fn keep_unpublished_tips(tips: u32, sampled: u32) {
+    unsafe {
+        UNPUBLISHED_TIPS = tips;
+        SAMPLED_TIPS = sampled.min(tips);
+    }
+}
+
+/// Timer wakeup for the next measurement round, when the first publish group is due.
+pub fn sleep_duration(awake: Duration) -> Duration {
+    PublishGroup::ALL
+        .into_iter()
+        .map(|group| RUNTIME.interval(group))
+        .min()
+        .unwrap_or(Duration::from_micros(CONFIG.deep_sleep_interval_us))
+        .saturating_sub(awake)
+        .max(MIN_SLEEP)
+}
+
+/// Wakes the station on a rain tip and, when `wind` is set, on the anemometer.
+///
+/// Each wakeup is only armed while its contact is open. The anemometer one is meant for a
+/// calm wind, so a station woken by the wind measures it once rather than on every rotation.
+pub fn arm_wakeups(wind: bool) {
+    unsafe {
+        for gpio in [RAIN_GPIO, ANEMO_GPIO] {
+            rtc_gpio_init(gpio);
+            rtc_gpio_set_direction(gpio, rtc_gpio_mode_t_RTC_GPIO_MODE_INPUT_ONLY);
+            rtc_gpio_pullup_en(gpio);
+            rtc_gpio_pulldown_dis(gpio);
+        }
+        // Keeps the pull-ups powered for the EXT1 wakeup
+        esp_sleep_pd_config(
+            esp_sleep_pd_domain_t_ESP_PD_DOMAIN_RTC_PERIPH,
+            esp_sleep_pd_option_t_ESP_PD_OPTION_ON,
+        );
+        // A contact left closed would wake the station at once, over and over
+        if cfg!(feature = "rain") && rtc_gpio_get_level(RAIN_GPIO) != 0 {
+            esp_sleep_enable_ext0_wakeup(RAIN_GPIO, 0);
+        }
+        WIND_ARMED = cfg!(feature = "anemometer") && wind && rtc_gpio_get_level(ANEMO_GPIO) != 0;
+        if WIND_ARMED {
+            esp_sleep_enable_ext1_wakeup(
+                1u64 << ANEMO_GPIO,
+                esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ALL_LOW,
+            );
+        }
+    }
+}
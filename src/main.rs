@@ -1,25 +1,185 @@
-use as5600::As5600;
-use bosch_bme680::*;
+use chrono::{Datelike, Timelike};
 use core::cell::RefCell;
+use core::sync::atomic::Ordering;
 use embedded_hal_bus::i2c;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::{
-    delay::{Ets, FreeRtos},
+    delay::FreeRtos,
     gpio::*,
     i2c::{I2cConfig, I2cDriver},
     peripherals::Peripherals,
-    sys::esp_deep_sleep_start,
+    spi::{
+        config::{Config as SpiConfig, MODE_1},
+        SpiDeviceDriver, SpiDriver, SpiDriverConfig, SPI3,
+    },
+    sys::{
+        esp_deep_sleep_start, esp_efuse_mac_get_default, esp_random, esp_sleep_enable_timer_wakeup,
+        esp_sleep_get_wakeup_cause, esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED,
+    },
     units::Hertz,
 };
+use esp_idf_svc::mqtt::client::EspMqttConnection;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sntp::{EspSntp, SntpConf};
 use log::info;
+use once_cell::sync::Lazy;
+use provisioning::{ConfigSource, WeatherStationProvisioner, CONFIG};
+use rain_stats::calendar_period;
+use std::fmt::Write;
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::{Duration, Instant};
-use weather_station::*;
+#[cfg(feature = "as5600")]
+use weather_station::sensors::as5600::As5600Sensor;
+#[cfg(feature = "bme680")]
+use weather_station::sensors::bme680::Bme680Sensor;
+use weather_station::{
+    demo::DemoWeather,
+    derive::Derived,
+    dht::DhtKind,
+    diag::RateLimiter,
+    forecast::{is_summer, zambretti},
+    iaq::AirQuality,
+    interlock::InterlockMode,
+    modbus::{FN_READ_HOLDING_REGISTERS, FN_READ_INPUT_REGISTERS},
+    power::{
+        BatterySample, ChargeCounter, ChargeState, FuelGaugeSample, InaModel, POWER_SAMPLE_PERIOD,
+    },
+    rain::tips_to_mm,
+    reading::{TimedReading, WeatherReading},
+    runtime::{
+        parse_interval_command, parse_maintenance_command, parse_station_command, unknown_fields,
+        Backoff, ButtonPress, Field, FieldSelection, PublishGroup, StationCommand, RUNTIME,
+    },
+    sensors::{
+        angle::{AngleSensor, AngleStatus, WindVane},
+        as5048a::As5048a,
+        battery_adc::BatteryAdc,
+        dht::DhtSensor,
+        env::{EnvData, EnvOffsets, EnvSensor},
+        ina2xx::{Ina2xx, PowerMonitor},
+        ina3221::Ina3221Channel,
+        max17048::Max17048,
+        mt6701::Mt6701,
+        pulse::{ReedAnemometer, RotationCounter},
+        tca9548a::TcaMux,
+        Measurement, Sensor, SensorHealth, TrackedSensor,
+    },
+    stats::*,
+    time::{unix_time_ms, TimezonedClock},
+    *,
+};
+mod acoustic_mic;
+mod anemometer;
+#[cfg(feature = "ble_ess")]
+mod ble_ess;
+#[cfg(feature = "bthome")]
+mod bthome_adv;
+mod button;
+mod captive_portal;
+mod cellular;
+mod cwop;
+mod diag_ap;
+mod diagnostics;
+mod ecowitt_uploader;
+mod emergency;
+#[cfg(feature = "epaper")]
+mod epaper;
+#[cfg(feature = "esphome")]
+mod esphome_api;
+mod ethernet;
+mod gateway;
+mod http;
+mod iaq_store;
+mod influx;
+mod init;
+mod irrigation;
+mod logger;
+#[cfg(feature = "lora")]
+mod lora;
+mod low_power;
+mod maintenance;
+mod mdns;
+mod modbus_rtu;
+mod modbus_tcp;
+mod mppt;
 mod mqtt;
+mod network;
+mod nmea_out;
+mod offline_buffer;
+#[cfg(feature = "oled")]
+mod oled;
+mod ota;
+mod provisioning;
+mod quiet_hours;
+mod rain_stats;
+mod remote_config;
+mod restore;
+mod safe_mode;
+mod sdi12_bus;
+mod syslog;
+mod transport;
+mod udp;
+mod uploader;
+mod watchdog;
 mod wifi;
+mod wifi_monitor;
+mod ws;
+mod wu_uploader;
+
+static STATION_ID: Lazy<heapless::String<16>> = Lazy::new(init_station_id);
+
+// Kept in RTC memory so the rolling windows survive deep sleep
+#[link_section = ".rtc.data"]
+static mut PRECIPITATION: PrecipitationAccumulation = PrecipitationAccumulation::new();
+#[link_section = ".rtc.data"]
+static mut RAIN_TIPS: RainTipLog = RainTipLog::new();
+#[link_section = ".rtc.data"]
+static mut WIND_ROSE: WindRose = WindRose::new();
+#[link_section = ".rtc.data"]
+static mut HOURLY: HourlyAggregator = HourlyAggregator::new();
+#[link_section = ".rtc.data"]
+static mut TRENDS: TrendTracker = TrendTracker::new();
+#[link_section = ".rtc.data"]
+static mut TEMPERATURE_24H: Extremes24h = Extremes24h::new();
+#[link_section = ".rtc.data"]
+static mut GUST_24H: Extremes24h = Extremes24h::new();
+#[link_section = ".rtc.data"]
+static mut CHARGE: ChargeCounter = ChargeCounter::new();
 
 fn main() {
     esp_idf_svc::sys::link_patches();
-    esp_idf_svc::log::EspLogger::initialize_default();
-    esp_idf_svc::log::set_target_level("weather-station", log::LevelFilter::Error).unwrap();
+    logger::init(log::LevelFilter::Info, log::LevelFilter::Error);
+
+    // Provisioning goes first, CONFIG reads before it get the compiled values
+    let nvs = EspDefaultNvsPartition::take().expect("fail taking nvs");
+    let mut provisioner = WeatherStationProvisioner::new(nvs.clone())
+        .map_err(|e| log::error!("Fail opening provisioning storage: {e}"))
+        .ok();
+    let source = provisioner
+        .as_mut()
+        .map_or(ConfigSource::Compiled, |provisioner| {
+            provisioner.provision()
+        });
+    logger::set_clock(TimezonedClock::from_config(&CONFIG));
+    info!("Config source: {source:?}");
+    // A rain tip wakeup in low power mode goes back to sleep from here
+    low_power::handle_tip_wakeup();
+    let mut boot_guard = safe_mode::BootGuard::new(nvs.clone(), source == ConfigSource::SpiffsFile)
+        .map_err(|e| log::error!("Fail opening boot state: {e}"))
+        .ok();
+    // The runtime intervals start from the active config
+    for (group, seconds) in PublishGroup::ALL.into_iter().zip([
+        CONFIG.wind_interval_s,
+        CONFIG.env_interval_s,
+        CONFIG.rain_interval_s,
+        CONFIG.diag_interval_s,
+    ]) {
+        RUNTIME.set_interval_s(group, seconds);
+    }
+    if !quiet_hours::set_schedule(CONFIG.quiet_hours) {
+        log::error!("Invalid quiet_hours schedule {}", CONFIG.quiet_hours);
+    }
+    RUNTIME.set_fields(FieldSelection::parse(CONFIG.published_fields));
 
     //SETUP
     let p = Peripherals::take().unwrap();
@@ -29,70 +189,1811 @@ fn main() {
         p.pins.gpio22,
         &I2cConfig::new().baudrate(Hertz(100_000)),
     )
-    .expect("fail creating i2c");
+    .unwrap_or_else(|e| init::restart_later("i2c", e));
     let i2c_bus = RefCell::new(i2c);
-    let mut delay_prov = Ets;
+
+    // Only main touches the RTC statics, there is no concurrent access
+    let precip = unsafe { &mut *core::ptr::addr_of_mut!(PRECIPITATION) };
+    let rain_tips = unsafe { &mut *core::ptr::addr_of_mut!(RAIN_TIPS) };
+    let wind_rose = unsafe { &mut *core::ptr::addr_of_mut!(WIND_ROSE) };
+    let hourly = unsafe { &mut *core::ptr::addr_of_mut!(HOURLY) };
+    let charge = unsafe { &mut *core::ptr::addr_of_mut!(CHARGE) };
+    let trends = unsafe { &mut *core::ptr::addr_of_mut!(TRENDS) };
+    let temperature_24h = unsafe { &mut *core::ptr::addr_of_mut!(TEMPERATURE_24H) };
+    let gust_24h = unsafe { &mut *core::ptr::addr_of_mut!(GUST_24H) };
+    let (wakeup_tips, unpublished_tips, sampled_tips) = low_power::take_tips();
+    RAIN_COUNT.fetch_add(wakeup_tips + unpublished_tips, Ordering::Relaxed);
+    // Their own time was not kept, they are dated on this wakeup
+    for _ in 0..wakeup_tips {
+        rain_tips.record(unix_time_ms());
+    }
+    let (mut last_known, mut pending_restore) = match restore::LastKnownStore::load(nvs.clone()) {
+        Ok((store, record)) => (Some(store), record),
+        Err(e) => {
+            log::error!("Fail loading last known values: {e}");
+            (None, None)
+        }
+    };
+    if unsafe { esp_sleep_get_wakeup_cause() } != esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED {
+        precip.skip(quiet_hours::last_sleep());
+        // The broker kept the retained values through the sleep
+        pending_restore = None;
+    }
+    let mut offline = if CONFIG.offline_buffer_enabled {
+        offline_buffer::OfflineBuffer::open(nvs.clone())
+            .map_err(|e| log::error!("Fail opening offline buffer: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    let mut rain_stats = rain_stats::RainStatsStore::load(nvs.clone())
+        .map_err(|e| log::error!("Fail loading rain statistics: {e}"))
+        .ok();
+    // Only a BME680 measures the gas resistance
+    let mut iaq = if cfg!(feature = "bme680") && CONFIG.bme680_enabled {
+        iaq_store::IaqStore::load(nvs.clone())
+            .map_err(|e| log::error!("Fail loading the IAQ baseline: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    let clock = TimezonedClock::from_config(&CONFIG);
+
+    // Sensors and inputs that failed their setup, published with the diagnostics
+    let mut degraded: Vec<&'static str> = Vec::new();
 
     //PIN_INTERRUPTS
-    let mut pin_anemo = PinDriver::input(p.pins.gpio27).unwrap();
-    let mut pin_rain = PinDriver::input(p.pins.gpio25).unwrap();
-    set_intterupt(&mut pin_rain, &mut pin_anemo)
-        .unwrap_or_else(|e| log::error!("An Error occured setting the interrupts: {e}"));
+    // Left floating in builds without the gauge or the anemometer
+    let mut rotation_counter = cfg!(feature = "anemometer")
+        .then(|| RotationCounter::new(p.pcnt0, p.pins.gpio27, CONFIG.anemo_debounce_ms))
+        .and_then(|counter| {
+            counter
+                .map_err(|e| {
+                    log::error!("Fail starting the anemometer pulse counter: {e}");
+                    degraded.push("anemometer");
+                })
+                .ok()
+        });
+    let pin_rain = cfg!(feature = "rain")
+        .then(|| PinDriver::input(p.pins.gpio25))
+        .and_then(|pin| {
+            pin.map_err(|e| {
+                log::error!("Fail setting the rain gauge pin: {e}");
+                degraded.push("rain_gauge");
+            })
+            .ok()
+        });
+    set_intterupt(
+        pin_rain,
+        CONFIG.rain_debounce_ms,
+        CONFIG.deep_sleep_interval_us,
+    )
+    .unwrap_or_else(|e| log::error!("An Error occured setting the interrupts: {e}"));
+    // Pins come from the config, they are not claimed by any other driver
+    let pin_button = (CONFIG.button_gpio >= 0)
+        .then(|| PinDriver::input(unsafe { AnyIOPin::new(CONFIG.button_gpio) }))
+        .and_then(|pin| {
+            pin.map_err(|e| log::error!("Fail setting the button pin: {e}"))
+                .ok()
+        })
+        .map(|mut pin| {
+            set_button_interrupt(&mut pin).unwrap_or_else(|e| {
+                log::error!("An Error occured setting the button interrupt: {e}")
+            });
+            pin
+        });
+    let status_led = (CONFIG.status_led_gpio >= 0)
+        .then(|| PinDriver::output(unsafe { AnyOutputPin::new(CONFIG.status_led_gpio) }))
+        .and_then(|pin| {
+            pin.map_err(|e| log::error!("Fail setting the status LED pin: {e}"))
+                .ok()
+        });
+
+    //NETWORK
+    // Shared by the W5500, the e-paper display and the LoRa radio, the first one enabled gets it
+    let mut spi2 = Some(p.spi2);
+    let spi_eth = match CONFIG.network_mode {
+        "ethernet" | "ethernet_wifi" => spi2.take(),
+        _ => None,
+    };
+    // Shared by the cellular modem and the MPPT charger in the same way
+    let mut uart1 = Some(p.uart1);
+    let uart_modem = match CONFIG.network_mode {
+        "cellular" | "wifi_cellular" => uart1.take(),
+        _ => None,
+    };
+    // Bluetooth goes to BTHome or the GATT service, the first one enabled gets it
+    #[cfg(any(feature = "bthome", feature = "ble_ess"))]
+    let (wifi_modem, mut bt_modem) = {
+        let (wifi_modem, bt_modem) = p.modem.split();
+        (wifi_modem, Some(bt_modem))
+    };
+    #[cfg(not(any(feature = "bthome", feature = "ble_ess")))]
+    let wifi_modem = p.modem;
+    // Shared by the WiFi monitor and the network drivers, it cannot be taken twice
+    let sys_loop =
+        EspSystemEventLoop::take().unwrap_or_else(|e| init::restart_later("the event loop", e));
+    // Before the network comes up, so the first association is seen
+    let mut wifi_monitor = if CONFIG.wifi_quality_enabled
+        && matches!(
+            CONFIG.network_mode,
+            "wifi" | "ethernet_wifi" | "wifi_cellular"
+        ) {
+        wifi_monitor::WifiMonitor::start(sys_loop.clone(), nvs.clone())
+            .map_err(|e| log::error!("Fail starting the WiFi monitor: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    let portal = provisioner
+        .as_mut()
+        .is_some_and(|provisioner| provisioner.take_portal_request());
+    // The modem is consumed by a failed attempt, the whole boot is retried
+    let mut network = network::Network::connect(
+        wifi_modem,
+        spi_eth,
+        uart_modem,
+        sys_loop,
+        nvs.clone(),
+        portal,
+    )
+    .unwrap_or_else(|e| init::restart_later("the network", e));
+    let ip_address = network.ip();
+    // Kept for the whole run, SNTP keeps correcting the clock in the background. It survives
+    // deep sleep, so a wakeup is synced before the first answer
+    let _sntp = (!CONFIG.ntp_server.is_empty())
+        .then(|| {
+            let mut conf = SntpConf::default();
+            conf.servers[0] = CONFIG.ntp_server;
+            EspSntp::new(&conf)
+                .map_err(|e| log::error!("Fail starting SNTP: {e}"))
+                .ok()
+        })
+        .flatten();
+    // Kept for the whole run like SNTP, lwIP answers the queries on whichever netif is up
+    let _mdns = (!CONFIG.mdns_hostname.is_empty())
+        .then(|| {
+            mdns::mdns_start()
+                .map_err(|e| log::error!("Fail starting mDNS: {e}"))
+                .ok()
+        })
+        .flatten();
+    // Cold boots only, deep sleep wakeups keep the stored config. Runs before safe mode so a
+    // fixed config can be pushed to a crash looping station
+    let cold_boot =
+        unsafe { esp_sleep_get_wakeup_cause() } == esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED;
+    let config_fetch = match provisioner.as_mut() {
+        Some(provisioner) if cold_boot && !CONFIG.config_url.is_empty() => {
+            match remote_config::fetch_remote_config(provisioner) {
+                Ok(remote_config::FetchOutcome::Updated) => {
+                    info!("New config fetched, rebooting to apply it");
+                    if let Some(guard) = boot_guard.as_mut() {
+                        guard
+                            .exit_safe_mode()
+                            .unwrap_or_else(|e| log::error!("Fail leaving safe mode: {e}"));
+                    }
+                    unsafe { esp_idf_svc::sys::esp_restart() }
+                }
+                Ok(outcome) => Some(outcome.name().to_string()),
+                Err(e) => {
+                    log::warn!("Fail fetching config, keeping the stored one: {e}");
+                    Some(format!("error: {e}"))
+                }
+            }
+        }
+        _ => None,
+    };
+    // After a crash loop only WiFi and MQTT come up, nothing below is started
+    let mut boot_guard = match boot_guard {
+        Some(guard) if guard.safe_mode => safe_mode::run_safe_mode(guard, ip_address),
+        guard => guard,
+    };
+    if CONFIG.syslog_enabled {
+        match syslog::SyslogSink::new() {
+            Ok(sink) => logger::attach_syslog(sink),
+            Err(e) => log::error!("Fail creating syslog sink: {e}"),
+        }
+    }
+
+    //PEER STATIONS
+    let peer_readings = Arc::new(Mutex::new(transport::WeatherNetwork::default()));
+    let gateway_nodes = if CONFIG.gateway_enabled {
+        gateway::NodeTable::new(CONFIG.gateway_peers)
+            .map(|table| Arc::new(Mutex::new(table)))
+            .map_err(|e| log::error!("Fail creating gateway: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    let _espnow = if CONFIG.network_hub_enabled || gateway_nodes.is_some() {
+        transport::espnow_listen(
+            Some(peer_readings.clone()).filter(|_| CONFIG.network_hub_enabled),
+            gateway_nodes.clone(),
+        )
+        .map_err(|e| log::error!("Fail starting ESP-NOW: {e}"))
+        .ok()
+    } else {
+        None
+    };
+
+    //HTTP API
+    let history: http::ReadingHistory = Arc::new(Mutex::new(CircularBuffer::default()));
+    // Local commands reach the main loop along with the MQTT events
+    let (event_tx, event_rx) = mpsc::channel();
+    let mut http_server = if CONFIG.http_enabled || CONFIG.network_hub_enabled {
+        http::http_server_create()
+            .map_err(|e| log::error!("Fail starting http server: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    if let Some(server) = http_server.as_mut() {
+        if CONFIG.network_hub_enabled {
+            http::register_network_endpoint(server, peer_readings.clone())
+                .unwrap_or_else(|e| log::error!("Fail registering network endpoint: {e}"));
+        }
+        http::register_interpolate_endpoint(server, history.clone())
+            .unwrap_or_else(|e| log::error!("Fail registering interpolate endpoint: {e}"));
+        if CONFIG.http_enabled {
+            http::register_api_endpoints(server, history.clone(), event_tx.clone())
+                .unwrap_or_else(|e| log::error!("Fail registering REST API: {e}"));
+        }
+        if !CONFIG.settings_password.is_empty() {
+            http::register_settings_page(server, history.clone(), nvs.clone())
+                .unwrap_or_else(|e| log::error!("Fail registering settings page: {e}"));
+        }
+    }
+    let live_feed = http_server.as_mut().and_then(|server| {
+        ws::register_ws_endpoint(server)
+            .map_err(|e| log::error!("Fail registering websocket endpoint: {e}"))
+            .ok()
+    });
+
+    //UPLOADER
+    if CONFIG.uploader_enabled {
+        uploader::uploader_start(history.clone())
+            .unwrap_or_else(|e| log::error!("Fail starting uploader: {e}"));
+    }
+    if CONFIG.influx_enabled {
+        influx::influx_start(history.clone())
+            .unwrap_or_else(|e| log::error!("Fail starting InfluxDB writer: {e}"));
+    }
+    if CONFIG.wunderground_enabled {
+        wu_uploader::wu_uploader_start(history.clone())
+            .unwrap_or_else(|e| log::error!("Fail starting Weather Underground upload: {e}"));
+    }
+    if CONFIG.ecowitt_enabled {
+        ecowitt_uploader::ecowitt_start(history.clone())
+            .unwrap_or_else(|e| log::error!("Fail starting Ecowitt reports: {e}"));
+    }
+    if CONFIG.cwop_enabled {
+        cwop::cwop_start(history.clone())
+            .unwrap_or_else(|e| log::error!("Fail starting CWOP reports: {e}"));
+    }
+
+    //MODBUS
+    let latest_reading = Arc::new(Mutex::new(WeatherReading::default()));
+    let mut diag_ap = diag_ap::DiagAp::new(latest_reading.clone());
+    if CONFIG.modbus_enabled {
+        modbus_tcp::modbus_serve(latest_reading.clone())
+            .unwrap_or_else(|e| log::error!("Fail starting modbus server: {e}"));
+    }
+    #[cfg(feature = "esphome")]
+    let esphome = if CONFIG.esphome_enabled {
+        esphome_api::esphome_serve()
+            .map_err(|e| log::error!("Fail starting ESPHome API server: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    #[cfg(not(feature = "esphome"))]
+    if CONFIG.esphome_enabled {
+        log::warn!("Built without the esphome feature, no ESPHome API server");
+    }
+    // UART2 drives the RS485 transceiver, for the wind sensor, the RTU slave or NMEA
+    let mut uart2 = Some(p.uart2);
+    if CONFIG.modbus_rtu_enabled {
+        if CONFIG.wind_source == "modbus" {
+            log::error!("The RS485 bus is used by the wind sensor, Modbus RTU slave disabled");
+        } else if let Some(uart) = uart2.take() {
+            modbus_rtu::modbus_rtu_serve(uart, latest_reading.clone())
+                .unwrap_or_else(|e| log::error!("Fail starting modbus RTU slave: {e}"));
+        }
+    }
+
+    //CHARGE CONTROLLER
+    let mppt = if CONFIG.mppt_enabled {
+        match uart1.take() {
+            Some(uart) => mppt::mppt_listen(uart)
+                .map_err(|e| log::error!("Fail starting VE.Direct listener: {e}"))
+                .ok(),
+            None => {
+                log::error!("UART1 is used by the cellular modem, VE.Direct listener disabled");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    //SDI-12 PROBES
+    let sdi12 = if CONFIG.sdi12_enabled {
+        match uart1.take() {
+            Some(uart) => sdi12_bus::sdi12_start(uart)
+                .map_err(|e| log::error!("Fail starting SDI-12 bus: {e}"))
+                .ok(),
+            None => {
+                log::error!("UART1 is already in use, SDI-12 bus disabled");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    //RS485 WIND SENSOR
+    let mut ultrasonic = match CONFIG.wind_source {
+        "modbus"
+            if ![FN_READ_HOLDING_REGISTERS, FN_READ_INPUT_REGISTERS]
+                .contains(&CONFIG.wind_modbus_function) =>
+        {
+            log::error!(
+                "Invalid wind_modbus_function {}",
+                CONFIG.wind_modbus_function
+            );
+            None
+        }
+        "modbus" => uart2.take().and_then(|uart| {
+            anemometer::UltrasonicAnemometer::new(uart)
+                .map_err(|e| {
+                    log::error!("Fail starting RS485 anemometer: {e}");
+                    degraded.push("rs485_anemometer");
+                })
+                .ok()
+        }),
+        "pulse" => None,
+        other => {
+            log::error!("Unknown wind_source {other}, using the pulse anemometer");
+            None
+        }
+    };
+
+    //NMEA OUTPUT
+    let mut nmea = if CONFIG.nmea_enabled {
+        match uart2.take() {
+            Some(uart) => nmea_out::NmeaOutput::new(uart)
+                .map_err(|e| log::error!("Fail starting NMEA output: {e}"))
+                .ok(),
+            None => {
+                log::error!("The RS485 bus is already in use, NMEA output disabled");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    //IRRIGATION INTERLOCK
+    let mut interlock = if CONFIG.interlock_enabled && CONFIG.interlock_gpio >= 0 {
+        irrigation::IrrigationInterlock::new(nvs.clone(), cold_boot)
+            .map_err(|e| log::error!("Fail starting irrigation interlock: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
+    //ACOUSTIC RAIN SENSING
+    let mut acoustic = if CONFIG.acoustic_enabled && CONFIG.mic_sd_gpio >= 0 {
+        acoustic_mic::AcousticMonitor::start(p.i2s0, nvs.clone(), cold_boot)
+            .map_err(|e| log::error!("Fail starting acoustic rain sensing: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
+    //DISPLAY
+    #[cfg(feature = "epaper")]
+    let epaper = if CONFIG.epaper_enabled {
+        match spi2.take() {
+            Some(spi) => epaper::epaper_start(spi)
+                .map_err(|e| log::error!("Fail starting e-paper display: {e}"))
+                .ok(),
+            None => {
+                log::error!("SPI2 is used by the Ethernet controller, e-paper display disabled");
+                None
+            }
+        }
+    } else {
+        None
+    };
 
-    //WIFI
-    let mut wifi = wifi::wifi_init(p.modem).unwrap();
-    wifi::connect_wifi(&mut wifi).expect("couldn't connect to wifi");
+    //LORA
+    #[cfg(feature = "lora")]
+    let mut lora = if CONFIG.lora_enabled {
+        match spi2.take() {
+            Some(spi) => lora::LoraRadio::new(spi)
+                .map_err(|e| log::error!("Fail starting LoRa radio: {e}"))
+                .ok(),
+            None => {
+                log::error!("SPI2 is already used, LoRa radio disabled");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    //LAN BEACON
+    let mut beacon = if CONFIG.udp_beacon_enabled {
+        udp::UdpBeacon::new()
+            .map_err(|e| log::error!("Fail creating udp beacon: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
+    //BLE BROADCAST
+    // Bluetooth stays off when it cannot start, the rest of the station does not need it
+    #[cfg(feature = "bthome")]
+    let bthome = if CONFIG.bthome_enabled {
+        bt_modem.take().and_then(|modem| {
+            bthome_adv::bthome_start(modem, nvs.clone())
+                .map_err(|e| log::error!("Fail starting BTHome advertising: {e}"))
+                .ok()
+        })
+    } else {
+        None
+    };
+
+    //BLE GATT SERVICE
+    #[cfg(feature = "ble_ess")]
+    let ble_ess = if CONFIG.ble_ess_enabled {
+        match bt_modem.take() {
+            Some(modem) => ble_ess::EssServer::start(modem, nvs.clone())
+                .map_err(|e| log::error!("Fail starting BLE environmental sensing: {e}"))
+                .ok(),
+            None => {
+                log::error!("Bluetooth is used by BTHome, BLE environmental sensing disabled");
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     //I2C PERIPHERALS
-    let mut as5600 = As5600::new(i2c::RefCellDevice::new(&i2c_bus));
-    let mut bme = Bme680::new(
-        i2c::RefCellDevice::new(&i2c_bus),
-        DeviceAddress::Secondary,
-        &mut delay_prov,
-        &bosch_bme680::Configuration::default(),
-        20,
-    )
-    .expect("Fail initiating bme");
+    // Every sensor behind the mux selects its channel through its own handle
+    let mux_handle = || {
+        CONFIG
+            .tca9548a_enabled
+            .then(|| TcaMux::new(i2c::RefCellDevice::new(&i2c_bus)))
+    };
+    let vane: Option<Box<dyn AngleSensor + '_>> = match CONFIG.vane_sensor {
+        #[cfg(feature = "as5600")]
+        "as5600" => Some(Box::new(As5600Sensor::new(i2c::RefCellDevice::new(
+            &i2c_bus,
+        )))),
+        #[cfg(not(feature = "as5600"))]
+        "as5600" => {
+            log::warn!("Built without the as5600 feature, no wind vane");
+            None
+        }
+        "mt6701" => Some(Box::new(Mt6701::new(i2c::RefCellDevice::new(&i2c_bus)))),
+        "as5048a" => as5048a_create(p.spi3)
+            .map(|sensor| Box::new(sensor) as Box<dyn AngleSensor>)
+            .map_err(|e| {
+                log::error!("Fail initiating AS5048A: {e}");
+                degraded.push("vane");
+            })
+            .ok(),
+        other => {
+            log::error!("Unknown vane_sensor {other}");
+            None
+        }
+    };
+    let mut vane = vane
+        .map(|sensor| WindVane::new(sensor).behind_mux(mux_handle(), CONFIG.as5600_mux_channel));
+    // Same address as the first AS5600, only reachable through the mux
+    #[cfg(feature = "as5600")]
+    let vane_2 = CONFIG.vane_2_enabled.then(|| {
+        Box::new(As5600Sensor::new(i2c::RefCellDevice::new(&i2c_bus))) as Box<dyn AngleSensor + '_>
+    });
+    #[cfg(not(feature = "as5600"))]
+    let vane_2: Option<Box<dyn AngleSensor + '_>> = None;
+    let mut vane_2 = vane_2.map(|sensor| {
+        WindVane::new(sensor)
+            .extra(CONFIG.vane_2_name, CONFIG.vane_2_offset_deg)
+            .behind_mux(mux_handle(), CONFIG.as5600_2_mux_channel)
+    });
+    let ina = |addr| -> Option<Box<dyn PowerMonitor + '_>> {
+        match InaModel::from_name(CONFIG.ina_model) {
+            Some(model) => Ina2xx::new(
+                i2c::RefCellDevice::new(&i2c_bus),
+                addr,
+                model,
+                CONFIG.ina_shunt_ohm,
+                CONFIG.ina_max_current_a,
+            )
+            .map_err(|e| log::error!("Fail initiating INA at 0x{addr:02X}: {e}"))
+            .ok()
+            .map(|ina| Box::new(ina) as Box<dyn PowerMonitor>),
+            None => {
+                log::error!("Unknown INA model {}", CONFIG.ina_model);
+                None
+            }
+        }
+    };
+    let ina3221 = |channel| -> Option<Box<dyn PowerMonitor + '_>> {
+        Ina3221Channel::new(
+            i2c::RefCellDevice::new(&i2c_bus),
+            CONFIG.ina_battery_addr,
+            channel,
+            CONFIG.ina_shunt_ohm,
+        )
+        .map_err(|e| log::error!("Fail initiating INA3221 channel {channel}: {e}"))
+        .ok()
+        .map(|ina| Box::new(ina) as Box<dyn PowerMonitor>)
+    };
+    let (mut ina_battery, mut ina_solar) = match (CONFIG.ina_enabled, CONFIG.ina_model) {
+        (false, _) => (None, None),
+        (true, "ina3221") => (
+            (CONFIG.ina3221_battery_channel != 0)
+                .then(|| ina3221(CONFIG.ina3221_battery_channel))
+                .flatten(),
+            (CONFIG.ina3221_solar_channel != 0)
+                .then(|| ina3221(CONFIG.ina3221_solar_channel))
+                .flatten(),
+        ),
+        (true, _) => (
+            ina(CONFIG.ina_battery_addr),
+            (CONFIG.ina_solar_addr != 0)
+                .then(|| ina(CONFIG.ina_solar_addr))
+                .flatten(),
+        ),
+    };
+    let mut battery_adc = (CONFIG.battery_adc_gpio >= 0)
+        .then(|| {
+            BatteryAdc::new(
+                CONFIG.battery_adc_gpio,
+                CONFIG.battery_divider_ratio,
+                CONFIG.battery_empty_v,
+                CONFIG.battery_full_v,
+            )
+            .map_err(|e| log::error!("Fail initiating battery ADC: {e}"))
+            .ok()
+        })
+        .flatten();
+    let mut fuel_gauge = match (CONFIG.fuel_gauge_enabled, CONFIG.fuel_gauge_model) {
+        (false, _) => None,
+        (true, model @ ("max17048" | "max17049")) => Max17048::new(
+            i2c::RefCellDevice::new(&i2c_bus),
+            if model == "max17049" { 2 } else { 1 },
+            CONFIG.fuel_gauge_low_soc,
+        )
+        .map_err(|e| log::error!("Fail initiating fuel gauge: {e}"))
+        .ok(),
+        (true, other) => {
+            log::error!("Unknown fuel gauge model {other}");
+            None
+        }
+    };
+    // Open drain and latched until the alert is acknowledged by a read. Pins come from the
+    // config, they are not claimed by any other driver
+    let fuel_gauge_alert = (fuel_gauge.is_some() && CONFIG.fuel_gauge_alert_gpio >= 0)
+        .then(|| {
+            PinDriver::input(unsafe { AnyIOPin::new(CONFIG.fuel_gauge_alert_gpio) }).and_then(
+                |mut pin| {
+                    pin.set_pull(Pull::Up)?;
+                    Ok(pin)
+                },
+            )
+        })
+        .and_then(|pin| {
+            pin.map_err(|e| log::error!("Fail setting the fuel gauge alert pin: {e}"))
+                .ok()
+        });
+    #[cfg(feature = "oled")]
+    let mut oled = if CONFIG.oled_enabled {
+        oled::Oled::new(i2c::RefCellDevice::new(&i2c_bus))
+            .map_err(|e| log::error!("Fail initiating OLED display: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    // Demo mode runs without the sensor head, the BME680 must not be probed
+    let mut demo = demo_mode_active(&CONFIG).then(|| {
+        log::warn!("DEMO MODE: sensors are replaced by synthetic data");
+        DemoWeather::new(unsafe { esp_random() })
+    });
+    let mut env_sensors: Vec<TrackedSensor> = Vec::new();
+    // Set up again by the main loop, published as degraded until then
+    let mut pending_env: Vec<init::PendingSensor> = Vec::new();
+    #[cfg(feature = "bme680")]
+    if demo.is_none() {
+        let configured = [
+            (
+                CONFIG.bme680_enabled,
+                CONFIG.bme680_name,
+                CONFIG.bme680_address,
+                CONFIG.bme680_mux_channel,
+                EnvOffsets {
+                    temperature: CONFIG.bme680_temp_offset,
+                    humidity: CONFIG.bme680_humidity_offset,
+                    pressure: CONFIG.bme680_pressure_offset,
+                },
+            ),
+            (
+                CONFIG.bme680_2_enabled,
+                CONFIG.bme680_2_name,
+                CONFIG.bme680_2_address,
+                CONFIG.bme680_2_mux_channel,
+                EnvOffsets {
+                    temperature: CONFIG.bme680_2_temp_offset,
+                    humidity: CONFIG.bme680_2_humidity_offset,
+                    pressure: CONFIG.bme680_2_pressure_offset,
+                },
+            ),
+        ];
+        for (enabled, name, address, channel, offsets) in configured {
+            if !enabled {
+                continue;
+            }
+            let i2c_bus = &i2c_bus;
+            let setup = move || {
+                let bme = Bme680Sensor::new(i2c_bus, &mut mux_handle(), address, channel)?;
+                let sensor =
+                    EnvSensor::new(name, offsets, Box::new(bme)).behind_mux(mux_handle(), channel);
+                Ok(Box::new(sensor) as Box<dyn Sensor>)
+            };
+            match init::start_sensor(name, setup) {
+                Ok(sensor) => env_sensors.push(TrackedSensor::new(sensor)),
+                Err(pending) => pending_env.push(pending),
+            }
+        }
+    }
+    #[cfg(not(feature = "bme680"))]
+    if CONFIG.bme680_enabled || CONFIG.bme680_2_enabled {
+        log::warn!("Built without the bme680 feature, the BME680 is ignored");
+    }
+    if demo.is_none() {
+        if CONFIG.dht_enabled {
+            let offsets = EnvOffsets {
+                temperature: CONFIG.dht_temp_offset,
+                humidity: CONFIG.dht_humidity_offset,
+                pressure: 0.0,
+            };
+            match DhtKind::from_name(CONFIG.dht_type) {
+                Some(kind) => {
+                    // The pin comes from the config, no other driver claims it
+                    let setup = move || {
+                        let dht =
+                            DhtSensor::start(unsafe { AnyIOPin::new(CONFIG.dht_gpio) }, kind)?;
+                        let sensor = EnvSensor::new(CONFIG.dht_name, offsets, Box::new(dht));
+                        Ok(Box::new(sensor) as Box<dyn Sensor>)
+                    };
+                    match init::start_sensor(CONFIG.dht_name, setup) {
+                        Ok(sensor) => env_sensors.push(TrackedSensor::new(sensor)),
+                        Err(pending) => pending_env.push(pending),
+                    }
+                }
+                None => log::error!("Unknown dht_type {}", CONFIG.dht_type),
+            }
+        }
+
+        // Fields of the sensors this build or this station lacks are never published
+        let mut missing = Vec::new();
+        if !cfg!(feature = "rain") {
+            missing.extend([Field::Rain, Field::RainTotals, Field::RainStats]);
+        }
+        if ultrasonic.is_none() {
+            if !cfg!(feature = "anemometer") {
+                missing.extend([Field::WindSpeed, Field::WindGust, Field::WindRose]);
+            }
+            if vane.is_none() {
+                missing.extend([Field::WindDirection, Field::WindRose]);
+            }
+        }
+        if env_sensors.is_empty() && pending_env.is_empty() {
+            missing.extend([
+                Field::Temperature,
+                Field::Humidity,
+                Field::Pressure,
+                Field::Trends,
+                Field::Derived,
+            ]);
+        }
+        RUNTIME.set_missing(&missing);
+    }
 
     // MQTT LOOP
-    let (mut mqtt_cli, mut mqtt_conn) = mqtt::mqtt_create().expect("Fail creating mqtt client");
+    let (mut mqtt_cli, mqtt_conn) = init::retry("mqtt", init::BOOT_ATTEMPTS, mqtt::mqtt_create)
+        .unwrap_or_else(|e| init::restart_later("mqtt", e));
+    init::boot_completed();
     std::thread::scope(|s| {
+        if let Some(pin_button) = pin_button {
+            let tx = event_tx.clone();
+            std::thread::Builder::new()
+                .stack_size(3000)
+                .spawn_scoped(s, move || button::watch_button(pin_button, status_led, tx))
+                .expect("An error occurred with button thread");
+        }
+
         info!("Starting MQTT client");
 
         // Create a thread that will keep alive the connection between broker and client
-        std::thread::Builder::new()
-            .stack_size(6000)
-            .spawn_scoped(s, move || {
-                info!("MQTT Listening for messages");
-                while let Ok(event) = mqtt_conn.next() {
-                    info!("[Queue] Event: {}", event.payload());
-                }
-                info!("Connection closed");
-            })
-            .expect("An error occurred with mqtt client");
+        let spawn_listener = |mut mqtt_conn: EspMqttConnection| {
+            let event_tx = event_tx.clone();
+            std::thread::Builder::new()
+                .stack_size(6000)
+                .spawn_scoped(s, move || {
+                    info!("MQTT Listening for messages");
+                    mqtt::forward_events(&mut mqtt_conn, event_tx);
+                    info!("Connection closed");
+                })
+                .expect("An error occurred with mqtt client");
+        };
+        spawn_listener(mqtt_conn);
 
         let active_duration = Duration::from_secs(CONFIG.active_duration_s + 1);
         let start_time = Instant::now();
+        let mut last_precip_sample = Instant::now();
+        let mut last_wind_sample = Instant::now();
+        let mut last_power_sample = Instant::now();
+        let mut battery_power = None;
+        let mut fuel_gauge_sample: Option<FuelGaugeSample> = None;
+        let mut battery_sample: Option<BatterySample> = None;
+        #[cfg(feature = "epaper")]
+        let mut battery_v = None;
+        let mut solar_power = None;
+        // The accumulation is kept in RTC memory with the tips it already holds
+        let mut rain_tips_sampled = sampled_tips;
+        let mut diag_limiter = RateLimiter::new(diagnostics::DIAG_MIN_INTERVAL);
+        let mut wifi_quality_limiter =
+            RateLimiter::new(Duration::from_secs(CONFIG.wifi_quality_interval_s.into()));
+        let mut chip_temp = diagnostics::ChipTempSensor::new();
+        let mut chip_overheating = false;
+        let mut scheduler = PublishScheduler::new();
+        let mut wind_average = WindVectorAverage::default();
+        let mut vane_2_average = WindVectorAverage::default();
+        // Last wind direction and outdoor measurement, NMEA leaves the missing ones empty
+        let mut last_wind_angle = None;
+        let mut last_outdoor: Option<EnvData> = None;
+        let mut air_quality: Option<AirQuality> = None;
+        let mut wifi_stats: Option<mqtt::WifiStats> = None;
+        // The env sensors carry their own
+        let mut vane_health = SensorHealth::default();
+        let mut vane_2_health = SensorHealth::default();
+        let mut temperature_watch = emergency::TemperatureWatch::default();
+        let mut anemometer_health = SensorHealth::default();
+        let mut reading = WeatherReading {
+            demo: demo.is_some(),
+            ..Default::default()
+        };
+        let mut last_demo_step = Instant::now();
+        let mut reed_anemometer = ReedAnemometer::new(CONFIG.anemo_kmh_per_hz);
+        let mut gusts = GustTracker::default();
+        let mut last_gust_sample = Instant::now();
+        let mut rotations_sampled = 0;
+        let gust_period = Duration::from_secs(CONFIG.gust_sample_s.max(1) as u64);
+        // Only sensor polling is adaptive, the loop itself keeps a short tick so that the
+        // reed switch interrupts are re-armed promptly
+        let polling = SensorPollingScheduler::new(CONFIG.polling_base_interval_s);
+
+        let mut quiet = quiet_hours::QuietHours::new();
+        let mut maintenance = maintenance::Maintenance::new(cold_boot);
+        let mut connection = mqtt::ConnectionState::default();
+        let mut reconnect = Backoff::new(mqtt::RECONNECT_FIRST, mqtt::RECONNECT_MAX);
+        let mut reconnect_at: Option<Instant> = None;
+        // A hung I2C transaction or a deadlocked mutex resets the station instead of freezing it
+        let watch = (CONFIG.watchdog_timeout_s > 0)
+            .then(|| {
+                watchdog::configure(Duration::from_secs(CONFIG.watchdog_timeout_s as u64))
+                    .and_then(|_| watchdog::TaskWatch::start())
+                    .map_err(|e| log::error!("Fail starting the task watchdog: {e}"))
+                    .ok()
+            })
+            .flatten();
 
-        while start_time.elapsed() < active_duration {
-            check_rain_flag(&mut pin_rain);
-            check_rotation_flag(&mut pin_anemo);
+        // A quiet period without deep sleep keeps the station awake until it ends
+        while start_time.elapsed() < active_duration || quiet.keeps_awake() {
+            watchdog::feed();
+            while let Ok(event) = event_rx.try_recv() {
+                match event {
+                    mqtt::MqttEvent::Connected => {
+                        connection.connected();
+                        reconnect.reset();
+                        mqtt::publish_online(&mut mqtt_cli);
+                        mqtt::subscribe_commands(&mut mqtt_cli);
+                        // Reaching the broker proves the update, it could take the next one
+                        match ota::confirm_running_image() {
+                            Ok(true) => {
+                                log::warn!("Updated firmware confirmed");
+                                mqtt::publish_ota_status(
+                                    &mut mqtt_cli,
+                                    &format!("running {}", env!("CARGO_PKG_VERSION")),
+                                );
+                            }
+                            Ok(false) => {}
+                            Err(e) => log::error!("Fail confirming the firmware: {e}"),
+                        }
+                        mqtt::publish_discovery_beacon(&mut mqtt_cli, ip_address, false);
+                        mqtt::publish_ha_discovery(&mut mqtt_cli);
+                        mqtt::publish_maintenance(&mut mqtt_cli, &maintenance);
+                        if let Some(acoustic) = &acoustic {
+                            mqtt::publish_acoustic_thresholds(&mut mqtt_cli, acoustic);
+                        }
+                    }
+                    mqtt::MqttEvent::Disconnected => connection.disconnected(),
+                    mqtt::MqttEvent::Closed => {
+                        connection.disconnected();
+                        let delay = reconnect.next_delay();
+                        log::warn!("MQTT connection closed, new client in {}s", delay.as_secs());
+                        reconnect_at = Some(Instant::now() + delay);
+                    }
+                    mqtt::MqttEvent::Received { topic, payload } if topic == mqtt::diag_topic() => {
+                        diagnostics::handle_diag(
+                            &mut mqtt_cli,
+                            &payload,
+                            &mut diag_limiter,
+                            &i2c_bus,
+                        )
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::interval_topic() =>
+                    {
+                        match parse_interval_command(&String::from_utf8_lossy(&payload)) {
+                            Some((group, seconds)) => {
+                                info!("{} interval set to {seconds}s", group.name());
+                                RUNTIME.set_interval_s(group, seconds);
+                            }
+                            None => log::warn!("Invalid interval command"),
+                        }
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::command_topic() =>
+                    {
+                        match parse_station_command(&String::from_utf8_lossy(&payload)) {
+                            Some(StationCommand::Measure) => {
+                                info!("Command: publishing now");
+                                scheduler.force_all();
+                            }
+                            Some(StationCommand::Reboot) => {
+                                log::warn!("Command: rebooting");
+                                mqtt::publish_system_event(&mut mqtt_cli, "reboot");
+                                // Lets the event reach the broker
+                                FreeRtos::delay_ms(500);
+                                unsafe { esp_idf_svc::sys::esp_restart() }
+                            }
+                            Some(StationCommand::Interval(group, seconds)) => {
+                                info!("{} interval set to {seconds}s", group.name());
+                                RUNTIME.set_interval_s(group, seconds);
+                            }
+                            Some(StationCommand::Ota { url, sha256 }) => {
+                                ota::update_and_reboot(&mut mqtt_cli, url, sha256);
+                            }
+                            Some(StationCommand::LogLevel(level)) => {
+                                logger::set_station_level(level);
+                                info!("Log level set to {level}");
+                            }
+                            None => log::warn!("Invalid station command"),
+                        }
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::quiet_hours_topic() =>
+                    {
+                        let schedule = String::from_utf8_lossy(&payload);
+                        if quiet_hours::set_schedule(&schedule) {
+                            info!("Quiet hours schedule set to '{schedule}'");
+                        } else {
+                            log::warn!("Invalid quiet hours schedule '{schedule}'");
+                        }
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::config_topic() =>
+                    {
+                        let json = String::from_utf8_lossy(&payload);
+                        let outcome = match provisioner.as_mut() {
+                            Some(provisioner) if json.trim().is_empty() => provisioner
+                                .clear()
+                                .map(|cleared| cleared.then_some("cleared")),
+                            Some(provisioner) => provisioner
+                                .store_update(&json)
+                                .map(|stored| stored.then_some("stored")),
+                            None => Err(anyhow::anyhow!("no provisioning storage")),
+                        };
+                        match outcome {
+                            // A retained update must not reboot the station on every connection
+                            Ok(None) => mqtt::publish_config_update(&mut mqtt_cli, "unchanged"),
+                            Ok(Some(status)) => {
+                                log::warn!("Config {status}, rebooting to apply it");
+                                mqtt::publish_config_update(&mut mqtt_cli, status);
+                                // Lets the status reach the broker
+                                FreeRtos::delay_ms(500);
+                                unsafe { esp_idf_svc::sys::esp_restart() }
+                            }
+                            Err(e) => {
+                                log::warn!("Config update rejected: {e}");
+                                mqtt::publish_config_update(&mut mqtt_cli, &format!("error: {e}"));
+                            }
+                        }
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::fields_topic() =>
+                    {
+                        let list = String::from_utf8_lossy(&payload);
+                        for name in unknown_fields(&list) {
+                            log::warn!("Unknown field {name} in the field selection");
+                        }
+                        RUNTIME.set_fields(FieldSelection::parse(&list));
+                        info!("Published fields set to '{list}'");
+                        // Subscribers learn the new selection from the beacon
+                        mqtt::publish_discovery_beacon(&mut mqtt_cli, ip_address, false);
+                        mqtt::publish_ha_discovery(&mut mqtt_cli);
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::maintenance_topic() =>
+                    {
+                        let command = String::from_utf8_lossy(&payload);
+                        match parse_maintenance_command(&command, CONFIG.maintenance_duration_s) {
+                            Some(0) => maintenance.stop(),
+                            Some(seconds) => maintenance.start(Duration::from_secs(seconds as u64)),
+                            None => log::warn!("Invalid maintenance command '{command}'"),
+                        }
+                        mqtt::publish_maintenance(&mut mqtt_cli, &maintenance);
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::interlock_topic() =>
+                    {
+                        let command = String::from_utf8_lossy(&payload);
+                        match (InterlockMode::from_name(command.trim()), interlock.as_mut()) {
+                            (Some(mode), Some(interlock)) => {
+                                info!("Irrigation interlock set to {}", mode.name());
+                                interlock.set_mode(mode);
+                            }
+                            (None, _) => log::warn!("Invalid interlock command '{command}'"),
+                            (_, None) => log::warn!("Irrigation interlock is not available"),
+                        }
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::acoustic_topic() =>
+                    {
+                        let command = String::from_utf8_lossy(&payload);
+                        match acoustic.as_mut() {
+                            Some(acoustic) if acoustic.calibrate(&command) => {
+                                info!("Acoustic thresholds updated: {}", command.trim());
+                                mqtt::publish_acoustic_thresholds(&mut mqtt_cli, acoustic);
+                            }
+                            Some(_) => log::warn!("Invalid acoustic command '{command}'"),
+                            None => log::warn!("Acoustic rain sensing is not available"),
+                        }
+                    }
+                    mqtt::MqttEvent::Received { topic, .. }
+                        if topic == mqtt::test_publish_topic() =>
+                    {
+                        info!("Publishing synthetic test data");
+                        mqtt::test_publish(&mut mqtt_cli)
+                            .unwrap_or_else(|e| log::error!("Test publish failed: {e}"));
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::emergency_stop_topic() =>
+                    {
+                        let reason = String::from_utf8_lossy(&payload);
+                        let reason = if reason.is_empty() {
+                            "mqtt command".into()
+                        } else {
+                            reason
+                        };
+                        emergency::emergency_stop(&reason, mqtt_cli, &mut network, nvs)
+                    }
+                    mqtt::MqttEvent::Received { topic, payload }
+                        if topic == mqtt::rain_correct_topic() =>
+                    {
+                        let command = String::from_utf8_lossy(&payload);
+                        match (parse_rain_correction(&command), rain_stats.as_mut()) {
+                            (Some((scope, total)), Some(store)) => {
+                                let delta = store.stats.correct(scope, total);
+                                log::warn!(
+                                    "Rain {scope:?} total corrected to {total}mm ({delta:+}mm)"
+                                );
+                                store.save();
+                                mqtt::publish_rain_stats(&mut mqtt_cli, &store.stats);
+                            }
+                            (None, _) => log::warn!("Invalid rain correction command"),
+                            (_, None) => log::warn!("Rain statistics are not available"),
+                        }
+                    }
+                    mqtt::MqttEvent::Received { topic, .. } if topic == mqtt::wifi_log_topic() => {
+                        match wifi_monitor.as_ref() {
+                            Some(monitor) => {
+                                mqtt::publish_wifi_disconnects(&mut mqtt_cli, &monitor.log())
+                            }
+                            None => log::warn!("WiFi quality monitoring is not available"),
+                        }
+                    }
+                    mqtt::MqttEvent::Received { topic, .. } => {
+                        log::warn!("Unexpected message on {topic}")
+                    }
+                    mqtt::MqttEvent::Button(ButtonPress::Short) => {
+                        info!("Button: publishing now");
+                        scheduler.force_all();
+                    }
+                    mqtt::MqttEvent::Measure => {
+                        info!("HTTP: publishing now");
+                        scheduler.force_all();
+                    }
+                    mqtt::MqttEvent::Button(ButtonPress::Double) => {
+                        if maintenance.active() {
+                            maintenance.stop();
+                        } else {
+                            maintenance
+                                .start(Duration::from_secs(CONFIG.maintenance_duration_s as u64));
+                        }
+                        mqtt::publish_maintenance(&mut mqtt_cli, &maintenance);
+                    }
+                    mqtt::MqttEvent::Button(ButtonPress::Long) => {
+                        log::warn!("Button: rebooting into the setup portal");
+                        match provisioner.as_mut().map(|p| p.request_portal()) {
+                            Some(Ok(())) => unsafe { esp_idf_svc::sys::esp_restart() },
+                            Some(Err(e)) => log::error!("Fail requesting the setup portal: {e}"),
+                            None => log::error!("No provisioning storage for the setup portal"),
+                        }
+                    }
+                    mqtt::MqttEvent::Button(ButtonPress::VeryLong) => factory_reset(),
+                }
+            }
+            // The subscriptions are renewed by the Connected event of the new client
+            if reconnect_at.is_some_and(|at| Instant::now() >= at) {
+                reconnect_at = None;
+                match mqtt::mqtt_create() {
+                    Ok((cli, conn)) => {
+                        mqtt_cli = cli;
+                        spawn_listener(conn);
+                    }
+                    Err(e) => {
+                        let delay = reconnect.next_delay();
+                        log::error!(
+                            "Fail creating mqtt client, retrying in {}s: {e}",
+                            delay.as_secs()
+                        );
+                        reconnect_at = Some(Instant::now() + delay);
+                    }
+                }
+            }
+            // A few per tick, the measurements keep their pace during a long replay
+            if let Some(buffer) = offline.as_mut().filter(|_| connection.is_connected()) {
+                for _ in 0..mqtt::REPLAY_PER_TICK {
+                    let Some(record) = buffer.front() else {
+                        break;
+                    };
+                    if !mqtt::publish_replayed(&mut mqtt_cli, &record) {
+                        break;
+                    }
+                    buffer.pop_front();
+                }
+            }
+            // Held back until the broker is up and the clock can tell the age of the record
+            if connection.is_connected() && clock.is_synced() {
+                if let Some(last) = pending_restore.take() {
+                    let age = last.age(unix_time_ms());
+                    if age <= Duration::from_secs(CONFIG.restore_max_age_s as u64) {
+                        info!("Republishing the values from {}s ago", age.as_secs());
+                        mqtt::publish_restored(
+                            &mut mqtt_cli,
+                            &last,
+                            rain_stats.as_ref().map(|store| &store.stats),
+                            clock.local_now().num_days_from_ce(),
+                        );
+                    } else {
+                        info!("Last known values are {}s old, not restored", age.as_secs());
+                    }
+                }
+            }
 
-            if check_time_passed() {
-                let wind_direction = get_wind_direction(&mut as5600);
-                let bme_readings = get_bme_readings(&mut bme);
+            if maintenance.update() {
+                mqtt::publish_maintenance(&mut mqtt_cli, &maintenance);
+            }
+            reading.maintenance = maintenance.active();
+
+            // Hours are only placed on a synced clock
+            if clock.is_synced() {
+                if let Some(summary) = rain_tips.roll_hour(unix_time_ms(), CONFIG.mm_per_tip) {
+                    mqtt::publish_rain_hour(&mut mqtt_cli, &summary);
+                }
+            }
+            for _ in 0..take_new_rain_tips() {
+                let timestamp = unix_time_ms();
+                rain_tips.record(timestamp);
+                if CONFIG.rain_tip_events {
+                    mqtt::publish_rain_tip(&mut mqtt_cli, timestamp);
+                }
+            }
+            if let Some(counter) = rotation_counter.as_mut() {
+                counter.poll();
+            }
+            if let Some(demo) = demo.as_mut() {
+                let dt = last_demo_step.elapsed().as_secs_f32();
+                last_demo_step = Instant::now();
+                let (rotations, tips) = demo.step(dt, CONFIG.anemo_kmh_per_hz, CONFIG.mm_per_tip);
+                ROTATION_COUNT.fetch_add(rotations, Ordering::Relaxed);
+                RAIN_COUNT.fetch_add(tips, Ordering::Relaxed);
+                for _ in 0..tips {
+                    rain_tips.record(unix_time_ms());
+                }
+            }
+
+            if last_gust_sample.elapsed() >= gust_period {
+                rotations_sampled =
+                    sample_gust(&mut gusts, rotations_sampled, &mut last_gust_sample);
+            }
 
-                mqtt::publish_wifi_data(&mut mqtt_cli, &mut wifi);
-                mqtt::publish_bme_data(&mut mqtt_cli, bme_readings);
-                mqtt::publish_anemo_data(&mut mqtt_cli, wind_direction);
-                mqtt::publish_rain_data(&mut mqtt_cli);
+            if last_precip_sample.elapsed() >= PRECIP_SAMPLE_PERIOD {
+                last_precip_sample = Instant::now();
+                rain_tips_sampled = sample_rain(precip, rain_tips_sampled);
+                precip.tick();
+            }
+
+            // Charge is only counted while awake, a fuel gauge alert is handled right away
+            let gauge_alert = fuel_gauge_alert.as_ref().is_some_and(|pin| pin.is_low());
+            if last_power_sample.elapsed() >= POWER_SAMPLE_PERIOD || gauge_alert {
+                let elapsed = last_power_sample.elapsed().as_secs_f32();
+                last_power_sample = Instant::now();
+                if let Some(ina) = ina_battery.as_mut() {
+                    match ina.read() {
+                        Ok(mut sample) => {
+                            if CONFIG.ina_battery_inverted {
+                                sample.current_a = -sample.current_a;
+                            }
+                            charge.add(sample.current_a, elapsed);
+                            if fuel_gauge.is_none() {
+                                polling.set_low_battery(sample.voltage_v < CONFIG.low_battery_v);
+                            }
+                            #[cfg(feature = "epaper")]
+                            {
+                                battery_v = Some(sample.voltage_v);
+                            }
+                            battery_power = Some(sample);
+                        }
+                        Err(e) => log::error!("Fail reading battery power: {e}"),
+                    }
+                }
+                if let Some(gauge) = fuel_gauge.as_mut() {
+                    match gauge.read() {
+                        Ok(mut sample) => {
+                            if sample.alert {
+                                log::warn!(
+                                    "Fuel gauge alert, state of charge {:.1}%",
+                                    sample.soc_percent
+                                );
+                            }
+                            // Kept until published, the gauge forgets it once read
+                            sample.alert |= fuel_gauge_sample.is_some_and(|s| s.alert);
+                            polling.set_low_battery(
+                                sample.soc_percent < CONFIG.fuel_gauge_low_soc as f32,
+                            );
+                            fuel_gauge_sample = Some(sample);
+                        }
+                        Err(e) => log::error!("Fail reading fuel gauge: {e}"),
+                    }
+                }
+                if let Some(adc) = battery_adc.as_mut() {
+                    match adc.read() {
+                        Ok(sample) => {
+                            // The INA and the fuel gauge are the better judges when fitted
+                            if fuel_gauge.is_none() && ina_battery.is_none() {
+                                polling.set_low_battery(sample.voltage_v < CONFIG.low_battery_v);
+                            }
+                            #[cfg(feature = "epaper")]
+                            if ina_battery.is_none() {
+                                battery_v = Some(sample.voltage_v);
+                            }
+                            battery_sample = Some(sample);
+                        }
+                        Err(e) => log::error!("Fail reading battery ADC: {e}"),
+                    }
+                }
+                if let Some(ina) = ina_solar.as_mut() {
+                    solar_power = ina
+                        .read()
+                        .map_err(|e| log::error!("Fail reading solar power: {e}"))
+                        .ok();
+                }
+            }
+
+            if last_wind_sample.elapsed() >= polling.interval() {
+                last_wind_sample = Instant::now();
+                let angle = match demo.as_mut() {
+                    Some(demo) => Some(demo.wind_direction()),
+                    // Polled at the wind group interval instead
+                    None if ultrasonic.is_some() => None,
+                    None => vane.as_mut().and_then(|vane| {
+                        let result = vane.read();
+                        match vane_health.record(vane.id(), result) {
+                            Some(Measurement::WindAngle(angle)) => Some(angle),
+                            _ => None,
+                        }
+                    }),
+                };
+                if let Some(angle) = angle {
+                    wind_average.add(angle);
+                }
+                if let Some(vane) = vane_2.as_mut().filter(|_| demo.is_none()) {
+                    let result = vane.read();
+                    if let Some(Measurement::WindAngle(angle)) =
+                        vane_2_health.record(vane.id(), result)
+                    {
+                        vane_2_average.add(angle);
+                    }
+                }
+            }
+
+            // Resets once per local day, however long the station slept or was off
+            if clock.is_synced() {
+                let day = clock.local_now().num_days_from_ce();
+                if precip.reset_daily(day) | wind_rose.reset_daily(day) | charge.reset_daily(day) {
+                    info!("New local day, daily totals reset");
+                }
+            }
+            if clock.is_synced() && wind_rose.publish_due(unix_time_ms()) {
+                mqtt::publish_wind_rose(&mut mqtt_cli, wind_rose);
+            }
+            if clock.is_synced() {
+                if let Some(summary) = hourly.roll(unix_time_ms()) {
+                    mqtt::publish_hourly(&mut mqtt_cli, &summary);
+                }
+            }
+
+            if quiet.update(&clock) {
+                mqtt::publish_quiet_hours(&mut mqtt_cli, &quiet);
+            }
+            // Left as it is until the clock can place the rain history
+            if let Some(interlock) = interlock.as_mut().filter(|_| clock.is_synced()) {
+                if interlock.update((unix_time_ms() / 1000) as u32) {
+                    mqtt::publish_interlock(&mut mqtt_cli, interlock);
+                }
+            }
+            // In quiet hours with deep sleep or in low power mode a wakeup makes one
+            // measurement, once the wind had time to be sampled
+            let quiet_round = (quiet.sleeps_between_measurements() || CONFIG.low_power_enabled)
+                && start_time.elapsed() >= quiet_hours::MEASURE_WINDOW;
+            if quiet_round {
+                scheduler.force_all();
+            }
+
+            let mut published = false;
+            let split = CONFIG.split_group_publish;
+
+            if scheduler.due(PublishGroup::Wind) {
+                let mut wind_angle = wind_average.mean();
+                wind_average.clear();
+                let mut wind_speed = reed_anemometer.speed_kmh();
+                // The rotations of the unfinished gust sample only count toward the mean
+                rotations_sampled = 0;
+                last_gust_sample = Instant::now();
+                if let Some(anemometer) = ultrasonic.as_mut().filter(|_| demo.is_none()) {
+                    // Without a reading the group is published as calm with an unknown direction
+                    let result = anemometer.wind();
+                    (wind_speed, wind_angle) =
+                        match anemometer_health.record(anemometer.id(), result) {
+                            Some((speed, direction)) => (speed, Some(direction)),
+                            None => (0.0, None),
+                        };
+                }
+                reading.wind_direction_deg = wind_angle.unwrap_or_default();
+                last_wind_angle = wind_angle;
+                reading.wind_speed_kmh = wind_speed;
+                reading.wind_gust_kmh = gusts.take(wind_speed);
+                polling.adjust(precip.total_1h(), reading.wind_speed_kmh / 3.6);
+                hourly.add_wind(reading.wind_speed_kmh, wind_angle);
+                if let Some(angle) = wind_angle {
+                    wind_rose.add(
+                        angle,
+                        reading.wind_speed_kmh,
+                        RUNTIME.interval_s(PublishGroup::Wind) as f32,
+                        CONFIG.wind_calm_kmh,
+                    );
+                }
+                if let Some(vane) = &vane_2 {
+                    if let Some(angle) = vane_2_average.mean() {
+                        mqtt::publish_vane(&mut mqtt_cli, vane.id(), angle);
+                    }
+                    vane_2_average.clear();
+                }
+                if split {
+                    let wind_direction = wind_angle.map_or("NA", wind_direction_from_angle);
+                    mqtt::publish_anemo_data(
+                        &mut mqtt_cli,
+                        wind_direction.to_string(),
+                        reading.wind_speed_kmh,
+                        reading.wind_gust_kmh,
+                    );
+                }
+                published = true;
+            }
+
+            if scheduler.due(PublishGroup::Environment) {
+                let mut outdoor = demo.as_mut().map(|demo| {
+                    let now = clock.local_now();
+                    let hour = now.hour() as f32 + now.minute() as f32 / 60.0;
+                    let (temperature, humidity, pressure) = demo.environment(hour);
+                    EnvData {
+                        temperature,
+                        humidity,
+                        pressure: Some(pressure),
+                        gas_resistance: None,
+                    }
+                });
+                pending_env.retain_mut(|pending| match pending.retry() {
+                    Some(sensor) => {
+                        env_sensors.push(TrackedSensor::new(sensor));
+                        false
+                    }
+                    None => true,
+                });
+                for sensor in env_sensors.iter_mut() {
+                    let data = match sensor.read() {
+                        Some(Measurement::Environment(data)) => Some(data),
+                        _ => None,
+                    };
+                    if let Some(data) = &data {
+                        if temperature_watch.out_of_range(sensor.id(), data.temperature) {
+                            let reason = format!(
+                                "{} temperature out of range ({}C)",
+                                sensor.id(),
+                                data.temperature
+                            );
+                            emergency::emergency_stop(&reason, mqtt_cli, &mut network, nvs);
+                        }
+                    }
+                    mqtt::publish_env(&mut mqtt_cli, sensor, data.as_ref());
+                    if sensor.id() == CONFIG.env_outdoor {
+                        outdoor = data;
+                    }
+                }
+                last_outdoor = outdoor;
+                if let Some(sdi12) = &sdi12 {
+                    mqtt::publish_sdi12(&mut mqtt_cli, &sdi12.lock().unwrap());
+                    published = true;
+                }
+                if let Some(nodes) = gateway_nodes.as_ref().filter(|_| CONFIG.gateway_merge) {
+                    gateway::publish_nodes(&mut mqtt_cli, nodes, true);
+                }
+                // Derived metrics only come from the outdoor sensor
+                if let Some(bme_readings) = outdoor {
+                    reading.temperature = bme_readings.temperature;
+                    reading.humidity = bme_readings.humidity;
+                    if let Some(pressure) = bme_readings.pressure {
+                        reading.pressure = pressure;
+                        reading.sea_level_pressure = Some(sea_level_pressure(
+                            pressure,
+                            bme_readings.temperature,
+                            CONFIG.altitude_m,
+                        ));
+                    }
+                    if let (Some(gas), Some(store)) = (bme_readings.gas_resistance, iaq.as_mut()) {
+                        let quality = store.update(gas, bme_readings.humidity);
+                        if split {
+                            mqtt::publish_iaq(&mut mqtt_cli, &quality);
+                        }
+                        air_quality = Some(quality);
+                    }
+                    hourly.add_environment(
+                        reading.temperature,
+                        reading.humidity,
+                        bme_readings.pressure,
+                    );
+                    if clock.is_synced() {
+                        let now_s = (unix_time_ms() / 1000) as u32;
+                        trends.add(now_s, reading.temperature, reading.pressure);
+                        reading.trends = trends.trends(now_s, CONFIG.trend_window_s);
+                        // The direction of a calm wind tells nothing
+                        let wind_direction = (reading.wind_speed_kmh >= CONFIG.wind_calm_kmh)
+                            .then_some(reading.wind_direction_deg);
+                        let summer =
+                            is_summer(clock.local_now().month(), CONFIG.southern_hemisphere);
+                        reading.forecast = reading
+                            .sea_level_pressure
+                            .filter(|_| reading.trends.valid)
+                            .map(|pressure| {
+                                zambretti(
+                                    pressure,
+                                    reading.trends.tendency,
+                                    wind_direction,
+                                    summer,
+                                    CONFIG.southern_hemisphere,
+                                )
+                            });
+                    }
+                    if split {
+                        mqtt::publish_bme_data(
+                            &mut mqtt_cli,
+                            bme_readings,
+                            Derived::from_reading(&reading),
+                        );
+                        if let Some(forecast) = reading.forecast {
+                            mqtt::publish_forecast(&mut mqtt_cli, &reading.trends, forecast);
+                        }
+                    }
+                    published = true;
+                }
+            }
+
+            if scheduler.due(PublishGroup::Rain) {
+                // The published tips are taken off RAIN_COUNT, flush pending tips first. Tips
+                // counted meanwhile by the interrupt wait for the next publish
+                let tips = sample_rain(precip, rain_tips_sampled);
+                rain_tips_sampled = 0;
+                reading.rain_mm = tips_to_mm(tips, CONFIG.mm_per_tip);
+                reading.rain_totals = precip.totals();
+                hourly.add_rain(reading.rain_mm);
+                if let Some(interlock) = interlock.as_mut().filter(|_| clock.is_synced()) {
+                    interlock.add_rain((unix_time_ms() / 1000) as u32, reading.rain_mm);
+                }
+                if let Some(acoustic) = &acoustic {
+                    // Servicing the gauge is loud, it is not rain
+                    if maintenance.active() {
+                        acoustic.discard_report();
+                    } else {
+                        let report = acoustic.take_report();
+                        mqtt::publish_acoustic(&mut mqtt_cli, acoustic, &report, tips);
+                    }
+                }
+                if let Some(store) = rain_stats.as_mut() {
+                    // Totals are keyed to the local calendar, they only roll on a synced clock
+                    let rolled =
+                        clock.is_synced() && store.stats.roll(calendar_period(clock.local_now()));
+                    store.stats.add(reading.rain_mm);
+                    if rolled || reading.rain_mm > 0.0 {
+                        store.save();
+                    }
+                    mqtt::publish_rain_stats(&mut mqtt_cli, &store.stats);
+                }
+                if split {
+                    mqtt::publish_rain_data(&mut mqtt_cli, reading.rain_mm);
+                    mqtt::publish_precipitation(&mut mqtt_cli, precip);
+                }
+                RAIN_COUNT.fetch_sub(tips, Ordering::Relaxed);
+                published = true;
+            }
+
+            // Diagnostics are not part of the consolidated payload
+            if scheduler.due(PublishGroup::Diagnostics) {
+                // No wind vane to check with the RS485 sensor
+                if let Some(vane) = vane
+                    .as_mut()
+                    .filter(|_| demo.is_none() && ultrasonic.is_none())
+                {
+                    if vane.status() == AngleStatus::TooStrong {
+                        emergency::emergency_stop(
+                            "vane magnet too strong",
+                            mqtt_cli,
+                            &mut network,
+                            nvs,
+                        );
+                    }
+                }
+                let ip = network.ip();
+                let wifi_reconnects = network.wifi_reconnects();
+                wifi_stats = mqtt::publish_device_telemetry(
+                    &mut mqtt_cli,
+                    network.active_wifi(),
+                    ip,
+                    wifi_reconnects,
+                    &connection,
+                );
+                if let Some(monitor) = wifi_monitor.as_mut() {
+                    if let Some(stats) = wifi_stats {
+                        monitor.sample_rssi(stats.rssi);
+                    }
+                    monitor.save();
+                    if wifi_quality_limiter.allow() {
+                        if let Some(report) = monitor.report() {
+                            mqtt::publish_wifi_quality(&mut mqtt_cli, &report);
+                        }
+                    }
+                }
+                if let Some(state) =
+                    ChargeState::from_samples(battery_power.as_ref(), solar_power.as_ref())
+                {
+                    mqtt::publish_charge_state(&mut mqtt_cli, state);
+                }
+                if let Some(sample) = battery_power.take() {
+                    mqtt::publish_power(&mut mqtt_cli, "battery", &sample, Some(&*charge));
+                }
+                if let Some(sample) = solar_power.take() {
+                    mqtt::publish_power(&mut mqtt_cli, "solar", &sample, None);
+                }
+                if let Some(sample) = fuel_gauge_sample.take() {
+                    mqtt::publish_fuel_gauge(&mut mqtt_cli, &sample);
+                }
+                if let Some(sample) = battery_sample.take() {
+                    mqtt::publish_battery(&mut mqtt_cli, &sample);
+                }
+                if CONFIG.uploader_enabled {
+                    mqtt::publish_upload_rejected(&mut mqtt_cli, uploader::rejected_uploads());
+                }
+                mqtt::publish_quiet_hours(&mut mqtt_cli, &quiet);
+                mqtt::publish_maintenance(&mut mqtt_cli, &maintenance);
+                if let Some(interlock) = &interlock {
+                    mqtt::publish_interlock(&mut mqtt_cli, interlock);
+                }
+                if let Some(status) = network.cellular_status() {
+                    mqtt::publish_cellular(&mut mqtt_cli, &status);
+                }
+                if let Some(status) = &config_fetch {
+                    mqtt::publish_config_fetch(&mut mqtt_cli, status);
+                }
+                let degraded_now: Vec<&str> = degraded
+                    .iter()
+                    .copied()
+                    .chain(pending_env.iter().map(|pending| pending.name()))
+                    .collect();
+                mqtt::publish_degraded(&mut mqtt_cli, &degraded_now);
+                // Degraded sensors go without health, they were never set up
+                let health: Vec<(&str, Option<&SensorHealth>)> = env_sensors
+                    .iter()
+                    .map(|sensor| (sensor.id(), Some(&sensor.health)))
+                    .chain(vane.as_ref().map(|vane| (vane.id(), Some(&vane_health))))
+                    .chain(
+                        vane_2
+                            .as_ref()
+                            .map(|vane| (vane.id(), Some(&vane_2_health))),
+                    )
+                    .chain(
+                        ultrasonic
+                            .as_ref()
+                            .map(|anemometer| (anemometer.id(), Some(&anemometer_health))),
+                    )
+                    .chain(degraded_now.iter().map(|name| (*name, None)))
+                    .collect();
+                mqtt::publish_sensor_health(&mut mqtt_cli, &health);
+                if let Some(anemometer) = &ultrasonic {
+                    mqtt::publish_anemometer_errors(&mut mqtt_cli, anemometer.errors);
+                }
+                if let Some(buffer) = offline.as_ref().filter(|buffer| buffer.dropped() > 0) {
+                    mqtt::publish_offline_dropped(&mut mqtt_cli, buffer.dropped());
+                }
+                if let Some(mppt) = &mppt {
+                    mqtt::publish_mppt(&mut mqtt_cli, &mppt.lock().unwrap());
+                }
+                if let Some(celsius) = chip_temp.as_mut().and_then(|sensor| sensor.read()) {
+                    mqtt::publish_chip_temp(&mut mqtt_cli, celsius);
+                    let changed =
+                        diagnostics::check_chip_temp(&mut mqtt_cli, celsius, &mut chip_overheating);
+                    if let Some(live_feed) = live_feed
+                        .as_ref()
+                        .filter(|_| changed && !maintenance.active())
+                    {
+                        live_feed.push(format!(
+                            "{{\"alert\": \"chip_temp_high\", \"active\": {chip_overheating}, \"chip_temp\": {celsius}}}"
+                        ));
+                    }
+                }
+            }
+
+            if published {
+                // Fresh values replace the restored ones
+                pending_restore = None;
+                // Only readings with a trustworthy timestamp are worth replaying
+                if let Some(buffer) = offline
+                    .as_mut()
+                    .filter(|_| !connection.is_connected() && clock.is_synced())
+                {
+                    buffer.push(TimedReading {
+                        timestamp_ms: unix_time_ms(),
+                        reading,
+                    });
+                }
+                if let Some(store) = last_known.as_mut().filter(|_| clock.is_synced()) {
+                    store.update(restore::LastKnown {
+                        timestamp_ms: unix_time_ms(),
+                        reading,
+                        rain_today_mm: precip.total_today(),
+                        day: clock.local_now().num_days_from_ce(),
+                    });
+                }
+                *latest_reading.lock().unwrap() = reading;
+                history.lock().unwrap().push(TimedReading {
+                    timestamp_ms: unix_time_ms(),
+                    reading,
+                });
+                temperature_24h.add(unix_time_ms(), reading.temperature);
+                gust_24h.add(unix_time_ms(), reading.wind_gust_kmh);
+                if let Some(beacon) = beacon.as_mut() {
+                    beacon.send(&reading);
+                }
+                #[cfg(feature = "lora")]
+                if let Some(lora) = lora
+                    .as_mut()
+                    .filter(|_| CONFIG.lora_always || !connection.is_connected())
+                {
+                    lora.send_reading(&reading);
+                }
+                if let Some(nmea) = nmea.as_mut() {
+                    nmea.send(
+                        last_wind_angle,
+                        Some(reading.wind_speed_kmh),
+                        last_outdoor.map(|env| env.temperature),
+                        last_outdoor.and_then(|env| env.pressure),
+                        last_outdoor.map(|env| env.humidity),
+                    );
+                }
+                if !split {
+                    mqtt::publish_reading(
+                        &mut mqtt_cli,
+                        &reading,
+                        last_outdoor.as_ref(),
+                        air_quality,
+                        wifi_stats,
+                        sdi12.as_ref().map(|sdi12| sdi12.lock().unwrap()).as_deref(),
+                    );
+                }
+                if let Some(live_feed) = &live_feed {
+                    live_feed.push(reading.to_json());
+                }
+                #[cfg(feature = "esphome")]
+                if let Some(esphome) = &esphome {
+                    esphome.push(&reading);
+                }
+                #[cfg(feature = "bthome")]
+                if let Some(bthome) = &bthome {
+                    bthome.push(&reading);
+                }
+                #[cfg(feature = "ble_ess")]
+                if let Some(ble_ess) = &ble_ess {
+                    ble_ess.update(&reading);
+                }
+                #[cfg(feature = "oled")]
+                if let Some(oled) = oled.as_mut() {
+                    oled.set_reading(&reading);
+                }
+                #[cfg(feature = "epaper")]
+                if let Some(epaper) = &epaper {
+                    let now = clock.local_now();
+                    *epaper.lock().unwrap() = Some(epaper::DisplayStatus {
+                        temperature: reading.temperature,
+                        temperature_24h: temperature_24h.min_max(unix_time_ms()),
+                        humidity: reading.humidity,
+                        pressure: reading
+                            .sea_level_pressure
+                            .or((reading.pressure > 0.0).then_some(reading.pressure)),
+                        wind_speed_kmh: reading.wind_speed_kmh,
+                        gust_max_24h: gust_24h.min_max(unix_time_ms()).map(|(_, max)| max),
+                        wind_direction_deg: reading.wind_direction_deg,
+                        rain_today_mm: precip.total_today(),
+                        battery_v,
+                        time: format!("{:02}:{:02}", now.hour(), now.minute()),
+                    });
+                }
+            }
+            if let Some(uplink) = network.supervise() {
+                mqtt::publish_system_event(&mut mqtt_cli, &format!("uplink: {}", uplink.name()));
+            }
+            if CONFIG.diag_ap_after_min > 0 {
+                diag_ap.update(&mut network, http_server.as_mut());
+            }
+            #[cfg(feature = "oled")]
+            if let Some(oled) = oled.as_mut() {
+                oled.update(oled::LinkStatus {
+                    network: network.wifi_down_for().is_none(),
+                    mqtt: connection.is_connected(),
+                });
+            }
+            if let Some(nodes) = &gateway_nodes {
+                gateway::publish_nodes(&mut mqtt_cli, nodes, !CONFIG.gateway_merge);
+            }
+            if let Some(guard) = boot_guard.as_mut() {
+                if guard.healthy_uptime_reached(start_time.elapsed()) {
+                    guard.mark_healthy();
+                }
+            }
+            if quiet_round {
+                info!("Single measurement round done");
+                break;
             }
             FreeRtos::delay_ms(100);
         }
+        drop(watch);
 
-        info!("Going to deep sleep...");
+        // Reaching deep sleep is a clean end of the run
+        if let Some(guard) = boot_guard.as_mut() {
+            guard.mark_healthy();
+        }
+        // The rain gauge wakeup stays armed, tips are counted in quiet hours too
+        let sleep = if CONFIG.low_power_enabled && !quiet.sleeps_between_measurements() {
+            low_power::sleep_duration(start_time.elapsed())
+        } else {
+            quiet.sleep_duration(&clock)
+        };
+        if CONFIG.low_power_enabled {
+            let unpublished = RAIN_COUNT.load(Ordering::Relaxed);
+            low_power::keep_unpublished_tips(unpublished, rain_tips_sampled);
+            low_power::arm_wakeups(reading.wind_speed_kmh < low_power::CALM_KMH);
+        }
+        quiet_hours::record_sleep(sleep);
+        maintenance.record_sleep();
+        if let Some(buffer) = offline.as_mut() {
+            buffer.flush();
+        }
+        if let Some(store) = iaq.as_mut() {
+            store.save();
+        }
+        info!("Going to deep sleep for {}s...", sleep.as_secs());
         unsafe {
+            esp_sleep_enable_timer_wakeup(sleep.as_micros() as u64);
             esp_deep_sleep_start();
         }
     });
 }
+
+// Pins come from the config, they are not claimed by any other driver
+fn as5048a_create(spi: SPI3) -> anyhow::Result<As5048a<'static>> {
+    let (sclk, mosi, miso, cs) = unsafe {
+        (
+            AnyIOPin::new(CONFIG.as5048a_sclk_gpio),
+            AnyIOPin::new(CONFIG.as5048a_mosi_gpio),
+            AnyIOPin::new(CONFIG.as5048a_miso_gpio),
+            AnyIOPin::new(CONFIG.as5048a_cs_gpio),
+        )
+    };
+    let driver = SpiDriver::new(spi, sclk, mosi, Some(miso), &SpiDriverConfig::new())?;
+    let spi = SpiDeviceDriver::new(
+        driver,
+        Some(cs),
+        &SpiConfig::new()
+            .baudrate(Hertz(1_000_000))
+            .data_mode(MODE_1),
+    )?;
+    Ok(As5048a::new(spi))
+}
+
+// Erases the NVS partition (wifi credentials, stored counters) and reboots
+fn factory_reset() -> ! {
+    log::warn!("Factory reset");
+    unsafe {
+        esp_idf_svc::sys::nvs_flash_erase();
+        esp_idf_svc::sys::esp_restart()
+    }
+}
+
+// "ws-" followed by the last 3 bytes of the factory MAC address
+fn init_station_id() -> heapless::String<16> {
+    let mut mac = [0u8; 6];
+    unsafe {
+        esp_efuse_mac_get_default(mac.as_mut_ptr());
+    }
+    let mut id = heapless::String::new();
+    write!(id, "ws-{:02X}{:02X}{:02X}", mac[3], mac[4], mac[5]).ok();
+    id
+}
+
+/// Identifier of this station: `device_id` from the config, or derived from the MAC address.
+pub fn station_id() -> &'static str {
+    if CONFIG.device_id.is_empty() {
+        STATION_ID.as_str()
+    } else {
+        CONFIG.device_id
+    }
+}
+
+// Add the rotations counted since the last sample to the gust window, returns the new
+// rotation count
+fn sample_gust(gusts: &mut GustTracker, rotations_sampled: u32, since: &mut Instant) -> u32 {
+    let rotations = ROTATION_COUNT.load(Ordering::Relaxed);
+    gusts.add(
+        rotations.saturating_sub(rotations_sampled),
+        since.elapsed().as_secs_f32(),
+        CONFIG.anemo_kmh_per_hz,
+    );
+    *since = Instant::now();
+    rotations
+}
+
+// Add the tips counted since the last sample to the accumulation, returns the new tip count
+fn sample_rain(precip: &mut PrecipitationAccumulation, tips_sampled: u32) -> u32 {
+    let tips = RAIN_COUNT.load(Ordering::Relaxed);
+    precip.add(tips_to_mm(
+        tips.saturating_sub(tips_sampled),
+        CONFIG.mm_per_tip,
+    ));
+    tips
+}
@@ -0,0 +1,114 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use weather_station::*;
+
+use crate::quiet_hours;
+
+// Maintenance time left and pulses discarded when the station went to deep sleep
+#[link_section = ".rtc.data"]
+static mut REMAINING_MS: u64 = 0;
+#[link_section = ".rtc.data"]
+static mut DISCARDED_TIPS: u32 = 0;
+#[link_section = ".rtc.data"]
+static mut DISCARDED_ROTATIONS: u32 = 0;
+
+/// Maintenance mode, while the gauge and anemometer are being serviced.
+///
+/// Their pulses go to the discard counters and the alerts are held back. The mode expires on
+/// its own and survives deep sleep, the time spent asleep counts toward the expiry.
+pub struct Maintenance {
+    until: Option<Instant>,
+}
+
+impl Maintenance {
+    /// A deep sleep wakeup resumes the mode that was active before the sleep.
+    pub fn new(cold_boot: bool) -> Self {
+        let mut maintenance = Self { until: None };
+        if cold_boot {
+            return maintenance;
+        }
+        let remaining = Duration::from_millis(unsafe { REMAINING_MS })
+            .saturating_sub(quiet_hours::last_sleep());
+        if !remaining.is_zero() {
+            unsafe {
+                DISCARDED_RAIN_COUNT.store(DISCARDED_TIPS, Ordering::Relaxed);
+                DISCARDED_ROTATION_COUNT.store(DISCARDED_ROTATIONS, Ordering::Relaxed);
+            }
+            maintenance.set(Some(Instant::now() + remaining));
+        }
+        maintenance
+    }
+
+    pub fn start(&mut self, duration: Duration) {
+        if !self.active() {
+            DISCARDED_RAIN_COUNT.store(0, Ordering::Relaxed);
+            DISCARDED_ROTATION_COUNT.store(0, Ordering::Relaxed);
+        }
+        log::warn!("Maintenance mode for {}s", duration.as_secs());
+        self.set(Some(Instant::now() + duration));
+    }
+
+    pub fn stop(&mut self) {
+        if self.active() {
+            log::warn!(
+                "Leaving maintenance mode, {} tips and {} rotations discarded",
+                DISCARDED_RAIN_COUNT.load(Ordering::Relaxed),
+                DISCARDED_ROTATION_COUNT.load(Ordering::Relaxed)
+            );
+        }
+        self.set(None);
+    }
+
+    /// Returns true when the mode just expired.
+    pub fn update(&mut self) -> bool {
+        if self.until.is_some_and(|until| Instant::now() >= until) {
+            self.stop();
+            return true;
+        }
+        false
+    }
+
+    pub fn active(&self) -> bool {
+        self.until.is_some()
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.until.map_or(Duration::ZERO, |until| {
+            until.saturating_duration_since(Instant::now())
+        })
+    }
+
+    /// Keeps the mode and its counters through the coming deep sleep.
+    pub fn record_sleep(&self) {
+        unsafe {
+            REMAINING_MS = self.remaining().as_millis() as u64;
+            DISCARDED_TIPS = DISCARDED_RAIN_COUNT.load(Ordering::Relaxed);
+            DISCARDED_ROTATIONS = DISCARDED_ROTATION_COUNT.load(Ordering::Relaxed);
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"active\": {}, \"remaining_s\": {}, \"discarded_tips\": {}, \"discarded_rotations\": {}}}",
+            self.active(),
+            self.remaining().as_secs(),
+            DISCARDED_RAIN_COUNT.load(Ordering::Relaxed),
+            DISCARDED_ROTATION_COUNT.load(Ordering::Relaxed)
+        )
+    }
+
+    fn set(&mut self, until: Option<Instant>) {
+        self.until = until;
+        MAINTENANCE.store(until.is_some(), Ordering::Relaxed);
+    }
+}
+
+/// Discards a tip that woke the station from deep sleep, returns false when the mode is not
+/// active and the tip counts.
+pub fn discard_sleep_tip() -> bool {
+    let active = Duration::from_millis(unsafe { REMAINING_MS }) > quiet_hours::last_sleep();
+    if active {
+        unsafe { DISCARDED_TIPS += 1 };
+    }
+    active
+}
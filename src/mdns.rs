@@ -0,0 +1,38 @@
+use anyhow::Result;
+use esp_idf_svc::mdns::EspMdns;
+
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+const SERVICE_TYPE: &str = "_weather";
+const SERVICE_PROTO: &str = "_tcp";
+// Port of the HTTP API, the TXT record tells whether it runs
+const HTTP_PORT: u16 = 80;
+
+/// Advertises `<mdns_hostname>.local` and a `_weather._tcp` service named after the station,
+/// until it is dropped. The TXT record carries the station id and the MQTT topic.
+pub fn mdns_start() -> Result<EspMdns> {
+    let mut mdns = EspMdns::take()?;
+    mdns.set_hostname(CONFIG.mdns_hostname)?;
+    mdns.set_instance_name(station_id())?;
+    let http = if CONFIG.http_enabled || CONFIG.network_hub_enabled {
+        "true"
+    } else {
+        "false"
+    };
+    mdns.add_service(
+        Some(station_id()),
+        SERVICE_TYPE,
+        SERVICE_PROTO,
+        HTTP_PORT,
+        &[
+            ("station_id", station_id()),
+            ("topic", CONFIG.topic),
+            ("http", http),
+            ("version", env!("CARGO_PKG_VERSION")),
+        ],
+    )?;
+    log::info!("Advertised as {}.local", CONFIG.mdns_hostname);
+
+    Ok(mdns)
+}
@@ -0,0 +1,416 @@
+//! Modbus register map of the station.
+//!
+//! Input registers (function 0x04), read only:
+//!
+//! | addr | value           | unit / scale       |
+//! |------|-----------------|--------------------|
+//! | 0    | temperature     | °C × 100, signed   |
+//! | 1    | humidity        | % × 100            |
+//! | 2    | pressure        | hPa × 10           |
+//! | 3    | wind speed      | km/h × 100         |
+//! | 4    | wind direction  | degrees            |
+//! | 5    | rain            | mm × 100           |
+//!
+//! Holding registers (functions 0x03, 0x06, 0x10):
+//!
+//! | addr | value                | unit / range         |
+//! |------|----------------------|----------------------|
+//! | 0    | BME680 interval      | seconds, 1..=3600    |
+//! | 1    | wind vane offset     | degrees, -180..=180  |
+//!
+//! Registers are 16 bit values transmitted big-endian. Signed values use two's complement.
+//!
+//! The map is served over Modbus TCP, and read only by the RTU slave on the RS485 bus.
+use crate::{
+    compute::crc16,
+    reading::WeatherReading,
+    runtime::{PublishGroup, RUNTIME},
+};
+
+pub const FN_READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const FN_READ_INPUT_REGISTERS: u8 = 0x04;
+pub const FN_WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const FN_WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+pub const EXCEPTION_ILLEGAL_FUNCTION: u8 = 0x01;
+pub const EXCEPTION_ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+pub const EXCEPTION_ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+pub const INPUT_REGISTER_COUNT: usize = 6;
+pub const HOLDING_REGISTER_COUNT: usize = 2;
+// Max registers per read request allowed by the specification
+const MAX_READ_QUANTITY: usize = 125;
+
+pub fn input_registers(reading: &WeatherReading) -> [u16; INPUT_REGISTER_COUNT] {
+    [
+        scale(reading.temperature, 100.0) as i16 as u16,
+        scale(reading.humidity, 100.0) as u16,
+        scale(reading.pressure, 10.0) as u16,
+        scale(reading.wind_speed_kmh, 100.0) as u16,
+        reading.wind_direction_deg.round() as u16,
+        scale(reading.rain_mm, 100.0) as u16,
+    ]
+}
+
+pub fn holding_registers() -> [u16; HOLDING_REGISTER_COUNT] {
+    [
+        RUNTIME
+            .interval_s(PublishGroup::Environment)
+            .min(u16::MAX as u32) as u16,
+        RUNTIME.vane_offset_deg() as i16 as u16,
+    ]
+}
+
+/// Validate and apply a write to a holding register. Returns the exception code on failure.
+pub fn write_holding_register(addr: u16, value: u16) -> Result<(), u8> {
+    match addr {
+        0 if (1..=3600).contains(&value) => {
+            RUNTIME.set_interval_s(PublishGroup::Environment, value as u32)
+        }
+        1 if (-180..=180).contains(&(value as i16)) => {
+            RUNTIME.set_vane_offset_deg(value as i16 as i32)
+        }
+        0 | 1 => return Err(EXCEPTION_ILLEGAL_DATA_VALUE),
+        _ => return Err(EXCEPTION_ILLEGAL_DATA_ADDRESS),
+    }
+    Ok(())
+}
+
+/// Process a request PDU (function code and data) and build the response PDU.
+pub fn handle_pdu(pdu: &[u8], reading: &WeatherReading) -> Vec<u8> {
+    let Some(&function) = pdu.first() else {
+        return exception(0, EXCEPTION_ILLEGAL_FUNCTION);
+    };
+    let result = match function {
+        FN_READ_INPUT_REGISTERS => read_registers(pdu, &input_registers(reading)),
+        FN_READ_HOLDING_REGISTERS => read_registers(pdu, &holding_registers()),
+        FN_WRITE_SINGLE_REGISTER => write_single(pdu),
+        FN_WRITE_MULTIPLE_REGISTERS => write_multiple(pdu),
+        _ => Err(EXCEPTION_ILLEGAL_FUNCTION),
+    };
+
+    result.unwrap_or_else(|code| exception(function, code))
+}
+
+fn read_registers(pdu: &[u8], registers: &[u16]) -> Result<Vec<u8>, u8> {
+    let (start, quantity) = address_and_quantity(pdu)?;
+    if quantity == 0 || quantity > MAX_READ_QUANTITY {
+        return Err(EXCEPTION_ILLEGAL_DATA_VALUE);
+    }
+    let values = registers
+        .get(start..start + quantity)
+        .ok_or(EXCEPTION_ILLEGAL_DATA_ADDRESS)?;
+
+    let mut response = vec![pdu[0], (quantity * 2) as u8];
+    for value in values {
+        response.extend_from_slice(&value.to_be_bytes());
+    }
+    Ok(response)
+}
+
+fn write_single(pdu: &[u8]) -> Result<Vec<u8>, u8> {
+    if pdu.len() != 5 {
+        return Err(EXCEPTION_ILLEGAL_DATA_VALUE);
+    }
+    let addr = u16::from_be_bytes([pdu[1], pdu[2]]);
+    let value = u16::from_be_bytes([pdu[3], pdu[4]]);
+    write_holding_register(addr, value)?;
+    // Normal response echoes the request
+    Ok(pdu.to_vec())
+}
+
+fn write_multiple(pdu: &[u8]) -> Result<Vec<u8>, u8> {
+    let (start, quantity) = address_and_quantity(pdu)?;
+    let byte_count = *pdu.get(5).ok_or(EXCEPTION_ILLEGAL_DATA_VALUE)? as usize;
+    if quantity == 0 || byte_count != quantity * 2 || pdu.len() != 6 + byte_count {
+        return Err(EXCEPTION_ILLEGAL_DATA_VALUE);
+    }
+    if start + quantity > HOLDING_REGISTER_COUNT {
+        return Err(EXCEPTION_ILLEGAL_DATA_ADDRESS);
+    }
+    for (i, value) in pdu[6..].chunks_exact(2).enumerate() {
+        write_holding_register((start + i) as u16, u16::from_be_bytes([value[0], value[1]]))?;
+    }
+    Ok(pdu[..5].to_vec())
+}
+
+fn address_and_quantity(pdu: &[u8]) -> Result<(usize, usize), u8> {
+    if pdu.len() < 5 {
+        return Err(EXCEPTION_ILLEGAL_DATA_VALUE);
+    }
+    let start = u16::from_be_bytes([pdu[1], pdu[2]]) as usize;
+    let quantity = u16::from_be_bytes([pdu[3], pdu[4]]) as usize;
+    Ok((start, quantity))
+}
+
+fn exception(function: u8, code: u8) -> Vec<u8> {
+    vec![function | 0x80, code]
+}
+
+// Saturating float to int conversion happens on the `as` casts
+fn scale(value: f32, factor: f32) -> f32 {
+    (value * factor).round()
+}
+
+// RTU master, used to poll RS485 sensors
+
+/// Failure of an RTU exchange, the transport timeout is handled by the caller.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RtuError {
+    Crc,
+    /// Exception code returned by the slave
+    Exception(u8),
+    Malformed,
+}
+
+impl std::fmt::Display for RtuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RtuError::Crc => write!(f, "CRC mismatch"),
+            RtuError::Exception(code) => write!(f, "exception 0x{code:02X}"),
+            RtuError::Malformed => write!(f, "malformed response"),
+        }
+    }
+}
+
+impl std::error::Error for RtuError {}
+
+/// Read request frame for `quantity` registers with function 0x03 or 0x04.
+pub fn rtu_read_request(unit_id: u8, function: u8, start: u16, quantity: u16) -> Vec<u8> {
+    let mut frame = vec![unit_id, function];
+    frame.extend_from_slice(&start.to_be_bytes());
+    frame.extend_from_slice(&quantity.to_be_bytes());
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+/// Expected length of the response to a read of `quantity` registers.
+pub fn rtu_read_response_len(quantity: u16) -> usize {
+    5 + quantity as usize * 2
+}
+
+/// Register values of a read response. Exception responses are 5 bytes long, so `frame`
+/// may be shorter than the expected length.
+pub fn parse_rtu_read_response(
+    unit_id: u8,
+    function: u8,
+    quantity: u16,
+    frame: &[u8],
+) -> Result<Vec<u16>, RtuError> {
+    if frame.len() < 5 {
+        return Err(RtuError::Malformed);
+    }
+    let (body, crc) = frame.split_at(frame.len() - 2);
+    if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return Err(RtuError::Crc);
+    }
+    if body[0] != unit_id {
+        return Err(RtuError::Malformed);
+    }
+    if body[1] == function | 0x80 {
+        return Err(RtuError::Exception(body[2]));
+    }
+    if body[1] != function || body[2] as usize != quantity as usize * 2 {
+        return Err(RtuError::Malformed);
+    }
+    let data = &body[3..];
+    if data.len() != quantity as usize * 2 {
+        return Err(RtuError::Malformed);
+    }
+
+    Ok(data
+        .chunks_exact(2)
+        .map(|value| u16::from_be_bytes([value[0], value[1]]))
+        .collect())
+}
+
+// RTU slave, answers a master polling the station on the RS485 bus
+
+/// Response frame to an RTU request addressed to `unit_id`, `None` for frames addressed to
+/// other units. Only the reads (0x03 and 0x04) are served, other functions get an exception.
+///
+/// Frames with a bad CRC are an error and get no response, as the specification requires.
+pub fn handle_rtu_request(
+    unit_id: u8,
+    frame: &[u8],
+    reading: &WeatherReading,
+) -> Result<Option<Vec<u8>>, RtuError> {
+    if frame.len() < 4 {
+        return Err(RtuError::Malformed);
+    }
+    let (body, crc) = frame.split_at(frame.len() - 2);
+    if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return Err(RtuError::Crc);
+    }
+    // Broadcasts (address 0) only carry writes, which are not served
+    if body[0] != unit_id {
+        return Ok(None);
+    }
+
+    let pdu = &body[1..];
+    let response = match pdu[0] {
+        FN_READ_HOLDING_REGISTERS | FN_READ_INPUT_REGISTERS => handle_pdu(pdu, reading),
+        function => exception(function, EXCEPTION_ILLEGAL_FUNCTION),
+    };
+    let mut frame = vec![unit_id];
+    frame.extend_from_slice(&response);
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading() -> WeatherReading {
+        WeatherReading {
+            temperature: 21.5,
+            humidity: 55.25,
+            pressure: 1013.2,
+            wind_speed_kmh: 12.34,
+            wind_direction_deg: 270.0,
+            rain_mm: 1.5,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_input_registers_big_endian() {
+        let response = handle_pdu(&[FN_READ_INPUT_REGISTERS, 0, 0, 0, 6], &reading());
+        assert_eq!(
+            response,
+            [
+                FN_READ_INPUT_REGISTERS,
+                12,
+                0x08,
+                0x66,
+                0x15,
+                0x95,
+                0x27,
+                0x94,
+                0x04,
+                0xD2,
+                0x01,
+                0x0E,
+                0x00,
+                0x96,
+            ]
+        );
+        let response = handle_pdu(&[FN_READ_INPUT_REGISTERS, 0, 4, 0, 1], &reading());
+        assert_eq!(response, [FN_READ_INPUT_REGISTERS, 2, 0x01, 0x0E]);
+    }
+
+    #[test]
+    fn scales_negative_temperature_as_twos_complement() {
+        let reading = WeatherReading {
+            temperature: -12.34,
+            ..reading()
+        };
+        let response = handle_pdu(&[FN_READ_INPUT_REGISTERS, 0, 0, 0, 1], &reading);
+        assert_eq!(response, [FN_READ_INPUT_REGISTERS, 2, 0xFB, 0x2E]);
+        assert_eq!(input_registers(&reading)[0] as i16, -1234);
+    }
+
+    // The only test writing the holding registers, RUNTIME is shared by the tests
+    #[test]
+    fn writes_and_reads_holding_registers() {
+        let read = [FN_READ_HOLDING_REGISTERS, 0, 0, 0, 2];
+
+        let write = [FN_WRITE_SINGLE_REGISTER, 0, 1, 0xFF, 0xF6];
+        assert_eq!(handle_pdu(&write, &reading()), write);
+        assert_eq!(RUNTIME.vane_offset_deg(), -10);
+
+        let write = [FN_WRITE_MULTIPLE_REGISTERS, 0, 0, 0, 2, 4, 0, 60, 0, 5];
+        assert_eq!(handle_pdu(&write, &reading()), &write[..5]);
+        assert_eq!(
+            handle_pdu(&read, &reading()),
+            [FN_READ_HOLDING_REGISTERS, 4, 0, 60, 0, 5]
+        );
+
+        // Out of range values are refused and leave the registers alone
+        for write in [
+            [FN_WRITE_SINGLE_REGISTER, 0, 0, 0, 0],
+            [FN_WRITE_SINGLE_REGISTER, 0, 0, 0x0E, 0x11],
+            [FN_WRITE_SINGLE_REGISTER, 0, 1, 0, 181],
+            [FN_WRITE_SINGLE_REGISTER, 0, 1, 0xFF, 0x4B],
+        ] {
+            assert_eq!(
+                handle_pdu(&write, &reading()),
+                [
+                    FN_WRITE_SINGLE_REGISTER | 0x80,
+                    EXCEPTION_ILLEGAL_DATA_VALUE
+                ]
+            );
+        }
+        assert_eq!(
+            handle_pdu(&read, &reading()),
+            [FN_READ_HOLDING_REGISTERS, 4, 0, 60, 0, 5]
+        );
+    }
+
+    #[test]
+    fn rejects_illegal_addresses() {
+        assert_eq!(
+            handle_pdu(&[FN_READ_INPUT_REGISTERS, 0, 5, 0, 2], &reading()),
+            [
+                FN_READ_INPUT_REGISTERS | 0x80,
+                EXCEPTION_ILLEGAL_DATA_ADDRESS
+            ]
+        );
+        assert_eq!(
+            handle_pdu(&[FN_READ_HOLDING_REGISTERS, 0, 2, 0, 1], &reading()),
+            [
+                FN_READ_HOLDING_REGISTERS | 0x80,
+                EXCEPTION_ILLEGAL_DATA_ADDRESS
+            ]
+        );
+        assert_eq!(
+            handle_pdu(&[FN_WRITE_SINGLE_REGISTER, 0, 2, 0, 1], &reading()),
+            [
+                FN_WRITE_SINGLE_REGISTER | 0x80,
+                EXCEPTION_ILLEGAL_DATA_ADDRESS
+            ]
+        );
+        assert_eq!(
+            handle_pdu(
+                &[FN_WRITE_MULTIPLE_REGISTERS, 0, 1, 0, 2, 4, 0, 1, 0, 1],
+                &reading()
+            ),
+            [
+                FN_WRITE_MULTIPLE_REGISTERS | 0x80,
+                EXCEPTION_ILLEGAL_DATA_ADDRESS
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_illegal_values_and_functions() {
+        for quantity in [0, MAX_READ_QUANTITY as u8 + 1] {
+            assert_eq!(
+                handle_pdu(&[FN_READ_INPUT_REGISTERS, 0, 0, 0, quantity], &reading()),
+                [FN_READ_INPUT_REGISTERS | 0x80, EXCEPTION_ILLEGAL_DATA_VALUE]
+            );
+        }
+        // Byte count not matching the quantity
+        assert_eq!(
+            handle_pdu(
+                &[FN_WRITE_MULTIPLE_REGISTERS, 0, 0, 0, 1, 4, 0, 1],
+                &reading()
+            ),
+            [
+                FN_WRITE_MULTIPLE_REGISTERS | 0x80,
+                EXCEPTION_ILLEGAL_DATA_VALUE
+            ]
+        );
+        assert_eq!(
+            handle_pdu(&[FN_READ_INPUT_REGISTERS, 0, 0], &reading()),
+            [FN_READ_INPUT_REGISTERS | 0x80, EXCEPTION_ILLEGAL_DATA_VALUE]
+        );
+        assert_eq!(
+            handle_pdu(&[0x2B, 0, 0, 0, 1], &reading()),
+            [0x2B | 0x80, EXCEPTION_ILLEGAL_FUNCTION]
+        );
+    }
+}
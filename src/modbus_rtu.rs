@@ -0,0 +1,95 @@
+use anyhow::Result;
+use esp_idf_svc::hal::{
+    delay::{TickType, BLOCK},
+    gpio::{AnyIOPin, AnyOutputPin, PinDriver},
+    uart::{config::Config as UartConfig, UartDriver, UART2},
+    units::Hertz,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use weather_station::{modbus, reading::WeatherReading};
+
+use crate::provisioning::CONFIG;
+
+// RTU frames are delimited by 3.5 characters of silence, a few ms at the usual baud rates.
+// One tick is the shortest wait the UART driver offers
+const FRAME_GAP: Duration = Duration::from_millis(10);
+// Longest RTU frame
+const MAX_FRAME_LEN: usize = 256;
+
+/// Answer a Modbus RTU master on UART2, through the RS485 transceiver of the wind sensor.
+///
+/// Each request reads one copy of the latest reading, so a multi-register read never mixes
+/// two measurement cycles.
+pub fn modbus_rtu_serve(uart: UART2, readings: Arc<Mutex<WeatherReading>>) -> Result<()> {
+    // Pins come from the config, they are not claimed by any other driver
+    let (tx, rx, de_re) = unsafe {
+        (
+            AnyIOPin::new(CONFIG.rs485_tx_gpio),
+            AnyIOPin::new(CONFIG.rs485_rx_gpio),
+            AnyOutputPin::new(CONFIG.rs485_de_gpio),
+        )
+    };
+    let uart = UartDriver::new(
+        uart,
+        tx,
+        rx,
+        Option::<AnyIOPin>::None,
+        Option::<AnyIOPin>::None,
+        &UartConfig::new().baudrate(Hertz(CONFIG.modbus_rtu_baud)),
+    )?;
+    // Driver enable and receiver enable tied together, high to transmit
+    let mut de_re = PinDriver::output(de_re)?;
+    de_re.set_low()?;
+
+    std::thread::Builder::new()
+        .stack_size(4096)
+        .spawn(move || {
+            log::info!(
+                "Modbus RTU slave {} listening at {} baud",
+                CONFIG.modbus_rtu_address,
+                CONFIG.modbus_rtu_baud
+            );
+            let mut frame = [0u8; MAX_FRAME_LEN];
+            loop {
+                // The first byte starts a frame, the silence after the last one ends it
+                let mut len = match uart.read(&mut frame, BLOCK) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        log::error!("Modbus RTU read failed: {e}");
+                        continue;
+                    }
+                };
+                while len < MAX_FRAME_LEN {
+                    match uart.read(&mut frame[len..], TickType::from(FRAME_GAP).ticks()) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => len += read,
+                    }
+                }
+
+                let reading = *readings.lock().unwrap();
+                let response = match modbus::handle_rtu_request(
+                    CONFIG.modbus_rtu_address,
+                    &frame[..len],
+                    &reading,
+                ) {
+                    Ok(Some(response)) => response,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        log::warn!("Modbus RTU request dropped: {e}");
+                        continue;
+                    }
+                };
+
+                de_re.set_high().ok();
+                uart.write(&response)
+                    .and_then(|_| uart.wait_tx_done(BLOCK))
+                    .map_err(|e| log::error!("Modbus RTU response failed: {e}"))
+                    .ok();
+                // Release the bus even if sending failed
+                de_re.set_low().ok();
+            }
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use weather_station::{modbus, reading::WeatherReading};
+
+use crate::provisioning::CONFIG;
+
+const MODBUS_PORT: u16 = 502;
+const MAX_CLIENTS: usize = 3;
+// MBAP header: transaction id, protocol id, length, unit id
+const MBAP_LEN: usize = 7;
+const MAX_PDU_LEN: usize = 253;
+
+static ACTIVE_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Serve the register map on port 502, one thread per client connection.
+pub fn modbus_serve(readings: Arc<Mutex<WeatherReading>>) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", MODBUS_PORT))?;
+
+    std::thread::Builder::new()
+        .stack_size(6000)
+        .spawn(move || {
+            log::info!("Modbus TCP listening on port {MODBUS_PORT}");
+            for stream in listener.incoming().flatten() {
+                if ACTIVE_CLIENTS.fetch_add(1, Ordering::Relaxed) >= MAX_CLIENTS {
+                    ACTIVE_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                    log::warn!("Modbus client limit reached, dropping connection");
+                    continue;
+                }
+                let readings = readings.clone();
+                let spawned = std::thread::Builder::new().stack_size(4096).spawn(move || {
+                    handle_client(stream, &readings)
+                        .unwrap_or_else(|e| log::warn!("Modbus client error: {e}"));
+                    ACTIVE_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                });
+                if spawned.is_err() {
+                    log::error!("Fail spawning modbus client thread");
+                    ACTIVE_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn handle_client(mut stream: TcpStream, readings: &Mutex<WeatherReading>) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    let mut header = [0u8; MBAP_LEN];
+    let mut pdu = [0u8; MAX_PDU_LEN];
+
+    loop {
+        if stream.read_exact(&mut header).is_err() {
+            // Client closed the connection or went idle
+            return Ok(());
+        }
+        let protocol_id = u16::from_be_bytes([header[2], header[3]]);
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let unit_id = header[6];
+        if protocol_id != 0 || length < 2 || length - 1 > MAX_PDU_LEN {
+            anyhow::bail!("invalid MBAP header");
+        }
+        let pdu = &mut pdu[..length - 1];
+        stream.read_exact(pdu)?;
+
+        // 0 and 255 are commonly used by TCP clients when the unit id is irrelevant
+        if unit_id != CONFIG.modbus_unit_id && unit_id != 0 && unit_id != 0xFF {
+            continue;
+        }
+
+        let reading = *readings.lock().unwrap();
+        let response = modbus::handle_pdu(pdu, &reading);
+
+        let mut frame = Vec::with_capacity(MBAP_LEN + response.len());
+        frame.extend_from_slice(&header[..4]);
+        frame.extend_from_slice(&((response.len() + 1) as u16).to_be_bytes());
+        frame.push(unit_id);
+        frame.extend_from_slice(&response);
+        stream.write_all(&frame)?;
+    }
+}
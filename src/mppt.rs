@@ -0,0 +1,83 @@
+use anyhow::Result;
+use esp_idf_svc::hal::{
+    delay::BLOCK,
+    gpio::AnyIOPin,
+    uart::{config::Config as UartConfig, UartDriver, UART1},
+    units::Hertz,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use weather_station::vedirect::*;
+
+use crate::provisioning::CONFIG;
+
+// The charge controller sends a block every second
+const STALE_AFTER: Duration = Duration::from_secs(10);
+
+/// Last block received from the charge controller.
+#[derive(Default)]
+pub struct MpptStatus {
+    pub frame: Option<MpptFrame>,
+    received_at: Option<Instant>,
+    pub checksum_errors: u32,
+}
+
+impl MpptStatus {
+    pub fn is_stale(&self) -> bool {
+        self.received_at
+            .map_or(true, |received| received.elapsed() > STALE_AFTER)
+    }
+}
+
+/// Listen to the VE.Direct text frames of a Victron charge controller on UART1.
+pub fn mppt_listen(uart: UART1) -> Result<Arc<Mutex<MpptStatus>>> {
+    // Pins come from the config, they are not claimed by any other driver
+    let (tx, rx) = unsafe {
+        (
+            AnyIOPin::new(CONFIG.mppt_tx_gpio),
+            AnyIOPin::new(CONFIG.mppt_rx_gpio),
+        )
+    };
+    let driver = UartDriver::new(
+        uart,
+        tx,
+        rx,
+        Option::<AnyIOPin>::None,
+        Option::<AnyIOPin>::None,
+        &UartConfig::new().baudrate(Hertz(CONFIG.mppt_baud)),
+    )?;
+    let status = Arc::new(Mutex::new(MpptStatus::default()));
+
+    let shared = status.clone();
+    std::thread::Builder::new()
+        .stack_size(4096)
+        .spawn(move || {
+            let mut parser = VeDirectParser::new();
+            let mut buf = [0u8; 64];
+            loop {
+                let len = match driver.read(&mut buf, BLOCK) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        log::error!("VE.Direct read failed: {e}");
+                        continue;
+                    }
+                };
+                for &byte in &buf[..len] {
+                    match parser.push(byte) {
+                        Some(Ok(frame)) => {
+                            let mut status = shared.lock().unwrap();
+                            status.frame = Some(frame);
+                            status.received_at = Some(Instant::now());
+                        }
+                        Some(Err(FrameError::Checksum)) => {
+                            log::warn!("VE.Direct checksum failure");
+                            shared.lock().unwrap().checksum_errors += 1;
+                        }
+                        None => {}
+                    }
+                }
+            }
+        })?;
+
+    Ok(status)
+}
@@ -2,49 +2,1200 @@ use anyhow::Result;
 use core::sync::atomic::Ordering;
 use esp_idf_svc::{
     mqtt::client::*,
+    sys::{
+        esp_crt_bundle_attach, esp_get_free_heap_size, esp_get_minimum_free_heap_size,
+        esp_timer_get_time, EspError,
+    },
+    tls::X509,
     wifi::{BlockingWifi, EspWifi},
 };
-use std::time::Duration;
-use weather_station::*;
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::sync::mpsc::Sender;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use weather_station::{
+    acoustic::{amplitude_to_db, AcousticReport, AcousticThresholds},
+    beacon::escape_json,
+    derive::Derived,
+    ha_discovery,
+    iaq::AirQuality,
+    publish_options::{invalid_entries, options_for, PublishOptions},
+    reading::{ReadingPayload, TimedReading, Trends, WeatherReading},
+    runtime::{ButtonPress, Field, RUNTIME},
+    sdi12,
+    sensors::{env::EnvData, SensorHealth, TrackedSensor},
+    stats::{
+        HourlySummary, MinMeanMax, PrecipitationAccumulation, RainHourSummary, RainStatistics,
+        WindRose, ROSE_SECTORS,
+    },
+    time::{iso8601_utc, synced_iso8601_utc, utc_timestamp},
+    wifi_quality::{Disconnect, QualityReport},
+    *,
+};
+
+use crate::acoustic_mic::AcousticMonitor;
+use crate::cellular::CellularStatus;
+use crate::irrigation::IrrigationInterlock;
+use crate::maintenance::Maintenance;
+use crate::mppt::MpptStatus;
+use crate::provisioning::CONFIG;
+use crate::quiet_hours::QuietHours;
+use crate::restore;
+use crate::sdi12_bus::{Sdi12State, Sdi12Values};
+use crate::station_id;
+use crate::watchdog;
+use crate::wifi;
 
 //MQTT
 pub fn mqtt_create() -> Result<(EspMqttClient<'static>, EspMqttConnection)> {
-    let (mqtt_client, mqtt_connection) = EspMqttClient::new(
-        &CONFIG.broker_url,
-        &MqttClientConfiguration {
-            client_id: Some(CONFIG.client_id),
-            username: Some(CONFIG.mqtt_user),
-            password: Some(CONFIG.mqtt_pass),
-            keep_alive_interval: Some(Duration::from_secs(100)),
-            ..Default::default()
-        },
-    )?;
+    let status = status_topic();
+    let mut conf = MqttClientConfiguration {
+        client_id: Some(if CONFIG.client_id.is_empty() {
+            station_id()
+        } else {
+            CONFIG.client_id
+        }),
+        username: Some(CONFIG.mqtt_user),
+        password: Some(CONFIG.mqtt_pass),
+        keep_alive_interval: Some(Duration::from_secs(100)),
+        // Sent by the broker once the keep-alive runs out without a word from the station
+        lwt: Some(LwtConfiguration {
+            topic: &status,
+            payload: b"offline",
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+        ..Default::default()
+    };
+    if CONFIG.broker_url.starts_with("mqtts://") {
+        let [ca_cert, client_cert, client_key] = *PEMS.get_or_init(|| {
+            [
+                CONFIG.mqtt_ca_cert,
+                CONFIG.mqtt_client_cert,
+                CONFIG.mqtt_client_key,
+            ]
+            .map(nul_terminated)
+        });
+        if CONFIG.mqtt_ca_cert.is_empty() {
+            conf.crt_bundle_attach = Some(esp_crt_bundle_attach);
+        } else {
+            conf.server_certificate = Some(X509::pem_until_nul(ca_cert.as_bytes()));
+        }
+        // A provisioned config always has both, see `config_from_json`
+        if !CONFIG.mqtt_client_cert.is_empty() && !CONFIG.mqtt_client_key.is_empty() {
+            conf.client_certificate = Some(X509::pem_until_nul(client_cert.as_bytes()));
+            conf.private_key = Some(X509::pem_until_nul(client_key.as_bytes()));
+        }
+    } else if !(CONFIG.mqtt_ca_cert.is_empty() && CONFIG.mqtt_client_cert.is_empty()) {
+        log::warn!("MQTT certificates are ignored without an mqtts:// broker_url");
+    }
+
+    for entry in invalid_entries(CONFIG.mqtt_topic_options) {
+        log::warn!("mqtt_topic_options: invalid entry '{entry}' ignored");
+    }
+
+    let (mqtt_client, mqtt_connection) = EspMqttClient::new(CONFIG.broker_url, &conf)?;
     Ok((mqtt_client, mqtt_connection))
 }
 
-pub fn publish_bme_data(mqtt_cli: &mut EspMqttClient, bme_readings: bosch_bme680::MeasurmentData) {
+// The TLS stack wants the PEMs nul-terminated, kept for the clients created after a reconnection
+static PEMS: OnceLock<[&'static str; 3]> = OnceLock::new();
+
+fn nul_terminated(pem: &'static str) -> &'static str {
+    String::leak(format!("{}\n\0", pem.trim_end()))
+}
+
+/// Publishes with the flags `mqtt_topic_options` sets for the topic, `qos` and `retain` by
+/// default. Topics under the station topic are matched without its prefix.
+pub fn publish(
+    mqtt_cli: &mut EspMqttClient,
+    topic: &str,
+    qos: QoS,
+    retain: bool,
+    payload: &[u8],
+) -> Result<u32, EspError> {
+    let relative = topic
+        .strip_prefix(CONFIG.topic)
+        .and_then(|topic| topic.strip_prefix('/'))
+        .unwrap_or(topic);
+    let default = PublishOptions {
+        qos: qos as u8,
+        retain,
+    };
+    let options = options_for(CONFIG.mqtt_topic_options, relative, default);
+    let qos = match options.qos {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    };
+    mqtt_cli.publish(topic, qos, options.retain, payload)
+}
+
+/// Events forwarded from the connection thread to the main loop.
+pub enum MqttEvent {
+    Connected,
+    Disconnected,
+    /// The connection thread ended, the client must be created again
+    Closed,
+    Received {
+        topic: String,
+        payload: Vec<u8>,
+    },
+    // Local commands share the channel with the broker ones
+    Button(ButtonPress),
+    /// `POST /api/v1/measure`
+    Measure,
+}
+
+/// Delays before recreating a client whose connection was closed.
+pub const RECONNECT_FIRST: Duration = Duration::from_secs(2);
+pub const RECONNECT_MAX: Duration = Duration::from_secs(300);
+
+// Runs until the connection is closed
+pub fn forward_events(mqtt_conn: &mut EspMqttConnection, tx: Sender<MqttEvent>) {
+    while let Ok(event) = mqtt_conn.next() {
+        // Watched while forwarding only, the wait for the next event has no bound
+        let _watch = (CONFIG.watchdog_timeout_s > 0)
+            .then(|| {
+                watchdog::TaskWatch::start()
+                    .map_err(|e| log::error!("fail watching the mqtt listener: {e}"))
+                    .ok()
+            })
+            .flatten();
+        log::info!("[Queue] Event: {}", event.payload());
+        let forwarded = match event.payload() {
+            EventPayload::Connected(_) => MqttEvent::Connected,
+            EventPayload::Disconnected => MqttEvent::Disconnected,
+            EventPayload::Received {
+                topic: Some(topic),
+                data,
+                ..
+            } => MqttEvent::Received {
+                topic: topic.to_string(),
+                payload: data.to_vec(),
+            },
+            _ => continue,
+        };
+        tx.send(forwarded).ok();
+    }
+    tx.send(MqttEvent::Closed).ok();
+}
+
+/// Broker connection as seen from the main loop.
+#[derive(Default)]
+pub struct ConnectionState {
+    connected: bool,
+    connections: u32,
+    since: Option<Instant>,
+}
+
+impl ConnectionState {
+    pub fn connected(&mut self) {
+        self.connected = true;
+        self.connections += 1;
+        self.since = Some(Instant::now());
+        if self.connections > 1 {
+            log::info!("MQTT reconnected ({} connections)", self.connections);
+        }
+    }
+
+    pub fn disconnected(&mut self) {
+        if let Some(since) = self.since.take().filter(|_| self.connected) {
+            log::warn!("MQTT disconnected after {}s", since.elapsed().as_secs());
+        }
+        self.connected = false;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Connections to the broker after the first one.
+    pub fn reconnects(&self) -> u32 {
+        self.connections.saturating_sub(1)
+    }
+}
+
+// Retained `online`, the broker replaces it with the `offline` will when the station is gone
+pub fn status_topic() -> String {
+    format!("{}/status", CONFIG.topic)
+}
+
+pub fn publish_online(mqtt_cli: &mut EspMqttClient) {
+    publish(mqtt_cli, &status_topic(), QoS::AtLeastOnce, true, b"online")
+        .map_err(|e| log::error!("fail publishing status: {e}"))
+        .ok();
+}
+
+pub fn command_topic() -> String {
+    format!("{}/cmd", CONFIG.topic)
+}
+
+pub fn diag_topic() -> String {
+    format!("{}/cmd/diag", CONFIG.topic)
+}
+
+pub fn interval_topic() -> String {
+    format!("{}/cmd/interval", CONFIG.topic)
+}
+
+pub fn test_publish_topic() -> String {
+    format!("{}/cmd/test_publish", CONFIG.topic)
+}
+
+pub fn emergency_stop_topic() -> String {
+    format!("{}/cmd/emergency_stop", CONFIG.topic)
+}
+
+pub fn rain_correct_topic() -> String {
+    format!("{}/cmd/rain_correct", CONFIG.topic)
+}
+
+pub fn safe_mode_topic() -> String {
+    format!("{}/cmd/safe_mode", CONFIG.topic)
+}
+
+pub fn quiet_hours_topic() -> String {
+    format!("{}/cmd/quiet_hours", CONFIG.topic)
+}
+
+pub fn wifi_log_topic() -> String {
+    format!("{}/cmd/wifi_log", CONFIG.topic)
+}
+
+pub fn fields_topic() -> String {
+    format!("{}/cmd/fields", CONFIG.topic)
+}
+
+pub fn maintenance_topic() -> String {
+    format!("{}/cmd/maintenance", CONFIG.topic)
+}
+
+pub fn interlock_topic() -> String {
+    format!("{}/cmd/interlock", CONFIG.topic)
+}
+
+pub fn config_topic() -> String {
+    format!("{}/cmd/config", CONFIG.topic)
+}
+
+pub fn acoustic_topic() -> String {
+    format!("{}/cmd/acoustic", CONFIG.topic)
+}
+
+// Subscriptions are lost with the session, this must run on every (re)connection
+pub fn subscribe_commands(mqtt_cli: &mut EspMqttClient) {
+    mqtt_cli
+        .subscribe(&command_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to station commands: {e}"))
+        .ok();
+    mqtt_cli
+        .subscribe(&interval_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to interval commands: {e}"))
+        .ok();
+    mqtt_cli
+        .subscribe(&test_publish_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to test publish commands: {e}"))
+        .ok();
+    mqtt_cli
+        .subscribe(&emergency_stop_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to emergency stop commands: {e}"))
+        .ok();
+    mqtt_cli
+        .subscribe(&rain_correct_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to rain correction commands: {e}"))
+        .ok();
+    mqtt_cli
+        .subscribe(&quiet_hours_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to quiet hours commands: {e}"))
+        .ok();
+    mqtt_cli
+        .subscribe(&fields_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to field selection commands: {e}"))
+        .ok();
+    mqtt_cli
+        .subscribe(&maintenance_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to maintenance commands: {e}"))
+        .ok();
+    mqtt_cli
+        .subscribe(&config_topic(), QoS::AtLeastOnce)
+        .map_err(|e| log::error!("fail subscribing to config commands: {e}"))
+        .ok();
+    if CONFIG.interlock_enabled {
+        mqtt_cli
+            .subscribe(&interlock_topic(), QoS::AtLeastOnce)
+            .map_err(|e| log::error!("fail subscribing to interlock commands: {e}"))
+            .ok();
+    }
+    if CONFIG.acoustic_enabled {
+        mqtt_cli
+            .subscribe(&acoustic_topic(), QoS::AtLeastOnce)
+            .map_err(|e| log::error!("fail subscribing to acoustic commands: {e}"))
+            .ok();
+    }
+    if CONFIG.diag_enabled {
+        mqtt_cli
+            .subscribe(&diag_topic(), QoS::AtLeastOnce)
+            .map_err(|e| log::error!("fail subscribing to diag commands: {e}"))
+            .ok();
+    }
+    if CONFIG.wifi_quality_enabled {
+        mqtt_cli
+            .subscribe(&wifi_log_topic(), QoS::AtLeastOnce)
+            .map_err(|e| log::error!("fail subscribing to wifi log commands: {e}"))
+            .ok();
+    }
+}
+
+/// Shared topic hub software watches to find the stations on the network.
+const DISCOVERY_TOPIC: &str = "homeweather/discovery";
+
+// Retained under the station id so a hub subscribing later still sees every station
+pub fn publish_discovery_beacon(
+    mqtt_cli: &mut EspMqttClient,
+    ip_address: Ipv4Addr,
+    safe_mode: bool,
+) {
+    let mut capabilities = vec![];
+    // No sensor is started in safe mode
+    if !safe_mode {
+        if demo_mode_active(&CONFIG) {
+            capabilities.push("demo");
+        } else {
+            // The RS485 sensor measures both speed and direction
+            let modbus_wind = CONFIG.wind_source == "modbus";
+            if modbus_wind || cfg!(feature = "anemometer") {
+                capabilities.push("anemometer");
+            }
+            let vane_fitted = match CONFIG.vane_sensor {
+                "as5600" => cfg!(feature = "as5600"),
+                "mt6701" | "as5048a" => true,
+                _ => false,
+            };
+            if modbus_wind || vane_fitted {
+                capabilities.push("wind_vane");
+            }
+            if cfg!(feature = "rain") {
+                capabilities.push("rain_gauge");
+            }
+            if cfg!(feature = "bme680") && (CONFIG.bme680_enabled || CONFIG.bme680_2_enabled) {
+                capabilities.push("bme680");
+            }
+            if CONFIG.dht_enabled {
+                capabilities.push(CONFIG.dht_type);
+            }
+            if CONFIG.sdi12_enabled {
+                capabilities.push("sdi12");
+            }
+        }
+    }
+    let capabilities = capabilities
+        .iter()
+        .map(|c| format!("\"{c}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut fields = RUNTIME
+        .fields()
+        .fields()
+        .map(|field| format!("\"{}\"", field.name()))
+        .collect::<Vec<_>>();
+    // SDI-12 values go to the `sdi12` object of the state, under their table name
+    if CONFIG.sdi12_enabled && !safe_mode {
+        let table = sdi12::parse_field_table(CONFIG.sdi12_fields).unwrap_or_default();
+        fields.extend(
+            table
+                .iter()
+                .map(|field| format!("\"sdi12.{}\"", escape_json(field.name))),
+        );
+    }
+    let fields = fields.join(", ");
     let payload = format!(
-        "{{\"temperature\": {}, \"humidity\": {}, \"pressure\": {}}}",
-        bme_readings.temperature, bme_readings.humidity, bme_readings.pressure
+        "{{\"station_id\": \"{}\", \"ip_address\": \"{}\", \"firmware_version\": \"{}\", \"capabilities\": [{}], \"fields\": [{}], \"data_topic\": \"{}\", \"safe_mode\": {}}}",
+        escape_json(station_id()),
+        ip_address,
+        env!("CARGO_PKG_VERSION"),
+        capabilities,
+        fields,
+        escape_json(CONFIG.topic),
+        safe_mode
     );
-    let bme_topic = format!("{}/bme680", CONFIG.topic);
+    let topic = format!("{DISCOVERY_TOPIC}/{}", station_id());
 
-    mqtt_cli
-        .publish(
-            bme_topic.as_str(),
-            QoS::ExactlyOnce,
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+        .map_err(|e| log::error!("fail publishing discovery beacon: {e}"))
+        .ok();
+}
+
+// Retained, sensors without a state topic get an empty config so Home Assistant drops them
+pub fn publish_ha_discovery(mqtt_cli: &mut EspMqttClient) {
+    if !CONFIG.ha_discovery_enabled {
+        return;
+    }
+    let ctx = ha_discovery::DiscoveryContext {
+        station_id: station_id(),
+        topic: CONFIG.topic,
+        split: CONFIG.split_group_publish,
+        availability: CONFIG.maintenance_availability,
+        sw_version: env!("CARGO_PKG_VERSION"),
+    };
+    for sensor in &ha_discovery::SENSORS {
+        let fitted = !sensor.bme680 || CONFIG.bme680_enabled || demo_mode_active(&CONFIG);
+        let selected = sensor.field.map_or(true, |field| RUNTIME.publishes(field));
+        let payload = if fitted && selected {
+            sensor.config_payload(&ctx).unwrap_or_default()
+        } else {
+            String::new()
+        };
+        publish(
+            mqtt_cli,
+            &sensor.config_topic(CONFIG.ha_discovery_prefix, station_id()),
+            QoS::AtLeastOnce,
             true,
             payload.as_bytes(),
         )
-        .map_err(|e| log::error!("fail publishing bme data: {e}"))
+        .map_err(|e| log::error!("fail publishing {} discovery: {e}", sensor.object_id))
+        .ok();
+    }
+}
+
+// `source` is "battery" or "solar", daily charge is only known for the battery
+pub fn publish_power(
+    mqtt_cli: &mut EspMqttClient,
+    source: &str,
+    sample: &power::PowerSample,
+    charge: Option<&power::ChargeCounter>,
+) {
+    let daily = charge
+        .map(|c| format!(", \"mah_in\": {}, \"mah_out\": {}", c.mah_in(), c.mah_out()))
+        .unwrap_or_default();
+    let payload = format!(
+        "{{\"voltage\": {}, \"current\": {}, \"power\": {}{daily}}}",
+        sample.voltage_v, sample.current_a, sample.power_w
+    );
+    let topic = format!("{}/power/{source}", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        payload.as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing {source} power: {e}"))
+    .ok();
+}
+
+/// From the INA monitors, retained so a dashboard shows it at once.
+pub fn publish_charge_state(mqtt_cli: &mut EspMqttClient, state: power::ChargeState) {
+    let topic = format!("{}/power/charging", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        true,
+        state.name().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing charge state: {e}"))
+    .ok();
+}
+
+pub fn publish_fuel_gauge(mqtt_cli: &mut EspMqttClient, sample: &power::FuelGaugeSample) {
+    let values = [
+        ("voltage", sample.voltage_v.to_string()),
+        ("soc", sample.soc_percent.to_string()),
+        ("rate", sample.rate_percent_h.to_string()),
+        ("alert", sample.alert.to_string()),
+    ];
+
+    for (name, value) in values {
+        let topic = format!("{}/power/fuel_gauge/{name}", CONFIG.topic);
+        publish(mqtt_cli, &topic, QoS::AtLeastOnce, false, value.as_bytes())
+            .map_err(|e| log::error!("fail publishing fuel gauge {name}: {e}"))
+            .ok();
+    }
+}
+
+pub fn publish_battery(mqtt_cli: &mut EspMqttClient, sample: &power::BatterySample) {
+    let topic = format!("{}/power/battery_adc", CONFIG.topic);
+    let payload = format!(
+        "{{\"voltage\": {:.2}, \"percent\": {:.0}}}",
+        sample.voltage_v, sample.percent
+    );
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        payload.as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing battery voltage: {e}"))
+    .ok();
+}
+
+/// Field values and the status of every sensor, null values did not pass the checks.
+pub fn publish_sdi12(mqtt_cli: &mut EspMqttClient, state: &Sdi12State) {
+    let topic = format!("{}/sdi12", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        state.to_json().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing sdi12 data: {e}"))
+    .ok();
+}
+
+pub fn publish_mppt(mqtt_cli: &mut EspMqttClient, status: &MpptStatus) {
+    let mut values = vec![
+        ("stale", status.is_stale().to_string()),
+        ("checksum_errors", status.checksum_errors.to_string()),
+    ];
+    if let Some(frame) = status.frame.filter(|_| !status.is_stale()) {
+        let fields = [
+            ("battery_voltage", frame.battery_v.map(|v| v.to_string())),
+            ("battery_current", frame.battery_a.map(|v| v.to_string())),
+            ("panel_voltage", frame.panel_v.map(|v| v.to_string())),
+            ("panel_power", frame.panel_w.map(|v| v.to_string())),
+            (
+                "charger_state",
+                frame
+                    .charger_state
+                    .map(|cs| vedirect::charger_state_name(cs).to_string()),
+            ),
+            ("error", frame.error.map(|v| v.to_string())),
+            ("yield_today", frame.yield_today_kwh.map(|v| v.to_string())),
+        ];
+        values.extend(fields.into_iter().filter_map(|(name, v)| Some((name, v?))));
+    }
+
+    for (name, value) in values {
+        let topic = format!("{}/power/mppt/{name}", CONFIG.topic);
+        publish(mqtt_cli, &topic, QoS::AtLeastOnce, false, value.as_bytes())
+            .map_err(|e| log::error!("fail publishing mppt {name}: {e}"))
+            .ok();
+    }
+}
+
+pub fn publish_system_event(mqtt_cli: &mut EspMqttClient, event: &str) {
+    let topic = format!("{}/system/event", CONFIG.topic);
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, false, event.as_bytes())
+        .map_err(|e| log::error!("fail publishing system event: {e}"))
+        .ok();
+}
+
+// Retained, the outcome of the last update stays visible
+pub fn publish_ota_status(mqtt_cli: &mut EspMqttClient, status: &str) {
+    let topic = format!("{}/ota/status", CONFIG.topic);
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, status.as_bytes())
+        .map_err(|e| log::error!("fail publishing ota status: {e}"))
+        .ok();
+}
+
+pub fn publish_upload_rejected(mqtt_cli: &mut EspMqttClient, rejected: u32) {
+    let topic = format!("{}/diag/upload_rejected", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        rejected.to_string().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing upload rejections: {e}"))
+    .ok();
+}
+
+// Outcome of the boot-time config fetch: "not_modified", "unchanged" or "error: <reason>"
+pub fn publish_config_fetch(mqtt_cli: &mut EspMqttClient, status: &str) {
+    let topic = format!("{}/diag/config_fetch", CONFIG.topic);
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, status.as_bytes())
+        .map_err(|e| log::error!("fail publishing config fetch status: {e}"))
+        .ok();
+}
+
+// Sensors and inputs that failed their setup, a JSON array of their names
+pub fn publish_degraded(mqtt_cli: &mut EspMqttClient, degraded: &[&str]) {
+    let topic = format!("{}/diag/degraded", CONFIG.topic);
+    let payload = serde_json::to_string(degraded).unwrap_or_default();
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+        .map_err(|e| log::error!("fail publishing degraded sensors: {e}"))
         .ok();
 }
 
-pub fn publish_anemo_data(mqtt_cli: &mut EspMqttClient, wind_direction: String) {
+#[derive(Serialize)]
+struct SensorHealthEntry<'a> {
+    id: &'a str,
+    status: &'static str,
+    errors: u32,
+    consecutive_errors: u32,
+    // Null before the first good read, or when the clock was not synced at the time
+    last_success: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SensorHealthPayload<'a> {
+    sensors: Vec<SensorHealthEntry<'a>>,
+}
+
+// Health of every sensor, degraded ones (None) never got set up
+pub fn publish_sensor_health(
+    mqtt_cli: &mut EspMqttClient,
+    sensors: &[(&str, Option<&SensorHealth>)],
+) {
+    let topic = format!("{}/diagnostics", CONFIG.topic);
+    let entries: Vec<SensorHealthEntry> = sensors
+        .iter()
+        .map(|(id, health)| {
+            let status = health.map_or("degraded", SensorHealth::status);
+            let health = health.copied().unwrap_or_default();
+            SensorHealthEntry {
+                id,
+                status,
+                errors: health.errors,
+                consecutive_errors: health.consecutive_errors,
+                last_success: health.last_success_ms.and_then(synced_iso8601_utc),
+            }
+        })
+        .collect();
+    let payload = match serde_json::to_string(&SensorHealthPayload { sensors: entries }) {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::error!("fail serializing sensor health: {e}");
+            return;
+        }
+    };
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+        .map_err(|e| log::error!("fail publishing sensor health: {e}"))
+        .ok();
+}
+
+// Outcome of a `cmd/config` update: "stored", "cleared", "unchanged" or "error: <reason>"
+pub fn publish_config_update(mqtt_cli: &mut EspMqttClient, status: &str) {
+    let topic = format!("{}/diag/config_update", CONFIG.topic);
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, status.as_bytes())
+        .map_err(|e| log::error!("fail publishing config update status: {e}"))
+        .ok();
+}
+
+pub fn publish_quiet_hours(mqtt_cli: &mut EspMqttClient, quiet: &QuietHours) {
+    let topic = format!("{}/diag/quiet_hours", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        true,
+        quiet.to_json().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing quiet hours: {e}"))
+    .ok();
+}
+
+pub fn publish_maintenance(mqtt_cli: &mut EspMqttClient, maintenance: &Maintenance) {
+    let topic = format!("{}/maintenance", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        true,
+        maintenance.to_json().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing maintenance state: {e}"))
+    .ok();
+    if CONFIG.maintenance_availability {
+        let topic = format!("{}/availability", CONFIG.topic);
+        let availability = if maintenance.active() {
+            "maintenance"
+        } else {
+            "online"
+        };
+        publish(
+            mqtt_cli,
+            &topic,
+            QoS::AtLeastOnce,
+            true,
+            availability.as_bytes(),
+        )
+        .map_err(|e| log::error!("fail publishing availability: {e}"))
+        .ok();
+    }
+}
+
+pub fn publish_interlock(mqtt_cli: &mut EspMqttClient, interlock: &IrrigationInterlock) {
+    let topic = format!("{}/interlock/state", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        true,
+        interlock.to_json().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing interlock state: {e}"))
+    .ok();
+}
+
+/// Activity heard over the rain period, checked against the `tips` counted over it.
+pub fn publish_acoustic(
+    mqtt_cli: &mut EspMqttClient,
+    monitor: &AcousticMonitor,
+    report: &AcousticReport,
+    tips: u32,
+) {
+    if !RUNTIME.publishes(Field::Rain) {
+        return;
+    }
+    let topic = format!("{}/rain/acoustic", CONFIG.topic);
+    let thresholds = monitor.thresholds();
+    let payload = format!(
+        "{{\"level\": \"{}\", \"rms_db\": {:.1}, \"peak_db\": {:.1}, \"impacts\": {}, \"hail_impacts\": {}, \"hail_total\": {}, \"tips\": {}, \"consistent\": {}, \"windows\": {}, \"overruns\": {}}}",
+        report.level(&thresholds).name(),
+        report.mean_rms_db(),
+        amplitude_to_db(report.peak),
+        report.impacts,
+        report.hail_impacts,
+        monitor.hail_total(),
+        tips,
+        report.consistent_with_tips(&thresholds, tips),
+        report.windows,
+        report.overruns
+    );
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        payload.as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing acoustic data: {e}"))
+    .ok();
+}
+
+pub fn publish_acoustic_thresholds(mqtt_cli: &mut EspMqttClient, monitor: &AcousticMonitor) {
+    let topic = format!("{}/rain/acoustic/thresholds", CONFIG.topic);
+    let values = AcousticThresholds::NAMES
+        .iter()
+        .zip(monitor.thresholds().values())
+        .map(|(name, value)| format!("\"{name}\": {value:.1}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        true,
+        format!("{{{values}}}").as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing acoustic thresholds: {e}"))
+    .ok();
+}
+
+pub fn publish_cellular(mqtt_cli: &mut EspMqttClient, status: &CellularStatus) {
+    let topic = format!("{}/diag/cellular", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        status.to_json().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing cellular status: {e}"))
+    .ok();
+}
+
+// Failed RS485 exchanges (CRC errors, timeouts, exceptions) since boot, retries included
+pub fn publish_anemometer_errors(mqtt_cli: &mut EspMqttClient, errors: u32) {
+    let topic = format!("{}/diag/anemometer_errors", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        errors.to_string().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing anemometer errors: {e}"))
+    .ok();
+}
+
+/// Offline readings replayed per main loop tick.
+pub const REPLAY_PER_TICK: usize = 5;
+
+// Not retained, the state topics keep the latest values
+pub fn publish_replayed(mqtt_cli: &mut EspMqttClient, record: &TimedReading) -> bool {
+    let topic = format!("{}/replay", CONFIG.topic);
+    let payload = serde_json::to_string(&ReplayedPayload {
+        reading: record.reading.payload(RUNTIME.fields()),
+        timestamp: record.timestamp_ms / 1000,
+        time: iso8601_utc(record.timestamp_ms),
+    })
+    .unwrap_or_default();
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        payload.as_bytes(),
+    )
+    .map_err(|e| log::error!("fail replaying offline reading: {e}"))
+    .is_ok()
+}
+
+pub fn publish_offline_dropped(mqtt_cli: &mut EspMqttClient, dropped: u32) {
+    let topic = format!("{}/diag/offline_dropped", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        dropped.to_string().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing offline dropped count: {e}"))
+    .ok();
+}
+
+pub fn publish_chip_temp(mqtt_cli: &mut EspMqttClient, celsius: f32) {
+    let topic = format!("{}/diag/chip_temp", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        celsius.to_string().as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing chip temperature: {e}"))
+    .ok();
+}
+
+// Alert events are published on `<topic>/alert/<name>` with a JSON detail payload, none go
+// out in maintenance mode
+pub fn publish_alert(mqtt_cli: &mut EspMqttClient, name: &str, detail: &str) {
+    if MAINTENANCE.load(Ordering::Relaxed) {
+        log::info!("Maintenance mode, {name} alert not published");
+        return;
+    }
+    let topic = format!("{}/alert/{name}", CONFIG.topic);
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, false, detail.as_bytes())
+        .map_err(|e| log::error!("fail publishing {name} alert: {e}"))
+        .ok();
+}
+
+pub fn publish_diag_response(mqtt_cli: &mut EspMqttClient, response: &str) {
+    let topic = format!("{}/diag/response", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        response.as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing diag response: {e}"))
+    .ok();
+}
+
+/// Signal of the access point the station is connected to.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct WifiStats {
+    pub rssi: i8,
+    pub channel: u8,
+}
+
+/// Schema of the consolidated `<topic>/state` document, one per publish cycle.
+#[derive(Serialize)]
+struct StatePayload<'a> {
+    #[serde(flatten)]
+    reading: ReadingPayload,
+    // BME680 gas sensor of the outdoor sensor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gas_resistance: Option<f32>,
+    // Estimated from the gas resistance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iaq: Option<AirQuality>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wifi: Option<WifiStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sdi12: Option<Sdi12Values<'a>>,
+    // ISO 8601 UTC, left out until the clock is synced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<String>,
+}
+
+/// Schema of a reading replayed after an outage.
+#[derive(Serialize)]
+struct ReplayedPayload {
+    #[serde(flatten)]
+    reading: ReadingPayload,
+    timestamp: u64,
+    time: String,
+}
+
+/// Schema of a reading republished after a reboot.
+#[derive(Serialize)]
+struct RestoredPayload {
+    #[serde(flatten)]
+    reading: ReadingPayload,
+    restored: bool,
+    timestamp: u64,
+    time: String,
+}
+
+// Consolidated payload with the last known value of every group
+pub fn publish_reading(
+    mqtt_cli: &mut EspMqttClient,
+    reading: &WeatherReading,
+    outdoor: Option<&EnvData>,
+    iaq: Option<AirQuality>,
+    wifi: Option<WifiStats>,
+    sdi12: Option<&Sdi12State>,
+) {
+    let topic = format!("{}/state", CONFIG.topic);
+    let payload = StatePayload {
+        reading: reading.payload(RUNTIME.fields()),
+        gas_resistance: outdoor.and_then(|env| env.gas_resistance),
+        iaq,
+        wifi,
+        sdi12: sdi12.map(Sdi12Values),
+        time: utc_timestamp(),
+    };
+    let json = match serde_json::to_string(&payload) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("fail serializing consolidated reading: {e}");
+            return;
+        }
+    };
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, json.as_bytes())
+        .map_err(|e| log::error!("fail publishing consolidated reading: {e}"))
+        .ok();
+}
+
+/// Last known values from before a reboot, retained until the first fresh reading replaces them.
+///
+/// The consolidated state is left alone when groups are published separately, and the rain
+/// total of the day only goes out when it belongs to `today`.
+pub fn publish_restored(
+    mqtt_cli: &mut EspMqttClient,
+    last: &restore::LastKnown,
+    stats: Option<&RainStatistics>,
+    today: i32,
+) {
+    if !CONFIG.split_group_publish {
+        let payload = serde_json::to_string(&RestoredPayload {
+            reading: last.reading.payload(RUNTIME.fields()),
+            restored: true,
+            timestamp: last.timestamp_ms / 1000,
+            time: iso8601_utc(last.timestamp_ms),
+        })
+        .unwrap_or_default();
+        let topic = format!("{}/state", CONFIG.topic);
+        publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+            .map_err(|e| log::error!("fail publishing restored reading: {e}"))
+            .ok();
+    }
+
+    if last.day == today && RUNTIME.publishes(Field::RainTotals) {
+        let topic = format!("{}/rain/today", CONFIG.topic);
+        publish(
+            mqtt_cli,
+            &topic,
+            QoS::AtLeastOnce,
+            true,
+            last.rain_today_mm.to_string().as_bytes(),
+        )
+        .map_err(|e| log::error!("fail publishing restored rain total: {e}"))
+        .ok();
+    }
+    if let Some(stats) = stats {
+        publish_rain_stats(mqtt_cli, stats);
+    }
+}
+
+// Commissioning check, the synthetic reading is never retained
+pub fn test_publish(client: &mut EspMqttClient) -> Result<()> {
+    let topic = format!("{}/state", CONFIG.topic);
+    let payload = WeatherReading::synthetic().to_json();
+
+    publish(client, &topic, QoS::AtLeastOnce, false, payload.as_bytes())?;
+    Ok(())
+}
+
+// Values are left out when the sensor could not be read, the error counters always go out
+pub fn publish_env(mqtt_cli: &mut EspMqttClient, sensor: &TrackedSensor, data: Option<&EnvData>) {
+    let mut values = vec![
+        ("errors", sensor.health.errors.to_string()),
+        (
+            "consecutive_errors",
+            sensor.health.consecutive_errors.to_string(),
+        ),
+    ];
+    if let Some(data) = data {
+        let measurements = [
+            (Field::Temperature, Some(data.temperature)),
+            (Field::Humidity, Some(data.humidity)),
+            (Field::Pressure, data.pressure),
+        ];
+        for (field, value) in measurements {
+            if let Some(value) = value.filter(|_| RUNTIME.publishes(field)) {
+                values.push((field.name(), value.to_string()));
+            }
+        }
+    }
+
+    for (name, value) in values {
+        let topic = format!("{}/env/{}/{name}", CONFIG.topic, sensor.id());
+        publish(mqtt_cli, &topic, QoS::AtLeastOnce, false, value.as_bytes())
+            .map_err(|e| log::error!("fail publishing {} {name}: {e}", sensor.id()))
+            .ok();
+    }
+}
+
+// Pressure is null when the outdoor sensor is a DHT, nothing is sent without a selected field.
+// The derived values use the last wind speed, the wind group being published first
+pub fn publish_bme_data(
+    mqtt_cli: &mut EspMqttClient,
+    bme_readings: EnvData,
+    derived: Option<Derived>,
+) {
+    let mut measurements = vec![
+        (
+            Field::Temperature,
+            "temperature",
+            bme_readings.temperature.to_string(),
+        ),
+        (
+            Field::Humidity,
+            "humidity",
+            bme_readings.humidity.to_string(),
+        ),
+        (
+            Field::Pressure,
+            "pressure",
+            bme_readings
+                .pressure
+                .map_or("null".to_string(), |p| p.to_string()),
+        ),
+    ];
+    if let Some(pressure) = bme_readings.pressure {
+        let reduced = sea_level_pressure(pressure, bme_readings.temperature, CONFIG.altitude_m);
+        measurements.push((Field::Pressure, "sea_level_pressure", reduced.to_string()));
+    }
+    if let Some(derived) = derived {
+        measurements.extend([
+            (Field::Derived, "dew_point", derived.dew_point.to_string()),
+            (Field::Derived, "heat_index", derived.heat_index.to_string()),
+            (Field::Derived, "wind_chill", derived.wind_chill.to_string()),
+        ]);
+    }
+    let mut entries: Vec<String> = measurements
+        .iter()
+        .filter(|(field, _, _)| RUNTIME.publishes(*field))
+        .map(|(_, name, value)| format!("\"{name}\": {value}"))
+        .collect();
+    if entries.is_empty() {
+        return;
+    }
+    entries.push(format!("\"demo\": {}", demo_mode_active(&CONFIG)));
+    entries.push(format!(
+        "\"maintenance\": {}",
+        MAINTENANCE.load(Ordering::Relaxed)
+    ));
+    if let Some(time) = utc_timestamp() {
+        entries.push(format!("\"time\": \"{time}\""));
+    }
+    let payload = format!("{{{}}}", entries.join(", "));
+    let bme_topic = format!("{}/bme680", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        bme_topic.as_str(),
+        QoS::ExactlyOnce,
+        true,
+        payload.as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing bme data: {e}"))
+    .ok();
+}
+
+// The consolidated payload carries the tendency in `trends` and the forecast at the top level
+pub fn publish_forecast(mqtt_cli: &mut EspMqttClient, trends: &Trends, forecast: &str) {
+    if !RUNTIME.publishes(Field::Trends) {
+        return;
+    }
+    let topic = format!("{}/forecast", CONFIG.topic);
+    let payload = format!(
+        "{{\"forecast\": \"{}\", \"pressure_tendency\": \"{}\", \"pressure_rate\": {}}}",
+        escape_json(forecast),
+        trends.tendency.name(),
+        trends.pressure_per_h
+    );
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+        .map_err(|e| log::error!("fail publishing forecast: {e}"))
+        .ok();
+}
+
+// The consolidated payload carries it under `iaq`
+pub fn publish_iaq(mqtt_cli: &mut EspMqttClient, quality: &AirQuality) {
+    let topic = format!("{}/iaq", CONFIG.topic);
+    let payload = serde_json::to_string(quality).unwrap_or_default();
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+        .map_err(|e| log::error!("fail publishing air quality: {e}"))
+        .ok();
+}
+
+// Averaged direction of the second vane, in degrees and as a compass point
+pub fn publish_vane(mqtt_cli: &mut EspMqttClient, name: &str, angle: f32) {
+    if !RUNTIME.publishes(Field::WindDirection) {
+        return;
+    }
+    let values = [
+        (
+            "wind_direction",
+            wind_direction_from_angle(angle).to_string(),
+        ),
+        ("wind_direction_deg", angle.round().to_string()),
+    ];
+    for (field, value) in values {
+        let topic = format!("{}/vane/{name}/{field}", CONFIG.topic);
+        publish(mqtt_cli, &topic, QoS::AtLeastOnce, false, value.as_bytes())
+            .map_err(|e| log::error!("fail publishing {name} {field}: {e}"))
+            .ok();
+    }
+}
+
+pub fn publish_anemo_data(
+    mqtt_cli: &mut EspMqttClient,
+    wind_direction: String,
+    wind_speed: f32,
+    wind_gust: f32,
+) {
     let anemo_topic = format!("{}/anemo/wind_direction", CONFIG.topic);
 
-    mqtt_cli
-        .publish(
+    if RUNTIME.publishes(Field::WindDirection) {
+        publish(
+            mqtt_cli,
             anemo_topic.as_str(),
             QoS::ExactlyOnce,
             true,
@@ -52,13 +1203,12 @@ pub fn publish_anemo_data(mqtt_cli: &mut EspMqttClient, wind_direction: String)
         )
         .map_err(|e| log::error!("fail publishing anemo data: {e}"))
         .ok();
-    //calculation with anemo dimension * 3.6 to have km/h
-    let wind_speed = (ROTATION_COUNT.load(Ordering::Relaxed) as f32) * 0.0173833 * 3.6;
-    ROTATION_COUNT.store(0, Ordering::Relaxed);
+    }
     let topic = format!("{}/anemo/wind_speed", CONFIG.topic);
 
-    mqtt_cli
-        .publish(
+    if RUNTIME.publishes(Field::WindSpeed) {
+        publish(
+            mqtt_cli,
             &topic,
             QoS::ExactlyOnce,
             true,
@@ -68,51 +1218,312 @@ pub fn publish_anemo_data(mqtt_cli: &mut EspMqttClient, wind_direction: String)
             log::error!("Couldn't publish wind speed: {e}");
         })
         .ok();
+    }
+    let topic = format!("{}/anemo/wind_gust", CONFIG.topic);
+
+    if RUNTIME.publishes(Field::WindGust) {
+        publish(
+            mqtt_cli,
+            &topic,
+            QoS::ExactlyOnce,
+            true,
+            wind_gust.to_string().as_bytes(),
+        )
+        .map_err(|e| log::error!("Couldn't publish wind gust: {e}"))
+        .ok();
+    }
 }
 
-pub fn publish_rain_data(mqtt_cli: &mut EspMqttClient) {
+pub fn publish_wind_rose(mqtt_cli: &mut EspMqttClient, rose: &WindRose) {
+    if !RUNTIME.publishes(Field::WindRose) {
+        return;
+    }
+    let sectors: Vec<String> = ROSE_SECTORS
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            format!(
+                "{{\"sector\": \"{}\", \"occupancy\": {}, \"mean_speed\": {}}}",
+                name,
+                rose.occupancy_pct(i),
+                rose.mean_speed_kmh(i)
+            )
+        })
+        .collect();
+    let rose_topic = format!("{}/wind/rose", CONFIG.topic);
+    let calm_topic = format!("{}/wind/calm", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &rose_topic,
+        QoS::AtLeastOnce,
+        true,
+        format!("[{}]", sectors.join(", ")).as_bytes(),
+    )
+    .map_err(|e| log::error!("Error publishing wind rose: {e}"))
+    .ok();
+    publish(
+        mqtt_cli,
+        &calm_topic,
+        QoS::AtLeastOnce,
+        true,
+        rose.calm_pct().to_string().as_bytes(),
+    )
+    .map_err(|e| log::error!("Error publishing calm percentage: {e}"))
+    .ok();
+}
+
+// Retained so that dashboards get the last complete hour right away
+pub fn publish_hourly(mqtt_cli: &mut EspMqttClient, summary: &HourlySummary) {
+    if !RUNTIME.publishes(Field::Hourly) {
+        return;
+    }
+    let stats = |s: &MinMeanMax| {
+        format!(
+            "{{\"mean\": {}, \"min\": {}, \"max\": {}, \"samples\": {}}}",
+            s.mean(),
+            s.min,
+            s.max,
+            s.count
+        )
+    };
+    let direction = summary
+        .dominant_direction
+        .map_or("NA", wind_direction_from_angle);
+    let payload = format!(
+        "{{\"hour_start\": {}, \"complete\": {}, \"temperature\": {}, \"humidity\": {}, \"pressure\": {{\"mean\": {}, \"change\": {}, \"samples\": {}}}, \"wind\": {{\"mean\": {}, \"max\": {}, \"direction\": \"{}\", \"samples\": {}}}, \"rain\": {}}}",
+        summary.hour_start_ms / 1000,
+        summary.complete,
+        stats(&summary.temperature),
+        stats(&summary.humidity),
+        summary.pressure.mean(),
+        summary.pressure_change,
+        summary.pressure.count,
+        summary.wind_speed.mean(),
+        summary.wind_speed.max,
+        direction,
+        summary.wind_speed.count,
+        summary.rain_mm
+    );
+    let topic = format!("{}/hourly", CONFIG.topic);
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+        .map_err(|e| log::error!("Error publishing hourly summary: {e}"))
+        .ok();
+}
+
+pub fn publish_rain_data(mqtt_cli: &mut EspMqttClient, rain_quantity: f32) {
     let topic = format!("{}/rain", CONFIG.topic);
-    let rain_quantity = (RAIN_COUNT.load(Ordering::Relaxed) as f32) * 0.233;
-    RAIN_COUNT.store(0, Ordering::Relaxed);
+    if !RUNTIME.publishes(Field::Rain) {
+        return;
+    }
 
-    mqtt_cli
-        .publish(
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::ExactlyOnce,
+        true,
+        rain_quantity.to_string().as_bytes(),
+    )
+    .map_err(|e| {
+        log::error!("Error publishing rain data: {e}");
+    })
+    .ok();
+}
+
+// Retained so the long term totals are known right after a subscriber connects
+pub fn publish_rain_stats(mqtt_cli: &mut EspMqttClient, stats: &RainStatistics) {
+    if !RUNTIME.publishes(Field::RainStats) {
+        return;
+    }
+    let totals = [
+        ("week", stats.week_mm),
+        ("month", stats.month_mm),
+        ("year", stats.year_mm),
+        ("last_month", stats.last_month_mm),
+    ];
+
+    for (name, total) in totals {
+        let topic = format!("{}/rain/stats/{name}", CONFIG.topic);
+        publish(
+            mqtt_cli,
+            &topic,
+            QoS::AtLeastOnce,
+            true,
+            total.to_string().as_bytes(),
+        )
+        .map_err(|e| log::error!("fail publishing rain {name} total: {e}"))
+        .ok();
+    }
+}
+
+pub fn publish_precipitation(mqtt_cli: &mut EspMqttClient, precip: &PrecipitationAccumulation) {
+    if !RUNTIME.publishes(Field::RainTotals) {
+        return;
+    }
+    let windows = [
+        ("1h", precip.total_1h()),
+        ("3h", precip.total_3h()),
+        ("6h", precip.total_6h()),
+        ("12h", precip.total_12h()),
+        ("24h", precip.total_24h()),
+        ("today", precip.total_today()),
+    ];
+
+    for (window, total) in windows {
+        let topic = format!("{}/rain/{}", CONFIG.topic, window);
+        publish(
+            mqtt_cli,
             &topic,
             QoS::ExactlyOnce,
             true,
-            rain_quantity.to_string().as_bytes(),
+            total.to_string().as_bytes(),
         )
         .map_err(|e| {
-            log::error!("Error publishing rain data: {e}");
+            log::error!("Error publishing {window} rain total: {e}");
         })
         .ok();
+    }
+}
+
+// Lightweight per tip event, not retained
+pub fn publish_rain_tip(mqtt_cli: &mut EspMqttClient, timestamp_ms: u64) {
+    let topic = format!("{}/rain/tip", CONFIG.topic);
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtMostOnce,
+        false,
+        timestamp_ms.to_string().as_bytes(),
+    )
+    .map_err(|e| log::error!("Error publishing rain tip: {e}"))
+    .ok();
+}
+
+pub fn publish_rain_hour(mqtt_cli: &mut EspMqttClient, summary: &RainHourSummary) {
+    if !RUNTIME.publishes(Field::RainTotals) {
+        return;
+    }
+    let topic = format!("{}/rain/peak_rate", CONFIG.topic);
+    let payload = format!(
+        "{{\"hour_start\": {}, \"tips\": {}, \"peak_5min_mm_h\": {}, \"truncated\": {}}}",
+        summary.hour_start_ms / 1000,
+        summary.tips,
+        summary.peak_5min_mm_h,
+        summary.truncated
+    );
+
+    publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+        .map_err(|e| log::error!("Error publishing hourly rain summary: {e}"))
+        .ok();
 }
 
-pub fn publish_wifi_data(mqtt_cli: &mut EspMqttClient, wifi: &mut BlockingWifi<EspWifi>) {
-    let scan_result = wifi.wifi_mut().scan();
-    let topic = format!("{}/wifi", CONFIG.topic);
+// Also returns the stats, for the consolidated payload
+#[derive(Serialize)]
+struct DeviceTelemetry {
+    // Absent on Ethernet and cellular, or when the access point was not found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rssi: Option<i8>,
+    ip: String,
+    free_heap: u32,
+    min_free_heap: u32,
+    uptime_s: u64,
+    wifi_reconnects: u32,
+    mqtt_reconnects: u32,
+}
 
-    match scan_result {
+/// Publishes the state of the device on `<topic>/device`, and the RSSI alone on
+/// `<topic>/wifi` as before. The WiFi stats are returned for the state document.
+pub fn publish_device_telemetry(
+    mqtt_cli: &mut EspMqttClient,
+    wifi: Option<&mut BlockingWifi<EspWifi>>,
+    ip: Ipv4Addr,
+    wifi_reconnects: u32,
+    connection: &ConnectionState,
+) -> Option<WifiStats> {
+    let wifi_stats = wifi.and_then(scan_wifi_stats);
+    if let Some(stats) = wifi_stats {
+        let topic = format!("{}/wifi", CONFIG.topic);
+        publish(
+            mqtt_cli,
+            &topic,
+            QoS::ExactlyOnce,
+            true,
+            stats.rssi.to_string().as_bytes(),
+        )
+        .map_err(|e| {
+            log::error!("Fail publishing wifi data: {e}");
+        })
+        .ok();
+    }
+
+    let telemetry = DeviceTelemetry {
+        rssi: wifi_stats.map(|stats| stats.rssi),
+        ip: ip.to_string(),
+        free_heap: unsafe { esp_get_free_heap_size() },
+        min_free_heap: unsafe { esp_get_minimum_free_heap_size() },
+        uptime_s: unsafe { esp_timer_get_time() } as u64 / 1_000_000,
+        wifi_reconnects,
+        mqtt_reconnects: connection.reconnects(),
+    };
+    let topic = format!("{}/device", CONFIG.topic);
+    match serde_json::to_string(&telemetry) {
+        Ok(payload) => {
+            publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+                .map_err(|e| log::error!("fail publishing device telemetry: {e}"))
+                .ok();
+        }
+        Err(e) => log::error!("fail serializing device telemetry: {e}"),
+    }
+    wifi_stats
+}
+
+// Signal of the access point in use, found by a scan
+fn scan_wifi_stats(wifi: &mut BlockingWifi<EspWifi>) -> Option<WifiStats> {
+    let ssid = wifi::current_ssid(wifi)?;
+    match wifi.wifi_mut().scan() {
         Ok(access_points) => {
-            // Filter to find the access point with SSID "MEO-BD8310"
-            if let Some(net) = access_points.iter().find(|ap| ap.ssid == CONFIG.wifi_ssid) {
-                mqtt_cli
-                    .publish(
-                        &topic,
-                        QoS::ExactlyOnce,
-                        true,
-                        net.signal_strength.to_string().as_bytes(),
-                    )
-                    .map_err(|e| {
-                        log::error!("Fail publishing wifi data: {e}");
-                    })
-                    .ok();
-            } else {
-                log::warn!("{} not found.", CONFIG.wifi_ssid);
+            if let Some(net) = access_points.iter().find(|ap| ap.ssid == ssid.as_str()) {
+                return Some(WifiStats {
+                    rssi: net.signal_strength,
+                    channel: net.channel,
+                });
             }
+            log::warn!("{ssid} not found.");
         }
         Err(e) => {
             log::warn!("Failed to scan WiFi networks: {:?}", e);
         }
     }
+    None
+}
+
+/// Publishes the connection quality report on `<topic>/wifi/quality`.
+pub fn publish_wifi_quality(mqtt_cli: &mut EspMqttClient, report: &QualityReport) {
+    let topic = format!("{}/wifi/quality", CONFIG.topic);
+    match serde_json::to_string(report) {
+        Ok(payload) => {
+            publish(mqtt_cli, &topic, QoS::AtLeastOnce, true, payload.as_bytes())
+                .map_err(|e| log::error!("fail publishing wifi quality: {e}"))
+                .ok();
+        }
+        Err(e) => log::error!("fail serializing wifi quality: {e}"),
+    }
+}
+
+// Answers the wifi_log command, not retained
+pub fn publish_wifi_disconnects(mqtt_cli: &mut EspMqttClient, log: &[Disconnect]) {
+    let topic = format!("{}/wifi/disconnects", CONFIG.topic);
+    let payload = serde_json::to_string(log).unwrap_or_default();
+
+    publish(
+        mqtt_cli,
+        &topic,
+        QoS::AtLeastOnce,
+        false,
+        payload.as_bytes(),
+    )
+    .map_err(|e| log::error!("fail publishing wifi disconnects: {e}"))
+    .ok();
 }
@@ -0,0 +1,428 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    hal::{modem::WifiModemPeripheral, peripheral::Peripheral, spi::SPI2, uart::UART1},
+    ipv4::{self, ClientSettings, Mask, Subnet},
+    nvs::EspDefaultNvsPartition,
+    wifi::{BlockingWifi, EspWifi},
+};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+use weather_station::runtime::RUNTIME;
+
+use crate::captive_portal;
+use crate::cellular::{Cellular, CellularStatus};
+use crate::ethernet::{self, EthLink};
+use crate::provisioning::CONFIG;
+use crate::wifi;
+
+// Time given to the Ethernet link and DHCP at boot before falling back to WiFi
+const ETH_BOOT_TIMEOUT: Duration = Duration::from_secs(10);
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const WIFI_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+// Power up, network attach and PPP negotiation
+const CELLULAR_BOOT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Fixed address of a netif, for networks without DHCP. An empty `dns` leaves it unset.
+pub fn fixed_ip(
+    ip: &str,
+    gateway: &str,
+    netmask_bits: u8,
+    dns: &str,
+) -> Result<ipv4::Configuration> {
+    let ip: Ipv4Addr = ip.parse()?;
+    let gateway: Ipv4Addr = gateway.parse()?;
+    let dns = match dns {
+        "" => None,
+        dns => Some(dns.parse()?),
+    };
+
+    Ok(ipv4::Configuration::Client(
+        ipv4::ClientConfiguration::Fixed(ClientSettings {
+            ip,
+            subnet: Subnet {
+                gateway,
+                mask: Mask(netmask_bits),
+            },
+            dns,
+            secondary_dns: None,
+        }),
+    ))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Uplink {
+    Wifi,
+    Ethernet,
+    Cellular,
+}
+
+impl Uplink {
+    pub fn name(self) -> &'static str {
+        match self {
+            Uplink::Wifi => "wifi",
+            Uplink::Ethernet => "ethernet",
+            Uplink::Cellular => "cellular",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NetworkMode {
+    Wifi,
+    Ethernet,
+    // Ethernet whenever its link is up, WiFi otherwise
+    EthernetWifi,
+    Cellular,
+    // WiFi, cellular after `cellular_after_s` of WiFi outage
+    WifiCellular,
+}
+
+impl NetworkMode {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wifi" => Some(NetworkMode::Wifi),
+            "ethernet" => Some(NetworkMode::Ethernet),
+            "ethernet_wifi" => Some(NetworkMode::EthernetWifi),
+            "cellular" => Some(NetworkMode::Cellular),
+            "wifi_cellular" => Some(NetworkMode::WifiCellular),
+            _ => None,
+        }
+    }
+}
+
+/// The WiFi and Ethernet interfaces selected by `network_mode`, and the supervision of
+/// their links.
+///
+/// MQTT, HTTP and SNTP run on lwIP and follow whichever interface is up, switching uplink
+/// only means bringing the WiFi station or the cellular call up or down.
+pub struct Network {
+    mode: NetworkMode,
+    wifi: Option<BlockingWifi<EspWifi<'static>>>,
+    eth: Option<EthLink>,
+    cellular: Option<Cellular>,
+    active: Uplink,
+    eth_link: bool,
+    last_check: Instant,
+    last_wifi_attempt: Instant,
+    wifi_lost: Option<Instant>,
+    // A reconnection was started and has not come through yet
+    wifi_reconnecting: bool,
+    // Start of the current WiFi outage, while WiFi is the uplink
+    wifi_down_since: Option<Instant>,
+    wifi_reconnects: u32,
+}
+
+impl Network {
+    /// Brings the uplink up, blocking until it has an address.
+    ///
+    /// In `wifi` mode, the setup portal takes over when the station cannot join the network
+    /// after `portal_after_attempts` tries, or at once with `portal`. It ends with a reboot.
+    pub fn connect(
+        modem: impl Peripheral<P = impl WifiModemPeripheral> + 'static,
+        spi: Option<SPI2>,
+        uart: Option<UART1>,
+        sys_loop: EspSystemEventLoop,
+        nvs: EspDefaultNvsPartition,
+        portal: bool,
+    ) -> Result<Self> {
+        let mode = NetworkMode::from_name(CONFIG.network_mode).unwrap_or_else(|| {
+            log::error!("Unknown network_mode {}, using wifi", CONFIG.network_mode);
+            NetworkMode::Wifi
+        });
+
+        let mut eth = None;
+        if matches!(mode, NetworkMode::Ethernet | NetworkMode::EthernetWifi) {
+            let Some(spi) = spi else {
+                bail!("SPI2 is not available for the Ethernet controller");
+            };
+            let mut link = ethernet::eth_init(spi, sys_loop.clone())?;
+            log::info!("Starting ethernet");
+            link.start()?;
+            eth = Some(link);
+        }
+        let mut cellular = None;
+        if matches!(mode, NetworkMode::Cellular | NetworkMode::WifiCellular) {
+            let Some(uart) = uart else {
+                bail!("UART1 is not available for the cellular modem");
+            };
+            cellular = Some(Cellular::start(uart)?);
+        }
+        // Started whenever WiFi may be used, ESP-NOW needs it too
+        let mut wifi = None;
+        if matches!(
+            mode,
+            NetworkMode::Wifi | NetworkMode::EthernetWifi | NetworkMode::WifiCellular
+        ) {
+            let mut driver = wifi::wifi_init(modem, sys_loop.clone(), nvs.clone())?;
+            wifi::start_wifi(&mut driver)?;
+            wifi = Some(driver);
+        }
+
+        let mut network = Self {
+            mode,
+            wifi,
+            eth,
+            cellular,
+            active: Uplink::Wifi,
+            eth_link: false,
+            last_check: Instant::now(),
+            last_wifi_attempt: Instant::now(),
+            wifi_lost: None,
+            wifi_reconnecting: false,
+            wifi_down_since: None,
+            wifi_reconnects: 0,
+        };
+        if let Some(eth) = network.eth.as_ref() {
+            let timeout = (mode == NetworkMode::EthernetWifi).then_some(ETH_BOOT_TIMEOUT);
+            match wait_eth_up(eth, timeout) {
+                Ok(()) => {
+                    log::info!("Ethernet netif up");
+                    network.set_active(Uplink::Ethernet);
+                    network.eth_link = true;
+                }
+                Err(e) if mode == NetworkMode::EthernetWifi => {
+                    log::warn!("Ethernet not up ({e}), falling back to wifi");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if mode == NetworkMode::Cellular {
+            network.set_active(Uplink::Cellular);
+        } else if network.active == Uplink::Wifi {
+            if let Some(driver) = network.wifi.as_mut() {
+                if mode == NetworkMode::Wifi && portal {
+                    captive_portal::run_portal(driver, nvs);
+                }
+                let mut result = wifi::connect_wifi(driver, &nvs);
+                let mut attempts = 1;
+                while result.is_err() && mode == NetworkMode::Wifi {
+                    if CONFIG.portal_after_attempts == 0 {
+                        break;
+                    }
+                    if attempts >= CONFIG.portal_after_attempts {
+                        log::warn!("Wifi not connected after {attempts} attempts");
+                        captive_portal::run_portal(driver, nvs);
+                    }
+                    std::thread::sleep(WIFI_RETRY_INTERVAL);
+                    attempts += 1;
+                    result = wifi::connect_wifi(driver, &nvs);
+                }
+                match result {
+                    Ok(()) => {}
+                    Err(e) if mode == NetworkMode::WifiCellular => {
+                        log::warn!("Wifi not connected ({e}), switching to cellular");
+                        network.wifi_lost = Some(Instant::now());
+                        network.set_active(Uplink::Cellular);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        if let Some(cellular) = network.cellular.as_ref() {
+            let start = Instant::now();
+            while network.active == Uplink::Cellular && !cellular.status().connected {
+                if start.elapsed() > CELLULAR_BOOT_TIMEOUT {
+                    bail!("cellular link not up");
+                }
+                std::thread::sleep(Duration::from_millis(500));
+            }
+        }
+        log::info!("Uplink: {}", network.active.name());
+
+        Ok(network)
+    }
+
+    /// How long WiFi has been down, None while it is up or not the uplink.
+    pub fn wifi_down_for(&self) -> Option<Duration> {
+        self.wifi_down_since
+            .filter(|_| self.active == Uplink::Wifi)
+            .map(|since| since.elapsed())
+    }
+
+    /// Brings up the diagnostic access point next to the station, which keeps trying to
+    /// reconnect. Returns the address of the access point.
+    pub fn start_diag_ap(&mut self) -> Result<Ipv4Addr> {
+        let Some(wifi) = self.wifi.as_mut() else {
+            bail!("WiFi is not started");
+        };
+        wifi::start_diag_ap(wifi)
+    }
+
+    pub fn stop_diag_ap(&mut self) -> Result<()> {
+        let Some(wifi) = self.wifi.as_mut() else {
+            bail!("WiFi is not started");
+        };
+        wifi::stop_diag_ap(wifi)
+    }
+
+    /// WiFi reconnections that came through since boot.
+    pub fn wifi_reconnects(&self) -> u32 {
+        self.wifi_reconnects
+    }
+
+    // Unspecified when the active netif has no address yet
+    pub fn ip(&self) -> Ipv4Addr {
+        match (self.active, &self.eth, &self.wifi) {
+            (Uplink::Ethernet, Some(eth), _) => eth
+                .eth()
+                .netif()
+                .get_ip_info()
+                .map(|info| info.ip)
+                .unwrap_or(Ipv4Addr::UNSPECIFIED),
+            (Uplink::Cellular, ..) => self
+                .cellular_status()
+                .and_then(|status| status.ip)
+                .unwrap_or(Ipv4Addr::UNSPECIFIED),
+            (_, _, Some(wifi)) => wifi::station_ip(wifi),
+            _ => Ipv4Addr::UNSPECIFIED,
+        }
+    }
+
+    pub fn cellular_status(&self) -> Option<CellularStatus> {
+        self.cellular.as_ref().map(Cellular::status)
+    }
+
+    /// The WiFi driver while it is the uplink, for the signal report.
+    pub fn active_wifi(&mut self) -> Option<&mut BlockingWifi<EspWifi<'static>>> {
+        self.wifi.as_mut().filter(|_| self.active == Uplink::Wifi)
+    }
+
+    /// Follows the link events, returns the new uplink when it changed.
+    ///
+    /// A lost WiFi connection is retried every 10 s. An Ethernet link going down hands over
+    /// to WiFi in `ethernet_wifi` mode, and WiFi is dropped again once the link is back. In
+    /// `wifi_cellular` mode a WiFi outage longer than `cellular_after_s` dials the modem, and
+    /// the call is hung up as soon as WiFi reconnects.
+    pub fn supervise(&mut self) -> Option<Uplink> {
+        if self.last_check.elapsed() < LINK_CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let eth_link = self
+            .eth
+            .as_ref()
+            .is_some_and(|eth| eth.eth().is_connected().unwrap_or(false));
+        if eth_link != self.eth_link {
+            self.eth_link = eth_link;
+            log::warn!("Ethernet link {}", if eth_link { "up" } else { "down" });
+        }
+
+        let previous = self.active;
+        match self.mode {
+            // The netif gets its address back by itself when the link returns, the modem
+            // thread redials a dropped call
+            NetworkMode::Ethernet | NetworkMode::Cellular => {}
+            NetworkMode::Wifi => self.retry_wifi(),
+            NetworkMode::EthernetWifi if eth_link => {
+                if self.active == Uplink::Wifi {
+                    self.set_active(Uplink::Ethernet);
+                    if let Some(wifi) = self.wifi.as_mut() {
+                        wifi.wifi_mut()
+                            .disconnect()
+                            .map_err(|e| log::error!("fail disconnecting wifi: {e}"))
+                            .ok();
+                    }
+                }
+            }
+            NetworkMode::EthernetWifi => {
+                if self.active == Uplink::Ethernet {
+                    self.set_active(Uplink::Wifi);
+                    // Connect right away instead of waiting for the retry interval
+                    self.last_wifi_attempt = Instant::now() - WIFI_RETRY_INTERVAL;
+                }
+                self.retry_wifi();
+            }
+            NetworkMode::WifiCellular => {
+                let wifi_up = self
+                    .wifi
+                    .as_ref()
+                    .is_some_and(|wifi| wifi.is_connected().unwrap_or(false));
+                if wifi_up {
+                    self.wifi_lost = None;
+                    if self.active == Uplink::Cellular {
+                        self.set_active(Uplink::Wifi);
+                    }
+                } else {
+                    let lost = *self.wifi_lost.get_or_insert_with(Instant::now);
+                    let outage = Duration::from_secs(CONFIG.cellular_after_s as u64);
+                    if self.active == Uplink::Wifi && lost.elapsed() >= outage {
+                        self.set_active(Uplink::Cellular);
+                    }
+                }
+                self.retry_wifi();
+            }
+        }
+
+        (self.active != previous).then(|| {
+            log::warn!("Uplink switched to {}", self.active.name());
+            self.active
+        })
+    }
+
+    // Publishing slows down on the metered cellular link
+    fn set_active(&mut self, uplink: Uplink) {
+        self.active = uplink;
+        if let Some(cellular) = self.cellular.as_ref() {
+            cellular.set_enabled(uplink == Uplink::Cellular);
+        }
+        RUNTIME.set_interval_factor(match uplink {
+            Uplink::Cellular => CONFIG.cellular_interval_factor,
+            _ => 1,
+        });
+    }
+
+    // Non blocking, the next supervision sees the result
+    fn retry_wifi(&mut self) {
+        let Some(wifi) = self.wifi.as_mut() else {
+            return;
+        };
+        if wifi.is_connected().unwrap_or(false) {
+            self.wifi_down_since = None;
+            if self.wifi_reconnecting {
+                self.wifi_reconnecting = false;
+                self.wifi_reconnects += 1;
+            }
+            return;
+        }
+        self.wifi_down_since.get_or_insert_with(Instant::now);
+        if self.last_wifi_attempt.elapsed() < WIFI_RETRY_INTERVAL {
+            return;
+        }
+        self.last_wifi_attempt = Instant::now();
+        self.wifi_reconnecting = true;
+        log::info!("Wifi disconnected, reconnecting");
+        wifi.wifi_mut()
+            .connect()
+            .map_err(|e| log::error!("fail reconnecting wifi: {e}"))
+            .ok();
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(wifi) = self.wifi.as_mut() {
+            wifi.disconnect()
+                .map_err(|e| log::error!("fail disconnecting wifi: {e}"))
+                .ok();
+            wifi.stop()
+                .map_err(|e| log::error!("fail stopping wifi: {e}"))
+                .ok();
+        }
+        if let Some(cellular) = self.cellular.as_ref() {
+            cellular.set_enabled(false);
+        }
+        if let Some(eth) = self.eth.as_mut() {
+            eth.stop()
+                .map_err(|e| log::error!("fail stopping ethernet: {e}"))
+                .ok();
+        }
+    }
+}
+
+fn wait_eth_up(eth: &EthLink, timeout: Option<Duration>) -> Result<()> {
+    match timeout {
+        Some(timeout) => eth.ip_wait_while(|| eth.eth().is_up().map(|up| !up), Some(timeout))?,
+        None => eth.wait_netif_up()?,
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! NMEA 0183 sentences for marine instruments.
+//!
+//! Missing values are sent as empty fields, as the standard requires, never as zeros.
+
+const KMH_PER_KNOT: f32 = 1.852;
+
+/// Relative wind (no heading is available): angle in degrees and speed in knots.
+pub fn mwv(talker: &str, angle_deg: Option<f32>, speed_kmh: Option<f32>) -> String {
+    let status = if angle_deg.is_some() && speed_kmh.is_some() {
+        'A'
+    } else {
+        'V'
+    };
+    sentence(
+        talker,
+        "MWV",
+        &format!(
+            "{},R,{},N,{status}",
+            field(angle_deg.map(|angle| angle.rem_euclid(360.0)), 1),
+            field(speed_kmh.map(|speed| speed / KMH_PER_KNOT), 1)
+        ),
+    )
+}
+
+/// Air temperature (°C), barometric pressure (bar) and relative humidity (%) transducers.
+pub fn xdr(
+    talker: &str,
+    temperature: Option<f32>,
+    pressure_hpa: Option<f32>,
+    humidity: Option<f32>,
+) -> String {
+    sentence(
+        talker,
+        "XDR",
+        &format!(
+            "C,{},C,AIRTEMP,P,{},B,BARO,H,{},P,HUMIDITY",
+            field(temperature, 1),
+            field(pressure_hpa.map(|hpa| hpa / 1000.0), 4),
+            field(humidity, 1)
+        ),
+    )
+}
+
+/// `$<talker><kind>,<fields>*<checksum>` followed by CR LF.
+pub fn sentence(talker: &str, kind: &str, fields: &str) -> String {
+    let body = format!("{talker}{kind},{fields}");
+    format!("${body}*{:02X}\r\n", checksum(&body))
+}
+
+/// XOR of the characters between `$` and `*`.
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |sum, byte| sum ^ byte)
+}
+
+/// Talker ids are two uppercase letters, e.g. `WI` for weather instruments.
+pub fn is_valid_talker(talker: &str) -> bool {
+    talker.len() == 2 && talker.bytes().all(|b| b.is_ascii_uppercase())
+}
+
+fn field(value: Option<f32>, decimals: usize) -> String {
+    value
+        .filter(|value| value.is_finite())
+        .map_or(String::new(), |value| format!("{value:.decimals$}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_matches_reference_sentences() {
+        // The GGA and RMC examples of the NMEA 0183 references
+        assert_eq!(
+            checksum("GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            0x47
+        );
+        assert_eq!(
+            checksum("GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W"),
+            0x6A
+        );
+    }
+
+    #[test]
+    fn formats_mwv() {
+        assert_eq!(
+            mwv("WI", Some(270.0), Some(18.52)),
+            "$WIMWV,270.0,R,10.0,N,A*17\r\n"
+        );
+        assert_eq!(
+            mwv("II", Some(-10.0), Some(10.0)),
+            "$IIMWV,350.0,R,5.4,N,A*3A\r\n"
+        );
+    }
+
+    #[test]
+    fn formats_xdr() {
+        assert_eq!(
+            xdr("WI", Some(21.5), Some(1013.2), Some(55.0)),
+            "$WIXDR,C,21.5,C,AIRTEMP,P,1.0132,B,BARO,H,55.0,P,HUMIDITY*12\r\n"
+        );
+    }
+
+    #[test]
+    fn leaves_missing_values_empty() {
+        assert_eq!(mwv("WI", None, None), "$WIMWV,,R,,N,V*34\r\n");
+        assert_eq!(mwv("WI", Some(45.0), None), "$WIMWV,45.0,R,,N,V*2B\r\n");
+        assert_eq!(
+            xdr("WI", None, None, Some(f32::NAN)),
+            "$WIXDR,C,,C,AIRTEMP,P,,B,BARO,H,,P,HUMIDITY*0B\r\n"
+        );
+    }
+}
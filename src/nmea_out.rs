@@ -0,0 +1,71 @@
+use anyhow::Result;
+use esp_idf_svc::hal::{
+    delay::BLOCK,
+    gpio::{AnyIOPin, AnyOutputPin, Output, PinDriver},
+    uart::{config::Config as UartConfig, UartTxDriver, UART2},
+    units::Hertz,
+};
+use weather_station::nmea;
+
+use crate::provisioning::CONFIG;
+
+// NMEA 0183 standard rate
+const NMEA_BAUD: u32 = 4800;
+
+/// Talker on UART2 through the RS485 transceiver, NMEA 0183 uses the same RS422 levels.
+pub struct NmeaOutput<'d> {
+    uart: UartTxDriver<'d>,
+    // Kept high, the station is the only talker on the pair
+    _de_re: PinDriver<'d, AnyOutputPin, Output>,
+}
+
+impl NmeaOutput<'_> {
+    pub fn new(uart: UART2) -> Result<Self> {
+        if !nmea::is_valid_talker(CONFIG.nmea_talker) {
+            anyhow::bail!("invalid nmea_talker {}", CONFIG.nmea_talker);
+        }
+        // Pins come from the config, they are not claimed by any other driver
+        let (tx, de_re) = unsafe {
+            (
+                AnyIOPin::new(CONFIG.rs485_tx_gpio),
+                AnyOutputPin::new(CONFIG.rs485_de_gpio),
+            )
+        };
+        let uart = UartTxDriver::new(
+            uart,
+            tx,
+            Option::<AnyIOPin>::None,
+            Option::<AnyIOPin>::None,
+            &UartConfig::new().baudrate(Hertz(NMEA_BAUD)),
+        )?;
+        let mut de_re = PinDriver::output(de_re)?;
+        de_re.set_high()?;
+
+        Ok(Self {
+            uart,
+            _de_re: de_re,
+        })
+    }
+
+    /// Sends the wind and the environment sentences of one measurement cycle.
+    pub fn send(
+        &mut self,
+        wind_angle_deg: Option<f32>,
+        wind_speed_kmh: Option<f32>,
+        temperature: Option<f32>,
+        pressure_hpa: Option<f32>,
+        humidity: Option<f32>,
+    ) {
+        let talker = CONFIG.nmea_talker;
+        for sentence in [
+            nmea::mwv(talker, wind_angle_deg, wind_speed_kmh),
+            nmea::xdr(talker, temperature, pressure_hpa, humidity),
+        ] {
+            self.uart
+                .write(sentence.as_bytes())
+                .and_then(|_| self.uart.wait_done(BLOCK))
+                .map_err(|e| log::error!("fail sending NMEA sentence: {e}"))
+                .ok();
+        }
+    }
+}
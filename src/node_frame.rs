@@ -0,0 +1,117 @@
+//! Frames of satellite sensor nodes that do not measure the weather, e.g. a soil probe in the
+//! garden. A frame carries up to `MAX_NODE_VALUES` measurements, each tagged with its kind:
+//! version, count, then a kind byte and a little endian f32 per value, and a CRC-16 of all.
+use crate::compute::crc16;
+use crate::reading::NodeFrameError;
+
+/// Version byte of a sensor frame, weather frames use `NODE_FRAME_VERSION`.
+pub const SENSOR_FRAME_VERSION: u8 = 2;
+pub const MAX_NODE_VALUES: usize = 8;
+const VALUE_LEN: usize = 1 + 4;
+
+/// What a node value measures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NodeValueKind {
+    /// Volumetric water content, %
+    SoilMoisture = 1,
+    /// °C
+    SoilTemperature = 2,
+    /// %
+    LeafWetness = 3,
+    /// °C
+    Temperature = 4,
+    /// %
+    Humidity = 5,
+    /// lx
+    Illuminance = 6,
+    /// V
+    BatteryVoltage = 7,
+}
+
+impl NodeValueKind {
+    pub const ALL: [NodeValueKind; 7] = [
+        NodeValueKind::SoilMoisture,
+        NodeValueKind::SoilTemperature,
+        NodeValueKind::LeafWetness,
+        NodeValueKind::Temperature,
+        NodeValueKind::Humidity,
+        NodeValueKind::Illuminance,
+        NodeValueKind::BatteryVoltage,
+    ];
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| *kind as u8 == code)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NodeValueKind::SoilMoisture => "soil_moisture",
+            NodeValueKind::SoilTemperature => "soil_temperature",
+            NodeValueKind::LeafWetness => "leaf_wetness",
+            NodeValueKind::Temperature => "temperature",
+            NodeValueKind::Humidity => "humidity",
+            NodeValueKind::Illuminance => "illuminance",
+            NodeValueKind::BatteryVoltage => "battery_voltage",
+        }
+    }
+}
+
+/// Measurements of one sensor frame. Values of an unknown kind are skipped, so older
+/// gateways keep the ones they know.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SensorFrame {
+    pub values: heapless::Vec<(NodeValueKind, f32), MAX_NODE_VALUES>,
+}
+
+impl SensorFrame {
+    pub fn to_bytes(&self) -> heapless::Vec<u8, { 2 + MAX_NODE_VALUES * VALUE_LEN + 2 }> {
+        let mut frame = heapless::Vec::new();
+        frame.push(SENSOR_FRAME_VERSION).ok();
+        frame.push(self.values.len() as u8).ok();
+        for (kind, value) in &self.values {
+            frame.push(*kind as u8).ok();
+            frame.extend_from_slice(&value.to_le_bytes()).ok();
+        }
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes()).ok();
+        frame
+    }
+
+    pub fn from_bytes(frame: &[u8]) -> Result<Self, NodeFrameError> {
+        if frame.len() < 4 {
+            return Err(NodeFrameError::Length);
+        }
+        let (body, crc) = frame.split_at(frame.len() - 2);
+        if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+            return Err(NodeFrameError::Crc);
+        }
+        if body[0] != SENSOR_FRAME_VERSION {
+            return Err(NodeFrameError::Version(body[0]));
+        }
+        let count = body[1] as usize;
+        if count > MAX_NODE_VALUES || body.len() != 2 + count * VALUE_LEN {
+            return Err(NodeFrameError::Length);
+        }
+        let mut values = heapless::Vec::new();
+        for value in body[2..].chunks_exact(VALUE_LEN) {
+            if let Some(kind) = NodeValueKind::from_code(value[0]) {
+                let value = f32::from_le_bytes([value[1], value[2], value[3], value[4]]);
+                values.push((kind, value)).ok();
+            }
+        }
+        Ok(Self { values })
+    }
+
+    /// JSON object of the values, keyed by kind.
+    #[cfg(feature = "std")]
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .values
+            .iter()
+            .filter(|(_, value)| value.is_finite())
+            .map(|(kind, value)| format!("\"{}\": {value}", kind.name()))
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+}
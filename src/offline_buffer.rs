@@ -0,0 +1,167 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use std::collections::VecDeque;
+use weather_station::{reading::TimedReading, store_forward::*};
+
+use crate::provisioning::CONFIG;
+
+const NVS_NAMESPACE: &str = "offline";
+// Sequence numbers of the oldest batch and of the next one to write
+const FIRST_KEY: &str = "first";
+const NEXT_KEY: &str = "next";
+const MAX_BATCH_LEN: usize = 100;
+
+/// Readings taken while the broker is unreachable, replayed with their timestamps once it is
+/// back.
+///
+/// Up to `offline_batch_len` readings are queued in RAM. When the queue is full, and before a
+/// deep sleep, they are written to NVS as a batch. Beyond `offline_max_batches` the oldest
+/// batch is dropped.
+pub struct OfflineBuffer {
+    nvs: EspNvs<NvsDefault>,
+    ram: VecDeque<TimedReading>,
+    // Oldest readings, taken out of NVS or the RAM queue for the replay
+    replay: VecDeque<TimedReading>,
+    first: u32,
+    next: u32,
+    dropped: u32,
+}
+
+impl OfflineBuffer {
+    pub fn open(nvs: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+        let first = nvs.get_u32(FIRST_KEY)?.unwrap_or(0);
+        let next = nvs.get_u32(NEXT_KEY)?.unwrap_or(first);
+        if next != first {
+            log::info!(
+                "{} batches of offline readings to replay",
+                next.wrapping_sub(first)
+            );
+        }
+        Ok(Self {
+            nvs,
+            ram: VecDeque::new(),
+            replay: VecDeque::new(),
+            first,
+            next,
+            dropped: 0,
+        })
+    }
+
+    pub fn push(&mut self, record: TimedReading) {
+        self.ram.push_back(record);
+        if self.ram.len() >= batch_capacity() {
+            self.spill();
+        }
+    }
+
+    /// Oldest reading still to replay.
+    pub fn front(&mut self) -> Option<TimedReading> {
+        while self.replay.is_empty() && self.first != self.next {
+            // Out of NVS from now on, `flush` puts back what is left before a deep sleep
+            let batch = self.read_batch(self.first).unwrap_or_default();
+            self.nvs.remove(&batch_key(self.first)).ok();
+            self.first = self.first.wrapping_add(1);
+            self.save_index();
+            self.replay.extend(batch);
+        }
+        if self.replay.is_empty() {
+            self.replay.append(&mut self.ram);
+        }
+        self.replay.front().copied()
+    }
+
+    /// Drops the reading returned by `front` once it was published.
+    pub fn pop_front(&mut self) {
+        self.replay.pop_front();
+    }
+
+    /// Readings lost since the boot, to the size limit or to NVS errors.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Keeps everything still in RAM in NVS, before a deep sleep.
+    pub fn flush(&mut self) {
+        if !self.replay.is_empty() {
+            // Older than every stored batch, it goes back in front of them
+            let seq = self.first.wrapping_sub(1);
+            let records: Vec<TimedReading> = self.replay.drain(..).collect();
+            match self.nvs.set_blob(&batch_key(seq), &encode_batch(&records)) {
+                Ok(()) => {
+                    self.first = seq;
+                    self.save_index();
+                }
+                Err(e) => self.drop_records(records.len(), e),
+            }
+        }
+        self.spill();
+    }
+
+    // The RAM queue goes to the newest batch when it has room, to a new one otherwise
+    fn spill(&mut self) {
+        if self.ram.is_empty() {
+            return;
+        }
+        let mut records = vec![];
+        let mut seq = self.next;
+        if self.first != self.next {
+            let newest = self.next.wrapping_sub(1);
+            let stored = self.read_batch(newest).unwrap_or_default();
+            if stored.len() + self.ram.len() <= batch_capacity() {
+                records = stored;
+                seq = newest;
+            }
+        }
+        let spilled = self.ram.len();
+        records.extend(self.ram.drain(..));
+        if let Err(e) = self.nvs.set_blob(&batch_key(seq), &encode_batch(&records)) {
+            self.drop_records(spilled, e);
+            return;
+        }
+        self.next = seq.wrapping_add(1);
+
+        let max_batches = CONFIG.offline_max_batches.max(1);
+        while self.next.wrapping_sub(self.first) > max_batches {
+            let lost = self.read_batch(self.first).map_or(0, |batch| batch.len());
+            self.dropped += lost as u32;
+            log::warn!("Offline buffer full, {lost} oldest readings dropped");
+            self.nvs.remove(&batch_key(self.first)).ok();
+            self.first = self.first.wrapping_add(1);
+        }
+        self.save_index();
+    }
+
+    fn read_batch(&self, seq: u32) -> Option<Vec<TimedReading>> {
+        // Whatever `offline_batch_len` the batch was written with
+        let mut buf = vec![0u8; batch_len(MAX_BATCH_LEN)];
+        match self.nvs.get_blob(&batch_key(seq), &mut buf) {
+            Ok(blob) => blob.and_then(decode_batch),
+            Err(e) => {
+                log::error!("fail reading offline batch {seq}: {e}");
+                None
+            }
+        }
+    }
+
+    fn save_index(&mut self) {
+        self.nvs
+            .set_u32(FIRST_KEY, self.first)
+            .and_then(|()| self.nvs.set_u32(NEXT_KEY, self.next))
+            .map_err(|e| log::error!("fail storing offline buffer index: {e}"))
+            .ok();
+    }
+
+    fn drop_records(&mut self, count: usize, e: impl std::fmt::Display) {
+        self.dropped += count as u32;
+        log::error!("fail storing {count} offline readings: {e}");
+    }
+}
+
+fn batch_capacity() -> usize {
+    (CONFIG.offline_batch_len as usize).clamp(1, MAX_BATCH_LEN)
+}
+
+fn batch_key(seq: u32) -> String {
+    format!("b{seq}")
+}
@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10},
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+use embedded_hal::i2c::I2c;
+use ssd1306::{mode::BufferedGraphicsMode, prelude::*, I2CDisplayInterface, Ssd1306};
+use std::time::{Duration, Instant};
+use weather_station::{reading::WeatherReading, stats::ROSE_SECTORS};
+
+use crate::provisioning::CONFIG;
+
+const PAGES: usize = 5;
+// Width of a status icon, they sit in the top right corner
+const ICON_W: i32 = 12;
+
+/// Connection state shown by the icons.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkStatus {
+    pub network: bool,
+    pub mqtt: bool,
+}
+
+/// 128x64 SSD1306 on the sensor I2C bus, cycling through one page per measurement every
+/// `oled_page_s`. Driven from the main loop, which owns the bus.
+pub struct Oled<I2C: I2c> {
+    display: Ssd1306<I2CInterface<I2C>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>,
+    reading: Option<WeatherReading>,
+    links: LinkStatus,
+    page: usize,
+    drawn_at: Option<Instant>,
+    dirty: bool,
+}
+
+impl<I2C: I2c> Oled<I2C> {
+    pub fn new(i2c: I2C) -> Result<Self> {
+        let interface = I2CDisplayInterface::new_custom_address(i2c, CONFIG.oled_address);
+        let mut display = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+            .into_buffered_graphics_mode();
+        display
+            .init()
+            .map_err(|e| anyhow!("SSD1306 init failed: {e:?}"))?;
+
+        Ok(Self {
+            display,
+            reading: None,
+            links: LinkStatus::default(),
+            page: 0,
+            drawn_at: None,
+            dirty: true,
+        })
+    }
+
+    pub fn set_reading(&mut self, reading: &WeatherReading) {
+        self.reading = Some(*reading);
+        self.dirty = true;
+    }
+
+    /// Turns the page when it is due and redraws on changes, cheap otherwise.
+    pub fn update(&mut self, links: LinkStatus) {
+        let period = Duration::from_secs(CONFIG.oled_page_s.max(1) as u64);
+        if self.drawn_at.is_some_and(|at| at.elapsed() >= period) {
+            self.page = (self.page + 1) % PAGES;
+            self.dirty = true;
+        }
+        if links != self.links {
+            self.links = links;
+            self.dirty = true;
+        }
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        self.drawn_at = Some(Instant::now());
+        self.display.clear_buffer();
+        self.draw_page();
+        self.draw_icons();
+        self.display
+            .flush()
+            .unwrap_or_else(|e| log::warn!("Fail refreshing the OLED display: {e:?}"));
+    }
+
+    fn draw_page(&mut self) {
+        let small = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        let big = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        let Some(reading) = self.reading else {
+            Text::new("Waiting for data", Point::new(0, 36), small)
+                .draw(&mut self.display)
+                .ok();
+            return;
+        };
+        let sector = ((reading.wind_direction_deg.rem_euclid(360.0) / 22.5 + 0.5) as usize) % 16;
+        let (title, value, detail) = match self.page {
+            0 => (
+                "Temperature",
+                format!("{:.1} C", reading.temperature),
+                String::new(),
+            ),
+            1 => (
+                "Humidity",
+                format!("{:.0} %", reading.humidity),
+                String::new(),
+            ),
+            2 => (
+                "Pressure",
+                format!("{:.0} hPa", reading.pressure),
+                reading
+                    .sea_level_pressure
+                    .map_or(String::new(), |p| format!("Sea level {p:.0} hPa")),
+            ),
+            3 => (
+                "Wind",
+                format!("{:.1} km/h", reading.wind_speed_kmh),
+                format!("{} gust {:.1}", ROSE_SECTORS[sector], reading.wind_gust_kmh),
+            ),
+            _ => (
+                "Rain today",
+                format!("{:.1} mm", reading.rain_totals.today_mm),
+                format!("Last hour {:.1} mm", reading.rain_totals.last_1h_mm),
+            ),
+        };
+        Text::new(title, Point::new(0, 8), small)
+            .draw(&mut self.display)
+            .ok();
+        Text::with_alignment(&value, Point::new(64, 40), big, Alignment::Center)
+            .draw(&mut self.display)
+            .ok();
+        Text::with_alignment(&detail, Point::new(64, 60), small, Alignment::Center)
+            .draw(&mut self.display)
+            .ok();
+    }
+
+    // WiFi bars and an `M` box for the broker, crossed out while down
+    fn draw_icons(&mut self) {
+        let stroke = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+        let small = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        let network = Point::new(128 - 2 * ICON_W - 2, 0);
+        for (i, height) in [3, 5, 8].into_iter().enumerate() {
+            Rectangle::new(
+                network + Point::new(i as i32 * 4, 8 - height),
+                Size::new(3, height as u32),
+            )
+            .into_styled(stroke)
+            .draw(&mut self.display)
+            .ok();
+        }
+        let mqtt = Point::new(128 - ICON_W, 0);
+        Rectangle::new(mqtt, Size::new(ICON_W as u32, 9))
+            .into_styled(stroke)
+            .draw(&mut self.display)
+            .ok();
+        Text::new("M", mqtt + Point::new(3, 8), small)
+            .draw(&mut self.display)
+            .ok();
+        for (origin, up) in [(network, self.links.network), (mqtt, self.links.mqtt)] {
+            if !up {
+                Line::new(
+                    origin + Point::new(0, 8),
+                    origin + Point::new(ICON_W - 1, 0),
+                )
+                .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 2))
+                .draw(&mut self.display)
+                .ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::{
+    hal::delay::FreeRtos,
+    http::{
+        client::{Configuration, EspHttpConnection},
+        Method,
+    },
+    io::Read,
+    mqtt::client::EspMqttClient,
+    ota::{EspOta, SlotState},
+    sys::{esp_crt_bundle_attach, esp_restart},
+};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+use crate::{mqtt, watchdog};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// On the heap, the main task stack is only 8 KB
+const CHUNK_LEN: usize = 4096;
+// Progress is published every this many percent
+const PROGRESS_STEP: usize = 10;
+
+/// Runs a requested update and reboots into the new firmware, returns when it failed.
+pub fn update_and_reboot(mqtt_cli: &mut EspMqttClient, url: &str, sha256: [u8; 32]) {
+    log::warn!("Firmware update from {url}");
+    mqtt::publish_ota_status(mqtt_cli, "started");
+    match update(mqtt_cli, url, sha256) {
+        Ok(()) => {
+            mqtt::publish_ota_status(mqtt_cli, "rebooting");
+            // Lets the status reach the broker
+            FreeRtos::delay_ms(500);
+            unsafe { esp_restart() }
+        }
+        Err(e) => {
+            log::error!("Firmware update failed: {e}");
+            mqtt::publish_ota_status(mqtt_cli, &format!("failed: {e}"));
+        }
+    }
+}
+
+/// Downloads the image at `url` to the inactive OTA partition and makes it the boot one.
+///
+/// The image is checked by the bootloader format and checksum, and against `sha256`. On any
+/// error the partition is left alone and the running firmware keeps booting.
+fn update(mqtt_cli: &mut EspMqttClient, url: &str, sha256: [u8; 32]) -> Result<()> {
+    let mut conn = EspHttpConnection::new(&Configuration {
+        timeout: Some(REQUEST_TIMEOUT),
+        crt_bundle_attach: Some(esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    conn.initiate_request(Method::Get, url, &[])?;
+    conn.initiate_response()?;
+    match conn.status() {
+        200 => {}
+        status => bail!("status {status}"),
+    }
+    let total = conn
+        .header("Content-Length")
+        .and_then(|len| len.parse::<usize>().ok());
+
+    let mut ota = EspOta::new()?;
+    let mut update = ota.initiate_update()?;
+    let mut hasher = Sha256::new();
+    let mut download = || -> Result<usize> {
+        let mut buf = vec![0u8; CHUNK_LEN];
+        let mut written = 0;
+        let mut reported = 0;
+        loop {
+            // A slow download must not trip the main loop watchdog
+            watchdog::feed();
+            let len = conn.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            update.write(&buf[..len])?;
+            hasher.update(&buf[..len]);
+            written += len;
+            if let Some(total) = total.filter(|total| *total > 0) {
+                let percent = written * 100 / total;
+                if percent >= reported + PROGRESS_STEP {
+                    reported = percent - percent % PROGRESS_STEP;
+                    mqtt::publish_ota_status(mqtt_cli, &format!("downloading {reported}%"));
+                }
+            }
+        }
+        if total.is_some_and(|total| total != written) {
+            bail!(
+                "{written} bytes received out of {}",
+                total.unwrap_or_default()
+            );
+        }
+        if hasher.finalize_reset()[..] != sha256 {
+            bail!("SHA-256 mismatch");
+        }
+        Ok(written)
+    };
+    match download() {
+        Ok(written) => {
+            // Checks the image before switching the boot partition to it
+            update.complete()?;
+            log::warn!("Firmware update of {written} bytes flashed");
+            Ok(())
+        }
+        Err(e) => {
+            update.abort().ok();
+            Err(e)
+        }
+    }
+}
+
+/// Marks the running firmware valid. A freshly updated firmware that is not marked valid
+/// before the next reset, deep sleep included, is rolled back by the bootloader.
+///
+/// Returns true when the firmware was waiting for it.
+pub fn confirm_running_image() -> Result<bool> {
+    let mut ota = EspOta::new()?;
+    if ota.get_running_slot()?.state != SlotState::Unverified {
+        return Ok(false);
+    }
+    ota.mark_running_slot_valid()?;
+    Ok(true)
+}
@@ -0,0 +1,190 @@
+use crate::{
+    compute::*,
+    runtime::{PublishGroup, RUNTIME},
+    sensors::angle::AngleSensor,
+};
+use anyhow::Result;
+use esp_idf_svc::{
+    hal::{delay::BLOCK, gpio::*, task::notification::Notification},
+    sys::{esp_sleep_enable_gpio_wakeup, esp_sleep_enable_timer_wakeup, esp_timer_get_time},
+};
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Instant;
+
+// GLOBAL ATOMIC VAR
+pub static ROTATION_COUNT: AtomicU32 = AtomicU32::new(0);
+pub static RAIN_COUNT: AtomicU32 = AtomicU32::new(0);
+pub static BUTTON_FLAG: AtomicBool = AtomicBool::new(false);
+// While set the gauge and anemometer are being handled, their pulses are discarded
+pub static MAINTENANCE: AtomicBool = AtomicBool::new(false);
+pub static DISCARDED_RAIN_COUNT: AtomicU32 = AtomicU32::new(0);
+pub static DISCARDED_ROTATION_COUNT: AtomicU32 = AtomicU32::new(0);
+
+// Tips counted since the main loop last took them, for the tip events
+static NEW_RAIN_TIPS: AtomicU32 = AtomicU32::new(0);
+// Time of the last counted tip, in ms since boot
+static RAIN_TIP_MS: AtomicU32 = AtomicU32::new(0);
+
+// Runs in the ISR: atomics only. Edges closer than `debounce_ms` to the last tip are contact
+// bounce
+fn count_rain_edge(debounce_ms: u32) {
+    let now_ms = (unsafe { esp_timer_get_time() } / 1000) as u32;
+    if now_ms.wrapping_sub(RAIN_TIP_MS.load(Ordering::Relaxed)) < debounce_ms {
+        return;
+    }
+    RAIN_TIP_MS.store(now_ms, Ordering::Relaxed);
+    if MAINTENANCE.load(Ordering::Relaxed) {
+        DISCARDED_RAIN_COUNT.fetch_add(1, Ordering::Relaxed);
+    } else {
+        RAIN_COUNT.fetch_add(1, Ordering::Relaxed);
+        NEW_RAIN_TIPS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn button_pin_callback() {
+    BUTTON_FLAG.store(true, Ordering::Relaxed);
+}
+
+// The button is active low, both edges are needed to time the press
+pub fn set_button_interrupt(pin_button: &mut PinDriver<AnyIOPin, Input>) -> Result<()> {
+    pin_button.set_pull(Pull::Up)?;
+    pin_button.set_interrupt_type(InterruptType::AnyEdge)?;
+    unsafe {
+        pin_button.subscribe(button_pin_callback)?;
+    }
+    pin_button.enable_interrupt()?;
+
+    Ok(())
+}
+
+// The rain pin is None in builds without the gauge. The anemometer is counted by the PCNT
+// peripheral, see `sensors::pulse::RotationCounter`
+pub fn set_intterupt(
+    pin_rain: Option<PinDriver<'static, Gpio25, Input>>,
+    rain_debounce_ms: u32,
+    sleep_interval_us: u64,
+) -> Result<()> {
+    if let Some(pin_rain) = pin_rain {
+        start_rain_counter(pin_rain, rain_debounce_ms)?;
+    }
+
+    unsafe {
+        esp_sleep_enable_gpio_wakeup();
+        esp_sleep_enable_timer_wakeup(sleep_interval_us); //wake up every 60 seconds
+    }
+
+    Ok(())
+}
+
+// The tips are counted in the ISR itself, none is lost while the main loop is busy. The
+// interrupt disables itself on firing, a thread owning the pin enables it again
+fn start_rain_counter(
+    mut pin_rain: PinDriver<'static, Gpio25, Input>,
+    debounce_ms: u32,
+) -> Result<()> {
+    pin_rain.set_pull(Pull::Up)?;
+    pin_rain.set_interrupt_type(InterruptType::PosEdge)?;
+    let notification = Notification::new();
+    let notifier = notification.notifier();
+    unsafe {
+        pin_rain.subscribe(move || {
+            count_rain_edge(debounce_ms);
+            notifier.notify_and_yield(NonZeroU32::MIN);
+        })?;
+    }
+    std::thread::Builder::new()
+        .stack_size(2048)
+        .spawn(move || loop {
+            pin_rain
+                .enable_interrupt()
+                .map_err(|e| log::error!("fail enabling rain interrupt: {e}"))
+                .ok();
+            notification.wait(BLOCK);
+        })?;
+
+    Ok(())
+}
+
+/// Tracks when each publish group is due, following the runtime intervals.
+pub struct PublishScheduler {
+    last: [Instant; 4],
+    forced: [bool; 4],
+}
+
+impl PublishScheduler {
+    pub fn new() -> Self {
+        Self {
+            last: [Instant::now(); 4],
+            forced: [false; 4],
+        }
+    }
+
+    pub fn due(&mut self, group: PublishGroup) -> bool {
+        let forced = std::mem::take(&mut self.forced[group as usize]);
+        let last = &mut self.last[group as usize];
+        if forced || last.elapsed() >= RUNTIME.interval(group) {
+            *last = Instant::now();
+            return true;
+        }
+        false
+    }
+
+    // Makes every group due on the next check
+    pub fn force_all(&mut self) {
+        self.forced = [true; 4];
+    }
+}
+
+impl Default for PublishScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tips counted by the interrupt since the previous call, the discarded ones apart.
+pub fn take_new_rain_tips() -> u32 {
+    NEW_RAIN_TIPS.swap(0, Ordering::Relaxed)
+}
+
+/// Mean wind speed in km/h since `since`, from the rotations counted meanwhile.
+///
+/// The count is taken and reset in one atomic swap, so no rotation is lost or counted twice,
+/// and `since` restarts the interval.
+pub fn measure_wind_speed(since: &mut Instant, kmh_per_hz: f32) -> f32 {
+    let rotations = ROTATION_COUNT.swap(0, Ordering::Relaxed);
+    let now = Instant::now();
+    let elapsed = now.duration_since(*since);
+    *since = now;
+    wind_speed_kmh(rotations, elapsed.as_secs_f32(), kmh_per_hz)
+}
+
+// Rotations counted during maintenance are set aside
+pub fn add_rotations(rotations: u32) {
+    let count = if MAINTENANCE.load(Ordering::Relaxed) {
+        &DISCARDED_ROTATION_COUNT
+    } else {
+        &ROTATION_COUNT
+    };
+    count.fetch_add(rotations, Ordering::Relaxed);
+}
+
+// Angle in degrees from north, corrected with the runtime vane offset
+pub fn get_wind_angle(vane: &mut dyn AngleSensor) -> Option<f32> {
+    let reading = match vane.raw_angle() {
+        Ok(value) => value,
+        Err(e) => {
+            log::error!("Couldn't read wind direction: {e}");
+            return None;
+        }
+    };
+
+    Some(apply_vane_offset(reading, RUNTIME.vane_offset_deg()))
+}
+
+pub fn get_wind_direction(vane: &mut dyn AngleSensor) -> String {
+    match get_wind_angle(vane) {
+        Some(angle) => wind_direction_from_angle(angle).to_string(),
+        None => "NA".to_string(),
+    }
+}
@@ -0,0 +1,205 @@
+//! Setup portal served on a SoftAP: the form fields and a DNS responder sending every name to
+//! the station, so phones open the form on their own.
+
+/// Config fields the setup form can set.
+pub const PORTAL_FIELDS: [&str; 5] = [
+    "wifi_ssid",
+    "wifi_pass",
+    "broker_url",
+    "mqtt_user",
+    "mqtt_pass",
+];
+
+/// Known fields of an `application/x-www-form-urlencoded` body. Empty fields are left out, the
+/// current value is kept for them.
+pub fn parse_form(body: &str) -> Option<Vec<(&'static str, String)>> {
+    let mut fields = vec![];
+    for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let Some(field) = PORTAL_FIELDS.iter().find(|field| **field == name) else {
+            continue;
+        };
+        let value = url_decode(value)?;
+        if !value.is_empty() {
+            fields.push((*field, value));
+        }
+    }
+    Some(fields)
+}
+
+/// Decodes `+` and `%XX` escapes, None when an escape is malformed or the result is not UTF-8.
+pub fn url_decode(value: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut chars = value.bytes();
+    while let Some(c) = chars.next() {
+        match c {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [chars.next()?, chars.next()?];
+                let hex = core::str::from_utf8(&hex).ok()?;
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            c => bytes.push(c),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// How a settings page field is entered and stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    Text,
+    // Never sent back to the browser
+    Secret,
+    Unsigned,
+    Float,
+}
+
+pub struct SettingField {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub kind: FieldKind,
+}
+
+const fn field(name: &'static str, label: &'static str, kind: FieldKind) -> SettingField {
+    SettingField { name, label, kind }
+}
+
+/// Config fields of the settings page served on the station network.
+pub const SETTINGS_FIELDS: [SettingField; 15] = [
+    field("wifi_ssid", "WiFi network", FieldKind::Text),
+    field("wifi_pass", "WiFi password", FieldKind::Secret),
+    field("broker_url", "Broker URL", FieldKind::Text),
+    field("mqtt_user", "MQTT user", FieldKind::Text),
+    field("mqtt_pass", "MQTT password", FieldKind::Secret),
+    field("topic", "MQTT topic", FieldKind::Text),
+    field("wind_interval_s", "Wind interval (s)", FieldKind::Unsigned),
+    field(
+        "env_interval_s",
+        "Environment interval (s)",
+        FieldKind::Unsigned,
+    ),
+    field("rain_interval_s", "Rain interval (s)", FieldKind::Unsigned),
+    field(
+        "diag_interval_s",
+        "Diagnostics interval (s)",
+        FieldKind::Unsigned,
+    ),
+    field("mm_per_tip", "Rain per tip (mm)", FieldKind::Float),
+    field(
+        "anemo_kmh_per_hz",
+        "Wind per rotation/s (km/h)",
+        FieldKind::Float,
+    ),
+    field(
+        "bme680_temp_offset",
+        "Temperature offset (°C)",
+        FieldKind::Float,
+    ),
+    field(
+        "bme680_humidity_offset",
+        "Humidity offset (%)",
+        FieldKind::Float,
+    ),
+    field(
+        "bme680_pressure_offset",
+        "Pressure offset (hPa)",
+        FieldKind::Float,
+    ),
+];
+
+/// Settings page fields of a form body, with their text checked against their kind. Empty
+/// fields are left out like in the setup form. The error names the first invalid field.
+pub fn parse_settings(body: &str) -> Result<Vec<(&'static SettingField, String)>, String> {
+    let mut fields = vec![];
+    for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let Some(field) = SETTINGS_FIELDS.iter().find(|field| field.name == name) else {
+            continue;
+        };
+        let value = url_decode(value).ok_or_else(|| format!("{name}: malformed value"))?;
+        let value = match field.kind {
+            FieldKind::Text | FieldKind::Secret => value,
+            _ => value.trim().to_string(),
+        };
+        if value.is_empty() {
+            continue;
+        }
+        let valid = match field.kind {
+            FieldKind::Text | FieldKind::Secret => true,
+            FieldKind::Unsigned => value.parse::<u32>().is_ok(),
+            FieldKind::Float => value.parse::<f32>().is_ok_and(f32::is_finite),
+        };
+        if !valid {
+            return Err(format!("{name}: not a valid number"));
+        }
+        fields.push((field, value));
+    }
+    Ok(fields)
+}
+
+/// Escapes a value for an HTML attribute.
+pub fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+const DNS_HEADER_LEN: usize = 12;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TTL_S: u32 = 60;
+
+/// Answer to a DNS query pointing any A question at `ip`, None for what is not a standard
+/// query with a single question. Other record types get an empty answer.
+pub fn dns_answer(query: &[u8], ip: [u8; 4]) -> Option<Vec<u8>> {
+    if query.len() < DNS_HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    // A response or another opcode than QUERY
+    if flags & 0xF800 != 0 || questions != 1 {
+        return None;
+    }
+    let mut end = DNS_HEADER_LEN;
+    loop {
+        let len = *query.get(end)? as usize;
+        end += 1;
+        if len == 0 {
+            break;
+        }
+        // No compression in a question
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        end += len;
+    }
+    let qtype = u16::from_be_bytes([*query.get(end)?, *query.get(end + 1)?]);
+    end += 4;
+    if end > query.len() {
+        return None;
+    }
+
+    let answers = u16::from(qtype == DNS_TYPE_A);
+    let mut response = query[..end].to_vec();
+    // Response, recursion desired copied, recursion available
+    let rd = flags & 0x0100;
+    response[2..4].copy_from_slice(&(0x8080 | rd).to_be_bytes());
+    response[6..8].copy_from_slice(&answers.to_be_bytes());
+    response[8..12].fill(0);
+    if answers == 1 {
+        // Name pointer to the question
+        response.extend_from_slice(&[0xC0, DNS_HEADER_LEN as u8]);
+        response.extend_from_slice(&DNS_TYPE_A.to_be_bytes());
+        response.extend_from_slice(&1u16.to_be_bytes());
+        response.extend_from_slice(&DNS_TTL_S.to_be_bytes());
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&ip);
+    }
+    Some(response)
+}
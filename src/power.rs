@@ -0,0 +1,295 @@
+use core::time::Duration;
+
+/// Period at which the INA devices are read and the battery charge integrated.
+pub const POWER_SAMPLE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Measurement of one INA device. Current is positive when flowing from IN+ to IN-.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PowerSample {
+    pub voltage_v: f32,
+    pub current_a: f32,
+    pub power_w: f32,
+}
+
+pub const REG_CONFIG: u8 = 0x00;
+pub const REG_BUS_VOLTAGE: u8 = 0x02;
+pub const REG_POWER: u8 = 0x03;
+pub const REG_CURRENT: u8 = 0x04;
+pub const REG_CALIBRATION: u8 = 0x05;
+// INA226 only
+pub const REG_MASK_ENABLE: u8 = 0x06;
+const INA226_OVF: u16 = 1 << 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InaModel {
+    Ina219,
+    Ina226,
+}
+
+impl InaModel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ina219" => Some(InaModel::Ina219),
+            "ina226" => Some(InaModel::Ina226),
+            _ => None,
+        }
+    }
+
+    /// Continuous shunt and bus conversions.
+    pub fn config_word(self) -> u16 {
+        match self {
+            // 32 V bus range, +-320 mV shunt range, 12 bit
+            InaModel::Ina219 => 0x399F,
+            // 16 samples average, 1.1 ms conversions
+            InaModel::Ina226 => 0x4527,
+        }
+    }
+
+    /// Calibration register for the shunt, along with the current LSB in amps.
+    ///
+    /// The LSB is chosen so `max_current_a` uses the whole signed 15 bit range. The register
+    /// is clamped when the shunt is too small for the requested resolution.
+    pub fn calibration(self, shunt_ohm: f32, max_current_a: f32) -> (u16, f32) {
+        let current_lsb = max_current_a / 32768.0;
+        let scale = match self {
+            InaModel::Ina219 => 0.04096,
+            InaModel::Ina226 => 0.00512,
+        };
+        let cal = (scale / (current_lsb * shunt_ohm)).min(u16::MAX as f32) as u16;
+        match self {
+            // Bit 0 is read only on the INA219
+            InaModel::Ina219 => (cal & !1, current_lsb),
+            InaModel::Ina226 => (cal, current_lsb),
+        }
+    }
+
+    fn power_lsb(self, current_lsb: f32) -> f32 {
+        match self {
+            InaModel::Ina219 => 20.0 * current_lsb,
+            InaModel::Ina226 => 25.0 * current_lsb,
+        }
+    }
+
+    /// Bus voltage in volts, None when the INA219 flags a math overflow.
+    pub fn bus_voltage_v(self, raw: u16) -> Option<f32> {
+        match self {
+            InaModel::Ina219 if raw & 0x1 != 0 => None,
+            InaModel::Ina219 => Some((raw >> 3) as f32 * 0.004),
+            InaModel::Ina226 => Some(raw as f32 * 0.00125),
+        }
+    }
+
+    /// INA226 overflow flag, read from the mask/enable register.
+    pub fn overflowed(self, mask_enable: u16) -> bool {
+        self == InaModel::Ina226 && mask_enable & INA226_OVF != 0
+    }
+
+    pub fn sample(
+        self,
+        bus: f32,
+        current_raw: u16,
+        power_raw: u16,
+        current_lsb: f32,
+    ) -> PowerSample {
+        PowerSample {
+            voltage_v: bus,
+            current_a: current_raw as i16 as f32 * current_lsb,
+            power_w: power_raw as f32 * self.power_lsb(current_lsb),
+        }
+    }
+}
+
+pub const INA3221_REG_MANUFACTURER_ID: u8 = 0xFE;
+pub const INA3221_MANUFACTURER_ID: u16 = 0x5449;
+// All channels, 16 samples average, 1.1 ms conversions, continuous shunt and bus
+pub const INA3221_CONFIG: u16 = 0x7527;
+
+/// Shunt and bus voltage registers of an INA3221 channel (1 to 3).
+pub fn ina3221_registers(channel: u8) -> (u8, u8) {
+    let base = 2 * (channel.clamp(1, 3) - 1);
+    (0x01 + base, 0x02 + base)
+}
+
+/// Sample of an INA3221 channel. It has no current or power register, both are computed from
+/// the shunt voltage (40 uV LSB) and the bus voltage (8 mV LSB).
+pub fn ina3221_sample(shunt_raw: u16, bus_raw: u16, shunt_ohm: f32) -> PowerSample {
+    let shunt_v = (shunt_raw as i16 >> 3) as f32 * 40e-6;
+    let voltage_v = (bus_raw as i16 >> 3) as f32 * 0.008;
+    let current_a = shunt_v / shunt_ohm;
+    PowerSample {
+        voltage_v,
+        current_a,
+        power_w: (voltage_v * current_a).abs(),
+    }
+}
+
+// Currents and panel power below this are noise
+const IDLE_CURRENT_A: f32 = 0.005;
+const IDLE_SOLAR_W: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargeState {
+    Charging,
+    Discharging,
+    Idle,
+}
+
+impl ChargeState {
+    /// From the battery current, positive when charging, or without it from the panel power.
+    /// None when neither is measured.
+    pub fn from_samples(
+        battery: Option<&PowerSample>,
+        solar: Option<&PowerSample>,
+    ) -> Option<Self> {
+        match (battery, solar) {
+            (Some(battery), _) if battery.current_a > IDLE_CURRENT_A => Some(Self::Charging),
+            (Some(battery), _) if battery.current_a < -IDLE_CURRENT_A => Some(Self::Discharging),
+            (Some(_), _) => Some(Self::Idle),
+            (None, Some(solar)) if solar.power_w > IDLE_SOLAR_W => Some(Self::Charging),
+            (None, Some(_)) => Some(Self::Discharging),
+            (None, None) => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Charging => "charging",
+            Self::Discharging => "discharging",
+            Self::Idle => "idle",
+        }
+    }
+}
+
+pub const MAX17048_ADDR: u8 = 0x36;
+pub const MAX17048_REG_VCELL: u8 = 0x02;
+pub const MAX17048_REG_SOC: u8 = 0x04;
+pub const MAX17048_REG_VERSION: u8 = 0x08;
+pub const MAX17048_REG_CONFIG: u8 = 0x0C;
+pub const MAX17048_REG_CRATE: u8 = 0x16;
+pub const MAX17048_REG_STATUS: u8 = 0x1A;
+// CONFIG: alert flag and the 5 bit empty alert threshold (32 - ATHD %)
+const MAX17048_ALRT: u16 = 1 << 5;
+const MAX17048_ATHD: u16 = 0x1F;
+// STATUS: reset indicator and SOC low, the other alerts are not enabled
+pub const MAX17048_STATUS_RI: u16 = 1 << 8;
+const MAX17048_STATUS_HD: u16 = 1 << 12;
+
+/// Reading of a MAX17048 (1 cell) or MAX17049 (2 cells) fuel gauge.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FuelGaugeSample {
+    pub voltage_v: f32,
+    pub soc_percent: f32,
+    /// Positive while charging
+    pub rate_percent_h: f32,
+    /// State of charge dropped below the alert threshold
+    pub alert: bool,
+}
+
+impl FuelGaugeSample {
+    pub fn from_registers(vcell: u16, soc: u16, crate_raw: u16, cells: u8) -> Self {
+        Self {
+            voltage_v: vcell as f32 * 78.125e-6 * cells as f32,
+            soc_percent: soc as f32 / 256.0,
+            rate_percent_h: crate_raw as i16 as f32 * 0.208,
+            alert: false,
+        }
+    }
+}
+
+/// CONFIG register with the alert flag cleared and the empty alert at `threshold_percent`
+/// (1 to 32 %), keeping the RCOMP compensation and sleep bits.
+pub fn max17048_config(config: u16, threshold_percent: u8) -> u16 {
+    let athd = 32 - threshold_percent.clamp(1, 32) as u16;
+    (config & !(MAX17048_ALRT | MAX17048_ATHD)) | athd
+}
+
+/// Whether the gauge raised its SOC low alert.
+pub fn max17048_alerted(config: u16, status: u16) -> bool {
+    config & MAX17048_ALRT != 0 && status & MAX17048_STATUS_HD != 0
+}
+
+/// STATUS register with the reset and SOC low flags cleared, so they can be raised again.
+pub fn max17048_clear_status(status: u16) -> u16 {
+    status & !(MAX17048_STATUS_RI | MAX17048_STATUS_HD)
+}
+
+/// Battery measured through a resistor divider on an ADC pin.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BatterySample {
+    pub voltage_v: f32,
+    /// Linear between the empty and full voltages, 0 to 100
+    pub percent: f32,
+}
+
+impl BatterySample {
+    /// `pin_mv` is the voltage on the pin, `ratio` the battery voltage over it.
+    pub fn from_pin_mv(pin_mv: u32, ratio: f32, empty_v: f32, full_v: f32) -> Self {
+        let voltage_v = pin_mv as f32 / 1000.0 * ratio;
+        let percent = if full_v > empty_v {
+            ((voltage_v - empty_v) / (full_v - empty_v) * 100.0).clamp(0.0, 100.0)
+        } else {
+            0.0
+        };
+        Self { voltage_v, percent }
+    }
+}
+
+/// ADC1 channel of an ESP32 pin, None for the pins without one. ADC2 is left out, it cannot
+/// be read while WiFi is on.
+pub fn adc1_channel(gpio: i32) -> Option<u8> {
+    match gpio {
+        36..=39 => Some((gpio - 36) as u8),
+        32..=35 => Some((gpio - 28) as u8),
+        _ => None,
+    }
+}
+
+/// Charge in and out of the battery over the local day, in mAh.
+pub struct ChargeCounter {
+    mah_in: f32,
+    mah_out: f32,
+    day: i32,
+}
+
+impl ChargeCounter {
+    pub const fn new() -> Self {
+        Self {
+            mah_in: 0.0,
+            mah_out: 0.0,
+            day: 0,
+        }
+    }
+
+    /// Account `current_a` flowing for `duration_s`, positive charging the battery.
+    pub fn add(&mut self, current_a: f32, duration_s: f32) {
+        let mah = current_a * 1000.0 * duration_s / 3600.0;
+        if mah >= 0.0 {
+            self.mah_in += mah;
+        } else {
+            self.mah_out -= mah;
+        }
+    }
+
+    pub fn mah_in(&self) -> f32 {
+        self.mah_in
+    }
+
+    pub fn mah_out(&self) -> f32 {
+        self.mah_out
+    }
+
+    /// Restart the counters, once per local `day`. Returns whether a reset happened.
+    pub fn reset_daily(&mut self, day: i32) -> bool {
+        if day == self.day {
+            return false;
+        }
+        *self = Self { day, ..Self::new() };
+        true
+    }
+}
+
+impl Default for ChargeCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,573 @@
+use anyhow::{anyhow, bail, Result};
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{esp, esp_vfs_spiffs_conf_t, esp_vfs_spiffs_register},
+};
+use serde_json::{Map, Value};
+use std::ops::Deref;
+use std::sync::OnceLock;
+use weather_station::{power::adc1_channel, runtime::unknown_fields, Config};
+
+const NVS_NAMESPACE: &str = "provisioning";
+const SPIFFS_BASE: &std::ffi::CStr = c"/spiffs";
+const CONFIG_FILE: &str = "/spiffs/config.json";
+
+/// Where the active configuration comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// `cfg.toml` values built into the firmware
+    Compiled,
+    /// Values provisioned on an earlier boot
+    NvsOverride,
+    /// Values read from SPIFFS on this boot, now stored in NVS
+    SpiffsFile,
+}
+
+static ACTIVE: OnceLock<Config> = OnceLock::new();
+
+/// Active configuration, the compiled one until provisioning ran.
+///
+/// Shadows `weather_station::CONFIG` in the binary so `CONFIG.field` keeps working.
+pub struct ActiveConfig;
+
+pub static CONFIG: ActiveConfig = ActiveConfig;
+
+impl Deref for ActiveConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        ACTIVE.get().unwrap_or(&weather_station::CONFIG)
+    }
+}
+
+trait FromJson: Sized {
+    fn from_json(value: &Value) -> Option<Self>;
+}
+
+impl FromJson for &'static str {
+    // Leaked once at boot, the config lives as long as the firmware
+    fn from_json(value: &Value) -> Option<Self> {
+        let leaked: &'static str = String::leak(value.as_str()?.to_string());
+        Some(leaked)
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(value: &Value) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl FromJson for f32 {
+    fn from_json(value: &Value) -> Option<Self> {
+        value.as_f64().map(|v| v as f32)
+    }
+}
+
+macro_rules! impl_from_json_int {
+    ($($ty:ty),*) => {$(
+        impl FromJson for $ty {
+            fn from_json(value: &Value) -> Option<Self> {
+                value.as_i64()?.try_into().ok()
+            }
+        }
+    )*};
+}
+
+impl_from_json_int!(u8, u16, u32, u64, i32);
+
+// Overrides the listed fields present in `json`, rejecting unknown keys and bad values
+macro_rules! override_fields {
+    ($config:expr, $json:expr, $($field:ident),* $(,)?) => {{
+        const FIELDS: &[&str] = &[$(stringify!($field)),*];
+        if let Some(key) = $json.keys().find(|key| !FIELDS.contains(&key.as_str())) {
+            bail!("unknown config field {key}");
+        }
+        $(
+            if let Some(value) = $json.get(stringify!($field)) {
+                $config.$field = FromJson::from_json(value)
+                    .ok_or_else(|| anyhow!("invalid value for {}", stringify!($field)))?;
+            }
+        )*
+    }};
+}
+
+fn config_from_json(json: &str) -> Result<Config> {
+    let json: Map<String, Value> = serde_json::from_str(json)?;
+    let mut config = weather_station::CONFIG;
+    override_fields!(
+        config,
+        json,
+        mqtt_user,
+        mqtt_pass,
+        broker_url,
+        mqtt_ca_cert,
+        mqtt_client_cert,
+        mqtt_client_key,
+        mqtt_topic_options,
+        wifi_ssid,
+        wifi_pass,
+        wifi_networks,
+        wifi_static_ip,
+        wifi_gateway,
+        wifi_netmask_bits,
+        wifi_dns,
+        wifi_quality_enabled,
+        wifi_quality_interval_s,
+        portal_after_attempts,
+        portal_password,
+        portal_timeout_s,
+        diag_ap_after_min,
+        topic,
+        client_id,
+        device_id,
+        network_mode,
+        eth_sclk_gpio,
+        eth_mosi_gpio,
+        eth_miso_gpio,
+        eth_cs_gpio,
+        eth_int_gpio,
+        eth_rst_gpio,
+        eth_static_ip,
+        eth_gateway,
+        eth_netmask_bits,
+        eth_dns,
+        cellular_apn,
+        cellular_baud,
+        cellular_rx_gpio,
+        cellular_tx_gpio,
+        cellular_pwrkey_gpio,
+        cellular_reset_gpio,
+        cellular_after_s,
+        cellular_interval_factor,
+        cellular_csq_interval_s,
+        deep_sleep_interval_us,
+        active_duration_s,
+        quiet_hours,
+        low_power_enabled,
+        network_hub_enabled,
+        gateway_enabled,
+        gateway_peers,
+        gateway_prefix,
+        gateway_offline_s,
+        gateway_merge,
+        modbus_enabled,
+        modbus_unit_id,
+        esphome_enabled,
+        esphome_key,
+        modbus_rtu_enabled,
+        modbus_rtu_address,
+        modbus_rtu_baud,
+        nmea_enabled,
+        nmea_talker,
+        wind_source,
+        rs485_rx_gpio,
+        rs485_tx_gpio,
+        rs485_de_gpio,
+        rs485_baud,
+        wind_modbus_unit_id,
+        wind_modbus_function,
+        wind_modbus_speed_register,
+        wind_modbus_direction_register,
+        wind_modbus_speed_scale,
+        wind_modbus_direction_scale,
+        diag_enabled,
+        tca9548a_enabled,
+        vane_sensor,
+        as5048a_sclk_gpio,
+        as5048a_mosi_gpio,
+        as5048a_miso_gpio,
+        as5048a_cs_gpio,
+        as5600_mux_channel,
+        vane_2_enabled,
+        vane_2_name,
+        as5600_2_mux_channel,
+        vane_2_offset_deg,
+        bme680_enabled,
+        bme680_mux_channel,
+        bme680_name,
+        bme680_address,
+        bme680_temp_offset,
+        bme680_humidity_offset,
+        bme680_pressure_offset,
+        bme680_2_enabled,
+        bme680_2_name,
+        bme680_2_address,
+        bme680_2_mux_channel,
+        bme680_2_temp_offset,
+        bme680_2_humidity_offset,
+        bme680_2_pressure_offset,
+        dht_enabled,
+        dht_gpio,
+        dht_type,
+        dht_name,
+        dht_temp_offset,
+        dht_humidity_offset,
+        env_outdoor,
+        altitude_m,
+        southern_hemisphere,
+        udp_beacon_enabled,
+        udp_beacon_addr,
+        udp_beacon_port,
+        bthome_enabled,
+        bthome_interval_ms,
+        bthome_low_power,
+        bthome_key,
+        ble_ess_enabled,
+        ble_ess_name,
+        ntp_server,
+        mdns_hostname,
+        utc_offset_minutes,
+        dst_active,
+        syslog_enabled,
+        syslog_host,
+        syslog_port,
+        syslog_level,
+        wind_interval_s,
+        env_interval_s,
+        rain_interval_s,
+        diag_interval_s,
+        split_group_publish,
+        published_fields,
+        rain_tip_events,
+        mm_per_tip,
+        rain_debounce_ms,
+        wind_calm_kmh,
+        anemo_kmh_per_hz,
+        anemo_debounce_ms,
+        gust_sample_s,
+        polling_base_interval_s,
+        trend_window_s,
+        restore_max_age_s,
+        offline_buffer_enabled,
+        offline_batch_len,
+        offline_max_batches,
+        http_enabled,
+        settings_password,
+        chip_temp_limit_c,
+        button_gpio,
+        status_led_gpio,
+        maintenance_duration_s,
+        maintenance_availability,
+        ha_discovery_enabled,
+        ha_discovery_prefix,
+        ina_enabled,
+        ina_model,
+        ina_battery_addr,
+        ina_solar_addr,
+        ina_shunt_ohm,
+        ina_max_current_a,
+        ina_battery_inverted,
+        ina3221_battery_channel,
+        ina3221_solar_channel,
+        low_battery_v,
+        battery_adc_gpio,
+        battery_divider_ratio,
+        battery_empty_v,
+        battery_full_v,
+        fuel_gauge_enabled,
+        fuel_gauge_model,
+        fuel_gauge_low_soc,
+        fuel_gauge_alert_gpio,
+        interlock_enabled,
+        interlock_gpio,
+        interlock_active_high,
+        interlock_window_h,
+        interlock_threshold_mm,
+        interlock_event_gap_s,
+        interlock_dry_out_s,
+        acoustic_enabled,
+        mic_sck_gpio,
+        mic_ws_gpio,
+        mic_sd_gpio,
+        acoustic_sample_rate_hz,
+        acoustic_window_ms,
+        acoustic_interval_s,
+        acoustic_budget_ms,
+        acoustic_light_db,
+        acoustic_moderate_db,
+        acoustic_heavy_db,
+        acoustic_impact_db,
+        acoustic_hail_db,
+        acoustic_light_impacts,
+        mppt_enabled,
+        mppt_rx_gpio,
+        mppt_tx_gpio,
+        mppt_baud,
+        sdi12_enabled,
+        sdi12_tx_gpio,
+        sdi12_rx_gpio,
+        sdi12_dir_gpio,
+        sdi12_fields,
+        sdi12_crc,
+        sdi12_retries,
+        sdi12_interval_s,
+        uploader_enabled,
+        uploader_url,
+        uploader_method,
+        uploader_headers,
+        uploader_interval_s,
+        uploader_template,
+        influx_enabled,
+        influx_url,
+        influx_org,
+        influx_bucket,
+        influx_token,
+        influx_measurement,
+        influx_interval_s,
+        wunderground_enabled,
+        wunderground_station_id,
+        wunderground_key,
+        wunderground_interval_s,
+        ecowitt_enabled,
+        ecowitt_url,
+        ecowitt_interval_s,
+        cwop_enabled,
+        cwop_callsign,
+        cwop_passcode,
+        cwop_server,
+        cwop_latitude,
+        cwop_longitude,
+        cwop_interval_s,
+        epaper_enabled,
+        epaper_sclk_gpio,
+        epaper_mosi_gpio,
+        epaper_cs_gpio,
+        epaper_dc_gpio,
+        epaper_rst_gpio,
+        epaper_busy_gpio,
+        epaper_refresh_s,
+        epaper_full_refresh_every,
+        oled_enabled,
+        oled_address,
+        oled_page_s,
+        lora_enabled,
+        lora_sclk_gpio,
+        lora_mosi_gpio,
+        lora_miso_gpio,
+        lora_cs_gpio,
+        lora_rst_gpio,
+        lora_frequency_hz,
+        lora_spreading_factor,
+        lora_tx_power_dbm,
+        lora_sync_word,
+        lora_always,
+        healthy_uptime_min,
+        watchdog_timeout_s,
+        config_url,
+        demo_mode,
+        demo_mode_release,
+    );
+    if config.mqtt_client_cert.is_empty() != config.mqtt_client_key.is_empty() {
+        bail!("mqtt_client_cert and mqtt_client_key go together");
+    }
+    if (1..8).contains(&config.portal_password.len()) {
+        bail!("portal_password needs 8 characters at least");
+    }
+    // Both AS5600 answer on the same address
+    if config.vane_2_enabled
+        && (!config.tca9548a_enabled || config.as5600_2_mux_channel == config.as5600_mux_channel)
+    {
+        bail!("vane_2_enabled needs the mux and its own as5600_2_mux_channel");
+    }
+    if config.battery_adc_gpio >= 0 && adc1_channel(config.battery_adc_gpio).is_none() {
+        bail!("battery_adc_gpio must be an ADC1 pin, 32 to 39");
+    }
+    // Not fatal, the unknown names are ignored
+    for name in unknown_fields(config.published_fields) {
+        log::warn!("published_fields: unknown field {name}");
+    }
+    Ok(config)
+}
+
+/// Loads the configuration from NVS, provisioning it from SPIFFS on first boot.
+///
+/// NVS keys are limited to 15 characters, so the validated JSON document is stored as a
+/// single blob rather than one key per field.
+pub struct WeatherStationProvisioner {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl WeatherStationProvisioner {
+    pub fn new(nvs: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(nvs, NVS_NAMESPACE, true)?,
+        })
+    }
+
+    /// Must run before anything reads `CONFIG`, values read earlier are the compiled ones.
+    pub fn provision(&mut self) -> ConfigSource {
+        let (json, source) = if self.nvs.get_u8("provisioned").ok().flatten() == Some(1) {
+            match self.stored_json() {
+                Ok(json) => (json, ConfigSource::NvsOverride),
+                Err(e) => {
+                    log::error!("Fail loading provisioned config: {e}");
+                    return ConfigSource::Compiled;
+                }
+            }
+        } else {
+            match read_spiffs_config() {
+                Some(json) => (json, ConfigSource::SpiffsFile),
+                None => return ConfigSource::Compiled,
+            }
+        };
+
+        let config = match config_from_json(&json) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Invalid config from {source:?}: {e}");
+                return ConfigSource::Compiled;
+            }
+        };
+        if source == ConfigSource::SpiffsFile {
+            self.store_json(&json)
+                .unwrap_or_else(|e| log::error!("Fail storing config to NVS: {e}"));
+        }
+        ACTIVE.set(config).ok();
+        source
+    }
+
+    fn stored_json(&self) -> Result<String> {
+        let len = self
+            .nvs
+            .blob_len("config")?
+            .ok_or_else(|| anyhow!("no config blob"))?;
+        let mut buf = vec![0u8; len];
+        let blob = self
+            .nvs
+            .get_blob("config", &mut buf)?
+            .ok_or_else(|| anyhow!("no config blob"))?;
+        Ok(String::from_utf8(blob.to_vec())?)
+    }
+
+    fn store_json(&mut self, json: &str) -> Result<()> {
+        self.nvs.set_blob("config", json.as_bytes())?;
+        self.nvs.set_u8("provisioned", 1)?;
+        Ok(())
+    }
+
+    /// Validates and stores the fields entered in the setup portal, on top of the stored
+    /// configuration.
+    pub fn store_portal(&mut self, fields: &[(&str, String)]) -> Result<()> {
+        self.merge(
+            fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), Value::String(value.clone())))
+                .collect(),
+        )
+        .map(|_| ())
+    }
+
+    /// Validates and stores a JSON object of config fields received at runtime, on top of the
+    /// stored configuration. A `null` field goes back to its compiled value.
+    ///
+    /// Returns false, without writing to NVS, when nothing changed.
+    pub fn store_update(&mut self, json: &str) -> Result<bool> {
+        match serde_json::from_str(json)? {
+            Value::Object(fields) if !fields.is_empty() => self.merge(fields),
+            Value::Object(_) => bail!("no field to update"),
+            _ => bail!("not a JSON object"),
+        }
+    }
+
+    /// Drops the stored configuration, the next boot uses the compiled one or the SPIFFS file.
+    ///
+    /// Returns false when there was none.
+    pub fn clear(&mut self) -> Result<bool> {
+        if self.nvs.get_u8("provisioned")? != Some(1) {
+            return Ok(false);
+        }
+        self.nvs.remove("provisioned")?;
+        self.nvs.remove("config")?;
+        self.nvs.remove("remote_hash")?;
+        self.nvs.remove("etag")?;
+        Ok(true)
+    }
+
+    fn merge(&mut self, fields: Map<String, Value>) -> Result<bool> {
+        let stored = match self.nvs.get_u8("provisioned")? {
+            Some(1) => serde_json::from_str::<Map<String, Value>>(&self.stored_json()?)?,
+            _ => Map::new(),
+        };
+        let mut json = stored.clone();
+        for (name, value) in fields {
+            match value {
+                Value::Null => json.remove(&name),
+                value => json.insert(name, value),
+            };
+        }
+        if json == stored {
+            return Ok(false);
+        }
+        let json = Value::Object(json).to_string();
+        config_from_json(&json)?;
+        self.store_json(&json)?;
+        Ok(true)
+    }
+
+    /// Opens the setup portal on the next boot, whatever the network does.
+    pub fn request_portal(&mut self) -> Result<()> {
+        self.nvs.set_u8("portal", 1)?;
+        Ok(())
+    }
+
+    /// Whether the portal was requested, the request is cleared.
+    pub fn take_portal_request(&mut self) -> bool {
+        let requested = self.nvs.get_u8("portal").ok().flatten() == Some(1);
+        if requested {
+            self.nvs.remove("portal").ok();
+        }
+        requested
+    }
+
+    /// ETag of the last configuration fetched from `config_url`.
+    pub fn remote_etag(&self) -> Option<String> {
+        let mut buf = [0u8; 128];
+        self.nvs
+            .get_str("etag", &mut buf)
+            .ok()
+            .flatten()
+            .map(str::to_string)
+    }
+
+    /// Validates and stores a fetched configuration. Returns false, without writing to NVS,
+    /// when the document is the one stored last time.
+    pub fn store_remote(&mut self, json: &str, etag: Option<&str>) -> Result<bool> {
+        let hash = fnv1a(json.as_bytes());
+        if self.nvs.get_u32("remote_hash")? == Some(hash) {
+            return Ok(false);
+        }
+        config_from_json(json)?;
+        self.store_json(json)?;
+        self.nvs.set_u32("remote_hash", hash)?;
+        match etag {
+            Some(etag) => self.nvs.set_str("etag", etag)?,
+            None => {
+                self.nvs.remove("etag")?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+// Detects a changed document, not meant to resist tampering
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C_9DC5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+// None when the partition or the file is missing, which is the normal case
+fn read_spiffs_config() -> Option<String> {
+    let conf = esp_vfs_spiffs_conf_t {
+        base_path: SPIFFS_BASE.as_ptr(),
+        partition_label: std::ptr::null(),
+        max_files: 2,
+        format_if_mount_failed: false,
+    };
+    if let Err(e) = unsafe { esp!(esp_vfs_spiffs_register(&conf)) } {
+        log::info!("No SPIFFS partition mounted: {e}");
+        return None;
+    }
+    std::fs::read_to_string(CONFIG_FILE).ok()
+}
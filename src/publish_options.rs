@@ -0,0 +1,65 @@
+//! QoS and retain flag overrides per topic, the `mqtt_topic_options` table.
+//!
+//! Entries are `pattern:qos:retain` separated by commas, e.g.
+//! `state:1:true,anemo/#:2:true,rain/tip:0:false`. Patterns use the MQTT wildcards, `+` for
+//! one level and `#` for the remaining ones. The first matching entry wins, topics matching
+//! none keep the flags chosen by their publisher.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublishOptions {
+    /// 0 to 2
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// Pattern and flags of a table entry, None when it is malformed.
+pub fn parse_entry(entry: &str) -> Option<(&str, PublishOptions)> {
+    let mut parts = entry.split(':').map(str::trim);
+    let (Some(pattern), Some(qos), Some(retain), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let qos = qos.parse::<u8>().ok().filter(|qos| *qos <= 2)?;
+    let retain = match retain {
+        "true" => true,
+        "false" => false,
+        _ => return None,
+    };
+    if pattern.is_empty() {
+        return None;
+    }
+    Some((pattern, PublishOptions { qos, retain }))
+}
+
+/// Entries of the table that cannot be parsed, for validation warnings.
+pub fn invalid_entries(table: &str) -> impl Iterator<Item = &str> {
+    entries(table).filter(|entry| parse_entry(entry).is_none())
+}
+
+/// MQTT topic filter matching, `a/#` also matches `a`.
+pub fn topic_matches(pattern: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for filter in pattern.split('/') {
+        if filter == "#" {
+            return true;
+        }
+        match levels.next() {
+            Some(level) if filter == "+" || filter == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// Flags of the first entry matching `topic`, `default` when none does.
+pub fn options_for(table: &str, topic: &str, default: PublishOptions) -> PublishOptions {
+    entries(table)
+        .filter_map(parse_entry)
+        .find(|(pattern, _)| topic_matches(pattern, topic))
+        .map_or(default, |(_, options)| options)
+}
+
+fn entries(table: &str) -> impl Iterator<Item = &str> {
+    table.split(',').map(str::trim).filter(|e| !e.is_empty())
+}
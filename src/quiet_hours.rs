@@ -0,0 +1,146 @@
+use chrono::Timelike;
+use std::time::Duration;
+use weather_station::{
+    runtime::{parse_quiet_hours, QuietPeriod, RUNTIME},
+    time::{unix_time_ms, TimezonedClock},
+};
+
+use crate::provisioning::CONFIG;
+
+// Awake time before the single measurement of a quiet hours wakeup, for the wind sampling
+pub const MEASURE_WINDOW: Duration = Duration::from_secs(10);
+
+// Quiet hours were in effect before the last sleep, so transitions are reported once
+#[link_section = ".rtc.data"]
+static mut QUIET_ACTIVE: bool = false;
+// Timer wakeup of the last deep sleep and when it started, 0 after a power on
+#[link_section = ".rtc.data"]
+static mut LAST_SLEEP_US: u64 = 0;
+#[link_section = ".rtc.data"]
+static mut SLEEP_STARTED_MS: u64 = 0;
+
+/// Follows the quiet hours schedule against the local time.
+///
+/// The schedule is evaluated on every loop tick, so a late SNTP sync or a clock correction
+/// takes effect at once. Until the clock is synced the normal intervals apply.
+pub struct QuietHours {
+    current: Option<QuietPeriod>,
+}
+
+impl QuietHours {
+    pub fn new() -> Self {
+        Self { current: None }
+    }
+
+    /// Returns true when quiet hours were entered or left.
+    pub fn update(&mut self, clock: &TimezonedClock) -> bool {
+        let period = clock
+            .is_synced()
+            .then(|| {
+                let now = clock.local_now();
+                RUNTIME.quiet_period_at((now.hour() * 60 + now.minute()) as u16)
+            })
+            .flatten();
+        if period != self.current {
+            self.current = period;
+            RUNTIME.set_quiet_interval_s(period.map(|p| p.interval_s));
+        }
+
+        let active = period.is_some();
+        if active == unsafe { QUIET_ACTIVE } {
+            return false;
+        }
+        unsafe { QUIET_ACTIVE = active };
+        match period {
+            Some(p) => log::info!(
+                "Entering quiet hours, publishing every {}s{}",
+                p.interval_s,
+                if p.deep_sleep { " with deep sleep" } else { "" }
+            ),
+            None => log::info!("Leaving quiet hours"),
+        }
+        true
+    }
+
+    /// The station stays awake through a quiet period without deep sleep.
+    pub fn keeps_awake(&self) -> bool {
+        self.current.is_some_and(|p| !p.deep_sleep)
+    }
+
+    /// A quiet period with deep sleep only needs one measurement per wakeup.
+    pub fn sleeps_between_measurements(&self) -> bool {
+        self.current.is_some_and(|p| p.deep_sleep)
+    }
+
+    /// Timer wakeup of the coming deep sleep: the quiet interval, cut at the end of the period
+    /// so the normal schedule resumes on time.
+    pub fn sleep_duration(&self, clock: &TimezonedClock) -> Duration {
+        let normal = Duration::from_micros(CONFIG.deep_sleep_interval_us);
+        match self.current.filter(|p| p.deep_sleep) {
+            Some(p) => {
+                let remaining = p.remaining_s(clock.local_now().num_seconds_from_midnight());
+                Duration::from_secs(p.interval_s.min(remaining) as u64).max(normal)
+            }
+            None => normal,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        match self.current {
+            Some(p) => format!(
+                "{{\"active\": true, \"interval_s\": {}, \"deep_sleep\": {}, \"start\": \"{:02}:{:02}\", \"end\": \"{:02}:{:02}\"}}",
+                p.interval_s,
+                p.deep_sleep,
+                p.start_min / 60,
+                p.start_min % 60,
+                p.end_min / 60,
+                p.end_min % 60
+            ),
+            None => "{\"active\": false}".to_string(),
+        }
+    }
+}
+
+impl Default for QuietHours {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Loads the schedule in the runtime config, returns false when it does not parse.
+pub fn set_schedule(schedule: &str) -> bool {
+    match parse_quiet_hours(schedule) {
+        Some((periods, count)) => {
+            RUNTIME.set_quiet_periods(&periods[..count]);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn record_sleep(duration: Duration) {
+    unsafe {
+        LAST_SLEEP_US = duration.as_micros() as u64;
+        SLEEP_STARTED_MS = unix_time_ms();
+    }
+}
+
+/// Timer wakeup left of the deep sleep in progress, for a wakeup going back to sleep at once.
+pub fn remaining_sleep() -> Duration {
+    let (planned_us, started_ms) = unsafe { (LAST_SLEEP_US, SLEEP_STARTED_MS) };
+    let slept = Duration::from_millis(unix_time_ms().saturating_sub(started_ms));
+    Duration::from_micros(planned_us).saturating_sub(slept)
+}
+
+/// Length of the deep sleep this boot woke up from.
+///
+/// Measured on the system clock, which keeps running in deep sleep, so a rain tip waking the
+/// station early is accounted for. The timer wakeup caps it in case the clock was corrected.
+pub fn last_sleep() -> Duration {
+    let (planned_us, started_ms) = unsafe { (LAST_SLEEP_US, SLEEP_STARTED_MS) };
+    if planned_us == 0 {
+        return Duration::from_micros(CONFIG.deep_sleep_interval_us);
+    }
+    let slept = Duration::from_millis(unix_time_ms().saturating_sub(started_ms));
+    slept.min(Duration::from_micros(planned_us))
+}
@@ -0,0 +1,11 @@
+//! Rain gauge calibration, from bucket tips to depth of rain.
+
+/// Depth of rain in mm for `tips` of a bucket holding `mm_per_tip`.
+pub fn tips_to_mm(tips: u32, mm_per_tip: f32) -> f32 {
+    tips as f32 * mm_per_tip
+}
+
+/// Tips, fractional, that `mm` of rain make. None for a bucket without a calibration.
+pub fn mm_to_tips(mm: f32, mm_per_tip: f32) -> Option<f32> {
+    (mm_per_tip > 0.0).then(|| mm / mm_per_tip)
+}
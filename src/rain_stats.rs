@@ -0,0 +1,50 @@
+use anyhow::Result;
+use chrono::{Datelike, NaiveDateTime};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use weather_station::stats::*;
+
+const NVS_NAMESPACE: &str = "rain_stats";
+const NVS_KEY: &str = "stats";
+
+/// Week, month and year rain totals, persisted in NVS so they survive resets and power loss.
+pub struct RainStatsStore {
+    nvs: EspNvs<NvsDefault>,
+    pub stats: RainStatistics,
+}
+
+impl RainStatsStore {
+    pub fn load(nvs: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+        // Sized from the stored record, other schema versions may have another length
+        let mut buf = vec![0u8; nvs.blob_len(NVS_KEY)?.unwrap_or_default()];
+        let stats = match nvs.get_blob(NVS_KEY, &mut buf)? {
+            // Older schema versions are migrated by `from_bytes`
+            Some(bytes) => RainStatistics::from_bytes(bytes).unwrap_or_else(|| {
+                log::warn!(
+                    "Unknown rain statistics schema {:?}, starting over",
+                    bytes.first()
+                );
+                RainStatistics::new()
+            }),
+            None => RainStatistics::new(),
+        };
+
+        Ok(Self { nvs, stats })
+    }
+
+    pub fn save(&mut self) {
+        self.nvs
+            .set_blob(NVS_KEY, &self.stats.to_bytes())
+            .map_err(|e| log::error!("fail storing rain statistics: {e}"))
+            .ok();
+    }
+}
+
+pub fn calendar_period(local: NaiveDateTime) -> CalendarPeriod {
+    CalendarPeriod {
+        year: local.year(),
+        month: local.month(),
+        iso_year: local.iso_week().year(),
+        iso_week: local.iso_week().week(),
+    }
+}
@@ -0,0 +1,332 @@
+use crate::compute::crc16;
+#[cfg(feature = "std")]
+use crate::derive::Derived;
+use crate::forecast::PressureTendency;
+#[cfg(feature = "std")]
+use crate::runtime::{Field, FieldSelection};
+use serde::Serialize;
+
+/// Consolidated set of measurements from one station.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WeatherReading {
+    pub temperature: f32,
+    pub humidity: f32,
+    pub pressure: f32,
+    // Pressure reduced to sea level, None without a pressure sensor
+    pub sea_level_pressure: Option<f32>,
+    pub wind_speed_kmh: f32,
+    pub wind_direction_deg: f32,
+    // Highest short sample speed of the wind window
+    pub wind_gust_kmh: f32,
+    pub rain_mm: f32,
+    // Set on test data, consumers must discard such readings
+    pub synthetic: bool,
+    // Reconstructed from the surrounding readings, not measured
+    pub interpolated: bool,
+    // Produced by the demo mode generator
+    pub demo: bool,
+    // Taken while the station was being serviced, the rain and wind are not meaningful
+    pub maintenance: bool,
+    pub trends: Trends,
+    // Zambretti forecast, None until the pressure trend is known
+    pub forecast: Option<&'static str>,
+    pub rain_totals: RainTotals,
+}
+
+/// Least-squares rate of change over the configured trend window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Trends {
+    #[serde(rename = "temperature")]
+    pub temperature_per_h: f32,
+    #[serde(rename = "pressure")]
+    pub pressure_per_h: f32,
+    #[serde(rename = "pressure_tendency")]
+    pub tendency: PressureTendency,
+    // False until the window holds enough history, or after a long data gap
+    pub valid: bool,
+}
+
+/// Rain over the last hour, the last 24 hours and since local midnight, in mm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct RainTotals {
+    #[serde(rename = "1h")]
+    pub last_1h_mm: f32,
+    #[serde(rename = "24h")]
+    pub last_24h_mm: f32,
+    #[serde(rename = "today")]
+    pub today_mm: f32,
+}
+
+/// Schema of a reading as JSON. The measurements left out by the field selection are
+/// skipped, the flags are always present.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ReadingPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sea_level_pressure: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_speed: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_direction: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_gust: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rain: Option<f32>,
+    pub synthetic: bool,
+    pub interpolated: bool,
+    pub demo: bool,
+    pub maintenance: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trends: Option<Trends>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forecast: Option<&'static str>,
+    // Top level fields like the measurements, flattening would need serde's alloc
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dew_point: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heat_index: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_chill: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rain_totals: Option<RainTotals>,
+}
+
+/// Version of the node frame, bump it when the layout changes.
+pub const NODE_FRAME_VERSION: u8 = 1;
+pub const NODE_FRAME_LEN: usize = 1 + WeatherReading::FRAME_LEN + 2;
+
+/// Why a node frame was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeFrameError {
+    Length,
+    Crc,
+    Version(u8),
+}
+
+/// Reading stamped with the unix time (ms) it was taken at.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimedReading {
+    pub timestamp_ms: u64,
+    pub reading: WeatherReading,
+}
+
+impl WeatherReading {
+    /// Obviously fake values used to check the publishing chain end to end.
+    pub fn synthetic() -> Self {
+        Self {
+            temperature: 99.9,
+            humidity: 100.0,
+            pressure: 999.9,
+            sea_level_pressure: Some(999.9),
+            wind_speed_kmh: 99.9 * 3.6,
+            wind_direction_deg: 0.0,
+            wind_gust_kmh: 99.9 * 3.6,
+            rain_mm: 9999.0,
+            synthetic: true,
+            interpolated: false,
+            demo: false,
+            maintenance: false,
+            trends: Trends::default(),
+            forecast: None,
+            rain_totals: RainTotals::default(),
+        }
+    }
+
+    /// Linear blend of `a` (t = 0) and `b` (t = 1). Wind direction follows the shortest arc.
+    pub fn interpolate(a: &WeatherReading, b: &WeatherReading, t: f32) -> WeatherReading {
+        let t = t.clamp(0.0, 1.0);
+        let lerp = |x: f32, y: f32| x + (y - x) * t;
+        let arc = ((b.wind_direction_deg - a.wind_direction_deg) % 360.0 + 540.0) % 360.0 - 180.0;
+        let direction = (a.wind_direction_deg + arc * t + 360.0) % 360.0;
+
+        WeatherReading {
+            temperature: lerp(a.temperature, b.temperature),
+            humidity: lerp(a.humidity, b.humidity),
+            pressure: lerp(a.pressure, b.pressure),
+            sea_level_pressure: a
+                .sea_level_pressure
+                .zip(b.sea_level_pressure)
+                .map(|(x, y)| lerp(x, y)),
+            wind_speed_kmh: lerp(a.wind_speed_kmh, b.wind_speed_kmh),
+            wind_direction_deg: direction,
+            wind_gust_kmh: lerp(a.wind_gust_kmh, b.wind_gust_kmh),
+            rain_mm: lerp(a.rain_mm, b.rain_mm),
+            synthetic: a.synthetic || b.synthetic,
+            interpolated: true,
+            demo: a.demo || b.demo,
+            maintenance: a.maintenance || b.maintenance,
+            trends: Trends {
+                temperature_per_h: lerp(a.trends.temperature_per_h, b.trends.temperature_per_h),
+                pressure_per_h: lerp(a.trends.pressure_per_h, b.trends.pressure_per_h),
+                tendency: PressureTendency::from_rate(lerp(
+                    a.trends.pressure_per_h,
+                    b.trends.pressure_per_h,
+                )),
+                valid: a.trends.valid && b.trends.valid,
+            },
+            // Only when both ends agree
+            forecast: a.forecast.filter(|_| a.forecast == b.forecast),
+            rain_totals: RainTotals {
+                last_1h_mm: lerp(a.rain_totals.last_1h_mm, b.rain_totals.last_1h_mm),
+                last_24h_mm: lerp(a.rain_totals.last_24h_mm, b.rain_totals.last_24h_mm),
+                today_mm: lerp(a.rain_totals.today_mm, b.rain_totals.today_mm),
+            },
+        }
+    }
+
+    /// Size of the binary frame exchanged between stations.
+    pub const FRAME_LEN: usize = 6 * 4;
+
+    pub fn to_bytes(&self) -> [u8; Self::FRAME_LEN] {
+        let fields = [
+            self.temperature,
+            self.humidity,
+            self.pressure,
+            self.wind_speed_kmh,
+            self.wind_direction_deg,
+            self.rain_mm,
+        ];
+        let mut frame = [0; Self::FRAME_LEN];
+        for (chunk, field) in frame.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        frame
+    }
+
+    pub fn from_bytes(frame: &[u8]) -> Option<Self> {
+        if frame.len() != Self::FRAME_LEN {
+            return None;
+        }
+        let mut fields = frame
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]));
+        let mut next = || fields.next().unwrap_or_default();
+
+        let (temperature, humidity, pressure) = (next(), next(), next());
+        let (wind_speed_kmh, wind_direction_deg, rain_mm) = (next(), next(), next());
+        Some(Self {
+            temperature,
+            humidity,
+            pressure,
+            // Not part of the frame
+            sea_level_pressure: None,
+            wind_speed_kmh,
+            wind_direction_deg,
+            // Not part of the frame
+            wind_gust_kmh: wind_speed_kmh,
+            rain_mm,
+            synthetic: false,
+            interpolated: false,
+            demo: false,
+            maintenance: false,
+            trends: Trends::default(),
+            forecast: None,
+            rain_totals: RainTotals::default(),
+        })
+    }
+
+    /// Frame sent by battery nodes to a gateway: version, reading, CRC-16 of both.
+    pub fn to_node_frame(&self) -> [u8; NODE_FRAME_LEN] {
+        let mut frame = [0; NODE_FRAME_LEN];
+        frame[0] = NODE_FRAME_VERSION;
+        frame[1..1 + Self::FRAME_LEN].copy_from_slice(&self.to_bytes());
+        let crc = crc16(&frame[..NODE_FRAME_LEN - 2]);
+        frame[NODE_FRAME_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+        frame
+    }
+
+    pub fn from_node_frame(frame: &[u8]) -> Result<Self, NodeFrameError> {
+        if frame.len() < 3 {
+            return Err(NodeFrameError::Length);
+        }
+        let (body, crc) = frame.split_at(frame.len() - 2);
+        if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+            return Err(NodeFrameError::Crc);
+        }
+        if body[0] != NODE_FRAME_VERSION {
+            return Err(NodeFrameError::Version(body[0]));
+        }
+        Self::from_bytes(&body[1..]).ok_or(NodeFrameError::Length)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn to_json(&self) -> String {
+        self.to_json_fields(FieldSelection::ALL)
+    }
+
+    /// JSON with the selected measurements only, the flags are always included.
+    #[cfg(feature = "std")]
+    pub fn to_json_fields(&self, fields: FieldSelection) -> String {
+        serde_json::to_string(&self.payload(fields)).unwrap_or_default()
+    }
+
+    /// The reading as serialized, for payloads that embed it.
+    #[cfg(feature = "std")]
+    pub fn payload(&self, fields: FieldSelection) -> ReadingPayload {
+        let selected = |field: Field, value: f32| fields.contains(field).then_some(value);
+        let derived = Derived::from_reading(self).filter(|_| fields.contains(Field::Derived));
+        ReadingPayload {
+            temperature: selected(Field::Temperature, self.temperature),
+            humidity: selected(Field::Humidity, self.humidity),
+            pressure: selected(Field::Pressure, self.pressure),
+            sea_level_pressure: self
+                .sea_level_pressure
+                .filter(|_| fields.contains(Field::Pressure)),
+            wind_speed: selected(Field::WindSpeed, self.wind_speed_kmh),
+            wind_direction: selected(Field::WindDirection, self.wind_direction_deg),
+            wind_gust: selected(Field::WindGust, self.wind_gust_kmh),
+            rain: selected(Field::Rain, self.rain_mm),
+            synthetic: self.synthetic,
+            interpolated: self.interpolated,
+            demo: self.demo,
+            maintenance: self.maintenance,
+            trends: fields.contains(Field::Trends).then_some(self.trends),
+            forecast: self.forecast.filter(|_| fields.contains(Field::Trends)),
+            dew_point: derived.map(|derived| derived.dew_point),
+            heat_index: derived.map(|derived| derived.heat_index),
+            wind_chill: derived.map(|derived| derived.wind_chill),
+            rain_totals: fields
+                .contains(Field::RainTotals)
+                .then_some(self.rain_totals),
+        }
+    }
+}
+
+/// Fill the gap between `t1` and `t2` with `steps + 1` evenly spaced interpolated readings.
+///
+/// `history` must be sorted by time. The gap is bounded by the last reading taken at or before
+/// `t1` and the first one taken at or after `t2`, `None` if either is missing.
+#[cfg(feature = "std")]
+pub fn interpolate_gap(
+    history: &[TimedReading],
+    t1: u64,
+    t2: u64,
+    steps: u32,
+) -> Option<Vec<TimedReading>> {
+    let before = history.iter().rev().find(|r| r.timestamp_ms <= t1)?;
+    let after = history.iter().find(|r| r.timestamp_ms >= t2)?;
+    if t2 < t1 || steps == 0 {
+        return None;
+    }
+    let span = after
+        .timestamp_ms
+        .saturating_sub(before.timestamp_ms)
+        .max(1) as f64;
+
+    let points = (0..=steps as u64)
+        .map(|i| {
+            let timestamp_ms = t1 + (t2 - t1) * i / steps as u64;
+            let t = (timestamp_ms - before.timestamp_ms) as f64 / span;
+            TimedReading {
+                timestamp_ms,
+                reading: WeatherReading::interpolate(&before.reading, &after.reading, t as f32),
+            }
+        })
+        .collect();
+    Some(points)
+}
@@ -0,0 +1,84 @@
+use anyhow::{anyhow, bail, Result};
+use esp_idf_svc::{
+    http::{
+        client::{Configuration, EspHttpConnection},
+        Method,
+    },
+    io::Read,
+    sys::esp_crt_bundle_attach,
+};
+use std::time::Duration;
+
+use crate::provisioning::{WeatherStationProvisioner, CONFIG};
+use crate::station_id;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Far above any real config document, protects the heap from a misbehaving server
+const MAX_CONFIG_LEN: usize = 8192;
+
+/// Result of the boot-time configuration fetch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchOutcome {
+    /// The server answered 304 to the stored ETag
+    NotModified,
+    /// The document is the one already stored
+    Unchanged,
+    /// A new configuration was stored, it is applied on the next boot
+    Updated,
+}
+
+impl FetchOutcome {
+    pub fn name(self) -> &'static str {
+        match self {
+            FetchOutcome::NotModified => "not_modified",
+            FetchOutcome::Unchanged => "unchanged",
+            FetchOutcome::Updated => "updated",
+        }
+    }
+}
+
+/// Fetches the JSON config from `config_url` (`{{station_id}}` is substituted) and stores it
+/// in NVS when it validates and differs from the stored one.
+pub fn fetch_remote_config(provisioner: &mut WeatherStationProvisioner) -> Result<FetchOutcome> {
+    let url = CONFIG.config_url.replace("{{station_id}}", station_id());
+    let mut conn = EspHttpConnection::new(&Configuration {
+        timeout: Some(REQUEST_TIMEOUT),
+        crt_bundle_attach: Some(esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+
+    let etag = provisioner.remote_etag();
+    let mut headers = vec![("Accept", "application/json")];
+    if let Some(etag) = etag.as_deref() {
+        headers.push(("If-None-Match", etag));
+    }
+    conn.initiate_request(Method::Get, &url, &headers)?;
+    conn.initiate_response()?;
+
+    match conn.status() {
+        304 => return Ok(FetchOutcome::NotModified),
+        200 => {}
+        status => bail!("status {status}"),
+    }
+    let etag = conn.header("ETag").map(str::to_string);
+
+    let mut body = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let len = conn.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        if body.len() + len > MAX_CONFIG_LEN {
+            bail!("config larger than {MAX_CONFIG_LEN} bytes");
+        }
+        body.extend_from_slice(&buf[..len]);
+    }
+    let json = String::from_utf8(body).map_err(|_| anyhow!("config is not UTF-8"))?;
+
+    if provisioner.store_remote(&json, etag.as_deref())? {
+        Ok(FetchOutcome::Updated)
+    } else {
+        Ok(FetchOutcome::Unchanged)
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use std::time::{Duration, Instant};
+use weather_station::reading::WeatherReading;
+
+const NVS_NAMESPACE: &str = "restore";
+const NVS_KEY: &str = "last";
+// Bump when the record layout changes, other versions are ignored
+const RECORD_VERSION: u8 = 1;
+const RECORD_LEN: usize = 1 + 8 + WeatherReading::FRAME_LEN + 4 + 4;
+// Flash wear bound, the daily rain total is saved as soon as it changes anyway
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Last consolidated reading and daily rain total, republished after a reboot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LastKnown {
+    pub timestamp_ms: u64,
+    pub reading: WeatherReading,
+    pub rain_today_mm: f32,
+    /// Local day the rain total belongs to, days since the common era
+    pub day: i32,
+}
+
+impl LastKnown {
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0u8; RECORD_LEN];
+        bytes[0] = RECORD_VERSION;
+        bytes[1..9].copy_from_slice(&self.timestamp_ms.to_le_bytes());
+        let reading_end = 9 + WeatherReading::FRAME_LEN;
+        bytes[9..reading_end].copy_from_slice(&self.reading.to_bytes());
+        bytes[reading_end..reading_end + 4].copy_from_slice(&self.rain_today_mm.to_le_bytes());
+        bytes[reading_end + 4..].copy_from_slice(&self.day.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != RECORD_LEN || bytes[0] != RECORD_VERSION {
+            return None;
+        }
+        let reading_end = 9 + WeatherReading::FRAME_LEN;
+        let word = |at: usize| bytes[at..at + 4].try_into().unwrap();
+        Some(Self {
+            timestamp_ms: u64::from_le_bytes(bytes[1..9].try_into().unwrap()),
+            reading: WeatherReading::from_bytes(&bytes[9..reading_end])?,
+            rain_today_mm: f32::from_le_bytes(word(reading_end)),
+            day: i32::from_le_bytes(word(reading_end + 4)),
+        })
+    }
+
+    pub fn age(&self, now_ms: u64) -> Duration {
+        Duration::from_millis(now_ms.saturating_sub(self.timestamp_ms))
+    }
+}
+
+/// Keeps the last known values in NVS, on a modest cadence to spare the flash.
+pub struct LastKnownStore {
+    nvs: EspNvs<NvsDefault>,
+    saved: Option<LastKnown>,
+    last_save: Instant,
+}
+
+impl LastKnownStore {
+    /// Opens the store and returns the record left by the previous boot, if any.
+    pub fn load(nvs: EspDefaultNvsPartition) -> Result<(Self, Option<LastKnown>)> {
+        let nvs = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+        let mut buf = [0u8; RECORD_LEN];
+        let saved = nvs
+            .get_blob(NVS_KEY, &mut buf)?
+            .and_then(LastKnown::from_bytes);
+
+        let store = Self {
+            nvs,
+            saved,
+            last_save: Instant::now(),
+        };
+        Ok((store, saved))
+    }
+
+    /// Saves the record when the daily rain total changed or `SAVE_INTERVAL` went by.
+    pub fn update(&mut self, record: LastKnown) {
+        let rain_changed = self.saved.map_or(true, |saved| {
+            saved.rain_today_mm != record.rain_today_mm || saved.day != record.day
+        });
+        if !rain_changed && self.last_save.elapsed() < SAVE_INTERVAL {
+            return;
+        }
+        self.last_save = Instant::now();
+        match self.nvs.set_blob(NVS_KEY, &record.to_bytes()) {
+            Ok(()) => self.saved = Some(record),
+            Err(e) => log::error!("fail storing last known values: {e}"),
+        }
+    }
+}
@@ -0,0 +1,513 @@
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use core::time::Duration;
+
+/// Sensor groups published on their own schedule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishGroup {
+    Wind,
+    Environment,
+    Rain,
+    Diagnostics,
+}
+
+impl PublishGroup {
+    pub const ALL: [PublishGroup; 4] = [
+        PublishGroup::Wind,
+        PublishGroup::Environment,
+        PublishGroup::Rain,
+        PublishGroup::Diagnostics,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PublishGroup::Wind => "wind",
+            PublishGroup::Environment => "environment",
+            PublishGroup::Rain => "rain",
+            PublishGroup::Diagnostics => "diagnostics",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|group| group.name() == name)
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Measurement and derived fields that can be left out of the published payloads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Temperature,
+    Humidity,
+    Pressure,
+    WindSpeed,
+    WindDirection,
+    WindGust,
+    Rain,
+    Trends,
+    /// Dew point, heat index and wind chill
+    Derived,
+    /// Rolling and daily rain totals, and the hourly peak rate
+    RainTotals,
+    /// Week, month and year rain statistics
+    RainStats,
+    WindRose,
+    Hourly,
+}
+
+impl Field {
+    pub const ALL: [Field; 13] = [
+        Field::Temperature,
+        Field::Humidity,
+        Field::Pressure,
+        Field::WindSpeed,
+        Field::WindDirection,
+        Field::WindGust,
+        Field::Rain,
+        Field::Trends,
+        Field::Derived,
+        Field::RainTotals,
+        Field::RainStats,
+        Field::WindRose,
+        Field::Hourly,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Temperature => "temperature",
+            Field::Humidity => "humidity",
+            Field::Pressure => "pressure",
+            Field::WindSpeed => "wind_speed",
+            Field::WindDirection => "wind_direction",
+            Field::WindGust => "wind_gust",
+            Field::Rain => "rain",
+            Field::Trends => "trends",
+            Field::Derived => "derived",
+            Field::RainTotals => "rain_totals",
+            Field::RainStats => "rain_stats",
+            Field::WindRose => "wind_rose",
+            Field::Hourly => "hourly",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.name() == name)
+    }
+}
+
+/// Set of published fields, one bit per `Field`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldSelection(u32);
+
+impl FieldSelection {
+    pub const ALL: FieldSelection = FieldSelection((1 << Field::ALL.len()) - 1);
+
+    /// Comma separated field names: the listed fields only, or every field but the ones
+    /// prefixed with `-`. Both can be combined, an empty list selects everything. Unknown
+    /// names are ignored, see `unknown_fields`.
+    pub fn parse(list: &str) -> Self {
+        let mut allowed: Option<u32> = None;
+        let mut denied = 0;
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.strip_prefix('-') {
+                Some(name) => denied |= Field::from_name(name.trim()).map_or(0, Self::bit),
+                None => *allowed.get_or_insert(0) |= Field::from_name(entry).map_or(0, Self::bit),
+            }
+        }
+        FieldSelection(allowed.unwrap_or(Self::ALL.0) & !denied)
+    }
+
+    pub fn contains(self, field: Field) -> bool {
+        self.0 & Self::bit(field) != 0
+    }
+
+    pub fn fields(self) -> impl Iterator<Item = Field> {
+        Field::ALL
+            .into_iter()
+            .filter(move |field| self.contains(*field))
+    }
+
+    fn bit(field: Field) -> u32 {
+        1 << field as u32
+    }
+}
+
+/// Names of a field selection list that are not fields, for validation warnings.
+pub fn unknown_fields(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(|entry| entry.trim().trim_start_matches('-').trim())
+        .filter(|name| !name.is_empty() && Field::from_name(name).is_none())
+}
+
+pub const MAX_QUIET_PERIODS: usize = 4;
+
+/// Local time range with its own publish interval, e.g. longer intervals at night.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuietPeriod {
+    /// Minutes since local midnight, the range wraps past midnight when end < start
+    pub start_min: u16,
+    pub end_min: u16,
+    pub interval_s: u32,
+    /// Deep sleep between the measurements instead of staying awake
+    pub deep_sleep: bool,
+}
+
+impl QuietPeriod {
+    pub fn contains(&self, minute_of_day: u16) -> bool {
+        if self.start_min <= self.end_min {
+            (self.start_min..self.end_min).contains(&minute_of_day)
+        } else {
+            minute_of_day >= self.start_min || minute_of_day < self.end_min
+        }
+    }
+
+    /// Seconds left until the end of the period.
+    pub fn remaining_s(&self, second_of_day: u32) -> u32 {
+        let end = self.end_min as u32 * 60;
+        (end + 86_400 - second_of_day % 86_400) % 86_400
+    }
+
+    // start | end << 11 | deep_sleep << 22 | set << 23
+    fn pack(self) -> u32 {
+        self.start_min as u32
+            | (self.end_min as u32) << 11
+            | (self.deep_sleep as u32) << 22
+            | 1 << 23
+    }
+
+    fn unpack(range: u32, interval_s: u32) -> Option<Self> {
+        (range & 1 << 23 != 0).then_some(Self {
+            start_min: (range & 0x7FF) as u16,
+            end_min: (range >> 11 & 0x7FF) as u16,
+            interval_s,
+            deep_sleep: range & 1 << 22 != 0,
+        })
+    }
+}
+
+/// Parameters that can be changed while the station is running.
+pub struct RuntimeConfig {
+    intervals_s: [AtomicU32; 4],
+    // Applied on top of every interval, e.g. on a metered uplink
+    interval_factor: AtomicU32,
+    quiet_ranges: [AtomicU32; MAX_QUIET_PERIODS],
+    quiet_intervals_s: [AtomicU32; MAX_QUIET_PERIODS],
+    // Interval of the quiet period in effect, 0 outside quiet hours
+    quiet_interval_s: AtomicU32,
+    vane_offset_deg: AtomicI32,
+    fields: AtomicU32,
+    // Fields of sensors the station lacks, never published whatever the selection
+    missing: AtomicU32,
+}
+
+pub static RUNTIME: RuntimeConfig = RuntimeConfig::new();
+
+impl RuntimeConfig {
+    pub const fn new() -> Self {
+        Self {
+            // Set from the active config at boot
+            intervals_s: [
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+            ],
+            interval_factor: AtomicU32::new(1),
+            quiet_ranges: [
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+            ],
+            quiet_intervals_s: [
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+            ],
+            quiet_interval_s: AtomicU32::new(0),
+            vane_offset_deg: AtomicI32::new(0),
+            fields: AtomicU32::new(FieldSelection::ALL.0),
+            missing: AtomicU32::new(0),
+        }
+    }
+
+    pub fn interval(&self, group: PublishGroup) -> Duration {
+        let factor = self.interval_factor.load(Ordering::Relaxed).max(1);
+        let seconds = match self.quiet_interval_s.load(Ordering::Relaxed) {
+            0 => self.interval_s(group),
+            quiet => quiet,
+        };
+        Duration::from_secs(seconds as u64 * factor as u64)
+    }
+
+    pub fn interval_s(&self, group: PublishGroup) -> u32 {
+        self.intervals_s[group.index()].load(Ordering::Relaxed)
+    }
+
+    pub fn set_interval_s(&self, group: PublishGroup, seconds: u32) {
+        self.intervals_s[group.index()].store(seconds, Ordering::Relaxed);
+    }
+
+    pub fn set_interval_factor(&self, factor: u32) {
+        self.interval_factor.store(factor, Ordering::Relaxed);
+    }
+
+    /// Replaces the quiet hours schedule, periods past `MAX_QUIET_PERIODS` are ignored.
+    pub fn set_quiet_periods(&self, periods: &[QuietPeriod]) {
+        for (idx, (range, interval)) in self
+            .quiet_ranges
+            .iter()
+            .zip(self.quiet_intervals_s.iter())
+            .enumerate()
+        {
+            let period = periods.get(idx);
+            interval.store(period.map_or(0, |p| p.interval_s), Ordering::Relaxed);
+            range.store(period.map_or(0, |p| p.pack()), Ordering::Relaxed);
+        }
+    }
+
+    /// First period of the schedule containing `minute_of_day`.
+    pub fn quiet_period_at(&self, minute_of_day: u16) -> Option<QuietPeriod> {
+        self.quiet_ranges
+            .iter()
+            .zip(self.quiet_intervals_s.iter())
+            .filter_map(|(range, interval)| {
+                QuietPeriod::unpack(
+                    range.load(Ordering::Relaxed),
+                    interval.load(Ordering::Relaxed),
+                )
+            })
+            .find(|period| period.contains(minute_of_day))
+    }
+
+    /// Publish interval override of the quiet period in effect, `None` outside quiet hours.
+    pub fn set_quiet_interval_s(&self, seconds: Option<u32>) {
+        self.quiet_interval_s
+            .store(seconds.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Angle added to the raw vane reading to align it with true north.
+    pub fn vane_offset_deg(&self) -> i32 {
+        self.vane_offset_deg.load(Ordering::Relaxed)
+    }
+
+    pub fn set_vane_offset_deg(&self, degrees: i32) {
+        self.vane_offset_deg.store(degrees, Ordering::Relaxed);
+    }
+
+    /// Fields included in the published payloads.
+    pub fn fields(&self) -> FieldSelection {
+        FieldSelection(self.fields.load(Ordering::Relaxed) & !self.missing.load(Ordering::Relaxed))
+    }
+
+    pub fn publishes(&self, field: Field) -> bool {
+        self.fields().contains(field)
+    }
+
+    pub fn set_fields(&self, fields: FieldSelection) {
+        self.fields.store(fields.0, Ordering::Relaxed);
+    }
+
+    /// Fields without a sensor to measure them, left out of every selection.
+    pub fn set_missing(&self, fields: &[Field]) {
+        let missing = fields
+            .iter()
+            .fold(0, |bits, field| bits | FieldSelection::bit(*field));
+        self.missing.store(missing, Ordering::Relaxed);
+    }
+}
+
+// Shorter presses are contact bounce
+const BUTTON_DEBOUNCE: Duration = Duration::from_millis(50);
+pub const BUTTON_LONG_PRESS: Duration = Duration::from_secs(5);
+pub const BUTTON_VERY_LONG_PRESS: Duration = Duration::from_secs(15);
+// A second short press started within this after the first one makes a double press
+pub const BUTTON_DOUBLE_PRESS_GAP: Duration = Duration::from_millis(500);
+
+/// Action of the enclosure button, selected by how long and how many times it was pressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonPress {
+    /// Immediate measurement and publish cycle
+    Short,
+    /// Two short presses: maintenance mode on or off
+    Double,
+    /// SoftAP provisioning, nothing is erased
+    Long,
+    /// Factory reset
+    VeryLong,
+}
+
+impl ButtonPress {
+    pub fn from_duration(held: Duration) -> Option<Self> {
+        if held < BUTTON_DEBOUNCE {
+            None
+        } else if held < BUTTON_LONG_PRESS {
+            Some(ButtonPress::Short)
+        } else if held < BUTTON_VERY_LONG_PRESS {
+            Some(ButtonPress::Long)
+        } else {
+            Some(ButtonPress::VeryLong)
+        }
+    }
+}
+
+// Payload of the interval command: `<group> <seconds>`, e.g. `wind 10`
+pub fn parse_interval_command(payload: &str) -> Option<(PublishGroup, u32)> {
+    let mut words = payload.split_whitespace();
+    let group = PublishGroup::from_name(words.next()?)?;
+    let seconds = words.next()?.parse().ok()?;
+    if words.next().is_some() || !(1..=86_400).contains(&seconds) {
+        return None;
+    }
+    Some((group, seconds))
+}
+
+/// Commands of the general `<topic>/cmd` topic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StationCommand<'a> {
+    /// Measure and publish every group right away
+    Measure,
+    Reboot,
+    Interval(PublishGroup, u32),
+    LogLevel(log::LevelFilter),
+    /// Firmware image to flash, with the SHA-256 it must match
+    Ota {
+        url: &'a str,
+        sha256: [u8; 32],
+    },
+}
+
+/// `measure`, `reboot`, `interval <group> <seconds>`, `log_level <level>` or
+/// `ota <url> <sha256>`. The level is one of `off`, `error`, `warn`, `info`, `debug` or
+/// `trace`, the URL is http or https and the SHA-256 is 64 hex digits.
+pub fn parse_station_command(payload: &str) -> Option<StationCommand<'_>> {
+    let payload = payload.trim();
+    let (name, args) = payload
+        .split_once(char::is_whitespace)
+        .unwrap_or((payload, ""));
+    match (name, args.trim()) {
+        ("measure", "") => Some(StationCommand::Measure),
+        ("reboot", "") => Some(StationCommand::Reboot),
+        ("interval", args) => parse_interval_command(args)
+            .map(|(group, seconds)| StationCommand::Interval(group, seconds)),
+        ("log_level", level) => level.parse().ok().map(StationCommand::LogLevel),
+        ("ota", args) => {
+            let mut words = args.split_whitespace();
+            let url = words
+                .next()
+                .filter(|url| url.starts_with("http://") || url.starts_with("https://"))?;
+            // An update without its digest is rejected
+            let sha256 = parse_sha256(words.next()?)?;
+            if words.next().is_some() {
+                return None;
+            }
+            Some(StationCommand::Ota { url, sha256 })
+        }
+        _ => None,
+    }
+}
+
+fn parse_sha256(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// Payload of the maintenance command: `on`, `on <seconds>` or `off`. Returns how long the
+/// maintenance mode lasts, `default_s` when not given, 0 to leave it.
+pub fn parse_maintenance_command(payload: &str, default_s: u32) -> Option<u32> {
+    let mut words = payload.split_whitespace();
+    let duration_s = match (words.next()?, words.next()) {
+        ("off", None) => return Some(0),
+        ("on", None) => default_s,
+        ("on", Some(seconds)) => seconds.parse().ok()?,
+        _ => return None,
+    };
+    if words.next().is_some() || !(1..=86_400).contains(&duration_s) {
+        return None;
+    }
+    Some(duration_s)
+}
+
+/// Parses a quiet hours schedule: comma separated `HH:MM-HH:MM/<interval_s>[/sleep]`
+/// entries, e.g. `22:00-06:00/600/sleep`. Returns the periods and how many are set, an empty
+/// schedule clears the quiet hours.
+pub fn parse_quiet_hours(schedule: &str) -> Option<([QuietPeriod; MAX_QUIET_PERIODS], usize)> {
+    let mut periods = [QuietPeriod::default(); MAX_QUIET_PERIODS];
+    let mut count = 0;
+    for entry in schedule.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut fields = entry.split('/');
+        let (start, end) = fields.next()?.split_once('-')?;
+        let interval_s = fields.next()?.trim().parse().ok()?;
+        let deep_sleep = match fields.next().map(str::trim) {
+            None => false,
+            Some("sleep") => true,
+            Some(_) => return None,
+        };
+        if fields.next().is_some() || !(1..=86_400).contains(&interval_s) {
+            return None;
+        }
+        let period = QuietPeriod {
+            start_min: parse_minute_of_day(start)?,
+            end_min: parse_minute_of_day(end)?,
+            interval_s,
+            deep_sleep,
+        };
+        if period.start_min == period.end_min {
+            return None;
+        }
+        *periods.get_mut(count)? = period;
+        count += 1;
+    }
+    Some((periods, count))
+}
+
+/// Delay between reconnection attempts, doubling after each failure up to `max`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    first: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub const fn new(first: Duration, max: Duration) -> Self {
+        Self {
+            first,
+            max,
+            next: first,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// Back to the first delay, once connected.
+    pub fn reset(&mut self) {
+        self.next = self.first;
+    }
+}
+
+// "HH:MM"
+fn parse_minute_of_day(text: &str) -> Option<u16> {
+    let (hours, minutes) = text.trim().split_once(':')?;
+    let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    hal::delay::FreeRtos,
+    mqtt::client::QoS,
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::*,
+};
+use std::net::Ipv4Addr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use weather_station::runtime::{parse_station_command, StationCommand};
+
+use crate::provisioning::CONFIG;
+use crate::{init, mqtt, ota};
+
+const NVS_NAMESPACE: &str = "boot";
+// Consecutive crashes before the station stops loading the optional drivers
+const CRASH_LOOP_THRESHOLD: u8 = 3;
+
+/// Counts consecutive crash reboots and decides whether to boot in safe mode.
+///
+/// A boot is unclean when the previous run ended in a panic, a watchdog or a brownout. The
+/// count is cleared once the station has been healthy for `healthy_uptime_min` or reaches deep
+/// sleep normally. Safe mode itself is sticky: it is left with the `exit` command or when a
+/// new configuration is provisioned.
+pub struct BootGuard {
+    nvs: EspNvs<NvsDefault>,
+    pub safe_mode: bool,
+    pub unclean_boots: u8,
+    healthy: bool,
+}
+
+impl BootGuard {
+    pub fn new(nvs: EspDefaultNvsPartition, config_updated: bool) -> Result<Self> {
+        let mut nvs = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+        let mut unclean_boots = nvs.get_u8("unclean")?.unwrap_or(0);
+        let mut safe_mode = nvs.get_u8("safe_mode")?.unwrap_or(0) == 1;
+
+        if crashed() {
+            unclean_boots = unclean_boots.saturating_add(1);
+            nvs.set_u8("unclean", unclean_boots)?;
+        }
+        if config_updated && safe_mode {
+            log::info!("New configuration provisioned, leaving safe mode");
+            safe_mode = false;
+            unclean_boots = 0;
+            nvs.set_u8("unclean", 0)?;
+            nvs.set_u8("safe_mode", 0)?;
+        } else if !safe_mode && unclean_boots >= CRASH_LOOP_THRESHOLD {
+            log::error!("{unclean_boots} unclean boots in a row, entering safe mode");
+            safe_mode = true;
+            nvs.set_u8("safe_mode", 1)?;
+        }
+
+        Ok(Self {
+            nvs,
+            safe_mode,
+            unclean_boots,
+            healthy: false,
+        })
+    }
+
+    /// Clears the unclean boot count, once per boot.
+    pub fn mark_healthy(&mut self) {
+        if self.healthy || self.safe_mode {
+            return;
+        }
+        self.healthy = true;
+        if self.unclean_boots > 0 {
+            self.nvs
+                .set_u8("unclean", 0)
+                .map_err(|e| log::error!("fail clearing unclean boot count: {e}"))
+                .ok();
+            self.unclean_boots = 0;
+        }
+    }
+
+    pub fn healthy_uptime_reached(&self, uptime: Duration) -> bool {
+        uptime >= Duration::from_secs(CONFIG.healthy_uptime_min as u64 * 60)
+    }
+
+    pub fn exit_safe_mode(&mut self) -> Result<()> {
+        self.nvs.set_u8("unclean", 0)?;
+        self.nvs.set_u8("safe_mode", 0)?;
+        Ok(())
+    }
+}
+
+fn crashed() -> bool {
+    matches!(
+        unsafe { esp_reset_reason() },
+        esp_reset_reason_t_ESP_RST_PANIC
+            | esp_reset_reason_t_ESP_RST_INT_WDT
+            | esp_reset_reason_t_ESP_RST_TASK_WDT
+            | esp_reset_reason_t_ESP_RST_WDT
+            | esp_reset_reason_t_ESP_RST_BROWNOUT
+    )
+}
+
+/// Safe mode main loop: no sensor or integration is started, only MQTT and its commands.
+///
+/// The station announces itself and listens for the active duration, then sleeps as usual.
+pub fn run_safe_mode(mut guard: BootGuard, ip_address: Ipv4Addr) -> ! {
+    let (mut mqtt_cli, mut mqtt_conn) = init::retry("mqtt", init::BOOT_ATTEMPTS, mqtt::mqtt_create)
+        .unwrap_or_else(|e| init::restart_later("mqtt", e));
+    let (event_tx, event_rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .stack_size(6000)
+        .spawn(move || mqtt::forward_events(&mut mqtt_conn, event_tx))
+        .expect("An error occurred with mqtt client");
+
+    let active_duration = Duration::from_secs(CONFIG.active_duration_s + 1);
+    let start_time = Instant::now();
+    while start_time.elapsed() < active_duration {
+        while let Ok(event) = event_rx.try_recv() {
+            match event {
+                mqtt::MqttEvent::Connected => {
+                    mqtt::publish_online(&mut mqtt_cli);
+                    mqtt::subscribe_commands(&mut mqtt_cli);
+                    // Only listened to here, the normal loop has no use for it
+                    mqtt_cli
+                        .subscribe(&mqtt::safe_mode_topic(), QoS::AtLeastOnce)
+                        .map_err(|e| log::error!("fail subscribing to safe mode commands: {e}"))
+                        .ok();
+                    mqtt::publish_discovery_beacon(&mut mqtt_cli, ip_address, true);
+                    mqtt::publish_system_event(
+                        &mut mqtt_cli,
+                        &format!("safe_mode: {} unclean boots", guard.unclean_boots),
+                    );
+                }
+                mqtt::MqttEvent::Received { topic, payload }
+                    if topic == mqtt::safe_mode_topic() && payload == b"exit" =>
+                {
+                    // The client does not report the retain flag, so a retained exit is
+                    // cleared to keep it from ending the next safe mode right away
+                    mqtt::publish(
+                        &mut mqtt_cli,
+                        &mqtt::safe_mode_topic(),
+                        QoS::AtLeastOnce,
+                        true,
+                        &[],
+                    )
+                    .map_err(|e| log::error!("Fail clearing the safe mode command: {e}"))
+                    .ok();
+                    match guard.exit_safe_mode() {
+                        Ok(()) => {
+                            log::warn!("Leaving safe mode, rebooting");
+                            mqtt::publish_system_event(&mut mqtt_cli, "safe_mode_exit");
+                            FreeRtos::delay_ms(500);
+                            unsafe { esp_restart() }
+                        }
+                        Err(e) => log::error!("Fail leaving safe mode: {e}"),
+                    }
+                }
+                // A fixed firmware is the way out of a crash loop caused by the current one
+                mqtt::MqttEvent::Received { topic, payload } if topic == mqtt::command_topic() => {
+                    match parse_station_command(&String::from_utf8_lossy(&payload)) {
+                        Some(StationCommand::Ota { url, sha256 }) => {
+                            ota::update_and_reboot(&mut mqtt_cli, url, sha256)
+                        }
+                        _ => log::warn!("Safe mode, only ota is accepted on {topic}"),
+                    }
+                }
+                mqtt::MqttEvent::Received { topic, .. } => {
+                    log::warn!("Safe mode, ignoring message on {topic}")
+                }
+                mqtt::MqttEvent::Disconnected
+                | mqtt::MqttEvent::Closed
+                | mqtt::MqttEvent::Button(_)
+                | mqtt::MqttEvent::Measure => {}
+            }
+        }
+        FreeRtos::delay_ms(100);
+    }
+
+    crate::quiet_hours::record_sleep(Duration::from_micros(CONFIG.deep_sleep_interval_us));
+    log::info!("Safe mode, going to deep sleep...");
+    unsafe { esp_deep_sleep_start() }
+}
@@ -0,0 +1,293 @@
+//! SDI-12 master side: commands, response parsing and the field table.
+//!
+//! The bus runs at 1200 baud 7E1 with inverted levels. Every command starts with a break
+//! (spacing for at least 12 ms) followed by at least 8.33 ms of marking. A measurement is
+//! `aM!` (or `aMC!` with CRC), answered `atttn`: the values are ready after `ttt` seconds, or
+//! earlier with a service request `a<CR><LF>`. They are then collected with `aD0!`, `aD1!`, ...
+//! until `n` values were returned.
+use core::time::Duration;
+
+pub const SDI12_BAUD: u32 = 1200;
+pub const BREAK: Duration = Duration::from_millis(12);
+pub const MARKING: Duration = Duration::from_micros(8_330);
+/// The sensor starts answering within 15 ms, a 75 characters line then takes 625 ms.
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_millis(800);
+// D0! to D9!
+const MAX_DATA_COMMANDS: u8 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sdi12Error {
+    /// No response, or an incomplete one
+    Timeout,
+    /// Response from another address than the one addressed
+    Address,
+    Format,
+    Crc,
+    /// The sensor returned fewer values than announced by the measurement
+    Missing,
+}
+
+impl Sdi12Error {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sdi12Error::Timeout => "timeout",
+            Sdi12Error::Address => "address",
+            Sdi12Error::Format => "format",
+            Sdi12Error::Crc => "crc",
+            Sdi12Error::Missing => "missing",
+        }
+    }
+}
+
+pub fn is_valid_address(address: u8) -> bool {
+    address.is_ascii_alphanumeric()
+}
+
+pub fn address_query() -> String {
+    "?!".to_string()
+}
+
+pub fn measure_command(address: u8, crc: bool) -> String {
+    format!("{}M{}!", address as char, if crc { "C" } else { "" })
+}
+
+pub fn data_command(address: u8, index: u8) -> String {
+    format!("{}D{index}!", address as char)
+}
+
+/// Address returned by `?!` or `a!`, only meaningful with a single sensor on the bus.
+pub fn parse_address(line: &str) -> Result<u8, Sdi12Error> {
+    match line.trim_end().as_bytes() {
+        [address] if is_valid_address(*address) => Ok(*address),
+        [] => Err(Sdi12Error::Timeout),
+        _ => Err(Sdi12Error::Format),
+    }
+}
+
+/// Answer to a measurement command: when the values are ready and how many there are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeasureResponse {
+    pub wait: Duration,
+    pub count: u8,
+}
+
+/// `atttn`, or `atttnn` for the concurrent and high volume variants.
+pub fn parse_measure_response(line: &str, address: u8) -> Result<MeasureResponse, Sdi12Error> {
+    let line = line.trim_end().as_bytes();
+    let Some((&first, rest)) = line.split_first() else {
+        return Err(Sdi12Error::Timeout);
+    };
+    if first != address {
+        return Err(Sdi12Error::Address);
+    }
+    if !(4..=5).contains(&rest.len()) || !rest.iter().all(u8::is_ascii_digit) {
+        return Err(Sdi12Error::Format);
+    }
+    let number = |digits: &[u8]| {
+        digits
+            .iter()
+            .fold(0u32, |n, digit| n * 10 + (digit - b'0') as u32)
+    };
+    Ok(MeasureResponse {
+        wait: Duration::from_secs(number(&rest[..3]) as u64),
+        count: number(&rest[3..]).min(u8::MAX as u32) as u8,
+    })
+}
+
+/// CRC-16 (0xA001 reflected, initial value 0) over the address and the values.
+pub fn crc(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, byte| {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+/// The CRC is sent as three printable characters, 6 bits each.
+pub fn encode_crc(crc: u16) -> [u8; 3] {
+    [
+        0x40 | (crc >> 12) as u8,
+        0x40 | ((crc >> 6) & 0x3F) as u8,
+        0x40 | (crc & 0x3F) as u8,
+    ]
+}
+
+/// Values of a `aDn!` response, e.g. `0+3.14-2.5`, with the three CRC characters at the
+/// end when the measurement asked for them.
+pub fn parse_values(line: &str, address: u8, with_crc: bool) -> Result<Vec<f32>, Sdi12Error> {
+    let mut line = line.trim_end().as_bytes();
+    if line.is_empty() {
+        return Err(Sdi12Error::Timeout);
+    }
+    if line[0] != address {
+        return Err(Sdi12Error::Address);
+    }
+    if with_crc {
+        let Some(split) = line.len().checked_sub(3).filter(|split| *split > 0) else {
+            return Err(Sdi12Error::Format);
+        };
+        let (body, received) = line.split_at(split);
+        if encode_crc(crc(body)) != received {
+            return Err(Sdi12Error::Crc);
+        }
+        line = body;
+    }
+    let values = core::str::from_utf8(&line[1..]).map_err(|_| Sdi12Error::Format)?;
+
+    // Every value starts with its sign
+    let mut parsed = vec![];
+    let mut start = None;
+    for (i, c) in values.char_indices() {
+        if c == '+' || c == '-' {
+            if let Some(start) = start {
+                parsed.push(parse_value(&values[start..i])?);
+            }
+            start = Some(i);
+        } else if start.is_none() || !(c.is_ascii_digit() || c == '.') {
+            return Err(Sdi12Error::Format);
+        }
+    }
+    if let Some(start) = start {
+        parsed.push(parse_value(&values[start..])?);
+    }
+    Ok(parsed)
+}
+
+fn parse_value(value: &str) -> Result<f32, Sdi12Error> {
+    value.parse().map_err(|_| Sdi12Error::Format)
+}
+
+/// Data commands to try before giving up on the announced values.
+pub fn data_commands() -> impl Iterator<Item = u8> {
+    0..MAX_DATA_COMMANDS
+}
+
+/// Maps the n-th value (from 1) of a sensor to a named field, multiplied by `scale`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sdi12Field<'a> {
+    pub address: u8,
+    pub index: u8,
+    pub name: &'a str,
+    pub scale: f32,
+}
+
+/// Table of `address:index:name[:scale]` entries separated by commas, e.g.
+/// `0:1:soil_moisture,0:2:soil_temperature,1:3:soil_ec:0.001`.
+pub fn parse_field_table(table: &str) -> Result<Vec<Sdi12Field<'_>>, String> {
+    table
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let parts: Vec<&str> = entry.split(':').map(str::trim).collect();
+            let invalid = || format!("invalid SDI-12 field '{entry}'");
+            let (address, index, name, scale) = match parts[..] {
+                [address, index, name] => (address, index, name, None),
+                [address, index, name, scale] => (address, index, name, Some(scale)),
+                _ => return Err(invalid()),
+            };
+            let address = match address.as_bytes() {
+                [address] if is_valid_address(*address) => *address,
+                _ => return Err(invalid()),
+            };
+            let index = index
+                .parse::<u8>()
+                .ok()
+                .filter(|index| *index > 0)
+                .ok_or_else(invalid)?;
+            let scale = match scale {
+                Some(scale) => scale.parse::<f32>().map_err(|_| invalid())?,
+                None => 1.0,
+            };
+            if name.is_empty() {
+                return Err(invalid());
+            }
+            Ok(Sdi12Field {
+                address,
+                index,
+                name,
+                scale,
+            })
+        })
+        .collect()
+}
+
+/// Distinct sensor addresses of a field table, in order of appearance.
+pub fn addresses(fields: &[Sdi12Field]) -> Vec<u8> {
+    let mut addresses = vec![];
+    for field in fields {
+        if !addresses.contains(&field.address) {
+            addresses.push(field.address);
+        }
+    }
+    addresses
+}
+
+/// Outcome of the polls of one sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sdi12SensorStatus {
+    pub address: u8,
+    /// Failed polls since boot, after the retries
+    pub errors: u32,
+    /// Failed polls since the last good one
+    pub consecutive_errors: u32,
+    /// Retries needed by the polls since boot
+    pub retries: u32,
+    pub last_error: Option<Sdi12Error>,
+}
+
+impl Sdi12SensorStatus {
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            errors: 0,
+            consecutive_errors: 0,
+            retries: 0,
+            last_error: None,
+        }
+    }
+
+    pub fn record(&mut self, result: Result<(), Sdi12Error>) {
+        match result {
+            Ok(()) => self.consecutive_errors = 0,
+            Err(e) => {
+                self.errors += 1;
+                self.consecutive_errors += 1;
+                self.last_error = Some(e);
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"ok\": {}, \"errors\": {}, \"consecutive_errors\": {}, \"retries\": {}, \"last_error\": {}}}",
+            self.consecutive_errors == 0,
+            self.errors,
+            self.consecutive_errors,
+            self.retries,
+            self.last_error
+                .map_or("null".to_string(), |e| format!("\"{}\"", e.name()))
+        )
+    }
+}
+
+/// Scaled value of every field of the table, None when its sensor could not be read.
+pub fn field_values(
+    fields: &[Sdi12Field],
+    sensor_values: impl Fn(u8) -> Option<Vec<f32>>,
+) -> Vec<Option<f32>> {
+    fields
+        .iter()
+        .map(|field| {
+            sensor_values(field.address)
+                .and_then(|values| values.get(field.index as usize - 1).copied())
+                .map(|value| value * field.scale)
+        })
+        .collect()
+}
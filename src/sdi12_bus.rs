@@ -0,0 +1,273 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    hal::{
+        delay::TickType,
+        gpio::{AnyIOPin, AnyOutputPin, Output, PinDriver},
+        uart::{
+            config::{Config as UartConfig, DataBits},
+            UartDriver, UART1,
+        },
+        units::Hertz,
+    },
+    sys::{
+        esp, uart_set_line_inverse, uart_signal_inv_t_UART_SIGNAL_RXD_INV,
+        uart_signal_inv_t_UART_SIGNAL_TXD_INV,
+    },
+};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use weather_station::sdi12::*;
+
+use crate::provisioning::CONFIG;
+
+// A 0x00 at this rate keeps the line spacing for 9 bits, 30 ms, long enough for a break
+const BREAK_BAUD: u32 = 300;
+
+/// Latest values of the SDI-12 field table and the status of each sensor.
+pub struct Sdi12State {
+    pub fields: Vec<Sdi12Field<'static>>,
+    /// One per field, None when its sensor failed the last poll
+    pub values: Vec<Option<f32>>,
+    pub sensors: Vec<Sdi12SensorStatus>,
+    polled_at: Option<Instant>,
+}
+
+impl Sdi12State {
+    /// Values older than a few polls are not published.
+    pub fn is_stale(&self) -> bool {
+        let max_age = Duration::from_secs(3 * CONFIG.sdi12_interval_s.max(1) as u64);
+        self.polled_at
+            .map_or(true, |polled| polled.elapsed() > max_age)
+    }
+
+    /// `name: value` entries, null for the values that could not be read.
+    pub fn values_json(&self) -> String {
+        serde_json::to_string(&Sdi12Values(self)).unwrap_or_default()
+    }
+
+    pub fn to_json(&self) -> String {
+        let sensors = self
+            .sensors
+            .iter()
+            .map(|sensor| format!("\"{}\": {}", sensor.address as char, sensor.to_json()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{{\"values\": {}, \"stale\": {}, \"sensors\": {{{sensors}}}}}",
+            self.values_json(),
+            self.is_stale()
+        )
+    }
+}
+
+/// Field values keyed by their table name, in the order of the table.
+pub struct Sdi12Values<'a>(pub &'a Sdi12State);
+
+impl Serialize for Sdi12Values<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let stale = self.0.is_stale();
+        let mut map = serializer.serialize_map(Some(self.0.fields.len()))?;
+        for (field, value) in self.0.fields.iter().zip(&self.0.values) {
+            map.serialize_entry(field.name, &value.filter(|_| !stale))?;
+        }
+        map.end()
+    }
+}
+
+/// Single data line through a level shifter, levels inverted by the UART. The optional
+/// direction pin drives a tri-state buffer, high while transmitting.
+struct Sdi12Bus {
+    driver: UartDriver<'static>,
+    direction: Option<PinDriver<'static, AnyOutputPin, Output>>,
+}
+
+impl Sdi12Bus {
+    fn command(&mut self, command: &str) -> Result<String, Sdi12Error> {
+        if let Some(direction) = self.direction.as_mut() {
+            direction.set_high().ok();
+        }
+        let sent = self.send(command);
+        if let Some(direction) = self.direction.as_mut() {
+            direction.set_low().ok();
+        }
+        sent.map_err(|e| {
+            log::error!("SDI-12 write failed: {e}");
+            Sdi12Error::Timeout
+        })?;
+        self.read_line(RESPONSE_TIMEOUT)
+    }
+
+    fn send(&mut self, command: &str) -> Result<()> {
+        let done = TickType::from(Duration::from_millis(100)).ticks();
+        self.driver.change_baudrate(Hertz(BREAK_BAUD))?;
+        self.driver.write(&[0x00])?;
+        self.driver.wait_tx_done(done)?;
+        self.driver.change_baudrate(Hertz(SDI12_BAUD))?;
+        std::thread::sleep(MARKING);
+        self.driver.write(command.as_bytes())?;
+        self.driver.wait_tx_done(done)?;
+        // Drops the echo of the command, the sensor waits 8.33 ms before answering
+        self.driver.clear_rx()?;
+        Ok(())
+    }
+
+    /// Line up to the LF, without the CR LF.
+    fn read_line(&mut self, timeout: Duration) -> Result<String, Sdi12Error> {
+        let deadline = Instant::now() + timeout;
+        let mut line = String::new();
+        let mut byte = [0u8];
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match self.driver.read(&mut byte, TickType::from(left).ticks()) {
+                Ok(1) => match byte[0] & 0x7F {
+                    b'\n' => return Ok(line.trim_end_matches('\r').to_string()),
+                    c => line.push(c as char),
+                },
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("SDI-12 read failed: {e}");
+                    break;
+                }
+            }
+        }
+        Err(Sdi12Error::Timeout)
+    }
+
+    /// `aM!` then the data commands until the announced values are collected.
+    fn measure(&mut self, address: u8, crc: bool) -> Result<Vec<f32>, Sdi12Error> {
+        let line = self.command(&measure_command(address, crc))?;
+        let response = parse_measure_response(&line, address)?;
+        if !response.wait.is_zero() {
+            // The service request only comes early, otherwise the wait simply runs out
+            self.read_line(response.wait + RESPONSE_TIMEOUT).ok();
+        }
+
+        let mut values = vec![];
+        for index in data_commands() {
+            if values.len() >= response.count as usize {
+                break;
+            }
+            let line = self.command(&data_command(address, index))?;
+            let received = parse_values(&line, address, crc)?;
+            if received.is_empty() {
+                break;
+            }
+            values.extend(received);
+        }
+        if values.len() < response.count as usize || response.count == 0 {
+            return Err(Sdi12Error::Missing);
+        }
+        Ok(values)
+    }
+}
+
+/// Poll the SDI-12 sensors of the field table on UART1 every `sdi12_interval_s`.
+pub fn sdi12_start(uart: UART1) -> Result<Arc<Mutex<Sdi12State>>> {
+    let fields = parse_field_table(CONFIG.sdi12_fields).map_err(anyhow::Error::msg)?;
+    if fields.is_empty() {
+        anyhow::bail!("no field in sdi12_fields");
+    }
+    // Pins come from the config, they are not claimed by any other driver
+    let (tx, rx) = unsafe {
+        (
+            AnyIOPin::new(CONFIG.sdi12_tx_gpio),
+            AnyIOPin::new(CONFIG.sdi12_rx_gpio),
+        )
+    };
+    let driver = UartDriver::new(
+        uart,
+        tx,
+        rx,
+        Option::<AnyIOPin>::None,
+        Option::<AnyIOPin>::None,
+        &UartConfig::new()
+            .baudrate(Hertz(SDI12_BAUD))
+            .data_bits(DataBits::DataBits7)
+            .parity_even(),
+    )?;
+    // Marking is the low level on the bus
+    esp!(unsafe {
+        uart_set_line_inverse(
+            driver.port(),
+            uart_signal_inv_t_UART_SIGNAL_TXD_INV | uart_signal_inv_t_UART_SIGNAL_RXD_INV,
+        )
+    })?;
+    let direction = if CONFIG.sdi12_dir_gpio >= 0 {
+        let mut pin = PinDriver::output(unsafe { AnyOutputPin::new(CONFIG.sdi12_dir_gpio) })?;
+        pin.set_low()?;
+        Some(pin)
+    } else {
+        None
+    };
+
+    let addresses = addresses(&fields);
+    let state = Arc::new(Mutex::new(Sdi12State {
+        values: vec![None; fields.len()],
+        sensors: addresses
+            .iter()
+            .map(|a| Sdi12SensorStatus::new(*a))
+            .collect(),
+        fields,
+        polled_at: None,
+    }));
+
+    let shared = state.clone();
+    std::thread::Builder::new()
+        .stack_size(4096)
+        .spawn(move || {
+            let mut bus = Sdi12Bus { driver, direction };
+            // Only answered when a single sensor is on the bus, helps checking the wiring
+            match bus
+                .command(&address_query())
+                .and_then(|l| parse_address(&l))
+            {
+                Ok(address) => log::info!("SDI-12 sensor at address {}", address as char),
+                Err(e) => log::info!("SDI-12 address query: {}", e.name()),
+            }
+            let interval = Duration::from_secs(CONFIG.sdi12_interval_s.max(1) as u64);
+            loop {
+                let started = Instant::now();
+                let mut readings = vec![];
+                for &address in &addresses {
+                    let mut result = Err(Sdi12Error::Timeout);
+                    let mut retries = 0;
+                    for attempt in 0..=CONFIG.sdi12_retries {
+                        if attempt > 0 {
+                            retries += 1;
+                        }
+                        result = bus.measure(address, CONFIG.sdi12_crc);
+                        match &result {
+                            Ok(_) => break,
+                            Err(e) => log::warn!(
+                                "SDI-12 sensor {} attempt {}: {}",
+                                address as char,
+                                attempt + 1,
+                                e.name()
+                            ),
+                        }
+                    }
+                    if let Err(e) = &result {
+                        log::error!("SDI-12 sensor {} failed: {}", address as char, e.name());
+                    }
+                    readings.push((address, retries, result));
+                }
+
+                let mut state = shared.lock().unwrap();
+                let values = field_values(&state.fields, |address| {
+                    readings
+                        .iter()
+                        .find(|(a, ..)| *a == address)
+                        .and_then(|(.., result)| result.as_ref().ok().cloned())
+                });
+                state.values = values;
+                for (sensor, (_, retries, result)) in state.sensors.iter_mut().zip(&readings) {
+                    sensor.retries += retries;
+                    sensor.record(result.as_ref().map(|_| ()).map_err(|e| *e));
+                }
+                state.polled_at = Some(Instant::now());
+                drop(state);
+                std::thread::sleep(interval.saturating_sub(started.elapsed()));
+            }
+        })?;
+    Ok(state)
+}
@@ -0,0 +1,91 @@
+use super::{tca9548a::TcaMux, Measurement, Sensor};
+use crate::{compute::apply_vane_offset, runtime::RUNTIME};
+use anyhow::Result;
+
+/// Magnet and communication health of an angle sensor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AngleStatus {
+    Ok,
+    TooWeak,
+    TooStrong,
+    NoMagnet,
+    Error,
+}
+
+impl AngleStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            AngleStatus::Ok => "ok",
+            AngleStatus::TooWeak => "too_weak",
+            AngleStatus::TooStrong => "too_strong",
+            AngleStatus::NoMagnet => "no_magnet",
+            AngleStatus::Error => "error",
+        }
+    }
+}
+
+/// Wind vane position sensor. Calibration, filtering and averaging are applied on top.
+pub trait AngleSensor {
+    /// Angle in degrees (0-360) as measured, without the vane offset.
+    fn raw_angle(&mut self) -> Result<f32>;
+    fn status(&mut self) -> AngleStatus;
+}
+
+/// A wind vane, an angle sensor read with the runtime vane offset or, for an extra vane, its
+/// own.
+pub struct WindVane<'a> {
+    sensor: Box<dyn AngleSensor + 'a>,
+    // Only for I2C sensors behind the mux
+    mux: Option<(TcaMux<'a>, u8)>,
+    id: &'a str,
+    // None for the runtime vane offset
+    offset_deg: Option<f32>,
+}
+
+impl<'a> WindVane<'a> {
+    pub fn new(sensor: Box<dyn AngleSensor + 'a>) -> Self {
+        Self {
+            sensor,
+            mux: None,
+            id: "vane",
+            offset_deg: None,
+        }
+    }
+
+    /// An extra vane, with its own id and a fixed offset.
+    pub fn extra(mut self, id: &'a str, offset_deg: f32) -> Self {
+        self.id = id;
+        self.offset_deg = Some(offset_deg);
+        self
+    }
+
+    /// Selects `channel` of the mux before each access, a no-op without a mux.
+    pub fn behind_mux(mut self, mux: Option<TcaMux<'a>>, channel: u8) -> Self {
+        self.mux = mux.map(|mux| (mux, channel));
+        self
+    }
+
+    pub fn status(&mut self) -> AngleStatus {
+        if let Some((mux, channel)) = self.mux.as_mut() {
+            if mux.select_channel(*channel).is_err() {
+                return AngleStatus::Error;
+            }
+        }
+        self.sensor.status()
+    }
+}
+
+impl Sensor for WindVane<'_> {
+    fn id(&self) -> &str {
+        self.id
+    }
+
+    fn read(&mut self) -> Result<Measurement> {
+        if let Some((mux, channel)) = self.mux.as_mut() {
+            mux.select_channel(*channel)?;
+        }
+        let raw = self.sensor.raw_angle()?;
+        let offset = self.offset_deg.unwrap_or_else(|| RUNTIME.vane_offset_deg());
+        Ok(Measurement::WindAngle(apply_vane_offset(raw, offset)))
+    }
+}
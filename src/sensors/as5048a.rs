@@ -0,0 +1,81 @@
+use super::angle::{AngleSensor, AngleStatus};
+use crate::compute::degrees_from_counts;
+use anyhow::{bail, Result};
+use esp_idf_svc::hal::spi::{SpiDeviceDriver, SpiDriver};
+
+const REG_NOP: u16 = 0x0000;
+const REG_CLEAR_ERROR: u16 = 0x0001;
+const REG_DIAGNOSTICS: u16 = 0x3FFD;
+const REG_ANGLE: u16 = 0x3FFF;
+const READ: u16 = 0x4000;
+// Set in a response when the previous command failed
+const ERROR_FLAG: u16 = 0x4000;
+const DATA_MASK: u16 = 0x3FFF;
+// Diagnostics register
+const DIAG_OCF: u16 = 1 << 8;
+const DIAG_COMP_LOW: u16 = 1 << 10;
+const DIAG_COMP_HIGH: u16 = 1 << 11;
+
+/// AS5048A, 14 bit over SPI (mode 1).
+///
+/// The response to a command comes with the next frame, so every read is followed by a NOP.
+pub struct As5048a<'d> {
+    spi: SpiDeviceDriver<'d, SpiDriver<'d>>,
+}
+
+impl<'d> As5048a<'d> {
+    pub fn new(spi: SpiDeviceDriver<'d, SpiDriver<'d>>) -> Self {
+        Self { spi }
+    }
+
+    fn read_register(&mut self, register: u16) -> Result<u16> {
+        self.transfer(command(register | READ))?;
+        let response = self.transfer(command(REG_NOP))?;
+        if !even_parity(response) {
+            bail!("AS5048A parity error");
+        }
+        if response & ERROR_FLAG != 0 {
+            // Reading the error register clears the flag
+            self.transfer(command(REG_CLEAR_ERROR | READ))?;
+            bail!("AS5048A command error");
+        }
+        Ok(response & DATA_MASK)
+    }
+
+    fn transfer(&mut self, frame: u16) -> Result<u16> {
+        let mut response = [0u8; 2];
+        self.spi.transfer(&mut response, &frame.to_be_bytes())?;
+        Ok(u16::from_be_bytes(response))
+    }
+}
+
+impl AngleSensor for As5048a<'_> {
+    fn raw_angle(&mut self) -> Result<f32> {
+        let raw = self.read_register(REG_ANGLE)?;
+        Ok(degrees_from_counts(raw, 14))
+    }
+
+    fn status(&mut self) -> AngleStatus {
+        match self.read_register(REG_DIAGNOSTICS) {
+            Ok(diag) if diag & DIAG_COMP_HIGH != 0 => AngleStatus::TooWeak,
+            Ok(diag) if diag & DIAG_COMP_LOW != 0 => AngleStatus::TooStrong,
+            Ok(diag) if diag & DIAG_OCF == 0 => AngleStatus::NoMagnet,
+            Ok(_) => AngleStatus::Ok,
+            Err(_) => AngleStatus::Error,
+        }
+    }
+}
+
+// Bit 15 makes the number of ones in the frame even
+fn command(word: u16) -> u16 {
+    let word = word & 0x7FFF;
+    if even_parity(word) {
+        word
+    } else {
+        word | 0x8000
+    }
+}
+
+fn even_parity(word: u16) -> bool {
+    word.count_ones() % 2 == 0
+}
@@ -0,0 +1,39 @@
+use super::angle::{AngleSensor, AngleStatus};
+use crate::compute::degrees_from_counts;
+use ::as5600::{status::Status, As5600};
+use anyhow::{anyhow, Result};
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_idf_svc::hal::i2c::I2cDriver;
+
+/// AS5600, 12 bit over I2C.
+pub struct As5600Sensor<'a> {
+    as5600: As5600<RefCellDevice<'a, I2cDriver<'a>>>,
+}
+
+impl<'a> As5600Sensor<'a> {
+    pub fn new(i2c: RefCellDevice<'a, I2cDriver<'a>>) -> Self {
+        Self {
+            as5600: As5600::new(i2c),
+        }
+    }
+}
+
+impl AngleSensor for As5600Sensor<'_> {
+    fn raw_angle(&mut self) -> Result<f32> {
+        let raw = self
+            .as5600
+            .angle()
+            .map_err(|e| anyhow!("AS5600 read failed: {e:?}"))?;
+        Ok(degrees_from_counts(raw, 12))
+    }
+
+    fn status(&mut self) -> AngleStatus {
+        match self.as5600.magnet_status() {
+            Ok(Status::MagnetDetected) => AngleStatus::Ok,
+            Ok(Status::MagnetLow) => AngleStatus::TooWeak,
+            Ok(Status::MagnetHigh) => AngleStatus::TooStrong,
+            Ok(_) => AngleStatus::NoMagnet,
+            Err(_) => AngleStatus::Error,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::power::*;
+use anyhow::{anyhow, Result};
+use esp_idf_svc::sys::{
+    adc1_config_channel_atten, adc1_config_width, adc1_get_raw, adc_atten_t_ADC_ATTEN_DB_11,
+    adc_bits_width_t_ADC_WIDTH_BIT_12, adc_unit_t_ADC_UNIT_1, esp_adc_cal_characteristics_t,
+    esp_adc_cal_characterize, esp_adc_cal_raw_to_voltage,
+};
+
+// Averaged per reading, the ESP32 ADC is noisy
+const SAMPLES: u32 = 16;
+// Reference used when the eFuse holds no calibration
+const DEFAULT_VREF_MV: u32 = 1100;
+
+/// Battery voltage divider on an ADC1 pin, read with the legacy driver. Its channel is the
+/// only one the `adc` diagnostic command reads.
+pub struct BatteryAdc {
+    channel: u8,
+    chars: esp_adc_cal_characteristics_t,
+    ratio: f32,
+    empty_v: f32,
+    full_v: f32,
+}
+
+impl BatteryAdc {
+    pub fn new(gpio: i32, ratio: f32, empty_v: f32, full_v: f32) -> Result<Self> {
+        let channel = adc1_channel(gpio).ok_or_else(|| anyhow!("GPIO{gpio} is not an ADC1 pin"))?;
+        let mut chars = esp_adc_cal_characteristics_t::default();
+        unsafe {
+            adc1_config_width(adc_bits_width_t_ADC_WIDTH_BIT_12);
+            adc1_config_channel_atten(channel as u32, adc_atten_t_ADC_ATTEN_DB_11);
+            esp_adc_cal_characterize(
+                adc_unit_t_ADC_UNIT_1,
+                adc_atten_t_ADC_ATTEN_DB_11,
+                adc_bits_width_t_ADC_WIDTH_BIT_12,
+                DEFAULT_VREF_MV,
+                &mut chars,
+            );
+        }
+        Ok(Self {
+            channel,
+            chars,
+            ratio,
+            empty_v,
+            full_v,
+        })
+    }
+
+    pub fn read(&mut self) -> Result<BatterySample> {
+        let mut total = 0;
+        for _ in 0..SAMPLES {
+            let raw = unsafe { adc1_get_raw(self.channel as u32) };
+            if raw < 0 {
+                return Err(anyhow!("ADC1 channel {} read failed", self.channel));
+            }
+            total += raw as u32;
+        }
+        let pin_mv = unsafe { esp_adc_cal_raw_to_voltage(total / SAMPLES, &self.chars) };
+        Ok(BatterySample::from_pin_mv(
+            pin_mv,
+            self.ratio,
+            self.empty_v,
+            self.full_v,
+        ))
+    }
+}
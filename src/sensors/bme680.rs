@@ -0,0 +1,57 @@
+use super::env::{EnvData, EnvironmentSensor};
+use super::tca9548a::{mux_select, TcaMux};
+use anyhow::{anyhow, bail, Result};
+use bosch_bme680::{Bme680, Configuration, DeviceAddress};
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_idf_svc::hal::{delay::Ets, i2c::I2cDriver};
+use std::cell::RefCell;
+
+// Initial guess used by the heater before the first measurement
+const AMBIENT_TEMPERATURE_C: i8 = 20;
+
+/// BME680 on the shared I2C bus.
+pub struct Bme680Sensor<'a> {
+    bme: Bme680<RefCellDevice<'a, I2cDriver<'a>>, Ets>,
+}
+
+impl<'a> Bme680Sensor<'a> {
+    /// `address` is 0x76 (SDO to ground) or 0x77, `mux_channel` only matters with the mux.
+    pub fn new(
+        i2c_bus: &'a RefCell<I2cDriver<'a>>,
+        mux: &mut Option<TcaMux>,
+        address: u8,
+        mux_channel: u8,
+    ) -> Result<Self> {
+        let address = match address {
+            0x76 => DeviceAddress::Primary,
+            0x77 => DeviceAddress::Secondary,
+            _ => bail!("invalid BME680 address 0x{address:02X}"),
+        };
+        mux_select(mux, mux_channel);
+        let bme = Bme680::new(
+            RefCellDevice::new(i2c_bus),
+            address,
+            Ets,
+            &Configuration::default(),
+            AMBIENT_TEMPERATURE_C,
+        )
+        .map_err(|e| anyhow!("BME680 init failed: {e:?}"))?;
+
+        Ok(Self { bme })
+    }
+}
+
+impl EnvironmentSensor for Bme680Sensor<'_> {
+    fn measure(&mut self) -> Result<EnvData> {
+        let data = self
+            .bme
+            .measure()
+            .map_err(|e| anyhow!("BME680 measure failed: {e:?}"))?;
+        Ok(EnvData {
+            temperature: data.temperature,
+            humidity: data.humidity,
+            pressure: Some(data.pressure),
+            gas_resistance: data.gas_resistance,
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use super::env::{EnvData, EnvironmentSensor};
+use crate::dht::*;
+use anyhow::{anyhow, bail, Result};
+use esp_idf_svc::hal::{
+    delay::Ets,
+    gpio::{AnyIOPin, InputOutput, PinDriver, Pull},
+    interrupt,
+};
+use esp_idf_svc::sys::esp_timer_get_time;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Readings are refreshed in the background, failures are retried after the minimum interval
+const POLL_PERIOD: Duration = Duration::from_secs(10);
+const STALE_AFTER: Duration = Duration::from_secs(30);
+// Longest level of the protocol is the 80 us response, anything above means a lost edge
+const EDGE_TIMEOUT_US: i64 = 200;
+// High pulses longer than this are 1 bits (26-28 us for 0, 70 us for 1)
+const BIT_THRESHOLD_US: i64 = 48;
+
+type Latest = Arc<Mutex<Option<(Instant, EnvData)>>>;
+
+/// DHT11/DHT22 on a single GPIO, bit-banged from its own thread.
+pub struct DhtSensor {
+    latest: Latest,
+}
+
+impl DhtSensor {
+    pub fn start(pin: AnyIOPin, kind: DhtKind) -> Result<Self> {
+        let mut pin = PinDriver::input_output_od(pin)?;
+        pin.set_pull(Pull::Up)?;
+        pin.set_high()?;
+        let latest: Latest = Arc::new(Mutex::new(None));
+
+        let shared = latest.clone();
+        std::thread::Builder::new()
+            .stack_size(3072)
+            .spawn(move || loop {
+                match read_frame(&mut pin, kind).and_then(|frame| {
+                    kind.decode(frame)
+                        .ok_or_else(|| anyhow!("checksum mismatch"))
+                }) {
+                    Ok((temperature, humidity)) => {
+                        let data = EnvData {
+                            temperature,
+                            humidity,
+                            pressure: None,
+                            gas_resistance: None,
+                        };
+                        *shared.lock().unwrap() = Some((Instant::now(), data));
+                        std::thread::sleep(POLL_PERIOD);
+                    }
+                    Err(e) => {
+                        log::debug!("DHT read failed, retrying: {e}");
+                        std::thread::sleep(DHT_MIN_INTERVAL);
+                    }
+                }
+            })?;
+
+        Ok(Self { latest })
+    }
+}
+
+impl EnvironmentSensor for DhtSensor {
+    fn measure(&mut self) -> Result<EnvData> {
+        match *self.latest.lock().unwrap() {
+            Some((at, data)) if at.elapsed() < STALE_AFTER => Ok(data),
+            _ => bail!("no valid DHT reading"),
+        }
+    }
+}
+
+fn read_frame(pin: &mut PinDriver<AnyIOPin, InputOutput>, kind: DhtKind) -> Result<[u8; 5]> {
+    pin.set_low()?;
+    std::thread::sleep(kind.start_signal());
+
+    // Bits are told apart by pulse width, preemption during the 5 ms transfer would corrupt it
+    interrupt::free(|| {
+        pin.set_high()?;
+        Ets::delay_us(30);
+        // Response: 80 us low, 80 us high, then the first bit
+        wait_level(pin, false)?;
+        wait_level(pin, true)?;
+        wait_level(pin, false)?;
+
+        let mut frame = [0u8; 5];
+        for bit in 0..40 {
+            wait_level(pin, true)?;
+            let high = wait_level(pin, false)?;
+            if high > BIT_THRESHOLD_US {
+                frame[bit / 8] |= 0x80 >> (bit % 8);
+            }
+        }
+        Ok(frame)
+    })
+}
+
+// Waits for the line to reach `high`, returns how long it took in microseconds
+fn wait_level(pin: &PinDriver<AnyIOPin, InputOutput>, high: bool) -> Result<i64> {
+    let start = unsafe { esp_timer_get_time() };
+    loop {
+        let elapsed = unsafe { esp_timer_get_time() } - start;
+        if pin.is_high() == high {
+            return Ok(elapsed);
+        }
+        if elapsed > EDGE_TIMEOUT_US {
+            bail!(
+                "DHT timeout waiting for the line to go {}",
+                if high { "high" } else { "low" }
+            );
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use super::{tca9548a::TcaMux, Measurement, Sensor};
+use anyhow::Result;
+
+/// Measurement of an environment sensor, pressure and gas are absent on humidity-only ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EnvData {
+    pub temperature: f32,
+    pub humidity: f32,
+    pub pressure: Option<f32>,
+    pub gas_resistance: Option<f32>,
+}
+
+/// A temperature and humidity sensor usable as an environment source.
+pub trait EnvironmentSensor {
+    fn measure(&mut self) -> Result<EnvData>;
+}
+
+/// Calibration offsets added to the raw readings.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EnvOffsets {
+    pub temperature: f32,
+    pub humidity: f32,
+    pub pressure: f32,
+}
+
+/// One configured sensor, named after what it measures (e.g. "outdoor", "enclosure").
+pub struct EnvSensor<'a> {
+    name: &'static str,
+    // Only for I2C sensors behind the mux
+    mux: Option<(TcaMux<'a>, u8)>,
+    offsets: EnvOffsets,
+    sensor: Box<dyn EnvironmentSensor + 'a>,
+}
+
+impl<'a> EnvSensor<'a> {
+    pub fn new(
+        name: &'static str,
+        offsets: EnvOffsets,
+        sensor: Box<dyn EnvironmentSensor + 'a>,
+    ) -> Self {
+        Self {
+            name,
+            mux: None,
+            offsets,
+            sensor,
+        }
+    }
+
+    /// Selects `channel` of the mux before each measurement, a no-op without a mux.
+    pub fn behind_mux(mut self, mux: Option<TcaMux<'a>>, channel: u8) -> Self {
+        self.mux = mux.map(|mux| (mux, channel));
+        self
+    }
+}
+
+impl Sensor for EnvSensor<'_> {
+    fn id(&self) -> &str {
+        self.name
+    }
+
+    /// Offset corrected measurement.
+    fn read(&mut self) -> Result<Measurement> {
+        if let Some((mux, channel)) = self.mux.as_mut() {
+            mux.select_channel(*channel)?;
+        }
+        let data = self.sensor.measure()?;
+        Ok(Measurement::Environment(EnvData {
+            temperature: data.temperature + self.offsets.temperature,
+            humidity: data.humidity + self.offsets.humidity,
+            pressure: data.pressure.map(|p| p + self.offsets.pressure),
+            gas_resistance: data.gas_resistance,
+        }))
+    }
+}
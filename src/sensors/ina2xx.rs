@@ -0,0 +1,81 @@
+use crate::power::*;
+use anyhow::{anyhow, bail, Result};
+use embedded_hal::i2c::I2c;
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_idf_svc::hal::i2c::I2cDriver;
+
+/// Voltage, current and power of one shunt, whichever device measures it.
+pub trait PowerMonitor {
+    fn read(&mut self) -> Result<PowerSample>;
+}
+
+/// INA219 or INA226 current/power monitor on a shunt resistor.
+pub struct Ina2xx<'a> {
+    i2c: RefCellDevice<'a, I2cDriver<'a>>,
+    addr: u8,
+    model: InaModel,
+    calibration: u16,
+    current_lsb: f32,
+}
+
+impl<'a> Ina2xx<'a> {
+    pub fn new(
+        i2c: RefCellDevice<'a, I2cDriver<'a>>,
+        addr: u8,
+        model: InaModel,
+        shunt_ohm: f32,
+        max_current_a: f32,
+    ) -> Result<Self> {
+        let (calibration, current_lsb) = model.calibration(shunt_ohm, max_current_a);
+        let mut ina = Self {
+            i2c,
+            addr,
+            model,
+            calibration,
+            current_lsb,
+        };
+        ina.write(REG_CONFIG, model.config_word())?;
+        ina.write(REG_CALIBRATION, calibration)?;
+        Ok(ina)
+    }
+
+    fn write(&mut self, reg: u8, value: u16) -> Result<()> {
+        let [hi, lo] = value.to_be_bytes();
+        self.i2c
+            .write(self.addr, &[reg, hi, lo])
+            .map_err(|e| anyhow!("INA write failed: {e:?}"))
+    }
+
+    fn read_reg(&mut self, reg: u8) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.i2c
+            .write_read(self.addr, &[reg], &mut buf)
+            .map_err(|e| anyhow!("INA read failed: {e:?}"))?;
+        Ok(u16::from_be_bytes(buf))
+    }
+}
+
+impl PowerMonitor for Ina2xx<'_> {
+    fn read(&mut self) -> Result<PowerSample> {
+        // A brownout resets the device, current and power read 0 until calibrated again
+        if self.read_reg(REG_CALIBRATION)? != self.calibration {
+            log::warn!("INA at 0x{:02X} lost its calibration", self.addr);
+            self.write(REG_CONFIG, self.model.config_word())?;
+            self.write(REG_CALIBRATION, self.calibration)?;
+        }
+        let bus = self.read_reg(REG_BUS_VOLTAGE)?;
+        let overflow = match self.model {
+            InaModel::Ina226 => self.model.overflowed(self.read_reg(REG_MASK_ENABLE)?),
+            InaModel::Ina219 => false,
+        };
+        let Some(voltage) = self.model.bus_voltage_v(bus).filter(|_| !overflow) else {
+            bail!(
+                "INA at 0x{:02X} overflowed, current above the configured range",
+                self.addr
+            );
+        };
+        let current = self.read_reg(REG_CURRENT)?;
+        let power = self.read_reg(REG_POWER)?;
+        Ok(self.model.sample(voltage, current, power, self.current_lsb))
+    }
+}
@@ -0,0 +1,70 @@
+use super::ina2xx::PowerMonitor;
+use crate::power::*;
+use anyhow::{anyhow, bail, Result};
+use embedded_hal::i2c::I2c;
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_idf_svc::hal::i2c::I2cDriver;
+
+/// One channel of an INA3221 triple monitor. Each channel in use gets its own instance on the
+/// same device.
+pub struct Ina3221Channel<'a> {
+    i2c: RefCellDevice<'a, I2cDriver<'a>>,
+    addr: u8,
+    channel: u8,
+    shunt_ohm: f32,
+}
+
+impl<'a> Ina3221Channel<'a> {
+    /// `channel` is 1 to 3.
+    pub fn new(
+        i2c: RefCellDevice<'a, I2cDriver<'a>>,
+        addr: u8,
+        channel: u8,
+        shunt_ohm: f32,
+    ) -> Result<Self> {
+        if !(1..=3).contains(&channel) {
+            bail!("INA3221 channel {channel} out of 1 to 3");
+        }
+        let mut ina = Self {
+            i2c,
+            addr,
+            channel,
+            shunt_ohm,
+        };
+        let id = ina.read_reg(INA3221_REG_MANUFACTURER_ID)?;
+        if id != INA3221_MANUFACTURER_ID {
+            bail!("no INA3221 at 0x{addr:02X}, manufacturer id 0x{id:04X}");
+        }
+        ina.write(REG_CONFIG, INA3221_CONFIG)?;
+        Ok(ina)
+    }
+
+    fn write(&mut self, reg: u8, value: u16) -> Result<()> {
+        let [hi, lo] = value.to_be_bytes();
+        self.i2c
+            .write(self.addr, &[reg, hi, lo])
+            .map_err(|e| anyhow!("INA3221 write failed: {e:?}"))
+    }
+
+    fn read_reg(&mut self, reg: u8) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.i2c
+            .write_read(self.addr, &[reg], &mut buf)
+            .map_err(|e| anyhow!("INA3221 read failed: {e:?}"))?;
+        Ok(u16::from_be_bytes(buf))
+    }
+}
+
+impl PowerMonitor for Ina3221Channel<'_> {
+    fn read(&mut self) -> Result<PowerSample> {
+        // A brownout restores the default config, which averages a single sample
+        if self.read_reg(REG_CONFIG)? != INA3221_CONFIG {
+            log::warn!("INA3221 at 0x{:02X} lost its config", self.addr);
+            self.write(REG_CONFIG, INA3221_CONFIG)?;
+        }
+        let (shunt_reg, bus_reg) = ina3221_registers(self.channel);
+        let shunt = self.read_reg(shunt_reg)?;
+        let bus = self.read_reg(bus_reg)?;
+        Ok(ina3221_sample(shunt, bus, self.shunt_ohm))
+    }
+}
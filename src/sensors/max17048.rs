@@ -0,0 +1,82 @@
+use crate::power::*;
+use anyhow::{anyhow, Result};
+use embedded_hal::i2c::I2c;
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_idf_svc::hal::i2c::I2cDriver;
+
+/// MAX17048/MAX17049 ModelGauge fuel gauge, the cell model is the one built into the chip.
+pub struct Max17048<'a> {
+    i2c: RefCellDevice<'a, I2cDriver<'a>>,
+    cells: u8,
+    threshold_percent: u8,
+}
+
+impl<'a> Max17048<'a> {
+    /// `cells` is 1 for the MAX17048 and 2 for the MAX17049.
+    pub fn new(
+        i2c: RefCellDevice<'a, I2cDriver<'a>>,
+        cells: u8,
+        threshold_percent: u8,
+    ) -> Result<Self> {
+        let mut gauge = Self {
+            i2c,
+            cells,
+            threshold_percent,
+        };
+        let version = gauge.read_reg(MAX17048_REG_VERSION)?;
+        log::info!("MAX1704x fuel gauge version 0x{version:04X}");
+        gauge.configure()?;
+        Ok(gauge)
+    }
+
+    /// Reads the gauge and acknowledges a pending alert, so the next crossing raises it again.
+    pub fn read(&mut self) -> Result<FuelGaugeSample> {
+        let status = self.read_reg(MAX17048_REG_STATUS)?;
+        // A power on reset restores the default 4 % threshold
+        if status & MAX17048_STATUS_RI != 0 {
+            log::warn!("MAX1704x was reset, configuring it again");
+            self.configure()?;
+        }
+        let config = self.read_reg(MAX17048_REG_CONFIG)?;
+        let mut sample = FuelGaugeSample::from_registers(
+            self.read_reg(MAX17048_REG_VCELL)?,
+            self.read_reg(MAX17048_REG_SOC)?,
+            self.read_reg(MAX17048_REG_CRATE)?,
+            self.cells,
+        );
+        sample.alert = max17048_alerted(config, status);
+        if sample.alert {
+            self.write(MAX17048_REG_STATUS, max17048_clear_status(status))?;
+            self.write(
+                MAX17048_REG_CONFIG,
+                max17048_config(config, self.threshold_percent),
+            )?;
+        }
+        Ok(sample)
+    }
+
+    fn configure(&mut self) -> Result<()> {
+        let config = self.read_reg(MAX17048_REG_CONFIG)?;
+        self.write(
+            MAX17048_REG_CONFIG,
+            max17048_config(config, self.threshold_percent),
+        )?;
+        let status = self.read_reg(MAX17048_REG_STATUS)?;
+        self.write(MAX17048_REG_STATUS, max17048_clear_status(status))
+    }
+
+    fn write(&mut self, reg: u8, value: u16) -> Result<()> {
+        let [hi, lo] = value.to_be_bytes();
+        self.i2c
+            .write(MAX17048_ADDR, &[reg, hi, lo])
+            .map_err(|e| anyhow!("MAX1704x write failed: {e:?}"))
+    }
+
+    fn read_reg(&mut self, reg: u8) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.i2c
+            .write_read(MAX17048_ADDR, &[reg], &mut buf)
+            .map_err(|e| anyhow!("MAX1704x read failed: {e:?}"))?;
+        Ok(u16::from_be_bytes(buf))
+    }
+}
@@ -0,0 +1,123 @@
+pub mod angle;
+pub mod as5048a;
+#[cfg(feature = "as5600")]
+pub mod as5600;
+pub mod battery_adc;
+#[cfg(feature = "bme680")]
+pub mod bme680;
+pub mod dht;
+pub mod env;
+pub mod ina2xx;
+pub mod ina3221;
+pub mod max17048;
+pub mod mt6701;
+pub mod pulse;
+pub mod tca9548a;
+
+use crate::time::unix_time_ms;
+use anyhow::{bail, Result};
+use env::EnvData;
+use std::fmt::Display;
+
+// Failed reads in a row before a sensor is reported as failing
+const FAILING_AFTER: u32 = 3;
+
+/// What a sensor read returns, one variant per kind of sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Measurement {
+    Environment(EnvData),
+    /// Degrees from north, vane offset applied
+    WindAngle(f32),
+    /// km/h, averaged since the previous read
+    WindSpeed(f32),
+    /// Sensors measuring both, in km/h and degrees from north
+    Wind {
+        speed_kmh: f32,
+        direction_deg: f32,
+    },
+    /// Rain gauge tips since the last publish
+    RainTips(u32),
+}
+
+/// Common interface of the station sensors, so they can be polled alike whatever the bus.
+pub trait Sensor {
+    /// Name used in topics and logs
+    fn id(&self) -> &str;
+    fn read(&mut self) -> Result<Measurement>;
+}
+
+/// Read outcomes of a sensor, published as diagnostics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SensorHealth {
+    /// Failed reads since boot
+    pub errors: u32,
+    /// Failed reads since the last good one
+    pub consecutive_errors: u32,
+    /// Unix time of the last good read, in ms
+    pub last_success_ms: Option<u64>,
+}
+
+impl SensorHealth {
+    /// Counts the outcome of a read, a failure is logged.
+    pub fn record<T, E: Display>(&mut self, id: &str, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.consecutive_errors = 0;
+                self.last_success_ms = Some(unix_time_ms());
+                Some(value)
+            }
+            Err(e) => {
+                self.errors += 1;
+                self.consecutive_errors += 1;
+                log::error!("Failed to get {id} readings: {e}");
+                None
+            }
+        }
+    }
+
+    /// "ok", "failing" after `FAILING_AFTER` failed reads in a row, or "unread" before the
+    /// first read.
+    pub fn status(&self) -> &'static str {
+        if self.consecutive_errors >= FAILING_AFTER {
+            "failing"
+        } else if self.last_success_ms.is_none() && self.errors == 0 {
+            "unread"
+        } else {
+            "ok"
+        }
+    }
+}
+
+/// A sensor with its health, published as diagnostics.
+pub struct TrackedSensor<'a> {
+    sensor: Box<dyn Sensor + 'a>,
+    pub health: SensorHealth,
+}
+
+impl<'a> TrackedSensor<'a> {
+    pub fn new(sensor: Box<dyn Sensor + 'a>) -> Self {
+        Self {
+            sensor,
+            health: SensorHealth::default(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        self.sensor.id()
+    }
+
+    /// None when the sensor could not be read, the failure is logged and counted. A NaN or
+    /// infinite temperature counts as a failed read.
+    pub fn read(&mut self) -> Option<Measurement> {
+        let result = self
+            .sensor
+            .read()
+            .and_then(|measurement| match measurement {
+                Measurement::Environment(data) if !data.temperature.is_finite() => {
+                    bail!("temperature {} is not a number", data.temperature)
+                }
+                measurement => Ok(measurement),
+            });
+        self.health.record(self.sensor.id(), result)
+    }
+}
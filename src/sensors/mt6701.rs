@@ -0,0 +1,42 @@
+use super::angle::{AngleSensor, AngleStatus};
+use crate::compute::degrees_from_counts;
+use anyhow::{anyhow, Result};
+use embedded_hal::i2c::I2c;
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_idf_svc::hal::i2c::I2cDriver;
+
+pub const MT6701_ADDR: u8 = 0x06;
+// Angle[13:6], then Angle[5:0] in the upper bits of the next register
+const REG_ANGLE_H: u8 = 0x03;
+
+/// MT6701, 14 bit over I2C.
+///
+/// The I2C interface does not report the field strength, the status only tells whether the
+/// sensor answers.
+pub struct Mt6701<'a> {
+    i2c: RefCellDevice<'a, I2cDriver<'a>>,
+}
+
+impl<'a> Mt6701<'a> {
+    pub fn new(i2c: RefCellDevice<'a, I2cDriver<'a>>) -> Self {
+        Self { i2c }
+    }
+}
+
+impl AngleSensor for Mt6701<'_> {
+    fn raw_angle(&mut self) -> Result<f32> {
+        let mut buf = [0u8; 2];
+        self.i2c
+            .write_read(MT6701_ADDR, &[REG_ANGLE_H], &mut buf)
+            .map_err(|e| anyhow!("MT6701 read failed: {e:?}"))?;
+        let raw = ((buf[0] as u16) << 6) | (buf[1] as u16 >> 2);
+        Ok(degrees_from_counts(raw, 14))
+    }
+
+    fn status(&mut self) -> AngleStatus {
+        match self.raw_angle() {
+            Ok(_) => AngleStatus::Ok,
+            Err(_) => AngleStatus::Error,
+        }
+    }
+}
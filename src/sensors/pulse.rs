@@ -0,0 +1,134 @@
+//! Pulse counted sensors: the tipping bucket rain gauge, counted by a GPIO interrupt, and
+//! the cup anemometer, counted by the PCNT peripheral.
+use super::{Measurement, Sensor};
+use crate::{
+    compute::{counter_delta, PulseDebounce},
+    platform::*,
+};
+use anyhow::Result;
+use esp_idf_svc::hal::{
+    gpio::{AnyInputPin, InputPin},
+    pcnt::*,
+    peripheral::Peripheral,
+};
+use std::{sync::atomic::Ordering, time::Instant};
+
+// The counter wraps to 0 there, it is polled far more often than that many rotations
+const PCNT_LIMIT: i16 = i16::MAX;
+// Pulses shorter than this many APB cycles (12.5 ns each) are glitches, the hardware maximum
+const PCNT_FILTER_CYCLES: u16 = 1023;
+
+/// Tipping bucket rain gauge. The count is only reset once the rain is published.
+pub struct RainGauge;
+
+impl RainGauge {
+    pub fn tips(&self) -> u32 {
+        RAIN_COUNT.load(Ordering::Relaxed)
+    }
+}
+
+impl Sensor for RainGauge {
+    fn id(&self) -> &str {
+        "rain"
+    }
+
+    fn read(&mut self) -> Result<Measurement> {
+        Ok(Measurement::RainTips(self.tips()))
+    }
+}
+
+/// Cup anemometer rotations counted in hardware, so none is missed while the main loop is
+/// busy with I2C or MQTT. The pulse pin gets its pull-up from the PCNT driver.
+pub struct RotationCounter<'d> {
+    pcnt: PcntDriver<'d>,
+    last: i16,
+    debounce: PulseDebounce,
+    last_poll: Instant,
+}
+
+impl<'d> RotationCounter<'d> {
+    pub fn new<PCNT: Pcnt>(
+        pcnt: impl Peripheral<P = PCNT> + 'd,
+        pin: impl Peripheral<P = impl InputPin> + 'd,
+        debounce_ms: u32,
+    ) -> Result<Self> {
+        let mut pcnt = PcntDriver::new(
+            pcnt,
+            Some(pin),
+            Option::<AnyInputPin>::None,
+            Option::<AnyInputPin>::None,
+            Option::<AnyInputPin>::None,
+        )?;
+        // Rising edges only, as the interrupt did
+        pcnt.channel_config(
+            PcntChannel::Channel0,
+            PinIndex::Pin0,
+            PinIndex::Pin1,
+            &PcntChannelConfig {
+                lctrl_mode: PcntControlMode::Keep,
+                hctrl_mode: PcntControlMode::Keep,
+                pos_mode: PcntCountMode::Increment,
+                neg_mode: PcntCountMode::Hold,
+                counter_h_lim: PCNT_LIMIT,
+                counter_l_lim: 0,
+            },
+        )?;
+        pcnt.set_filter_value(PCNT_FILTER_CYCLES)?;
+        pcnt.filter_enable()?;
+        pcnt.counter_pause()?;
+        pcnt.counter_clear()?;
+        pcnt.counter_resume()?;
+
+        Ok(Self {
+            pcnt,
+            last: 0,
+            debounce: PulseDebounce::new(debounce_ms),
+            last_poll: Instant::now(),
+        })
+    }
+
+    /// Adds the rotations counted since the previous poll to `ROTATION_COUNT`, without the
+    /// contact bounce.
+    pub fn poll(&mut self) {
+        match self.pcnt.get_counter_value() {
+            Ok(value) => {
+                let pulses = counter_delta(self.last, value, PCNT_LIMIT);
+                let elapsed_ms = self.last_poll.elapsed().as_millis() as u32;
+                self.last = value;
+                self.last_poll = Instant::now();
+                add_rotations(self.debounce.filter(pulses, elapsed_ms));
+            }
+            Err(e) => log::error!("fail reading the anemometer pulse counter: {e}"),
+        }
+    }
+}
+
+/// Cup anemometer, the speed is averaged over the time since the previous read.
+pub struct ReedAnemometer {
+    kmh_per_hz: f32,
+    since: Instant,
+}
+
+impl ReedAnemometer {
+    pub fn new(kmh_per_hz: f32) -> Self {
+        Self {
+            kmh_per_hz,
+            since: Instant::now(),
+        }
+    }
+
+    /// Also resets the rotation count the gusts are sampled from.
+    pub fn speed_kmh(&mut self) -> f32 {
+        measure_wind_speed(&mut self.since, self.kmh_per_hz)
+    }
+}
+
+impl Sensor for ReedAnemometer {
+    fn id(&self) -> &str {
+        "anemometer"
+    }
+
+    fn read(&mut self) -> Result<Measurement> {
+        Ok(Measurement::WindSpeed(self.speed_kmh()))
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::{bail, Result};
+use embedded_hal::i2c::I2c;
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_idf_svc::hal::i2c::I2cDriver;
+
+// Address with A0-A2 tied to ground
+pub const TCA9548A_ADDR: u8 = 0x70;
+const CHANNEL_COUNT: u8 = 8;
+
+/// TCA9548A I2C multiplexer, used to put sensors sharing an address on the same bus.
+pub struct TcaMux<'a> {
+    i2c: RefCellDevice<'a, I2cDriver<'a>>,
+}
+
+impl<'a> TcaMux<'a> {
+    pub fn new(i2c: RefCellDevice<'a, I2cDriver<'a>>) -> Self {
+        Self { i2c }
+    }
+
+    /// Route the downstream bus `ch` (0-7) to the main bus, disconnecting the others.
+    pub fn select_channel(&mut self, ch: u8) -> Result<()> {
+        if ch >= CHANNEL_COUNT {
+            bail!("invalid TCA9548A channel {ch}");
+        }
+        self.write_control(1 << ch)
+    }
+
+    pub fn disable_all(&mut self) -> Result<()> {
+        self.write_control(0)
+    }
+
+    fn write_control(&mut self, value: u8) -> Result<()> {
+        self.i2c
+            .write(TCA9548A_ADDR, &[value])
+            .map_err(|e| anyhow::anyhow!("TCA9548A write failed: {e:?}"))
+    }
+}
+
+// Select the channel of a sensor when the mux is in use, a no-op otherwise
+pub fn mux_select(mux: &mut Option<TcaMux>, ch: u8) {
+    if let Some(mux) = mux {
+        mux.select_channel(ch)
+            .unwrap_or_else(|e| log::error!("Fail selecting mux channel: {e}"));
+    }
+}
@@ -0,0 +1,841 @@
+use crate::{
+    compute::wind_speed_kmh,
+    forecast::PressureTendency,
+    reading::{RainTotals, Trends},
+    CircularBuffer,
+};
+use core::time::Duration;
+
+/// Period at which rain is sampled into the precipitation accumulation.
+pub const PRECIP_SAMPLE_PERIOD: Duration = Duration::from_secs(10);
+// Number of 10 s samples folded into one 10 min bucket
+const SAMPLES_PER_BUCKET: u32 = 60;
+// Tip timestamps kept per hour, beyond that tips are only counted
+const MAX_TIP_TIMESTAMPS: usize = 256;
+const HOUR_MS: u64 = 3_600_000;
+const PEAK_WINDOW_MS: u64 = 5 * 60_000;
+// One sample per environment publish, several hours across deep sleep cycles
+const TREND_SAMPLES: usize = 64;
+const MIN_TREND_SAMPLES: usize = 3;
+
+pub const ROSE_SECTORS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+
+/// Rolling precipitation totals over the standard meteorological windows.
+///
+/// The last hour is kept at 10 s resolution, the last day at 10 min resolution. Totals
+/// are computed on demand by summing the buckets covering the window.
+pub struct PrecipitationAccumulation {
+    ring_1h: CircularBuffer<f32, 360>,
+    ring_24h: CircularBuffer<f32, 144>,
+    current_sample: f32,
+    current_bucket: f32,
+    samples_in_bucket: u32,
+    today: f32,
+    // Local day (days since CE) the daily total started on
+    today_day: i32,
+}
+
+impl PrecipitationAccumulation {
+    pub const fn new() -> Self {
+        Self {
+            ring_1h: CircularBuffer::new(0.0),
+            ring_24h: CircularBuffer::new(0.0),
+            current_sample: 0.0,
+            current_bucket: 0.0,
+            samples_in_bucket: 0,
+            today: 0.0,
+            today_day: 0,
+        }
+    }
+
+    /// Add rain (in mm) to the sample currently being recorded.
+    pub fn add(&mut self, rain_mm: f32) {
+        self.current_sample += rain_mm;
+        self.today += rain_mm;
+    }
+
+    /// Restart the daily total, once per local `day`. Returns whether a reset happened.
+    pub fn reset_daily(&mut self, day: i32) -> bool {
+        if day == self.today_day {
+            return false;
+        }
+        self.today = 0.0;
+        self.today_day = day;
+        true
+    }
+
+    pub fn total_today(&self) -> f32 {
+        self.today
+    }
+
+    /// The totals carried by the consolidated reading.
+    pub fn totals(&self) -> RainTotals {
+        RainTotals {
+            last_1h_mm: self.total_1h(),
+            last_24h_mm: self.total_24h(),
+            today_mm: self.total_today(),
+        }
+    }
+
+    /// Close the current 10 s sample. Must be called every `PRECIP_SAMPLE_PERIOD`.
+    pub fn tick(&mut self) {
+        self.ring_1h.push(self.current_sample);
+        self.current_bucket += self.current_sample;
+        self.current_sample = 0.0;
+        self.samples_in_bucket += 1;
+
+        if self.samples_in_bucket >= SAMPLES_PER_BUCKET {
+            self.ring_24h.push(self.current_bucket);
+            self.current_bucket = 0.0;
+            self.samples_in_bucket = 0;
+        }
+    }
+
+    /// Advance the windows over a period where no rain was recorded (e.g. deep sleep).
+    pub fn skip(&mut self, elapsed: Duration) {
+        let samples = elapsed.as_secs() / PRECIP_SAMPLE_PERIOD.as_secs();
+        for _ in 0..samples.min(24 * 360) {
+            self.tick();
+        }
+    }
+
+    pub fn total_1h(&self) -> f32 {
+        self.current_sample + self.ring_1h.iter().sum::<f32>()
+    }
+
+    pub fn total_3h(&self) -> f32 {
+        self.total_hours(3)
+    }
+
+    pub fn total_6h(&self) -> f32 {
+        self.total_hours(6)
+    }
+
+    pub fn total_12h(&self) -> f32 {
+        self.total_hours(12)
+    }
+
+    pub fn total_24h(&self) -> f32 {
+        self.total_hours(24)
+    }
+
+    // The bucket being filled counts as one of the window buckets so the window never
+    // exceeds the requested length.
+    fn total_hours(&self, hours: usize) -> f32 {
+        let full_buckets = hours * 6 - 1;
+        self.current_sample
+            + self.current_bucket
+            + self.ring_24h.iter().rev().take(full_buckets).sum::<f32>()
+    }
+}
+
+impl Default for PrecipitationAccumulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Mean wind direction computed from unit vectors, so that 350° and 10° average to 0°.
+#[derive(Default)]
+pub struct WindVectorAverage {
+    sum_sin: f32,
+    sum_cos: f32,
+    count: u32,
+}
+
+impl WindVectorAverage {
+    pub fn add(&mut self, angle_deg: f32) {
+        let rad = angle_deg.to_radians();
+        self.sum_sin += libm::sinf(rad);
+        self.sum_cos += libm::cosf(rad);
+        self.count += 1;
+    }
+
+    /// Mean direction in degrees, `None` without samples or when they cancel out.
+    pub fn mean(&self) -> Option<f32> {
+        if self.count == 0 || (self.sum_sin == 0.0 && self.sum_cos == 0.0) {
+            return None;
+        }
+        let mean = libm::atan2f(self.sum_sin, self.sum_cos).to_degrees();
+        Some(if mean < 0.0 { mean + 360.0 } else { mean })
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Highest wind speed over the short samples of a publish window.
+#[derive(Default)]
+pub struct GustTracker {
+    max_kmh: Option<f32>,
+}
+
+impl GustTracker {
+    /// One short sample, the rotations counted over `elapsed_s`.
+    pub fn add(&mut self, rotations: u32, elapsed_s: f32, kmh_per_hz: f32) {
+        let speed = wind_speed_kmh(rotations, elapsed_s, kmh_per_hz);
+        self.max_kmh = Some(self.max_kmh.map_or(speed, |max| max.max(speed)));
+    }
+
+    /// Gust of the window ending now, never below its mean speed. Starts the next window.
+    pub fn take(&mut self, mean_kmh: f32) -> f32 {
+        self.max_kmh
+            .take()
+            .map_or(mean_kmh, |max| max.max(mean_kmh))
+    }
+}
+
+/// Timestamps (unix ms) of the rain tips of the current hour.
+///
+/// The log is bounded: during a cloudburst, tips past `MAX_TIP_TIMESTAMPS` are only counted
+/// and the hour summary is flagged as truncated.
+pub struct RainTipLog {
+    tips: [u64; MAX_TIP_TIMESTAMPS],
+    len: usize,
+    untracked: u32,
+    // Hour being logged, None until the first roll
+    hour: Option<u64>,
+}
+
+/// Rain statistics of one closed hour.
+pub struct RainHourSummary {
+    pub hour_start_ms: u64,
+    pub tips: u32,
+    pub peak_5min_mm_h: f32,
+    pub truncated: bool,
+}
+
+impl RainTipLog {
+    pub const fn new() -> Self {
+        Self {
+            tips: [0; MAX_TIP_TIMESTAMPS],
+            len: 0,
+            untracked: 0,
+            hour: None,
+        }
+    }
+
+    pub fn record(&mut self, timestamp_ms: u64) {
+        if self.len < MAX_TIP_TIMESTAMPS {
+            self.tips[self.len] = timestamp_ms;
+            self.len += 1;
+        } else {
+            self.untracked += 1;
+        }
+    }
+
+    /// Close the hour once `now_ms` is past it, returning its summary. `now_ms` must come from
+    /// a synced clock.
+    pub fn roll_hour(&mut self, now_ms: u64, mm_per_tip: f32) -> Option<RainHourSummary> {
+        let hour = now_ms / HOUR_MS;
+        // None on the first call since power on, there is no complete hour yet
+        let previous = self
+            .hour
+            .replace(hour)
+            .filter(|&previous| previous != hour)?;
+
+        let summary = RainHourSummary {
+            hour_start_ms: previous * HOUR_MS,
+            tips: self.len as u32 + self.untracked,
+            peak_5min_mm_h: self.peak_rate_mm_h(mm_per_tip),
+            truncated: self.untracked > 0,
+        };
+        self.len = 0;
+        self.untracked = 0;
+        Some(summary)
+    }
+
+    // Highest tip count in any 5 min window starting on a tip, scaled to mm/h
+    fn peak_rate_mm_h(&self, mm_per_tip: f32) -> f32 {
+        let tips = &self.tips[..self.len];
+        let mut peak = 0;
+        let mut end = 0;
+        for (start, &t) in tips.iter().enumerate() {
+            end = end.max(start);
+            while end < tips.len() && tips[end] < t + PEAK_WINDOW_MS {
+                end += 1;
+            }
+            peak = peak.max(end - start);
+        }
+        peak as f32 * mm_per_tip * (HOUR_MS / PEAK_WINDOW_MS) as f32
+    }
+}
+
+impl Default for RainTipLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Time spent by the wind in each of the 16 direction sectors since local midnight.
+pub struct WindRose {
+    sector_s: [f32; 16],
+    speed_sum: [f32; 16],
+    calm_s: f32,
+    day: i32,
+    published_hour: u64,
+}
+
+impl WindRose {
+    pub const fn new() -> Self {
+        Self {
+            sector_s: [0.0; 16],
+            speed_sum: [0.0; 16],
+            calm_s: 0.0,
+            day: 0,
+            published_hour: 0,
+        }
+    }
+
+    /// Account `duration_s` of wind, calm periods (below `calm_kmh`) have no direction.
+    pub fn add(&mut self, direction_deg: f32, speed_kmh: f32, duration_s: f32, calm_kmh: f32) {
+        if speed_kmh < calm_kmh {
+            self.calm_s += duration_s;
+            return;
+        }
+        let sector = ((direction_deg + 11.25) / 22.5) as usize % 16;
+        self.sector_s[sector] += duration_s;
+        self.speed_sum[sector] += speed_kmh * duration_s;
+    }
+
+    fn total_s(&self) -> f32 {
+        self.calm_s + self.sector_s.iter().sum::<f32>()
+    }
+
+    /// Share of the observed time spent in `sector`, in percent.
+    pub fn occupancy_pct(&self, sector: usize) -> f32 {
+        percent(self.sector_s[sector], self.total_s())
+    }
+
+    pub fn calm_pct(&self) -> f32 {
+        percent(self.calm_s, self.total_s())
+    }
+
+    pub fn mean_speed_kmh(&self, sector: usize) -> f32 {
+        if self.sector_s[sector] == 0.0 {
+            return 0.0;
+        }
+        self.speed_sum[sector] / self.sector_s[sector]
+    }
+
+    /// Restart the statistics, once per local `day`. Returns whether a reset happened.
+    pub fn reset_daily(&mut self, day: i32) -> bool {
+        if day == self.day {
+            return false;
+        }
+        *self = Self {
+            day,
+            published_hour: self.published_hour,
+            ..Self::new()
+        };
+        true
+    }
+
+    /// True once per hour (unix time), when the rose should be published.
+    pub fn publish_due(&mut self, now_ms: u64) -> bool {
+        let hour = now_ms / HOUR_MS;
+        if hour == self.published_hour {
+            return false;
+        }
+        self.published_hour = hour;
+        true
+    }
+}
+
+impl Default for WindRose {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn percent(part: f32, total: f32) -> f32 {
+    if total == 0.0 {
+        return 0.0;
+    }
+    part * 100.0 / total
+}
+
+/// Running min/mean/max of one quantity.
+#[derive(Clone, Copy, Default)]
+pub struct MinMeanMax {
+    pub count: u32,
+    sum: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl MinMeanMax {
+    pub const fn new() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, value: f32) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum / self.count as f32
+    }
+}
+
+/// Lowest and highest value over the last 24 hours, kept as one min/max per clock hour so
+/// the window slides without storing every sample.
+#[derive(Clone, Copy)]
+pub struct Extremes24h {
+    // Clock hour of each slot, indexed by the hour modulo 24. 0 for an unused slot
+    hours: [u64; 24],
+    min: [f32; 24],
+    max: [f32; 24],
+}
+
+impl Extremes24h {
+    pub const fn new() -> Self {
+        Self {
+            hours: [0; 24],
+            min: [0.0; 24],
+            max: [0.0; 24],
+        }
+    }
+
+    pub fn add(&mut self, now_ms: u64, value: f32) {
+        if !value.is_finite() {
+            return;
+        }
+        let hour = now_ms / HOUR_MS + 1;
+        let slot = (hour % 24) as usize;
+        if self.hours[slot] != hour {
+            self.hours[slot] = hour;
+            self.min[slot] = value;
+            self.max[slot] = value;
+        }
+        self.min[slot] = self.min[slot].min(value);
+        self.max[slot] = self.max[slot].max(value);
+    }
+
+    /// Min and max of the values added over the last 24 hours, None without any.
+    pub fn min_max(&self, now_ms: u64) -> Option<(f32, f32)> {
+        let hour = now_ms / HOUR_MS + 1;
+        (0..24)
+            .filter(|&slot| self.hours[slot] != 0 && hour - self.hours[slot].min(hour) < 24)
+            .map(|slot| (self.min[slot], self.max[slot]))
+            .reduce(|(min, max), (slot_min, slot_max)| (min.min(slot_min), max.max(slot_max)))
+    }
+}
+
+impl Default for Extremes24h {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Statistics of one closed clock hour.
+pub struct HourlySummary {
+    pub hour_start_ms: u64,
+    // False when the station started collecting after the top of the hour
+    pub complete: bool,
+    pub temperature: MinMeanMax,
+    pub humidity: MinMeanMax,
+    pub pressure: MinMeanMax,
+    pub pressure_change: f32,
+    pub wind_speed: MinMeanMax,
+    pub dominant_direction: Option<f32>,
+    pub rain_mm: f32,
+}
+
+/// Aggregates the samples of the current clock hour.
+pub struct HourlyAggregator {
+    hour: u64,
+    complete: bool,
+    temperature: MinMeanMax,
+    humidity: MinMeanMax,
+    pressure: MinMeanMax,
+    first_pressure: f32,
+    last_pressure: f32,
+    wind_speed: MinMeanMax,
+    sum_sin: f32,
+    sum_cos: f32,
+    rain_mm: f32,
+}
+
+impl HourlyAggregator {
+    pub const fn new() -> Self {
+        Self {
+            hour: 0,
+            complete: false,
+            temperature: MinMeanMax::new(),
+            humidity: MinMeanMax::new(),
+            pressure: MinMeanMax::new(),
+            first_pressure: 0.0,
+            last_pressure: 0.0,
+            wind_speed: MinMeanMax::new(),
+            sum_sin: 0.0,
+            sum_cos: 0.0,
+            rain_mm: 0.0,
+        }
+    }
+
+    // Pressure is None with humidity-only sensors (DHT)
+    pub fn add_environment(&mut self, temperature: f32, humidity: f32, pressure: Option<f32>) {
+        self.temperature.add(temperature);
+        self.humidity.add(humidity);
+        if let Some(pressure) = pressure {
+            if self.pressure.count == 0 {
+                self.first_pressure = pressure;
+            }
+            self.last_pressure = pressure;
+            self.pressure.add(pressure);
+        }
+    }
+
+    // Direction is weighted by speed so that calm periods don't skew the dominant direction
+    pub fn add_wind(&mut self, speed_kmh: f32, direction_deg: Option<f32>) {
+        self.wind_speed.add(speed_kmh);
+        if let Some(direction) = direction_deg {
+            let rad = direction.to_radians();
+            self.sum_sin += libm::sinf(rad) * speed_kmh;
+            self.sum_cos += libm::cosf(rad) * speed_kmh;
+        }
+    }
+
+    pub fn add_rain(&mut self, rain_mm: f32) {
+        self.rain_mm += rain_mm;
+    }
+
+    /// Close the hour once `now_ms` is past it, returning its summary.
+    pub fn roll(&mut self, now_ms: u64) -> Option<HourlySummary> {
+        let hour = now_ms / HOUR_MS;
+        if hour == self.hour {
+            return None;
+        }
+        let previous = core::mem::take(self);
+        self.hour = hour;
+        // Only an hour that started while collecting covers the full hour
+        self.complete = previous.hour != 0 && hour == previous.hour + 1;
+        if previous.hour == 0 {
+            return None;
+        }
+
+        let dominant_direction = if previous.sum_sin == 0.0 && previous.sum_cos == 0.0 {
+            None
+        } else {
+            let mean = libm::atan2f(previous.sum_sin, previous.sum_cos).to_degrees();
+            Some(if mean < 0.0 { mean + 360.0 } else { mean })
+        };
+
+        Some(HourlySummary {
+            hour_start_ms: previous.hour * HOUR_MS,
+            complete: previous.complete,
+            temperature: previous.temperature,
+            humidity: previous.humidity,
+            pressure: previous.pressure,
+            pressure_change: previous.last_pressure - previous.first_pressure,
+            wind_speed: previous.wind_speed,
+            dominant_direction,
+            rain_mm: previous.rain_mm,
+        })
+    }
+}
+
+impl Default for HourlyAggregator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct TrendSample {
+    time_s: u32,
+    temperature: f32,
+    pressure: f32,
+}
+
+/// Temperature and pressure history used for the rate of change metrics.
+pub struct TrendTracker {
+    samples: CircularBuffer<TrendSample, TREND_SAMPLES>,
+}
+
+impl TrendTracker {
+    pub const fn new() -> Self {
+        Self {
+            samples: CircularBuffer::new(TrendSample {
+                time_s: 0,
+                temperature: 0.0,
+                pressure: 0.0,
+            }),
+        }
+    }
+
+    pub fn add(&mut self, time_s: u32, temperature: f32, pressure: f32) {
+        self.samples.push(TrendSample {
+            time_s,
+            temperature,
+            pressure,
+        });
+    }
+
+    /// Slopes per hour over the last `window_s`.
+    ///
+    /// Valid once the samples span half the window, and as long as no two consecutive
+    /// samples (or the last one and `now_s`) are more than half a window apart.
+    pub fn trends(&self, now_s: u32, window_s: u32) -> Trends {
+        let start = now_s.saturating_sub(window_s);
+        let window = self.samples.as_slice();
+        let first = window.partition_point(|sample| sample.time_s < start);
+        let window = &window[first..];
+
+        let max_gap = window_s / 2;
+        let gapped = window
+            .windows(2)
+            .any(|pair| pair[1].time_s.saturating_sub(pair[0].time_s) > max_gap)
+            || window
+                .last()
+                .map_or(true, |last| now_s.saturating_sub(last.time_s) > max_gap);
+        let span = match (window.first(), window.last()) {
+            (Some(first), Some(last)) => last.time_s.saturating_sub(first.time_s),
+            _ => 0,
+        };
+        if window.len() < MIN_TREND_SAMPLES || span < max_gap || gapped {
+            return Trends::default();
+        }
+
+        let pressure_per_h = slope_per_hour(window, |sample| sample.pressure);
+        Trends {
+            temperature_per_h: slope_per_hour(window, |sample| sample.temperature),
+            pressure_per_h,
+            tendency: PressureTendency::from_rate(pressure_per_h),
+            valid: true,
+        }
+    }
+}
+
+impl Default for TrendTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn slope_per_hour(samples: &[TrendSample], value: impl Fn(&TrendSample) -> f32) -> f32 {
+    let origin = samples[0].time_s;
+    let hours = |sample: &TrendSample| sample.time_s.saturating_sub(origin) as f32 / 3600.0;
+    let n = samples.len() as f32;
+    let mean_x = samples.iter().map(hours).sum::<f32>() / n;
+    let mean_y = samples.iter().map(&value).sum::<f32>() / n;
+    let (cov, var) = samples.iter().fold((0.0, 0.0), |(cov, var), sample| {
+        let dx = hours(sample) - mean_x;
+        (cov + dx * (value(sample) - mean_y), var + dx * dx)
+    });
+    if var == 0.0 {
+        0.0
+    } else {
+        cov / var
+    }
+}
+
+/// Schema of the persisted rain statistics, bump it and migrate in `from_bytes` on change.
+pub const RAIN_STATS_VERSION: u8 = 1;
+pub const RAIN_STATS_LEN: usize = 27;
+
+/// Local calendar position of the station, as given by a synced clock.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CalendarPeriod {
+    pub year: i32,
+    pub month: u32,
+    pub iso_year: i32,
+    pub iso_week: u32,
+}
+
+/// Totals that can be corrected with the `<scope> <mm>` command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RainScope {
+    Week,
+    Month,
+    Year,
+    LastMonth,
+}
+
+impl RainScope {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "week" => Some(RainScope::Week),
+            "month" => Some(RainScope::Month),
+            "year" => Some(RainScope::Year),
+            "last_month" => Some(RainScope::LastMonth),
+            _ => None,
+        }
+    }
+}
+
+// Payload of the rain correction command: `<scope> <total mm>`, e.g. `month 42.5`
+pub fn parse_rain_correction(payload: &str) -> Option<(RainScope, f32)> {
+    let mut words = payload.split_whitespace();
+    let scope = RainScope::from_name(words.next()?)?;
+    let total: f32 = words.next()?.parse().ok()?;
+    if words.next().is_some() || !total.is_finite() || total < 0.0 {
+        return None;
+    }
+    Some((scope, total))
+}
+
+/// Rain totals of the current week, month and year and of the previous month.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RainStatistics {
+    pub week_mm: f32,
+    pub month_mm: f32,
+    pub year_mm: f32,
+    pub last_month_mm: f32,
+    // Period the totals belong to, all zero until the clock was first synced
+    period: CalendarPeriod,
+}
+
+impl RainStatistics {
+    pub const fn new() -> Self {
+        Self {
+            week_mm: 0.0,
+            month_mm: 0.0,
+            year_mm: 0.0,
+            last_month_mm: 0.0,
+            period: CalendarPeriod {
+                year: 0,
+                month: 0,
+                iso_year: 0,
+                iso_week: 0,
+            },
+        }
+    }
+
+    pub fn add(&mut self, rain_mm: f32) {
+        self.week_mm += rain_mm;
+        self.month_mm += rain_mm;
+        self.year_mm += rain_mm;
+    }
+
+    /// Restart the totals whose period ended. Returns whether anything changed.
+    pub fn roll(&mut self, now: CalendarPeriod) -> bool {
+        if now == self.period {
+            return false;
+        }
+        if (now.iso_year, now.iso_week) != (self.period.iso_year, self.period.iso_week) {
+            self.week_mm = 0.0;
+        }
+        if (now.year, now.month) != (self.period.year, self.period.month) {
+            // Only the month right before is "last month", a longer gap means it is unknown
+            let previous = if now.month == 1 {
+                (now.year - 1, 12)
+            } else {
+                (now.year, now.month - 1)
+            };
+            self.last_month_mm = if (self.period.year, self.period.month) == previous {
+                self.month_mm
+            } else {
+                0.0
+            };
+            self.month_mm = 0.0;
+        }
+        if now.year != self.period.year {
+            self.year_mm = 0.0;
+        }
+        self.period = now;
+        true
+    }
+
+    /// Set the `scope` total to `total_mm`, shifting the longer totals that contain it by the
+    /// same amount. A week correction is assumed to be within the current month.
+    /// Returns the applied difference.
+    pub fn correct(&mut self, scope: RainScope, total_mm: f32) -> f32 {
+        let delta = match scope {
+            RainScope::Week => total_mm - self.week_mm,
+            RainScope::Month => total_mm - self.month_mm,
+            RainScope::Year => total_mm - self.year_mm,
+            RainScope::LastMonth => total_mm - self.last_month_mm,
+        };
+        let shift = |total: &mut f32| *total = (*total + delta).max(0.0);
+        match scope {
+            RainScope::Week => {
+                shift(&mut self.week_mm);
+                shift(&mut self.month_mm);
+                shift(&mut self.year_mm);
+            }
+            RainScope::Month => {
+                shift(&mut self.month_mm);
+                shift(&mut self.year_mm);
+            }
+            RainScope::Year => shift(&mut self.year_mm),
+            RainScope::LastMonth => {
+                shift(&mut self.last_month_mm);
+                // Last month of January is in the previous year
+                if self.period.month != 1 {
+                    shift(&mut self.year_mm);
+                }
+            }
+        }
+        delta
+    }
+
+    /// Version byte followed by the totals and the period, little-endian.
+    pub fn to_bytes(&self) -> [u8; RAIN_STATS_LEN] {
+        let mut bytes = [0u8; RAIN_STATS_LEN];
+        bytes[0] = RAIN_STATS_VERSION;
+        let totals = [
+            self.week_mm,
+            self.month_mm,
+            self.year_mm,
+            self.last_month_mm,
+        ];
+        for (i, total) in totals.iter().enumerate() {
+            bytes[1 + i * 4..5 + i * 4].copy_from_slice(&total.to_le_bytes());
+        }
+        bytes[17..21].copy_from_slice(&self.period.year.to_le_bytes());
+        bytes[21] = self.period.month as u8;
+        bytes[22..26].copy_from_slice(&self.period.iso_year.to_le_bytes());
+        bytes[26] = self.period.iso_week as u8;
+        bytes
+    }
+
+    /// None for an unknown schema version or a truncated record.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.first()? {
+            1 if bytes.len() == RAIN_STATS_LEN => {
+                let f32_at = |i: usize| f32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+                let i32_at = |i: usize| i32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+                Some(Self {
+                    week_mm: f32_at(1),
+                    month_mm: f32_at(5),
+                    year_mm: f32_at(9),
+                    last_month_mm: f32_at(13),
+                    period: CalendarPeriod {
+                        year: i32_at(17),
+                        month: bytes[21] as u32,
+                        iso_year: i32_at(22),
+                        iso_week: bytes[26] as u32,
+                    },
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for RainStatistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,73 @@
+//! Record layout of the readings kept while the broker is unreachable.
+//!
+//! A batch is a version byte followed by fixed size records: the unix time (ms), the reading
+//! frame, the gust and the flags.
+use crate::reading::{TimedReading, WeatherReading};
+
+// Bump when the record layout changes, batches of other versions are dropped
+pub const BATCH_VERSION: u8 = 1;
+pub const RECORD_LEN: usize = 8 + WeatherReading::FRAME_LEN + 4 + 1;
+
+const DEMO: u8 = 1 << 0;
+const MAINTENANCE: u8 = 1 << 1;
+const INTERPOLATED: u8 = 1 << 2;
+
+pub fn encode_record(record: &TimedReading) -> [u8; RECORD_LEN] {
+    let reading = &record.reading;
+    let mut bytes = [0u8; RECORD_LEN];
+    let frame_end = 8 + WeatherReading::FRAME_LEN;
+    bytes[..8].copy_from_slice(&record.timestamp_ms.to_le_bytes());
+    bytes[8..frame_end].copy_from_slice(&reading.to_bytes());
+    bytes[frame_end..frame_end + 4].copy_from_slice(&reading.wind_gust_kmh.to_le_bytes());
+    bytes[frame_end + 4] = [
+        (reading.demo, DEMO),
+        (reading.maintenance, MAINTENANCE),
+        (reading.interpolated, INTERPOLATED),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .fold(0, |flags, (_, bit)| flags | bit);
+    bytes
+}
+
+pub fn decode_record(bytes: &[u8]) -> Option<TimedReading> {
+    if bytes.len() != RECORD_LEN {
+        return None;
+    }
+    let frame_end = 8 + WeatherReading::FRAME_LEN;
+    let mut reading = WeatherReading::from_bytes(&bytes[8..frame_end])?;
+    reading.wind_gust_kmh = f32::from_le_bytes(bytes[frame_end..frame_end + 4].try_into().ok()?);
+    let flags = bytes[frame_end + 4];
+    reading.demo = flags & DEMO != 0;
+    reading.maintenance = flags & MAINTENANCE != 0;
+    reading.interpolated = flags & INTERPOLATED != 0;
+    Some(TimedReading {
+        timestamp_ms: u64::from_le_bytes(bytes[..8].try_into().ok()?),
+        reading,
+    })
+}
+
+pub fn encode_batch<'a>(records: impl IntoIterator<Item = &'a TimedReading>) -> Vec<u8> {
+    let mut bytes = vec![BATCH_VERSION];
+    for record in records {
+        bytes.extend_from_slice(&encode_record(record));
+    }
+    bytes
+}
+
+/// Records of a batch, None when its version or length is wrong.
+pub fn decode_batch(bytes: &[u8]) -> Option<Vec<TimedReading>> {
+    let (&version, records) = bytes.split_first()?;
+    if version != BATCH_VERSION || records.len() % RECORD_LEN != 0 {
+        return None;
+    }
+    records
+        .chunks_exact(RECORD_LEN)
+        .map(decode_record)
+        .collect()
+}
+
+/// Size of a batch of `count` records.
+pub const fn batch_len(count: usize) -> usize {
+    1 + count * RECORD_LEN
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use log::{Level, Record};
+use std::net::UdpSocket;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::provisioning::CONFIG;
+use crate::station_id;
+use weather_station::time::utc_timestamp;
+
+const APP_NAME: &str = "weather-station";
+// local0
+const FACILITY: u8 = 16;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const MAX_PER_WINDOW: u32 = 10;
+// RFC 5424 NILVALUE, sent for the fields without a value
+const NIL: &str = "-";
+
+/// RFC 5424 sender forwarding log records to a remote syslog server over UDP.
+pub struct SyslogSink {
+    socket: UdpSocket,
+    min_level: Level,
+    window: Mutex<(Instant, u32)>,
+    dropped: AtomicU32,
+}
+
+impl SyslogSink {
+    pub fn new() -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect((CONFIG.syslog_host, CONFIG.syslog_port))?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            min_level: Level::from_str(CONFIG.syslog_level).unwrap_or(Level::Warn),
+            window: Mutex::new((Instant::now(), 0)),
+            dropped: AtomicU32::new(0),
+        })
+    }
+
+    // Never logs itself: that would recurse into the logger. Dropped records (rate limit or
+    // network down) are counted and reported with the next record that goes through.
+    pub fn send(&self, record: &Record) {
+        if record.level() > self.min_level {
+            return;
+        }
+        if !self.within_rate() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            let notice = format!("{dropped} syslog messages dropped");
+            self.send_line(Level::Warn, APP_NAME, &notice);
+        }
+        self.send_line(record.level(), record.target(), &record.args().to_string());
+    }
+
+    fn within_rate(&self) -> bool {
+        let Ok(mut window) = self.window.lock() else {
+            return false;
+        };
+        if window.0.elapsed() >= RATE_WINDOW {
+            *window = (Instant::now(), 0);
+        }
+        window.1 += 1;
+        window.1 <= MAX_PER_WINDOW
+    }
+
+    fn send_line(&self, level: Level, msg_id: &str, msg: &str) {
+        let line = format!(
+            "<{}>1 {} {} {} - {} - {}",
+            FACILITY * 8 + severity(level),
+            utc_timestamp().as_deref().unwrap_or(NIL),
+            hostname(),
+            APP_NAME,
+            msg_id.get(..32).unwrap_or(msg_id),
+            msg
+        );
+        if self.socket.send(line.as_bytes()).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// HOSTNAME of the record, the nil value when no identifier is configured
+fn hostname() -> &'static str {
+    match station_id() {
+        "" => NIL,
+        id => id,
+    }
+}
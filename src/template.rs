@@ -0,0 +1,80 @@
+use crate::reading::TimedReading;
+
+/// Names usable as `{{name}}` in an upload body template.
+pub const PLACEHOLDERS: [&str; 8] = [
+    "temperature",
+    "humidity",
+    "pressure",
+    "wind_speed",
+    "wind_direction",
+    "rain",
+    "timestamp",
+    "station_id",
+];
+
+// Splits the template into literal text and placeholder names
+fn for_each_part<'t>(
+    template: &'t str,
+    mut f: impl FnMut(Part<'t>) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        f(Part::Text(&rest[..start]))?;
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| {
+            format!(
+                "unclosed placeholder at byte {}",
+                template.len() - rest.len() + start
+            )
+        })?;
+        f(Part::Placeholder(after[..end].trim()))?;
+        rest = &after[end + 2..];
+    }
+    f(Part::Text(rest))
+}
+
+enum Part<'t> {
+    Text(&'t str),
+    Placeholder(&'t str),
+}
+
+/// Checks that every placeholder of the template is known.
+pub fn validate_template(template: &str) -> Result<(), String> {
+    for_each_part(template, |part| match part {
+        Part::Placeholder(name) if !PLACEHOLDERS.contains(&name) => {
+            Err(format!("unknown placeholder {{{{{name}}}}}"))
+        }
+        _ => Ok(()),
+    })
+}
+
+/// Substitutes the placeholders, the timestamp is in unix seconds.
+pub fn render_template(
+    template: &str,
+    timed: &TimedReading,
+    station_id: &str,
+) -> Result<String, String> {
+    let reading = &timed.reading;
+    let mut body = String::with_capacity(template.len());
+    for_each_part(template, |part| {
+        match part {
+            Part::Text(text) => body.push_str(text),
+            Part::Placeholder(name) => {
+                let value = match name {
+                    "temperature" => reading.temperature.to_string(),
+                    "humidity" => reading.humidity.to_string(),
+                    "pressure" => reading.pressure.to_string(),
+                    "wind_speed" => reading.wind_speed_kmh.to_string(),
+                    "wind_direction" => reading.wind_direction_deg.to_string(),
+                    "rain" => reading.rain_mm.to_string(),
+                    "timestamp" => (timed.timestamp_ms / 1000).to_string(),
+                    "station_id" => station_id.to_string(),
+                    _ => return Err(format!("unknown placeholder {{{{{name}}}}}")),
+                };
+                body.push_str(&value);
+            }
+        }
+        Ok(())
+    })?;
+    Ok(body)
+}
@@ -0,0 +1,93 @@
+use crate::Config;
+use chrono::{Duration, NaiveDateTime};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Any earlier date means the clock was never synced
+const MIN_SYNCED_TIMESTAMP: u64 = 1_704_067_200; // 2024-01-01
+
+/// UTC wall clock shifted to the station's local time.
+pub struct TimezonedClock {
+    utc_offset_minutes: i32,
+}
+
+impl TimezonedClock {
+    pub const fn new(utc_offset_minutes: i32) -> Self {
+        Self { utc_offset_minutes }
+    }
+
+    // DST shifts the configured offset by one hour
+    pub const fn from_config(config: &Config) -> Self {
+        let dst = if config.dst_active { 60 } else { 0 };
+        Self::new(config.utc_offset_minutes + dst)
+    }
+
+    pub fn is_synced(&self) -> bool {
+        unix_time_s() >= MIN_SYNCED_TIMESTAMP
+    }
+
+    pub fn local_now(&self) -> NaiveDateTime {
+        let utc = chrono::DateTime::from_timestamp(unix_time_s() as i64, 0)
+            .unwrap_or_default()
+            .naive_utc();
+        utc + Duration::minutes(self.utc_offset_minutes as i64)
+    }
+}
+
+/// Unix time in seconds of a timestamp taken earlier, None if the clock was not synced yet.
+pub fn synced_unix_time_s(unix_ms: u64) -> Option<u64> {
+    (unix_ms / 1000 >= MIN_SYNCED_TIMESTAMP).then_some(unix_ms / 1000)
+}
+
+/// ISO 8601 UTC time of a unix timestamp, to the second.
+pub fn iso8601_utc(unix_ms: u64) -> String {
+    chrono::DateTime::from_timestamp((unix_ms / 1000) as i64, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+/// Current ISO 8601 UTC time, None until the clock is synced.
+pub fn utc_timestamp() -> Option<String> {
+    (unix_time_s() >= MIN_SYNCED_TIMESTAMP).then(|| iso8601_utc(unix_time_ms()))
+}
+
+/// ISO 8601 UTC time of a unix timestamp taken earlier, None if the clock was not synced yet.
+pub fn synced_iso8601_utc(unix_ms: u64) -> Option<String> {
+    (unix_ms / 1000 >= MIN_SYNCED_TIMESTAMP).then(|| iso8601_utc(unix_ms))
+}
+
+/// `YYYY-MM-DD HH:MM:SS` UTC time of a unix timestamp taken earlier, as weather services
+/// expect it. None if the clock was not synced yet.
+pub fn synced_utc_datetime(unix_ms: u64) -> Option<String> {
+    (unix_ms / 1000 >= MIN_SYNCED_TIMESTAMP).then(|| {
+        chrono::DateTime::from_timestamp((unix_ms / 1000) as i64, 0)
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    })
+}
+
+/// `DDHHMM` UTC time of a unix timestamp taken earlier, the day of the month, hour and minute
+/// of APRS reports. None if the clock was not synced yet.
+pub fn synced_aprs_time(unix_ms: u64) -> Option<String> {
+    (unix_ms / 1000 >= MIN_SYNCED_TIMESTAMP).then(|| {
+        chrono::DateTime::from_timestamp((unix_ms / 1000) as i64, 0)
+            .unwrap_or_default()
+            .format("%d%H%M")
+            .to_string()
+    })
+}
+
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn unix_time_s() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use esp_idf_svc::espnow::{EspNow, ReceiveInfo};
+use std::sync::{Arc, Mutex};
+use weather_station::reading::WeatherReading;
+
+use crate::gateway::SharedNodeTable;
+
+const MAX_PEERS: usize = 8;
+
+/// Latest readings received over ESP-NOW from peer stations.
+#[derive(Default)]
+pub struct WeatherNetwork {
+    pub peers: heapless::Vec<[u8; 6], MAX_PEERS>,
+    readings: heapless::Vec<WeatherReading, MAX_PEERS>,
+}
+
+impl WeatherNetwork {
+    /// Store a received frame, registering the sender if it is a new peer.
+    pub fn handle_frame(&mut self, mac: &[u8; 6], frame: &[u8]) {
+        let Some(reading) = WeatherReading::from_bytes(frame) else {
+            log::warn!("Dropping malformed frame from {}", format_mac(mac));
+            return;
+        };
+
+        match self.peers.iter().position(|peer| peer == mac) {
+            Some(idx) => self.readings[idx] = reading,
+            None => {
+                if self.peers.push(*mac).is_err() {
+                    log::warn!("Peer table full, ignoring {}", format_mac(mac));
+                    return;
+                }
+                self.readings.push(reading).ok();
+                log::info!("New peer station {}", format_mac(mac));
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .peers
+            .iter()
+            .zip(self.readings.iter())
+            .map(|(mac, reading)| {
+                format!(
+                    "{{\"peer\": \"{}\", \"reading\": {}}}",
+                    format_mac(mac),
+                    reading.to_json()
+                )
+            })
+            .collect();
+
+        format!("[{}]", entries.join(", "))
+    }
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Start listening for peer frames. WiFi must be started before calling this.
+///
+/// Frames from the gateway nodes go to `nodes`, the others to `network` when the hub is
+/// enabled.
+pub fn espnow_listen(
+    network: Option<Arc<Mutex<WeatherNetwork>>>,
+    nodes: Option<SharedNodeTable>,
+) -> Result<EspNow<'static>> {
+    let espnow = EspNow::take()?;
+
+    espnow.register_recv_cb(move |info: &ReceiveInfo, data: &[u8]| {
+        if let Some(nodes) = nodes.as_ref() {
+            if let Ok(mut nodes) = nodes.lock() {
+                if nodes.is_allowed(info.src_addr) {
+                    nodes.handle_frame(info.src_addr, info.rx_ctrl.rssi() as i8, data);
+                    return;
+                }
+            }
+        }
+        if let Some(Ok(mut network)) = network.as_ref().map(|network| network.lock()) {
+            network.handle_frame(info.src_addr, data);
+        }
+    })?;
+
+    Ok(espnow)
+}
@@ -0,0 +1,35 @@
+use anyhow::Result;
+use std::net::UdpSocket;
+use weather_station::{beacon::*, reading::WeatherReading};
+
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+/// Broadcasts the latest readings on the LAN, independently of the MQTT connection.
+pub struct UdpBeacon {
+    socket: UdpSocket,
+    seq: u32,
+}
+
+impl UdpBeacon {
+    pub fn new() -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_broadcast(true)?;
+        Ok(Self { socket, seq: 0 })
+    }
+
+    pub fn send(&mut self, reading: &WeatherReading) {
+        let datagrams = beacon_datagrams(station_id(), self.seq, &reading.to_json(), SAFE_MTU);
+        self.seq = self.seq.wrapping_add(1);
+
+        for datagram in datagrams {
+            self.socket
+                .send_to(
+                    datagram.as_bytes(),
+                    (CONFIG.udp_beacon_addr, CONFIG.udp_beacon_port),
+                )
+                .map_err(|e| log::warn!("Fail sending udp beacon: {e}"))
+                .ok();
+        }
+    }
+}
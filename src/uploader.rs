@@ -0,0 +1,141 @@
+use anyhow::{anyhow, bail, Result};
+use esp_idf_svc::{
+    http::{
+        client::{Configuration, EspHttpConnection},
+        Method,
+    },
+    io::Write,
+    sys::esp_crt_bundle_attach,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use weather_station::{
+    reading::{TimedReading, WeatherReading},
+    template::*,
+};
+
+use crate::http::ReadingHistory;
+use crate::provisioning::CONFIG;
+use crate::station_id;
+
+const MAX_ATTEMPTS: u32 = 4;
+const FIRST_BACKOFF: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Uploads refused by the server (4xx), they are not retried
+static REJECTED: AtomicU32 = AtomicU32::new(0);
+
+pub fn rejected_uploads() -> u32 {
+    REJECTED.load(Ordering::Relaxed)
+}
+
+enum Outcome {
+    Sent,
+    Rejected(u16),
+    Retry(String),
+}
+
+/// Posts the latest reading to `uploader_url` every `uploader_interval_s`.
+///
+/// The template is checked before the thread starts so typos are reported at boot.
+pub fn uploader_start(history: ReadingHistory) -> Result<()> {
+    let method = match CONFIG.uploader_method {
+        "POST" => Method::Post,
+        "PUT" => Method::Put,
+        other => bail!("unsupported upload method {other}"),
+    };
+    let headers = parse_headers(CONFIG.uploader_headers)?;
+    validate_template(CONFIG.uploader_template).map_err(|e| anyhow!("upload template: {e}"))?;
+    let sample = TimedReading {
+        timestamp_ms: 0,
+        reading: WeatherReading::synthetic(),
+    };
+    let rendered = render_template(CONFIG.uploader_template, &sample, station_id())
+        .map_err(|e| anyhow!("upload template: {e}"))?;
+    serde_json::from_str::<serde_json::Value>(&rendered)
+        .map_err(|e| anyhow!("upload template does not render to JSON: {e}"))?;
+
+    std::thread::Builder::new()
+        .stack_size(8192)
+        .spawn(move || {
+            let mut last_uploaded = 0;
+            loop {
+                std::thread::sleep(Duration::from_secs(CONFIG.uploader_interval_s as u64));
+                let Some(latest) = history.lock().unwrap().last().copied() else {
+                    continue;
+                };
+                if latest.timestamp_ms == last_uploaded {
+                    continue;
+                }
+                match render_template(CONFIG.uploader_template, &latest, station_id()) {
+                    Ok(body) => upload(method, &headers, &body),
+                    Err(e) => log::error!("Fail rendering upload: {e}"),
+                }
+                last_uploaded = latest.timestamp_ms;
+            }
+        })?;
+
+    Ok(())
+}
+
+// `Name: value` pairs separated by `|`, e.g. `Authorization: Bearer abc|X-Station: roof`
+fn parse_headers(headers: &'static str) -> Result<Vec<(&'static str, &'static str)>> {
+    headers
+        .split('|')
+        .filter(|header| !header.trim().is_empty())
+        .map(|header| {
+            header
+                .split_once(':')
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .ok_or_else(|| anyhow!("invalid upload header {header}"))
+        })
+        .collect()
+}
+
+fn upload(method: Method, headers: &[(&str, &str)], body: &str) {
+    let mut backoff = FIRST_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        match send(method, headers, body) {
+            Ok(Outcome::Sent) => return,
+            Ok(Outcome::Rejected(status)) => {
+                let rejected = REJECTED.fetch_add(1, Ordering::Relaxed) + 1;
+                log::error!("Upload rejected with status {status} ({rejected} so far)");
+                return;
+            }
+            Ok(Outcome::Retry(reason)) | Err(reason) => {
+                log::warn!("Upload attempt {attempt}/{MAX_ATTEMPTS} failed: {reason}");
+            }
+        }
+        if attempt < MAX_ATTEMPTS {
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+fn send(method: Method, headers: &[(&str, &str)], body: &str) -> Result<Outcome, String> {
+    let mut conn = EspHttpConnection::new(&Configuration {
+        timeout: Some(REQUEST_TIMEOUT),
+        crt_bundle_attach: Some(esp_crt_bundle_attach),
+        ..Default::default()
+    })
+    .map_err(|e| e.to_string())?;
+
+    let content_length = body.len().to_string();
+    let mut all_headers = vec![
+        ("Content-Type", "application/json"),
+        ("Content-Length", content_length.as_str()),
+    ];
+    all_headers.extend_from_slice(headers);
+
+    conn.initiate_request(method, CONFIG.uploader_url, &all_headers)
+        .map_err(|e| e.to_string())?;
+    conn.write_all(body.as_bytes()).map_err(|e| e.to_string())?;
+    conn.initiate_response().map_err(|e| e.to_string())?;
+
+    Ok(match conn.status() {
+        200..=299 => Outcome::Sent,
+        status @ 400..=499 => Outcome::Rejected(status),
+        status => Outcome::Retry(format!("status {status}")),
+    })
+}
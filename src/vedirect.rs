@@ -0,0 +1,167 @@
+use heapless::String;
+
+/// Values of one checksummed VE.Direct block, fields the device did not send are None.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MpptFrame {
+    pub battery_v: Option<f32>,
+    pub battery_a: Option<f32>,
+    pub panel_v: Option<f32>,
+    pub panel_w: Option<f32>,
+    pub charger_state: Option<u8>,
+    pub error: Option<u8>,
+    pub yield_today_kwh: Option<f32>,
+}
+
+impl MpptFrame {
+    fn set(&mut self, label: &str, value: &str) {
+        let number = || value.parse::<f32>().ok();
+        match label {
+            // mV, mA, mV, W, 0.01 kWh
+            "V" => self.battery_v = number().map(|mv| mv / 1000.0),
+            "I" => self.battery_a = number().map(|ma| ma / 1000.0),
+            "VPV" => self.panel_v = number().map(|mv| mv / 1000.0),
+            "PPV" => self.panel_w = number(),
+            "CS" => self.charger_state = value.parse().ok(),
+            "ERR" => self.error = value.parse().ok(),
+            "H20" => self.yield_today_kwh = number().map(|v| v / 100.0),
+            _ => {}
+        }
+    }
+}
+
+/// Name of a VE.Direct `CS` charger state code.
+pub fn charger_state_name(cs: u8) -> &'static str {
+    match cs {
+        0 => "off",
+        2 => "fault",
+        3 => "bulk",
+        4 => "absorption",
+        5 => "float",
+        7 => "equalize",
+        245 => "starting",
+        247 => "auto_equalize",
+        252 => "external_control",
+        _ => "unknown",
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    Checksum,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    RecordBegin,
+    Label,
+    Value,
+    Checksum,
+}
+
+/// Byte by byte parser of the VE.Direct text protocol.
+///
+/// A block is a series of `\r\n<label>\t<value>` records closed by a `Checksum` record whose
+/// byte brings the sum of the whole block to 0 modulo 256.
+pub struct VeDirectParser {
+    state: State,
+    // Asynchronous hex protocol messages are interleaved and not part of the checksum
+    in_hex: bool,
+    sum: u8,
+    label: String<9>,
+    value: String<33>,
+    frame: MpptFrame,
+}
+
+impl VeDirectParser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Idle,
+            in_hex: false,
+            sum: 0,
+            label: String::new(),
+            value: String::new(),
+            frame: MpptFrame {
+                battery_v: None,
+                battery_a: None,
+                panel_v: None,
+                panel_w: None,
+                charger_state: None,
+                error: None,
+                yield_today_kwh: None,
+            },
+        }
+    }
+
+    /// Feed one byte, returns the frame once its checksum record is received.
+    pub fn push(&mut self, byte: u8) -> Option<Result<MpptFrame, FrameError>> {
+        if byte == b':' && self.state != State::Checksum {
+            self.in_hex = true;
+        }
+        if self.in_hex {
+            self.in_hex = byte != b'\n';
+            return None;
+        }
+        self.sum = self.sum.wrapping_add(byte);
+
+        match self.state {
+            State::Idle => {
+                if byte == b'\n' {
+                    self.state = State::RecordBegin;
+                }
+            }
+            // Line breaks left over around a hex message
+            State::RecordBegin if byte == b'\r' || byte == b'\n' => {}
+            State::RecordBegin => {
+                self.label.clear();
+                self.value.clear();
+                self.label.push(byte as char).ok();
+                self.state = State::Label;
+            }
+            State::Label => {
+                if byte == b'\t' {
+                    self.state = if self.label == "Checksum" {
+                        State::Checksum
+                    } else {
+                        State::Value
+                    };
+                } else if self.label.push(byte as char).is_err() {
+                    // Longer than any label of the protocol, resynchronize on the next block
+                    self.reset();
+                }
+            }
+            State::Value => match byte {
+                b'\n' => {
+                    self.frame.set(&self.label, &self.value);
+                    self.state = State::RecordBegin;
+                }
+                b'\r' => {}
+                _ => {
+                    // Long values (product strings) are truncated, none of the parsed ones are
+                    self.value.push(byte as char).ok();
+                }
+            },
+            State::Checksum => {
+                let valid = self.sum == 0;
+                let frame = self.frame;
+                self.reset();
+                return Some(if valid {
+                    Ok(frame)
+                } else {
+                    Err(FrameError::Checksum)
+                });
+            }
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for VeDirectParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,45 @@
+use esp_idf_svc::sys::{
+    esp, esp_task_wdt_add, esp_task_wdt_config_t, esp_task_wdt_delete, esp_task_wdt_reconfigure,
+    esp_task_wdt_reset, EspError,
+};
+use std::{marker::PhantomData, ptr, time::Duration};
+
+// The idle tasks of both cores stay watched, as in the default IDF configuration
+const IDLE_CORE_MASK: u32 = 0b11;
+
+/// Sets the task watchdog timeout. It panics on expiry, so the backtrace is logged and the
+/// reset counted as an unclean boot.
+pub fn configure(timeout: Duration) -> Result<(), EspError> {
+    let config = esp_task_wdt_config_t {
+        timeout_ms: timeout.as_millis() as u32,
+        idle_core_mask: IDLE_CORE_MASK,
+        trigger_panic: true,
+    };
+    esp!(unsafe { esp_task_wdt_reconfigure(&config) })
+}
+
+/// Watch of the calling task by the task watchdog, removed on drop. The task must `feed` it
+/// more often than the timeout.
+pub struct TaskWatch {
+    // Tied to the task that subscribed
+    _task: PhantomData<*const ()>,
+}
+
+impl TaskWatch {
+    pub fn start() -> Result<Self, EspError> {
+        esp!(unsafe { esp_task_wdt_add(ptr::null_mut()) })?;
+        Ok(Self { _task: PhantomData })
+    }
+}
+
+impl Drop for TaskWatch {
+    fn drop(&mut self) {
+        unsafe { esp_task_wdt_delete(ptr::null_mut()) };
+    }
+}
+
+/// Feeds the watchdog of the calling task, also from within long operations. A no-op in
+/// unwatched tasks.
+pub fn feed() {
+    unsafe { esp_task_wdt_reset() };
+}
@@ -1,35 +1,158 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    hal::modem::Modem,
-    netif::NetifStatus,
-    nvs::EspDefaultNvsPartition,
-    wifi::{BlockingWifi, ClientConfiguration, Configuration, EspWifi},
+    hal::{modem::WifiModemPeripheral, peripheral::Peripheral},
+    netif::{EspNetif, NetifConfiguration, NetifStatus},
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    wifi::{
+        AccessPointConfiguration, AuthMethod, BlockingWifi, ClientConfiguration, Configuration,
+        EspWifi,
+    },
 };
-use weather_station::*;
+use std::net::Ipv4Addr;
 
-pub fn wifi_init<'a>(modem: Modem) -> Result<BlockingWifi<EspWifi<'a>>> {
-    let sys_loop = EspSystemEventLoop::take().expect("wifi_init: fail taking eventloop");
-    let nvs = EspDefaultNvsPartition::take().expect("wifi_init: fail taking nvs");
+use crate::network::fixed_ip;
+use crate::provisioning::CONFIG;
+use crate::station_id;
 
-    let wifi = BlockingWifi::wrap(EspWifi::new(modem, sys_loop.clone(), Some(nvs))?, sys_loop)?;
+const NVS_NAMESPACE: &str = "wifi";
+// Network of the last successful connection
+const NVS_LAST_SSID: &str = "last_ssid";
+// 32 bytes and the nul
+const SSID_BUF_LEN: usize = 33;
 
-    Ok(wifi)
+/// Creates the WiFi driver, its station netif uses DHCP unless `wifi_static_ip` is set.
+pub fn wifi_init<'a>(
+    modem: impl Peripheral<P = impl WifiModemPeripheral> + 'a,
+    sys_loop: EspSystemEventLoop,
+    nvs: EspDefaultNvsPartition,
+) -> Result<BlockingWifi<EspWifi<'a>>> {
+    let mut wifi = EspWifi::new(modem, sys_loop.clone(), Some(nvs))?;
+    if !CONFIG.wifi_static_ip.is_empty() {
+        wifi.swap_netif_sta(static_netif()?)?;
+    }
+
+    Ok(BlockingWifi::wrap(wifi, sys_loop)?)
+}
+
+fn static_netif() -> Result<EspNetif> {
+    Ok(EspNetif::new_with_conf(&NetifConfiguration {
+        ip_configuration: Some(fixed_ip(
+            CONFIG.wifi_static_ip,
+            CONFIG.wifi_gateway,
+            CONFIG.wifi_netmask_bits,
+            CONFIG.wifi_dns,
+        )?),
+        ..NetifConfiguration::wifi_default_client()
+    })?)
+}
+
+pub fn start_wifi(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<()> {
+    set_network(wifi, CONFIG.wifi_ssid, CONFIG.wifi_pass)?;
+    log::info!("Starting wifi");
+    wifi.start()?;
+
+    Ok(())
 }
 
-pub fn connect_wifi(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<()> {
+fn set_network(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    ssid: &str,
+    password: &str,
+) -> Result<()> {
     let wifi_config: Configuration = Configuration::Client(ClientConfiguration {
-        ssid: heapless::String::try_from(CONFIG.wifi_ssid).expect("Invalid WIFI SSID"),
+        ssid: heapless::String::try_from(ssid).map_err(|_| anyhow!("invalid WiFi SSID {ssid}"))?,
         bssid: None,
-        password: heapless::String::try_from(CONFIG.wifi_pass).expect("Invalid WiFi password"),
+        password: heapless::String::try_from(password)
+            .map_err(|_| anyhow!("invalid WiFi password for {ssid}"))?,
         ..Default::default()
     });
-
     wifi.set_configuration(&wifi_config)?;
-    log::info!("Starting wifi");
-    wifi.start()?;
 
-    log::info!("Connecting.....");
+    Ok(())
+}
+
+/// `wifi_ssid` then the `ssid:password` pairs of `wifi_networks`, in priority order. The
+/// password goes up to the next comma and may hold colons.
+fn configured_networks() -> Vec<(&'static str, &'static str)> {
+    let fallbacks = CONFIG
+        .wifi_networks
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let network = entry.split_once(':').unwrap_or((entry, ""));
+            if network.0.is_empty() {
+                log::error!("Invalid wifi_networks entry {entry}");
+                return None;
+            }
+            Some(network)
+        });
+    std::iter::once((CONFIG.wifi_ssid, CONFIG.wifi_pass))
+        .filter(|(ssid, _)| !ssid.is_empty())
+        .chain(fallbacks)
+        .collect()
+}
+
+/// Joins the first configured network in range, the last one joined is tried first. The
+/// driver must be started.
+pub fn connect_wifi(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    nvs: &EspDefaultNvsPartition,
+) -> Result<()> {
+    let store = EspNvs::new(nvs.clone(), NVS_NAMESPACE, true)
+        .map_err(|e| log::error!("Fail opening WiFi storage: {e}"))
+        .ok();
+    let mut buf = [0u8; SSID_BUF_LEN];
+    let last = store
+        .as_ref()
+        .and_then(|store| store.get_str(NVS_LAST_SSID, &mut buf).ok().flatten())
+        .map(str::to_string);
+
+    let mut networks = configured_networks();
+    if networks.len() > 1 {
+        // Hidden networks never show in a scan, they are tried last
+        let visible = match wifi.scan() {
+            Ok(access_points) => access_points
+                .into_iter()
+                .map(|ap| ap.ssid.to_string())
+                .collect(),
+            Err(e) => {
+                log::warn!("Failed to scan WiFi networks: {e}");
+                Vec::new()
+            }
+        };
+        networks.sort_by_key(|(ssid, _)| {
+            (
+                !visible.iter().any(|seen| seen == ssid),
+                last.as_deref() != Some(*ssid),
+            )
+        });
+    }
+
+    let mut result = Err(anyhow!("no WiFi network configured"));
+    for (ssid, password) in networks {
+        log::info!("Connecting to {ssid}.....");
+        result = set_network(wifi, ssid, password).and_then(|()| join(wifi));
+        match &result {
+            Ok(()) => {
+                if last.as_deref() != Some(ssid) {
+                    if let Some(store) = store.as_ref() {
+                        store
+                            .set_str(NVS_LAST_SSID, ssid)
+                            .map_err(|e| log::error!("Fail storing the WiFi network: {e}"))
+                            .ok();
+                    }
+                }
+                break;
+            }
+            Err(e) => log::warn!("Fail connecting to {ssid}: {e}"),
+        }
+    }
+    result
+}
+
+fn join(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<()> {
     wifi.connect()?;
 
     wifi.wait_netif_up()?;
@@ -37,3 +160,59 @@ pub fn connect_wifi(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<()> {
 
     Ok(())
 }
+
+/// Adds an access point named `<station_id>-diag` to the station, protected like the setup
+/// portal by `portal_password`.
+pub fn start_diag_ap(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<Ipv4Addr> {
+    let Ok(Configuration::Client(client)) = wifi.get_configuration() else {
+        bail!("WiFi is not in station mode");
+    };
+    let ssid = format!("{}-diag", station_id());
+    wifi.set_configuration(&Configuration::Mixed(
+        client,
+        AccessPointConfiguration {
+            ssid: heapless::String::try_from(ssid.as_str()).unwrap_or_default(),
+            password: heapless::String::try_from(CONFIG.portal_password).unwrap_or_default(),
+            auth_method: if CONFIG.portal_password.is_empty() {
+                AuthMethod::None
+            } else {
+                AuthMethod::WPA2Personal
+            },
+            ..Default::default()
+        },
+    ))?;
+    let ip = wifi.wifi().ap_netif().get_ip_info()?.ip;
+    log::warn!("Diagnostic access point {ssid}, http://{ip}/diag");
+
+    Ok(ip)
+}
+
+/// Back to the station alone.
+pub fn stop_diag_ap(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<()> {
+    let Ok(Configuration::Mixed(client, _)) = wifi.get_configuration() else {
+        return Ok(());
+    };
+    wifi.set_configuration(&Configuration::Client(client))?;
+    log::info!("Diagnostic access point stopped");
+
+    Ok(())
+}
+
+/// SSID of the network the driver is set to join.
+pub fn current_ssid(wifi: &BlockingWifi<EspWifi>) -> Option<String> {
+    match wifi.get_configuration() {
+        Ok(Configuration::Client(client) | Configuration::Mixed(client, _)) => {
+            Some(client.ssid.to_string())
+        }
+        _ => None,
+    }
+}
+
+// Unspecified when the netif has no address yet
+pub fn station_ip(wifi: &BlockingWifi<EspWifi<'static>>) -> Ipv4Addr {
+    wifi.wifi()
+        .sta_netif()
+        .get_ip_info()
+        .map(|info| info.ip)
+        .unwrap_or(Ipv4Addr::UNSPECIFIED)
+}
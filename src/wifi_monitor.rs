@@ -0,0 +1,147 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{
+        esp, esp_event_base_t, esp_event_handler_register, esp_timer_get_time,
+        wifi_event_sta_disconnected_t, wifi_event_t_WIFI_EVENT_STA_CONNECTED,
+        wifi_event_t_WIFI_EVENT_STA_DISCONNECTED, WIFI_EVENT,
+    },
+};
+use std::ffi::c_void;
+use std::sync::Mutex;
+use weather_station::{
+    time::{synced_unix_time_s, unix_time_ms},
+    wifi_quality::*,
+};
+
+const NVS_NAMESPACE: &str = "wifi_quality";
+const NVS_KEY: &str = "totals";
+
+// Filled from the system event task, read by the main loop
+static QUALITY: Mutex<Option<WifiQuality>> = Mutex::new(None);
+
+/// Connection quality history fed by the WiFi events. The disconnect totals are persisted in
+/// NVS, the rest covers this boot only.
+pub struct WifiMonitor {
+    nvs: EspNvs<NvsDefault>,
+    saved: DisconnectTotals,
+    // The handlers need the default event loop to stay
+    _sys_loop: EspSystemEventLoop,
+}
+
+impl WifiMonitor {
+    /// Registers the event handlers on the loop shared with the WiFi driver. Started before
+    /// the driver to see the first connection.
+    pub fn start(sys_loop: EspSystemEventLoop, nvs: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(nvs, NVS_NAMESPACE, true)?;
+        // Sized from the stored record, other schema versions may have another length
+        let mut buf = vec![0u8; nvs.blob_len(NVS_KEY)?.unwrap_or_default()];
+        let saved = match nvs.get_blob(NVS_KEY, &mut buf)? {
+            Some(bytes) => DisconnectTotals::from_bytes(bytes).unwrap_or_else(|| {
+                log::warn!(
+                    "Unknown WiFi disconnect totals schema {:?}, starting over",
+                    bytes.first()
+                );
+                DisconnectTotals::default()
+            }),
+            None => DisconnectTotals::default(),
+        };
+        *QUALITY.lock().unwrap() = Some(WifiQuality::new(saved));
+
+        for event in [
+            wifi_event_t_WIFI_EVENT_STA_CONNECTED,
+            wifi_event_t_WIFI_EVENT_STA_DISCONNECTED,
+        ] {
+            esp!(unsafe {
+                esp_event_handler_register(
+                    WIFI_EVENT,
+                    event as i32,
+                    Some(wifi_event),
+                    std::ptr::null_mut(),
+                )
+            })?;
+        }
+
+        Ok(Self {
+            nvs,
+            saved,
+            _sys_loop: sys_loop,
+        })
+    }
+
+    /// Adds the RSSI of the current access point to the histogram.
+    pub fn sample_rssi(&self, rssi: i8) {
+        if let Some(quality) = QUALITY.lock().unwrap().as_mut() {
+            quality.sample_rssi(rssi);
+        }
+    }
+
+    pub fn report(&self) -> Option<QualityReport> {
+        QUALITY
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|quality| quality.report(uptime_s()))
+    }
+
+    /// Recent disconnects, the oldest first.
+    pub fn log(&self) -> Vec<Disconnect> {
+        QUALITY
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|quality| quality.log().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Stores the totals when a disconnect was counted since the last save.
+    pub fn save(&mut self) {
+        let Some(totals) = QUALITY.lock().unwrap().as_ref().map(WifiQuality::totals) else {
+            return;
+        };
+        if totals == self.saved {
+            return;
+        }
+        match self.nvs.set_blob(NVS_KEY, &totals.to_bytes()) {
+            Ok(()) => self.saved = totals,
+            Err(e) => log::error!("fail storing the WiFi disconnect totals: {e}"),
+        }
+    }
+}
+
+fn uptime_s() -> u64 {
+    (unsafe { esp_timer_get_time() }) as u64 / 1_000_000
+}
+
+unsafe extern "C" fn wifi_event(
+    _arg: *mut c_void,
+    _base: esp_event_base_t,
+    event_id: i32,
+    event_data: *mut c_void,
+) {
+    let Ok(mut quality) = QUALITY.lock() else {
+        return;
+    };
+    let Some(quality) = quality.as_mut() else {
+        return;
+    };
+    let event_id = event_id as u32;
+    if event_id == wifi_event_t_WIFI_EVENT_STA_CONNECTED {
+        quality.connected(uptime_s());
+    } else if event_id == wifi_event_t_WIFI_EVENT_STA_DISCONNECTED && !event_data.is_null() {
+        let event = &*(event_data as *const wifi_event_sta_disconnected_t);
+        let reason = event.reason as u16;
+        log::warn!(
+            "WiFi disconnected: {} ({reason}), rssi {}",
+            reason_name(reason),
+            event.rssi
+        );
+        quality.disconnected(
+            uptime_s(),
+            synced_unix_time_s(unix_time_ms()),
+            reason,
+            event.rssi,
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! WiFi connection quality: why the station drops off the network, how long associations
+//! last, and how the signal is spread over time.
+//!
+//! Disconnects are logged with the reason code of the IDF `wifi_err_reason_t`. The recent
+//! ones stay in memory, the counts per class of reason are persisted across reboots.
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::stats::MinMeanMax;
+
+// Recent disconnects kept in memory, the oldest are dropped first
+pub const DISCONNECT_LOG_LEN: usize = 32;
+
+pub const DISCONNECT_TOTALS_VERSION: u8 = 1;
+pub const DISCONNECT_TOTALS_LEN: usize = 1 + 7 * 4;
+
+// Lower bounds of the RSSI buckets in dBm, below the last one the signal is unusable
+const RSSI_EXCELLENT: i8 = -50;
+const RSSI_GOOD: i8 = -60;
+const RSSI_FAIR: i8 = -70;
+const RSSI_WEAK: i8 = -80;
+
+/// Name of an IDF disconnect reason code.
+pub fn reason_name(reason: u16) -> &'static str {
+    match reason {
+        1 => "unspecified",
+        2 => "auth_expire",
+        3 => "auth_leave",
+        4 => "assoc_expire",
+        5 => "assoc_toomany",
+        6 => "not_authed",
+        7 => "not_assoced",
+        8 => "assoc_leave",
+        9 => "assoc_not_authed",
+        15 => "4way_handshake_timeout",
+        16 => "group_key_update_timeout",
+        23 => "802_1x_auth_failed",
+        200 => "beacon_timeout",
+        201 => "no_ap_found",
+        202 => "auth_fail",
+        203 => "assoc_fail",
+        204 => "handshake_timeout",
+        205 => "connection_fail",
+        206 => "ap_tsf_reset",
+        207 => "roaming",
+        _ => "unknown",
+    }
+}
+
+/// Disconnects since the counts were first stored, by class of reason.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct DisconnectTotals {
+    /// The access point went silent, usually a weak signal
+    pub beacon_timeout: u32,
+    pub no_ap_found: u32,
+    /// Wrong password or rejected authentication
+    pub auth_fail: u32,
+    pub handshake_timeout: u32,
+    pub assoc_fail: u32,
+    /// Deauthenticated or disassociated on purpose, by the access point or the station
+    pub left: u32,
+    pub other: u32,
+}
+
+impl DisconnectTotals {
+    pub fn add(&mut self, reason: u16) {
+        let count = match reason {
+            200 => &mut self.beacon_timeout,
+            201 => &mut self.no_ap_found,
+            2 | 6 | 23 | 202 => &mut self.auth_fail,
+            15 | 16 | 204 => &mut self.handshake_timeout,
+            4 | 5 | 203 => &mut self.assoc_fail,
+            3 | 8 => &mut self.left,
+            _ => &mut self.other,
+        };
+        *count = count.saturating_add(1);
+    }
+
+    fn counts(&self) -> [u32; 7] {
+        [
+            self.beacon_timeout,
+            self.no_ap_found,
+            self.auth_fail,
+            self.handshake_timeout,
+            self.assoc_fail,
+            self.left,
+            self.other,
+        ]
+    }
+
+    pub fn to_bytes(&self) -> [u8; DISCONNECT_TOTALS_LEN] {
+        let mut bytes = [0u8; DISCONNECT_TOTALS_LEN];
+        bytes[0] = DISCONNECT_TOTALS_VERSION;
+        for (chunk, count) in bytes[1..].chunks_exact_mut(4).zip(self.counts()) {
+            chunk.copy_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != DISCONNECT_TOTALS_LEN || bytes[0] != DISCONNECT_TOTALS_VERSION {
+            return None;
+        }
+        let mut counts = bytes[1..]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()));
+        let mut next = || counts.next().unwrap_or_default();
+        Some(Self {
+            beacon_timeout: next(),
+            no_ap_found: next(),
+            auth_fail: next(),
+            handshake_timeout: next(),
+            assoc_fail: next(),
+            left: next(),
+            other: next(),
+        })
+    }
+}
+
+/// One disconnect, or one failed connection attempt.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Disconnect {
+    pub uptime_s: u64,
+    /// Unix time, absent before the clock is synced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    pub reason: u16,
+    pub reason_name: &'static str,
+    pub rssi: i8,
+    /// Length of the association that ended, absent for a failed attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connected_s: Option<u64>,
+}
+
+/// Signal samples per bucket.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct RssiHistogram {
+    pub excellent: u32,
+    pub good: u32,
+    pub fair: u32,
+    pub weak: u32,
+    pub unusable: u32,
+}
+
+impl RssiHistogram {
+    pub fn add(&mut self, rssi: i8) {
+        let count = match rssi {
+            r if r >= RSSI_EXCELLENT => &mut self.excellent,
+            r if r >= RSSI_GOOD => &mut self.good,
+            r if r >= RSSI_FAIR => &mut self.fair,
+            r if r >= RSSI_WEAK => &mut self.weak,
+            _ => &mut self.unusable,
+        };
+        *count = count.saturating_add(1);
+    }
+}
+
+/// Length of the associations that ended since boot.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct AssociationStats {
+    pub count: u32,
+    pub min_s: f32,
+    pub mean_s: f32,
+    pub max_s: f32,
+}
+
+/// Periodic connection quality report.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QualityReport {
+    /// Length of the current association, absent while disconnected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub associated_s: Option<u64>,
+    pub disconnects_since_boot: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_reason: Option<&'static str>,
+    pub associations: AssociationStats,
+    pub rssi: RssiHistogram,
+    pub totals: DisconnectTotals,
+}
+
+/// Connection quality history of this boot, on top of the persisted totals.
+pub struct WifiQuality {
+    log: VecDeque<Disconnect>,
+    totals: DisconnectTotals,
+    associated_since_s: Option<u64>,
+    associations: MinMeanMax,
+    rssi: RssiHistogram,
+    disconnects: u32,
+}
+
+impl WifiQuality {
+    pub fn new(totals: DisconnectTotals) -> Self {
+        Self {
+            log: VecDeque::with_capacity(DISCONNECT_LOG_LEN),
+            totals,
+            associated_since_s: None,
+            associations: MinMeanMax::new(),
+            rssi: RssiHistogram::default(),
+            disconnects: 0,
+        }
+    }
+
+    pub fn connected(&mut self, uptime_s: u64) {
+        self.associated_since_s = Some(uptime_s);
+    }
+
+    pub fn disconnected(&mut self, uptime_s: u64, timestamp: Option<u64>, reason: u16, rssi: i8) {
+        let connected_s = self
+            .associated_since_s
+            .take()
+            .map(|since| uptime_s.saturating_sub(since));
+        if let Some(duration) = connected_s {
+            self.associations.add(duration as f32);
+        }
+        self.totals.add(reason);
+        self.disconnects = self.disconnects.saturating_add(1);
+        if self.log.len() == DISCONNECT_LOG_LEN {
+            self.log.pop_front();
+        }
+        self.log.push_back(Disconnect {
+            uptime_s,
+            timestamp,
+            reason,
+            reason_name: reason_name(reason),
+            rssi,
+            connected_s,
+        });
+    }
+
+    pub fn sample_rssi(&mut self, rssi: i8) {
+        self.rssi.add(rssi);
+    }
+
+    pub fn totals(&self) -> DisconnectTotals {
+        self.totals
+    }
+
+    /// Recent disconnects, the oldest first.
+    pub fn log(&self) -> &VecDeque<Disconnect> {
+        &self.log
+    }
+
+    pub fn report(&self, uptime_s: u64) -> QualityReport {
+        QualityReport {
+            associated_s: self
+                .associated_since_s
+                .map(|since| uptime_s.saturating_sub(since)),
+            disconnects_since_boot: self.disconnects,
+            last_reason: self.log.back().map(|disconnect| disconnect.reason_name),
+            associations: AssociationStats {
+                count: self.associations.count,
+                min_s: self.associations.min,
+                mean_s: self.associations.mean(),
+                max_s: self.associations.max,
+            },
+            rssi: self.rssi,
+            totals: self.totals,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    http::server::{
+        ws::{EspHttpWsConnection, EspHttpWsDetachedSender},
+        EspHttpServer,
+    },
+    sys::{EspError, ESP_FAIL},
+    ws::FrameType,
+};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAX_WS_CLIENTS: usize = 4;
+// A client taking longer than this to accept a frame gets no further pushes
+const SLOW_CLIENT: Duration = Duration::from_millis(500);
+// Incoming frames are ignored, only small control messages are expected
+const MAX_INCOMING_LEN: usize = 128;
+
+type WsClients = Arc<Mutex<Vec<(i32, EspHttpWsDetachedSender)>>>;
+
+/// Pushes payloads to the WebSocket clients from a dedicated thread.
+///
+/// Only one payload waits to be sent, pushes made while it is pending are dropped so a slow
+/// network never delays the measurement loop.
+pub struct LiveFeed {
+    tx: SyncSender<String>,
+}
+
+impl LiveFeed {
+    pub fn push(&self, payload: String) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(payload) {
+            log::debug!("WebSocket push still pending, dropping this one");
+        }
+    }
+}
+
+/// Registers `/ws`, the handler only tracks clients, readings come through the `LiveFeed`.
+pub fn register_ws_endpoint(server: &mut EspHttpServer<'static>) -> Result<LiveFeed> {
+    let clients: WsClients = Arc::new(Mutex::new(Vec::new()));
+
+    let handler_clients = clients.clone();
+    server.ws_handler("/ws", move |ws: &mut EspHttpWsConnection| {
+        let session = ws.session();
+        if ws.is_new() {
+            let mut clients = handler_clients.lock().unwrap();
+            if clients.len() >= MAX_WS_CLIENTS {
+                log::warn!("WebSocket client limit reached, refusing session {session}");
+                // Failing the handler makes the server close the socket
+                return Err(EspError::from_infallible::<ESP_FAIL>());
+            }
+            clients.push((session, ws.create_detached_sender()?));
+            log::info!("WebSocket client {session} connected");
+        } else if ws.is_closed() {
+            remove_client(&handler_clients, session);
+        } else {
+            // The frame must be consumed even though the content is not used
+            let (_, len) = ws.recv(&mut [])?;
+            if len > MAX_INCOMING_LEN {
+                log::warn!("WebSocket client {session} sent {len} bytes, closing");
+                return Err(EspError::from_infallible::<ESP_FAIL>());
+            }
+            let mut buf = [0u8; MAX_INCOMING_LEN];
+            ws.recv(&mut buf[..len])?;
+        }
+        Ok::<(), EspError>(())
+    })?;
+
+    let (tx, rx) = mpsc::sync_channel::<String>(1);
+    std::thread::Builder::new()
+        .stack_size(4096)
+        .spawn(move || {
+            for payload in rx {
+                let mut clients = clients.lock().unwrap();
+                clients.retain_mut(|(session, sender)| {
+                    let start = Instant::now();
+                    let sent = sender.send(FrameType::Text(false), payload.as_bytes());
+                    match sent {
+                        Ok(()) if start.elapsed() <= SLOW_CLIENT => true,
+                        Ok(()) => {
+                            log::warn!("WebSocket client {session} too slow, dropping it");
+                            false
+                        }
+                        Err(e) => {
+                            log::info!("WebSocket client {session} gone: {e}");
+                            false
+                        }
+                    }
+                });
+            }
+        })?;
+
+    Ok(LiveFeed { tx })
+}
+
+fn remove_client(clients: &WsClients, session: i32) {
+    clients.lock().unwrap().retain(|(id, _)| *id != session);
+    log::info!("WebSocket client {session} disconnected");
+}
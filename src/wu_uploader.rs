@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::{
+    http::{
+        client::{Configuration, EspHttpConnection},
+        Method,
+    },
+    io::Read,
+    sys::esp_crt_bundle_attach,
+};
+use std::time::Duration;
+use weather_station::{
+    runtime::RUNTIME,
+    time::synced_utc_datetime,
+    wunderground::{observation_query, UPLOAD_URL},
+};
+
+use crate::http::ReadingHistory;
+use crate::provisioning::CONFIG;
+
+const MAX_ATTEMPTS: u32 = 4;
+const FIRST_BACKOFF: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Uploads the latest reading to the Weather Underground station `wunderground_station_id`
+/// every `wunderground_interval_s`. Demo and test readings are never sent.
+pub fn wu_uploader_start(history: ReadingHistory) -> Result<()> {
+    if CONFIG.wunderground_station_id.is_empty() || CONFIG.wunderground_key.is_empty() {
+        bail!("wunderground_station_id and wunderground_key are required");
+    }
+
+    std::thread::Builder::new()
+        .stack_size(8192)
+        .spawn(move || {
+            let mut last_uploaded = 0;
+            loop {
+                std::thread::sleep(Duration::from_secs(CONFIG.wunderground_interval_s as u64));
+                let Some(latest) = history.lock().unwrap().last().copied() else {
+                    continue;
+                };
+                if latest.timestamp_ms == last_uploaded
+                    || latest.reading.demo
+                    || latest.reading.synthetic
+                {
+                    continue;
+                }
+                // The server dates the upload when the clock is not set
+                let date_utc = synced_utc_datetime(latest.timestamp_ms);
+                let query = observation_query(
+                    CONFIG.wunderground_station_id,
+                    CONFIG.wunderground_key,
+                    &latest.reading,
+                    RUNTIME.fields(),
+                    date_utc.as_deref(),
+                );
+                upload(&format!("{UPLOAD_URL}?{query}"));
+                last_uploaded = latest.timestamp_ms;
+            }
+        })?;
+    log::info!(
+        "Uploading to Weather Underground station {}",
+        CONFIG.wunderground_station_id
+    );
+
+    Ok(())
+}
+
+fn upload(url: &str) {
+    let mut backoff = FIRST_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        match send(url) {
+            Ok((200, body)) if body.trim() == "success" => return,
+            // Wrong station id or key, retrying does not help
+            Ok((status, body)) if status < 500 => {
+                log::error!(
+                    "Weather Underground upload rejected ({status}): {}",
+                    body.trim()
+                );
+                return;
+            }
+            Ok((status, _)) => log::warn!(
+                "Weather Underground upload {attempt}/{MAX_ATTEMPTS} failed: status {status}"
+            ),
+            Err(e) => {
+                log::warn!("Weather Underground upload {attempt}/{MAX_ATTEMPTS} failed: {e}")
+            }
+        }
+        if attempt < MAX_ATTEMPTS {
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+// Status and the start of the body, which reads `success` once accepted
+fn send(url: &str) -> Result<(u16, String)> {
+    let mut conn = EspHttpConnection::new(&Configuration {
+        timeout: Some(REQUEST_TIMEOUT),
+        crt_bundle_attach: Some(esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+
+    conn.initiate_request(Method::Get, url, &[])?;
+    conn.initiate_response()?;
+    let status = conn.status();
+    let mut buf = [0u8; 128];
+    let len = conn.read(&mut buf)?;
+
+    Ok((status, String::from_utf8_lossy(&buf[..len]).into_owned()))
+}
@@ -0,0 +1,122 @@
+//! Observation upload of the Weather Underground personal weather station protocol.
+//!
+//! The `updateweatherstation` endpoint takes the observation as a query string in imperial
+//! units: °F, inches of mercury, mph and inches of rain.
+use crate::derive::Derived;
+use crate::reading::WeatherReading;
+use crate::runtime::{Field, FieldSelection};
+use core::fmt::Write;
+
+pub const UPLOAD_URL: &str =
+    "https://weatherstation.wunderground.com/weatherstation/updateweatherstation.php";
+const SOFTWARE_TYPE: &str = "weather_station";
+
+pub fn celsius_to_fahrenheit(celsius: f32) -> f32 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+pub fn kmh_to_mph(kmh: f32) -> f32 {
+    kmh / 1.609_344
+}
+
+pub fn hpa_to_inhg(hpa: f32) -> f32 {
+    hpa / 33.863_89
+}
+
+pub fn mm_to_in(mm: f32) -> f32 {
+    mm / 25.4
+}
+
+/// Escapes everything but the unreserved characters of a query string value.
+pub fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            write!(encoded, "%{byte:02X}").ok();
+        }
+    }
+    encoded
+}
+
+/// Query string of an observation, without the leading `?`. `date_utc` is the time it was
+/// taken as `YYYY-MM-DD HH:MM:SS`, None lets the server use the time of the upload. The
+/// measurements left out by `fields` are not sent, neither are the wind and the rain of a
+/// reading taken during maintenance.
+pub fn observation_query(
+    station_id: &str,
+    station_key: &str,
+    reading: &WeatherReading,
+    fields: FieldSelection,
+    date_utc: Option<&str>,
+) -> String {
+    let mut query = format!(
+        "ID={}&PASSWORD={}&action=updateraw&dateutc={}&softwaretype={SOFTWARE_TYPE}",
+        percent_encode(station_id),
+        percent_encode(station_key),
+        date_utc.map_or("now".to_string(), percent_encode)
+    );
+    let measured = |field: Field| fields.contains(field);
+    let outdoor = |field: Field| measured(field) && !reading.maintenance;
+    let values = [
+        (
+            "tempf",
+            measured(Field::Temperature).then(|| celsius_to_fahrenheit(reading.temperature)),
+            1,
+        ),
+        (
+            "humidity",
+            measured(Field::Humidity).then_some(reading.humidity),
+            0,
+        ),
+        (
+            "dewptf",
+            Derived::from_reading(reading)
+                .filter(|_| measured(Field::Derived))
+                .map(|derived| celsius_to_fahrenheit(derived.dew_point)),
+            1,
+        ),
+        // The sea level pressure is expected, as on a home barometer
+        (
+            "baromin",
+            reading
+                .sea_level_pressure
+                .filter(|_| measured(Field::Pressure))
+                .map(hpa_to_inhg),
+            3,
+        ),
+        (
+            "windspeedmph",
+            outdoor(Field::WindSpeed).then(|| kmh_to_mph(reading.wind_speed_kmh)),
+            1,
+        ),
+        (
+            "windgustmph",
+            outdoor(Field::WindGust).then(|| kmh_to_mph(reading.wind_gust_kmh)),
+            1,
+        ),
+        (
+            "winddir",
+            outdoor(Field::WindDirection).then_some(reading.wind_direction_deg),
+            0,
+        ),
+        // Rain over the last hour and since local midnight
+        (
+            "rainin",
+            outdoor(Field::Rain).then(|| mm_to_in(reading.rain_totals.last_1h_mm)),
+            2,
+        ),
+        (
+            "dailyrainin",
+            outdoor(Field::Rain).then(|| mm_to_in(reading.rain_totals.today_mm)),
+            2,
+        ),
+    ];
+    for (name, value, decimals) in values {
+        if let Some(value) = value.filter(|value| value.is_finite()) {
+            write!(query, "&{name}={value:.decimals$}").ok();
+        }
+    }
+    query
+}